@@ -0,0 +1,275 @@
+use crate::colors::*;
+use crate::html::*;
+use crate::layout::Rect;
+use crate::length::*;
+
+// The horizontal and vertical radius of the top left, top right, bottom right
+// and bottom left corners.
+pub type CornerRadii = [(f64, f64); 4];
+
+// Segments per rounded corner, even so a corner splits between its two sides.
+const CORNER_SEGMENTS: usize = 8;
+
+const TOP: usize = 0;
+const LEFT: usize = 3;
+
+pub fn has_radius(radii: &CornerRadii) -> bool {
+    radii.iter().any(|(x, y)| *x > 0.0 && *y > 0.0)
+}
+
+// Resolves the radii against the border box and scales them down together
+// when adjacent ones don't fit, CSS Backgrounds §5.5.
+pub fn get_corner_radii(computed_style: &ComputedStyle, rect: &Rect) -> CornerRadii {
+    let radius = &computed_style.border_radius;
+    let context = &computed_style.length_context;
+    let resolve = |(x, y): &(LengthPercentage, LengthPercentage)| -> (f64, f64) {
+        let x = x.resolve(context, rect.width).max(0.0);
+        let y = y.resolve(context, rect.height).max(0.0);
+        // a corner with a zero radius is square
+        if x == 0.0 || y == 0.0 {
+            return (0.0, 0.0);
+        }
+        (x, y)
+    };
+    let radii = [
+        resolve(&radius.top_left),
+        resolve(&radius.top_right),
+        resolve(&radius.bottom_right),
+        resolve(&radius.bottom_left),
+    ];
+
+    let mut factor: f64 = 1.0;
+    for (length, sum) in [
+        (rect.width, radii[0].0 + radii[1].0),
+        (rect.width, radii[3].0 + radii[2].0),
+        (rect.height, radii[0].1 + radii[3].1),
+        (rect.height, radii[1].1 + radii[2].1),
+    ]
+    .iter()
+    {
+        if *sum > 0.0 {
+            factor = factor.min(length / sum);
+        }
+    }
+    if factor < 1.0 {
+        return [
+            (radii[0].0 * factor, radii[0].1 * factor),
+            (radii[1].0 * factor, radii[1].1 * factor),
+            (radii[2].0 * factor, radii[2].1 * factor),
+            (radii[3].0 * factor, radii[3].1 * factor),
+        ];
+    }
+    return radii;
+}
+
+fn get_corner_point_counts(radii: &CornerRadii) -> [usize; 4] {
+    let count = |(x, y): (f64, f64)| if x > 0.0 && y > 0.0 { CORNER_SEGMENTS + 1 } else { 1 };
+    [count(radii[0]), count(radii[1]), count(radii[2]), count(radii[3])]
+}
+
+// The outline of the box `rect` with its corners rounded by `radii`, moved
+// in by `inset` on each side, clockwise from the start of the top left
+// corner. Every corner has `counts` points, so paths at different insets
+// line up point for point.
+fn get_path(rect: &Rect, radii: &CornerRadii, inset: &Margin, counts: &[usize; 4]) -> Vec<[f64; 2]> {
+    let x = rect.x + inset.left;
+    let y = rect.y + inset.top;
+    let right = rect.x + rect.width - inset.right;
+    let bottom = rect.y + rect.height - inset.bottom;
+    let inner_radius = |(rx, ry): (f64, f64), dx: f64, dy: f64| ((rx - dx).max(0.0), (ry - dy).max(0.0));
+    let corners = [
+        (inner_radius(radii[0], inset.left, inset.top), x, y, 1.0, 1.0),
+        (inner_radius(radii[1], inset.right, inset.top), right, y, -1.0, 1.0),
+        (inner_radius(radii[2], inset.right, inset.bottom), right, bottom, -1.0, -1.0),
+        (inner_radius(radii[3], inset.left, inset.bottom), x, bottom, 1.0, -1.0),
+    ];
+
+    let mut path = vec![];
+    for (i, ((rx, ry), corner_x, corner_y, sx, sy)) in corners.iter().enumerate() {
+        let center_x = corner_x + sx * rx;
+        let center_y = corner_y + sy * ry;
+        let start = std::f64::consts::PI * (1.0 + i as f64 / 2.0);
+        for k in 0..counts[i] {
+            let angle = if counts[i] > 1 {
+                start + std::f64::consts::FRAC_PI_2 * k as f64 / (counts[i] - 1) as f64
+            } else {
+                start
+            };
+            path.push([center_x + rx * angle.cos(), center_y + ry * angle.sin()]);
+        }
+    }
+    return path;
+}
+
+// A background clipped to the rounded border box.
+pub fn get_rounded_rect(rect: &Rect, radii: &CornerRadii) -> Vec<[f64; 2]> {
+    let zero = Margin {
+        top: 0.0,
+        right: 0.0,
+        bottom: 0.0,
+        left: 0.0,
+    };
+    return get_path(rect, radii, &zero, &get_corner_point_counts(radii));
+}
+
+// The segments of a path, with the side each belongs to and whether it is
+// the straight part of that side. A corner's first half goes with the side
+// before it.
+fn get_path_segments(counts: &[usize; 4]) -> Vec<(usize, usize, usize, bool)> {
+    let total: usize = counts.iter().sum();
+    let mut segments = vec![];
+    let mut start = 0;
+    for corner in 0..4 {
+        let count = counts[corner];
+        for k in 0..count - 1 {
+            let side = if k < (count - 1) / 2 { (corner + 3) % 4 } else { corner };
+            segments.push((start + k, start + k + 1, side, false));
+        }
+        segments.push((start + count - 1, (start + count) % total, corner, true));
+        start += count;
+    }
+    return segments;
+}
+
+fn shade(color: &Color, factor: f64) -> Color {
+    let (r, g, b, a) = color.to_srgba();
+    Color::from_srgba(r * factor, g * factor, b * factor, a)
+}
+
+// The bands a side is painted in, as fractions of its width from the
+// outside, with their colors. The 3D styles are darker on the top and left
+// for `inset` and `groove`, and on the bottom and right for the others.
+fn get_side_bands(side: &BorderSide, index: usize) -> Vec<(f64, f64, Color)> {
+    let dark = shade(&side.color, 0.5);
+    let light = side.color;
+    let top_left = index == TOP || index == LEFT;
+    let (first, second) = if top_left { (dark, light) } else { (light, dark) };
+    match side.style.as_str() {
+        "double" if side.width >= 3.0 => vec![(0.0, 1.0 / 3.0, side.color), (2.0 / 3.0, 1.0, side.color)],
+        "groove" => vec![(0.0, 0.5, first), (0.5, 1.0, second)],
+        "ridge" => vec![(0.0, 0.5, second), (0.5, 1.0, first)],
+        "inset" => vec![(0.0, 1.0, first)],
+        "outset" => vec![(0.0, 1.0, second)],
+        _ => vec![(0.0, 1.0, side.color)],
+    }
+}
+
+fn scale_widths(widths: &Margin, f: f64) -> Margin {
+    Margin {
+        top: widths.top * f,
+        right: widths.right * f,
+        bottom: widths.bottom * f,
+        left: widths.left * f,
+    }
+}
+
+// Cuts the straight part of a side into dashes or dots. `a` to `b` is the
+// outer edge and `d` to `c` the inner one. What lies beyond the inner edge
+// at the ends, the corner joins, stays solid.
+fn get_dashes(quad: [[f64; 2]; 4], width: f64, dotted: bool) -> Vec<Vec<[f64; 2]>> {
+    let [a, b, c, d] = quad;
+    let length = ((b[0] - a[0]).powi(2) + (b[1] - a[1]).powi(2)).sqrt();
+    if length == 0.0 || width == 0.0 {
+        return vec![];
+    }
+    let u = [(b[0] - a[0]) / length, (b[1] - a[1]) / length];
+    let project = |p: [f64; 2]| (p[0] - a[0]) * u[0] + (p[1] - a[1]) * u[1];
+    let inner_start = project(d);
+    let outer = |s: f64| [a[0] + u[0] * s, a[1] + u[1] * s];
+    let inner = |s: f64| [d[0] + u[0] * (s - inner_start), d[1] + u[1] * (s - inner_start)];
+    let start = inner_start.max(0.0);
+    let end = project(c).min(length);
+
+    let mut polygons = vec![vec![a, outer(start), inner(start), d], vec![outer(end), b, c, inner(end)]];
+    let span = end - start;
+    if span <= 0.0 {
+        return polygons;
+    }
+
+    if dotted {
+        let count = ((span + width) / (2.0 * width)).floor().max(1.0) as usize;
+        let radius = width / 2.0;
+        for i in 0..count {
+            let s = if count == 1 {
+                start + span / 2.0
+            } else {
+                start + radius + i as f64 * (span - width) / (count - 1) as f64
+            };
+            let o = outer(s);
+            let n = inner(s);
+            let center = [(o[0] + n[0]) / 2.0, (o[1] + n[1]) / 2.0];
+            polygons.push(
+                (0..8)
+                    .map(|k| {
+                        let angle = std::f64::consts::PI * k as f64 / 4.0;
+                        [center[0] + radius * angle.cos(), center[1] + radius * angle.sin()]
+                    })
+                    .collect(),
+            );
+        }
+    } else {
+        let dash = 3.0 * width;
+        let count = ((span + dash) / (2.0 * dash)).round().max(1.0) as usize;
+        let step = span / (2 * count - 1) as f64;
+        for i in 0..count {
+            let p = start + 2.0 * i as f64 * step;
+            let q = p + step;
+            polygons.push(vec![outer(p), outer(q), inner(q), inner(p)]);
+        }
+    }
+    return polygons;
+}
+
+// The convex polygons a border is painted with and their colors, before
+// opacity. Each side is cut from the outline of the border box to the
+// outline of the padding box, the corners split diagonally between sides.
+pub fn get_border_polygons(rect: &Rect, border: &Border, radii: &CornerRadii) -> Vec<(Vec<[f64; 2]>, Color)> {
+    let sides = [&border.top, &border.right, &border.bottom, &border.left];
+    let widths = border.widths();
+    let counts = get_corner_point_counts(radii);
+    let segments = get_path_segments(&counts);
+
+    let mut polygons = vec![];
+    for (index, side) in sides.iter().enumerate() {
+        if side.width <= 0.0 || side.color.is_transparent() {
+            continue;
+        }
+        let dashed = side.style == "dashed" || side.style == "dotted";
+        for (from, to, color) in get_side_bands(side, index) {
+            let outer = get_path(rect, radii, &scale_widths(&widths, from), &counts);
+            let inner = get_path(rect, radii, &scale_widths(&widths, to), &counts);
+            for (i, j, segment_side, straight) in &segments {
+                if *segment_side != index {
+                    continue;
+                }
+                let quad = [outer[*i], outer[*j], inner[*j], inner[*i]];
+                if *straight && dashed {
+                    for polygon in get_dashes(quad, side.width, side.style == "dotted") {
+                        polygons.push((polygon, color));
+                    }
+                } else {
+                    polygons.push((quad.to_vec(), color));
+                }
+            }
+        }
+    }
+    return polygons;
+}
+
+// An inline box broken across lines has no border at the breaks, and square
+// corners there.
+pub fn slice_border(border: &Border, radii: &CornerRadii, first: bool, last: bool) -> (Border, CornerRadii) {
+    let mut border = border.clone();
+    let mut radii = *radii;
+    if !first {
+        border.left.width = 0.0;
+        radii[0] = (0.0, 0.0);
+        radii[3] = (0.0, 0.0);
+    }
+    if !last {
+        border.right.width = 0.0;
+        radii[1] = (0.0, 0.0);
+        radii[2] = (0.0, 0.0);
+    }
+    return (border, radii);
+}
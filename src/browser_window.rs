@@ -1,51 +1,63 @@
 use crate::colors::*;
 use crate::css::*;
+use crate::cssom::*;
 use crate::debug::*;
+use crate::element_state::*;
 use crate::html::*;
 use crate::layout::*;
+use crate::length::*;
+use crate::loader::*;
+use crate::media::*;
+use crate::overflow::*;
 use crate::styles::*;
 use std::borrow::BorrowMut;
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::fmt::format;
 use std::time::Instant;
 
-extern crate find_folder;
 extern crate piston_window;
 
 use opengl_graphics::GlGraphics;
 use piston_window::character::CharacterCache;
 use piston_window::*;
 use std::cell::RefCell;
-use std::fs;
 use std::sync::Arc;
 use std::sync::Mutex;
 use std::thread;
 
+// The fonts are compiled in, like the user agent style sheet, so graviton
+// runs from any directory.
+const FONTS: &[(&str, &[u8])] = &[
+    ("Times New Roman 400.ttf", include_bytes!("../assets/Times New Roman 400.ttf")),
+    ("Times New Roman 700.ttf", include_bytes!("../assets/Times New Roman 700.ttf")),
+    (
+        "Times New Roman Italique 400.ttf",
+        include_bytes!("../assets/Times New Roman Italique 400.ttf"),
+    ),
+    (
+        "Times New Roman Italique 700.ttf",
+        include_bytes!("../assets/Times New Roman Italique 700.ttf"),
+    ),
+];
+
 pub fn create_browser_window(url: String) {
     let mut window: PistonWindow = WindowSettings::new("Graviton", [1366, 768])
         .exit_on_esc(true)
         .build()
         .unwrap();
 
-    let assets = find_folder::Search::ParentsThenKids(3, 3)
-        .for_folder("assets")
-        .unwrap();
-
     let mut glyphs_map: RefCell<HashMap<String, opengl_graphics::GlyphCache>> =
         RefCell::new(HashMap::new());
 
-    let mut add_font = |name: &str| {
-        let mut glyphs = opengl_graphics::GlyphCache::new(
-            assets.join(name),
+    let mut add_font = |name: &str, font: &'static [u8]| {
+        let mut glyphs = opengl_graphics::GlyphCache::from_bytes(
+            font,
             (),
             opengl_graphics::TextureSettings::new(),
         )
         .unwrap();
-        glyphs_map.borrow_mut().insert(
-            name.to_string(),
-            // window.load_font(assets.join(name)).unwrap(),
-            glyphs,
-        )
+        glyphs_map.borrow_mut().insert(name.to_string(), glyphs)
     };
 
     let mut scroll_y: f64 = 0.0;
@@ -62,35 +74,23 @@ pub fn create_browser_window(url: String) {
         };
     };
 
-    add_font("Times New Roman 400.ttf");
-    add_font("Times New Roman 700.ttf");
-    add_font("Times New Roman Italique 400.ttf");
-    add_font("Times New Roman Italique 700.ttf");
+    for (name, font) in FONTS {
+        add_font(name, font);
+    }
 
     let mut render_array: Vec<RenderItem> = vec![];
-    let mut dom_tree: RefCell<Vec<DomElement>> = RefCell::new(vec![]);
-    let mut parsed_css: RefCell<Vec<StyleRule>> = RefCell::new(vec![]);
+    let preferences: RefCell<UserPreferences> = RefCell::new(UserPreferences::new());
+    let mut document: RefCell<Document> = RefCell::new(Document::new());
     let mut styles: Vec<StyleRule> = vec![];
 
-    let default_css =
-        fs::read_to_string("default_styles.css").expect("error while reading the file");
-    let default_styles = parse_css(&default_css);
-
-    let color_conv = |c: ColorTupleA| {
-        // [
-        //     (c.0 / 255.0) as f32,
-        //     (c.1 / 255.0) as f32,
-        //     (c.2 / 255.0) as f32,
-        //     (c.3 / 255.0) as f32,
-        // ]
-        [c.0 as f32, c.1 as f32, c.2 as f32, c.3 as f32]
-    };
+    let default_styles = get_user_agent_rules();
+    let user_style_sheets: RefCell<Vec<StyleSheet>> = RefCell::new(vec![]);
 
     let rerender = |window: &PistonWindow, scroll_y: f64| {
         let s = Instant::now();
         let window_rect = get_window_rect(&window, scroll_y);
         let render_array: Vec<RenderItem> =
-            get_render_array(&mut dom_tree.borrow_mut(), &window_rect)
+            get_render_array(&mut document.borrow_mut().children, &window_rect)
                 .into_iter()
                 .collect();
         println!(
@@ -102,7 +102,7 @@ pub fn create_browser_window(url: String) {
         return render_array;
     };
 
-    let reflow = || {
+    let reflow = |window: &PistonWindow| {
         let s = Instant::now();
         let closure_ref = RefCell::new(|text: String, font_size: f64, font_family: String| {
             let mut glyphs_map = glyphs_map.borrow_mut();
@@ -111,42 +111,119 @@ pub fn create_browser_window(url: String) {
         });
 
         reflow(
-            &mut dom_tree.borrow_mut(),
+            &mut document.borrow_mut().children,
             &move |text, font_size, font_family| {
                 return (
                     (closure_ref.borrow_mut())(text, font_size, font_family),
                     font_size - 2.0 + 8.0,
                 );
             },
-            None,
+            Some(ReflowContext {
+                x: 0.0,
+                y: 0.0,
+                containing_width: window.size().width as f64,
+            }),
         );
         println!("Reflow took: {:?}", s.elapsed());
     };
 
     let recompute_styles = |window: &PistonWindow, styles: &Vec<StyleRule>| {
         let s = Instant::now();
-        compute_styles(&mut dom_tree.borrow_mut(), &styles, None);
+        let window_size = window.size();
+        let length_context = LengthContext::new(window_size.width as f64, window_size.height as f64);
+        let mut document = document.borrow_mut();
+        compute_styles(
+            &mut document.children,
+            &styles,
+            None,
+            &length_context,
+            preferences.borrow().color_scheme,
+        );
+        document.needs_restyle = false;
         println!("Computing styles took: {:?}", s.elapsed());
     };
 
+    // a paint only change keeps the layout
+    let apply_style_change = |window: &PistonWindow, change: StyleChange, scroll_y: f64| {
+        match change {
+            StyleChange::None => None,
+            StyleChange::Paint => Some(rerender(&window, scroll_y)),
+            StyleChange::Layout => {
+                reflow(&window);
+                Some(rerender(&window, scroll_y))
+            }
+        }
+    };
+
+    // restyles the elements whose state changed and lays the document out again
+    let apply_state_change = |window: &PistonWindow,
+                              changed: Vec<Vec<usize>>,
+                              flags: &[ElementStateFlag],
+                              styles: &Vec<StyleRule>,
+                              scroll_y: f64|
+     -> Option<Vec<RenderItem>> {
+        let change =
+            restyle_changed_elements(
+                &mut document.borrow_mut().children,
+                &changed,
+                flags,
+                styles,
+                preferences.borrow().color_scheme,
+            );
+
+        return apply_style_change(window, change, scroll_y);
+    };
+
     let recalc_all = |window: &PistonWindow, styles: &Vec<StyleRule>, scroll_y: f64| {
         recompute_styles(&window, &styles);
-        reflow();
+        reflow(&window);
         return rerender(&window, scroll_y);
     };
 
+    let loader = FileLoader;
+    let visited_urls: RefCell<HashSet<String>> = RefCell::new(HashSet::new());
+
+    let collect_styles = |window: &PistonWindow| {
+        let window_size = window.size();
+        let media_environment = MediaEnvironment::with_preferences(
+            window_size.width as f64,
+            window_size.height as f64,
+            &preferences.borrow(),
+        );
+        let user_styles = get_active_rules(&user_style_sheets.borrow(), &media_environment);
+        let author_styles = document.borrow().active_rules(&media_environment);
+
+        return [default_styles.clone(), user_styles, author_styles].concat();
+    };
+
     let refresh = |window: &PistonWindow, u: String| {
-        let contents = fs::read_to_string(u.clone()).expect("error while reading the file");
-        *dom_tree.borrow_mut() = parse_html(&contents);
+        let contents = loader.load(&u).expect("error while reading the file");
+        let children = parse_html(&contents);
 
         println!("{:#?}", tokenize(contents));
 
-        let style = get_styles(dom_tree.borrow_mut().clone(), None);
-        *parsed_css.borrow_mut() = parse_css(&style);
+        let style_sheets = get_style_sheets(&children, &u, &loader);
+
+        *user_style_sheets.borrow_mut() = match get_config_dir() {
+            Some(dir) => get_user_style_sheets(&dir, &u, &loader),
+            None => vec![],
+        };
+
+        println!("Styles: {:?}", style_sheets);
+
+        *document.borrow_mut() = Document {
+            url: u.clone(),
+            children,
+            style_sheets,
+            needs_restyle: true,
+        };
 
-        println!("Styles: {:?}", parsed_css.borrow_mut());
+        let visited_urls = visited_urls.borrow();
+        mark_visited_links(&mut document.borrow_mut().children, &|href| {
+            visited_urls.contains(&resolve_url(&u, href))
+        });
 
-        return [default_styles.clone(), parsed_css.borrow_mut().clone()].concat();
+        return collect_styles(window);
     };
 
     styles = refresh(&window, url.clone());
@@ -154,9 +231,13 @@ pub fn create_browser_window(url: String) {
 
     let mut pressed_up = false;
     let mut pressed_down = false;
+    let mut pressed_shift = false;
 
     let mut mouse_x = 0.0;
     let mut mouse_y = 0.0;
+    // the element under the mouse, hit tested once per mouse event against
+    // what was painted last
+    let mut mouse_path: Option<Vec<usize>> = None;
 
     let mut el_txt = "".to_string();
     let mut element: Option<&DomElement> = None;
@@ -169,30 +250,179 @@ pub fn create_browser_window(url: String) {
 
         // key down
 
+        // style sheets or inline styles changed through the CSSOM
+        if document.borrow().needs_restyle {
+            styles = collect_styles(&window);
+            render_array = recalc_all(&window, &styles, scroll_y);
+        }
+
         if let Some(Button::Keyboard(key)) = event.press_args() {
             if key == Key::F5 {
                 styles = refresh(&window, url.clone());
                 render_array = recalc_all(&window, &styles, scroll_y);
             }
 
+            // switches between the light and the dark color scheme
+            if key == Key::F6 {
+                {
+                    let mut preferences = preferences.borrow_mut();
+                    preferences.color_scheme = match preferences.color_scheme {
+                        ColorScheme::Light => ColorScheme::Dark,
+                        ColorScheme::Dark => ColorScheme::Light,
+                    };
+                }
+                styles = collect_styles(&window);
+                render_array = recalc_all(&window, &styles, scroll_y);
+            }
+
             if key == Key::Up {
                 pressed_up = true;
             } else if key == Key::Down {
                 pressed_down = true;
+            } else if key == Key::LShift || key == Key::RShift {
+                pressed_shift = true;
+            }
+
+            // keyboard focus matches :focus-visible
+            if key == Key::Tab {
+                let changed = {
+                    let mut document = document.borrow_mut();
+                    let focused = find_element_path(&document.children, &|e| e.state.focus);
+                    let next = get_next_focusable_path(
+                        &document.children,
+                        focused.as_ref().map(|p| p.as_slice()),
+                        pressed_shift,
+                    );
+                    focus_element(&mut document.children, next.as_ref().map(|p| p.as_slice()), true)
+                };
+                let flags = [
+                    ElementStateFlag::Focus,
+                    ElementStateFlag::FocusVisible,
+                    ElementStateFlag::FocusWithin,
+                ];
+                match apply_state_change(&window, changed, &flags, &styles, scroll_y) {
+                    Some(r) => render_array = r,
+                    None => {}
+                }
             }
         };
 
+        // mouse down activates and focuses, mouse up releases
+        if let Some(Button::Mouse(MouseButton::Left)) = event.press_args() {
+            let (changed, flags) = {
+                let mut document = document.borrow_mut();
+                mouse_path = get_element_path_at(&render_array, &get_window_rect(&window, scroll_y), mouse_x, mouse_y);
+                let path = mouse_path.as_ref().map(|p| p.as_slice());
+                let focus_path = path.and_then(|p| get_focusable_path(&document.children, p));
+
+                let mut changed = update_element_state(&mut document.children, path, ElementStateFlag::Active);
+                changed.extend(focus_element(
+                    &mut document.children,
+                    focus_path.as_ref().map(|p| p.as_slice()),
+                    false,
+                ));
+                (
+                    changed,
+                    [
+                        ElementStateFlag::Active,
+                        ElementStateFlag::Focus,
+                        ElementStateFlag::FocusVisible,
+                        ElementStateFlag::FocusWithin,
+                    ],
+                )
+            };
+            match apply_state_change(&window, changed, &flags, &styles, scroll_y) {
+                Some(r) => render_array = r,
+                None => {}
+            }
+        }
+
+        if let Some(Button::Mouse(MouseButton::Left)) = event.release_args() {
+            let (changed, checked, visited) = {
+                let mut document = document.borrow_mut();
+                let active = find_element_path(&document.children, &|e| {
+                    e.state.active && e.children.iter().all(|c| !c.state.active)
+                });
+                let changed = update_element_state(&mut document.children, None, ElementStateFlag::Active);
+
+                // a click toggles checkboxes and visits links
+                let mut checked: Vec<Vec<usize>> = vec![];
+                let mut visited: Vec<Vec<usize>> = vec![];
+                match active {
+                    Some(path) => {
+                        checked = toggle_checked(&mut document.children, &path);
+
+                        let link = (1..path.len() + 1)
+                            .rev()
+                            .map(|len| path[..len].to_vec())
+                            .find(|p| get_element_by_path(&document.children, p).map(is_link).unwrap_or(false));
+                        match link {
+                            Some(link) => {
+                                let href = get_element_by_path(&document.children, &link).unwrap().attributes["href"].clone();
+                                visited_urls.borrow_mut().insert(resolve_url(&document.url, &href));
+                                let url = document.url.clone();
+                                let visited_urls = visited_urls.borrow();
+                                visited = mark_visited_links(&mut document.children, &|href| {
+                                    visited_urls.contains(&resolve_url(&url, href))
+                                });
+                            }
+                            None => {}
+                        }
+                    }
+                    None => {}
+                }
+                (changed, checked, visited)
+            };
+
+            // checkedness isn't tracked as a state, those elements are always restyled
+            let change = {
+                let mut document = document.borrow_mut();
+                let color_scheme = preferences.borrow().color_scheme;
+                restyle_changed_elements(&mut document.children, &checked, &[], &styles, color_scheme)
+                    .max(restyle_changed_elements(
+                        &mut document.children,
+                        &visited,
+                        &[ElementStateFlag::Visited],
+                        &styles,
+                        color_scheme,
+                    ))
+                    .max(restyle_changed_elements(
+                        &mut document.children,
+                        &changed,
+                        &[ElementStateFlag::Active],
+                        &styles,
+                        color_scheme,
+                    ))
+            };
+            match apply_style_change(&window, change, scroll_y) {
+                Some(r) => render_array = r,
+                None => {}
+            }
+        }
+
         if let Some(Button::Keyboard(key)) = event.release_args() {
             if key == Key::Up {
                 pressed_up = false;
             } else if key == Key::Down {
                 pressed_down = false;
+            } else if key == Key::LShift || key == Key::RShift {
+                pressed_shift = false;
             }
         };
 
         // scroll event
+        // the innermost box under the cursor that can still scroll takes the
+        // wheel, then the page, shift turns it sideways
         if let Some(args) = event.mouse_scroll_args() {
-            scroll_y -= args[1] * 1.0;
+            let (dx, dy) = if pressed_shift { (-args[1], 0.0) } else { (-args[0], -args[1]) };
+            mouse_path = get_element_path_at(&render_array, &get_window_rect(&window, scroll_y), mouse_x, mouse_y);
+            let scrolled = match &mouse_path {
+                Some(path) => scroll_element_at(&mut document.borrow_mut().children, path, dx, dy),
+                None => false,
+            };
+            if !scrolled {
+                scroll_y += dy;
+            }
             render_array = rerender(&window, scroll_y);
         }
 
@@ -213,44 +443,53 @@ pub fn create_browser_window(url: String) {
 
         // on resize
         if let Some(size) = event.resize_args() {
-            reflow();
-            render_array = rerender(&window, scroll_y);
+            // media queries and viewport units depend on the window size
+            styles = collect_styles(&window);
+            render_array = recalc_all(&window, &styles, scroll_y);
         }
 
         if mouse.is_some() {
             mouse_x = mouse.unwrap()[0];
             mouse_y = mouse.unwrap()[1];
 
-            // get dom element at mouse position
-
-            // if should_rerender(
-            //     mouse_x,
-            //     mouse_y,
-            //     &mut dom_tree.borrow_mut(),
-            //     &*parsed_css.borrow_mut(),
-            // ) {
-                
-            //     recompute_styles(&window, &styles);
-            //     reflow();
-            //     render_array = rerender(&window, scroll_y);
-            // }
+            // only the elements whose style depends on :hover are restyled
+            mouse_path = get_element_path_at(&render_array, &get_window_rect(&window, scroll_y), mouse_x, mouse_y);
+            let change = should_rerender(
+                mouse_path.as_ref().map(|p| p.as_slice()),
+                &mut document.borrow_mut().children,
+                &styles,
+                preferences.borrow().color_scheme,
+            );
+            match apply_style_change(&window, change, scroll_y) {
+                Some(r) => render_array = r,
+                None => {}
+            }
         }
 
-        let mut dom_tree = dom_tree.borrow_mut();
-        let element = get_element_at(&dom_tree, mouse_x, mouse_y + scroll_y);
+        let document = document.borrow();
+        let element = mouse_path.as_ref().and_then(|p| get_element_by_path(&document.children, p));
         if element.is_some() {
             let el = element.unwrap();
-            el_txt = format!(
-                "{:?} {:#?} {:#?}",
-                el.tag_name, el.attributes, el.computed_style
-            );
+            let computed_style = get_computed_style(el);
+            el_txt = format!("{:?} {:#?}\n", el.tag_name, el.attributes);
+            for declaration in &computed_style.style.declarations {
+                el_txt += &format!("{}: {}\n", declaration.0, declaration.1);
+            }
+            if computed_style.unsupported_properties.len() > 0 {
+                el_txt += &format!(
+                    "unsupported: {}\n",
+                    computed_style.unsupported_properties.join(", ")
+                );
+            }
         }
 
         let window_size = &window.size();
 
+        let canvas_color = get_canvas_color(&document.children);
+
         if let Some(args) = event.render_args() {
             gl.draw(args.viewport(), |c, g| {
-                clear([1.0, 1.0, 1.0, 1.0], g);
+                clear(canvas_color.to_array(), g);
                 // let device = &mut c.de
 
                 // window.draw_2d(&event, |context, graphics, device| {
@@ -259,14 +498,49 @@ pub fn create_browser_window(url: String) {
 
                 let mut glyphs_map = glyphs_map.borrow_mut();
 
+                // clip rects are in window coordinates, the scissor in pixels
+                let scale = args.draw_size[0] as f64 / args.window_size[0].max(1.0);
+
                 for item in &render_array {
+                    // the items for hit testing aren't painted
+                    if item.path.is_some() {
+                        continue;
+                    }
+                    // fixed boxes stay where they are in the window
+                    let scroll_y = if item.fixed { 0.0 } else { scroll_y };
                     let item_y = item.y - scroll_y;
                     let glyphs = glyphs_map.get_mut(&item.font_path).unwrap();
 
-                    if item.background_color != (0.0, 0.0, 0.0, 0.0) {
-                        rectangle(
-                            color_conv(item.background_color),
+                    let draw_state = match &item.clip {
+                        Some(clip) => {
+                            let left = clip.x.max(0.0);
+                            let top = (clip.y - scroll_y).max(0.0);
+                            let right = (clip.x + clip.width).min(args.window_size[0]);
+                            let bottom = (clip.y + clip.height - scroll_y).min(args.window_size[1]);
+                            if right <= left || bottom <= top {
+                                continue;
+                            }
+                            c.draw_state.scissor([
+                                (left * scale) as u32,
+                                (top * scale) as u32,
+                                ((right - left) * scale).ceil() as u32,
+                                ((bottom - top) * scale).ceil() as u32,
+                            ])
+                        }
+                        None => c.draw_state,
+                    };
+
+                    if item.polygon.len() > 0 {
+                        Polygon::new(item.background_color.to_array()).draw(
+                            &item.polygon,
+                            &draw_state,
+                            c.transform.trans(0.0, -scroll_y),
+                            g,
+                        );
+                    } else if !item.background_color.is_transparent() {
+                        Rectangle::new(item.background_color.to_array()).draw(
                             [0.0, 0.0, item.width, item.height],
+                            &draw_state,
                             c.transform.trans(item.x, item_y),
                             g,
                         );
@@ -275,25 +549,25 @@ pub fn create_browser_window(url: String) {
                     if item.text != "" {
                         font_path = item.font_path.clone();
 
-                        let color = color_conv(item.color);
+                        let color = item.color.to_array();
 
                         text::Text::new_color(color, 2 * ((item.font_size) as u32))
                             .draw(
                                 &item.text,
                                 glyphs,
-                                &c.draw_state,
+                                &draw_state,
                                 c.transform
-                                    .trans(item.x, item_y + item.height - 4.0)
+                                    .trans(item.x, item.baseline - scroll_y)
                                     .zoom(0.5),
                                 g,
                             )
                             .unwrap();
 
                         if item.underline {
-                            rectangle(
-                                color,
+                            Rectangle::new(color).draw(
                                 [0.0, 0.0, item.width, 1.0],
-                                c.transform.trans(item.x, item_y + item.height - 3.0),
+                                &draw_state,
+                                c.transform.trans(item.x, item.baseline - scroll_y + 1.0),
                                 g,
                             );
                         }
@@ -1,76 +1,156 @@
-use crate::lisia_colors::Color;
+use crate::lisia_colors::parse_color;
 
-pub type ColorTuple = (f64, f64, f64);
-
-pub type ColorTupleA = (f64, f64, f64, f64);
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColorScheme {
+  Light,
+  Dark,
+}
 
-fn make_parse_err(s: &str, t: &str) -> String {
-  format!("Error while parsing color {} as {}", s, t)
+// How the channels of a `Color` are encoded.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColorSpace {
+  Srgb,
+  // sRGB without the transfer function, where compositing is done
+  LinearSrgb,
 }
 
-fn make_parse_css_err(s: &str) -> String {
-  format!("Error while parsing css color {}", s)
+// A color with premultiplied alpha. Channels go from 0 to 1.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Color {
+  pub r: f64,
+  pub g: f64,
+  pub b: f64,
+  pub a: f64,
+  pub space: ColorSpace,
 }
 
-pub fn hex_to_rgb(s: &str) -> Result<ColorTuple, String> {
-  let mut hex = s.replace("#", "").to_lowercase();
-  let hex_chars = hex.chars().collect::<Vec<char>>();
-  let count = hex_chars.len();
-
-  if count == 3 {
-    hex = hex_chars
-      .iter()
-      .map(|c| c.to_string().repeat(2))
-      .collect::<Vec<String>>()
-      .join("");
-  } else if count != 6 {
-    return Err(make_parse_err(s, "rgb"));
+impl Color {
+  pub const TRANSPARENT: Color = Color {
+    r: 0.0,
+    g: 0.0,
+    b: 0.0,
+    a: 0.0,
+    space: ColorSpace::Srgb,
+  };
+
+  pub const BLACK: Color = Color {
+    r: 0.0,
+    g: 0.0,
+    b: 0.0,
+    a: 1.0,
+    space: ColorSpace::Srgb,
+  };
+
+  pub const WHITE: Color = Color {
+    r: 1.0,
+    g: 1.0,
+    b: 1.0,
+    a: 1.0,
+    space: ColorSpace::Srgb,
+  };
+
+  // From channels that are not premultiplied.
+  pub fn from_straight(r: f64, g: f64, b: f64, a: f64, space: ColorSpace) -> Color {
+    Color {
+      r: r * a,
+      g: g * a,
+      b: b * a,
+      a,
+      space,
+    }
+  }
+
+  pub fn from_srgba(r: f64, g: f64, b: f64, a: f64) -> Color {
+    Color::from_straight(r, g, b, a, ColorSpace::Srgb)
+  }
+
+  // The channels divided by the alpha, in the color's own space.
+  pub fn to_straight(&self) -> (f64, f64, f64, f64) {
+    if self.a <= 0.0 {
+      return (0.0, 0.0, 0.0, 0.0);
+    }
+    (self.r / self.a, self.g / self.a, self.b / self.a, self.a)
+  }
+
+  pub fn to_srgba(&self) -> (f64, f64, f64, f64) {
+    self.to_space(ColorSpace::Srgb).to_straight()
   }
 
-  match usize::from_str_radix(&hex, 16) {
-    Ok(num) => Ok(hex_num_to_rgb(num)),
-    Err(_) => Err(make_parse_err(s, "rgb")),
+  pub fn to_space(&self, space: ColorSpace) -> Color {
+    if self.space == space {
+      return *self;
+    }
+    let transfer = match space {
+      ColorSpace::Srgb => linear_to_srgb,
+      ColorSpace::LinearSrgb => srgb_to_linear,
+    };
+    let (r, g, b, a) = self.to_straight();
+    Color::from_straight(transfer(r), transfer(g), transfer(b), a, space)
+  }
+
+  pub fn is_transparent(&self) -> bool {
+    self.a <= 0.0
+  }
+
+  // Fades the color the way `opacity` does.
+  pub fn with_opacity(&self, opacity: f64) -> Color {
+    let opacity = opacity.max(0.0).min(1.0);
+    Color {
+      r: self.r * opacity,
+      g: self.g * opacity,
+      b: self.b * opacity,
+      a: self.a * opacity,
+      space: self.space,
+    }
+  }
+
+  // Paints this color over `backdrop` (source-over), blending in linear light.
+  // The result is in sRGB.
+  pub fn over(&self, backdrop: &Color) -> Color {
+    let source = self.to_space(ColorSpace::LinearSrgb);
+    let backdrop = backdrop.to_space(ColorSpace::LinearSrgb);
+    let k = 1.0 - source.a;
+    let blended = Color {
+      r: source.r + backdrop.r * k,
+      g: source.g + backdrop.g * k,
+      b: source.b + backdrop.b * k,
+      a: source.a + backdrop.a * k,
+      space: ColorSpace::LinearSrgb,
+    };
+    blended.to_space(ColorSpace::Srgb)
+  }
+
+  // Straight sRGB, the way piston takes colors.
+  pub fn to_array(&self) -> [f32; 4] {
+    let (r, g, b, a) = self.to_srgba();
+    [r as f32, g as f32, b as f32, a as f32]
   }
 }
 
-pub fn parse_css_color(s: &str) -> Result<ColorTupleA, String> {
-  let s = s.to_string().replace("!important", "");
-  // if s.starts_with("#") {
-  //   match hex_to_rgb(&s) {
-  //     Ok(r) => Ok((r.0, r.1, r.2, 255.0)),
-  //     Err(_) => Err(make_parse_css_err(&s)),
-  //   }
-  // } else {
-  //   if s.starts_with("rgb") {
-  //     let start = s.find("(").unwrap_or(0) + 1;
-  //     let end = s.find(")").unwrap_or(0);
-  //     let parts = s[start..end].split(",").collect::<Vec<&str>>();
-
-  //     if parts.len() == 3 {
-  //       let r = parts[0].trim().parse::<f64>().unwrap_or(0.0);
-  //       let g = parts[1].trim().parse::<f64>().unwrap_or(0.0);
-  //       let b = parts[2].trim().parse::<f64>().unwrap_or(0.0);
-
-  //       return Ok((r, g, b, 255.0));
-  //     } else if parts.len() == 4 {
-  //       let r = parts[0].trim().parse::<f64>().unwrap_or(0.0);
-  //       let g = parts[1].trim().parse::<f64>().unwrap_or(0.0);
-  //       let b = parts[2].trim().parse::<f64>().unwrap_or(0.0);
-  //       let a = parts[3].trim().parse::<f64>().unwrap_or(0.0);
-
-  //       return Ok((r, g, b, a * 255.0));
-  //     }
-  //   }
-  //   Err(make_parse_css_err(&s))
-  // }
-  let arr = Color::new(&s).to_array().map(|x| x as f64);
-  Ok((arr[0], arr[1], arr[2], arr[3]))
+// The sRGB transfer function, also used by display-p3. Extended to negative
+// values by symmetry.
+pub fn srgb_to_linear(c: f64) -> f64 {
+  if c.abs() <= 0.04045 {
+    c / 12.92
+  } else {
+    c.signum() * ((c.abs() + 0.055) / 1.055).powf(2.4)
+  }
 }
 
-fn hex_num_to_rgb(num: usize) -> ColorTuple {
-  let r = (num >> 16) as f64;
-  let g = ((num >> 8) & 0x00FF) as f64;
-  let b = (num & 0x0000_00FF) as f64;
+pub fn linear_to_srgb(c: f64) -> f64 {
+  if c.abs() <= 0.0031308 {
+    c * 12.92
+  } else {
+    c.signum() * (1.055 * c.abs().powf(1.0 / 2.4) - 0.055)
+  }
+}
 
-  (r, g, b)
+// Parses a CSS color, with `currentColor` resolved to `current_color` and
+// system colors and `light-dark()` to `color_scheme`.
+pub fn parse_css_color_with_current(
+  s: &str,
+  current_color: &Color,
+  color_scheme: ColorScheme,
+) -> Result<Color, String> {
+  parse_color(&s.replace("!important", ""), Some(current_color), color_scheme)
 }
@@ -1,26 +1,36 @@
 use crate::colors::*;
+use crate::length::*;
+use crate::lisia_colors::is_color;
 use crate::styles::*;
 use crate::utils::*;
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub enum CssValue {
     String(String),
-    Number(f64),
-    Color(ColorTupleA),
+    Color(Color),
 }
 
 impl CssValue {
-    pub fn to_number(&self) -> f64 {
+    pub fn to_string(&self) -> &String {
         match &self {
-            CssValue::Number(obj) => *obj,
-            _ => 0.0,
+            CssValue::String(obj) => obj,
+            _ => panic!("Cannot convert to string"),
         }
     }
 
-    pub fn to_string(&self) -> &String {
+    pub fn to_css_string(&self) -> String {
         match &self {
-            CssValue::String(obj) => obj,
-            _ => panic!("Cannot convert to string"),
+            CssValue::String(obj) => obj.clone(),
+            CssValue::Color(c) => {
+                let (r, g, b, a) = c.to_srgba();
+                format!(
+                    "rgba({}, {}, {}, {})",
+                    (r * 255.0).round(),
+                    (g * 255.0).round(),
+                    (b * 255.0).round(),
+                    a
+                )
+            }
         }
     }
 }
@@ -29,102 +39,959 @@ pub fn css_string(st: &str) -> CssValue {
     CssValue::String(st.to_string())
 }
 
-pub fn parse_numeric_css_value(value: &str, base_font_size: f64) -> f64 {
-    let chars = value.chars().enumerate();
+pub fn parse_numeric_css_value(value: &str, context: &LengthContext, percentage_basis: f64) -> f64 {
+    match parse_length_percentage(value) {
+        Ok(v) => v.resolve(context, percentage_basis),
+        Err(e) => {
+            println!("Error while parsing css value {}: {}", value, e);
+            0.0
+        }
+    }
+}
+
+// `context` is the parent's context, so `em` and percentages refer to the
+// parent's font size.
+pub fn parse_font_size(value: &str, context: &LengthContext) -> f64 {
+    let parent_font_size = context.font_size;
+    match value.trim() {
+        "xx-small" => 9.0,
+        "x-small" => 10.0,
+        "small" => 13.0,
+        "medium" => 16.0,
+        "large" => 18.0,
+        "x-large" => 24.0,
+        "xx-large" => 32.0,
+        "xxx-large" => 48.0,
+        "smaller" => parent_font_size / 1.2,
+        "larger" => parent_font_size * 1.2,
+        v => parse_numeric_css_value(v, context, parent_font_size),
+    }
+}
+
+// `bolder` and `lighter` are relative to the parent's weight.
+pub fn parse_font_weight(value: &str, parent_font_weight: f64) -> f64 {
+    match value.trim() {
+        "normal" => 400.0,
+        "bold" => 700.0,
+        "bolder" => {
+            if parent_font_weight < 350.0 {
+                400.0
+            } else if parent_font_weight < 550.0 {
+                700.0
+            } else {
+                900.0
+            }
+        }
+        "lighter" => {
+            if parent_font_weight < 550.0 {
+                100.0
+            } else if parent_font_weight < 750.0 {
+                400.0
+            } else {
+                700.0
+            }
+        }
+        v => match parse_number(v) {
+            Ok(w) if w >= 1.0 && w <= 1000.0 => w,
+            _ => {
+                println!("Invalid font weight {}", v);
+                parent_font_weight
+            }
+        },
+    }
+}
+
+// Splits a value on whitespace, keeping function arguments and quoted strings
+// like `rgb(0, 0, 0)` or `"Times New Roman"` together.
+pub fn split_css_values(value: &str) -> Vec<String> {
+    split_css_top_level(value, |c| c.is_whitespace())
+}
+
+// Splits a value on commas outside of functions and quotes.
+pub fn split_css_list(value: &str) -> Vec<String> {
+    split_css_top_level(value, |c| c == ',')
+}
 
-    let mut unit: String = "".to_string();
-    let mut val_str: String = "".to_string();
+fn split_css_top_level(value: &str, is_separator: impl Fn(char) -> bool) -> Vec<String> {
+    let mut values: Vec<String> = vec![];
+    let mut current = "".to_string();
+    let mut depth = 0;
+    let mut quote: Option<char> = None;
 
-    let mut capturing_unit = false;
+    for c in value.chars() {
+        match quote {
+            Some(q) => {
+                if c == q {
+                    quote = None;
+                }
+                current.push(c);
+                continue;
+            }
+            None => {}
+        }
 
-    for (i, c) in chars {
-        if c == ' ' {
+        if c == '"' || c == '\'' {
+            quote = Some(c);
+        } else if c == '(' {
+            depth += 1;
+        } else if c == ')' {
+            depth -= 1;
+        } else if depth == 0 && is_separator(c) {
+            if current.trim() != "" {
+                values.push(current.trim().to_string());
+            }
+            current = "".to_string();
             continue;
         }
 
-        if c == 'p' || c == 'e' {
-            capturing_unit = true;
+        current.push(c);
+    }
+
+    if current.trim() != "" {
+        values.push(current.trim().to_string());
+    }
+
+    return values;
+}
+
+// One to four values, for the top, right, bottom and left. `None` for any
+// other count.
+pub fn parse_rect_like_value(value: &str) -> Option<(String, String, String, String)> {
+    let values = split_css_values(value);
+    let get = |i: usize| values[i].clone();
+
+    match values.len() {
+        1 => Some((get(0), get(0), get(0), get(0))),
+        2 => Some((get(0), get(1), get(0), get(1))),
+        3 => Some((get(0), get(1), get(2), get(1))),
+        4 => Some((get(0), get(1), get(2), get(3))),
+        _ => None,
+    }
+}
+
+const SIDES: &[&str] = &["top", "right", "bottom", "left"];
+
+const BORDER_STYLES: &[&str] = &[
+    "none", "hidden", "dotted", "dashed", "solid", "double", "groove", "ridge", "inset", "outset",
+];
+
+const CSS_WIDE_KEYWORDS: &[&str] = &["inherit", "initial", "unset", "revert", "revert-layer"];
+
+fn is_length_value(value: &str) -> bool {
+    parse_length_percentage(value).is_ok()
+}
+
+fn is_line_width(value: &str) -> bool {
+    value == "thin" || value == "medium" || value == "thick" || is_length_value(value)
+}
+
+fn is_image_value(value: &str) -> bool {
+    value == "none"
+        || value.starts_with("url(")
+        || value.contains("gradient(")
+        || value.starts_with("image(")
+        || value.starts_with("image-set(")
+}
+
+fn push_rect_like(declarations: &mut Vec<KeyValue>, value: &str, format_key: impl Fn(&str) -> String) -> bool {
+    let (top, right, bottom, left) = match parse_rect_like_value(value) {
+        Some(v) => v,
+        None => return false,
+    };
+
+    declarations.push(KeyValue(format_key("top"), top));
+    declarations.push(KeyValue(format_key("right"), right));
+    declarations.push(KeyValue(format_key("bottom"), bottom));
+    declarations.push(KeyValue(format_key("left"), left));
+
+    return true;
+}
+
+// `<line-width> || <line-style> || <color>`, shared by `border`, `border-*`
+// and `outline`. Omitted parts are reset to their initial values.
+fn parse_border_like(value: &str) -> Option<(String, String, String)> {
+    let mut width: Option<String> = None;
+    let mut style: Option<String> = None;
+    let mut color: Option<String> = None;
+
+    for part in split_css_values(value) {
+        if style.is_none() && BORDER_STYLES.contains(&part.as_str()) {
+            style = Some(part);
+        } else if width.is_none() && is_line_width(&part) {
+            width = Some(part);
+        } else if color.is_none() && is_color(&part) {
+            color = Some(part);
+        } else {
+            return None;
+        }
+    }
+
+    Some((
+        width.unwrap_or(S("medium")),
+        style.unwrap_or(S("none")),
+        color.unwrap_or(S("currentcolor")),
+    ))
+}
+
+fn expand_border(declarations: &mut Vec<KeyValue>, sides: &[&str], value: &str) -> bool {
+    let (width, style, color) = match parse_border_like(value) {
+        Some(v) => v,
+        None => return false,
+    };
+
+    for side in sides {
+        declarations.push(KeyValue(format!("border-{}-width", side), width.clone()));
+        declarations.push(KeyValue(format!("border-{}-style", side), style.clone()));
+        declarations.push(KeyValue(format!("border-{}-color", side), color.clone()));
+    }
+
+    return true;
+}
+
+// Up to four horizontal radii, optionally followed by `/` and up to four
+// vertical ones, for the corners from the top left clockwise.
+fn expand_border_radius(declarations: &mut Vec<KeyValue>, value: &str) -> bool {
+    let mut halves = value.splitn(2, '/');
+    let horizontal = halves.next().unwrap_or("").trim().to_string();
+    let vertical = halves.next().map(|v| v.trim().to_string());
+
+    let (top_left, top_right, bottom_right, bottom_left) = match parse_rect_like_value(&horizontal) {
+        Some(v) => v,
+        None => return false,
+    };
+    let mut corners = vec![
+        ("top-left", top_left),
+        ("top-right", top_right),
+        ("bottom-right", bottom_right),
+        ("bottom-left", bottom_left),
+    ];
+    match &vertical {
+        Some(v) => {
+            let (top_left, top_right, bottom_right, bottom_left) = match parse_rect_like_value(v) {
+                Some(v) => v,
+                None => return false,
+            };
+            for (corner, v) in corners.iter_mut().zip(vec![top_left, top_right, bottom_right, bottom_left]) {
+                corner.1 = format!("{} {}", corner.1, v);
+            }
         }
+        None => {}
+    }
+
+    for (corner, radius) in corners {
+        declarations.push(KeyValue(format!("border-{}-radius", corner), radius));
+    }
+
+    return true;
+}
+
+fn expand_outline(declarations: &mut Vec<KeyValue>, value: &str) -> bool {
+    // `auto` is a style only outlines have
+    let (auto, rest): (Vec<String>, Vec<String>) = split_css_values(value).into_iter().partition(|p| p == "auto");
+    if auto.len() > 1 || (auto.len() == 1 && rest.iter().any(|p| BORDER_STYLES.contains(&p.as_str()))) {
+        return false;
+    }
+    let (width, mut style, color) = match parse_border_like(&rest.join(" ")) {
+        Some(v) => v,
+        None => return false,
+    };
+    if auto.len() == 1 {
+        style = S("auto");
+    }
+
+    declarations.push(KeyValue(S("outline-width"), width));
+    declarations.push(KeyValue(S("outline-style"), style));
+    declarations.push(KeyValue(S("outline-color"), color));
+
+    return true;
+}
+
+const FONT_STYLES: &[&str] = &["italic", "oblique"];
+const FONT_WEIGHTS: &[&str] = &["bold", "bolder", "lighter"];
+const FONT_STRETCHES: &[&str] = &[
+    "ultra-condensed",
+    "extra-condensed",
+    "condensed",
+    "semi-condensed",
+    "semi-expanded",
+    "expanded",
+    "extra-expanded",
+    "ultra-expanded",
+];
+const FONT_SIZE_KEYWORDS: &[&str] = &[
+    "xx-small", "x-small", "small", "medium", "large", "x-large", "xx-large", "xxx-large",
+    "smaller", "larger",
+];
+const SYSTEM_FONTS: &[&str] = &["caption", "icon", "menu", "message-box", "small-caption", "status-bar"];
+
+fn expand_font(declarations: &mut Vec<KeyValue>, value: &str) -> bool {
+    let mut style = S("normal");
+    let mut variant = S("normal");
+    let mut weight = S("normal");
+    let mut stretch = S("normal");
+    let mut line_height = S("normal");
+    let size: String;
+    let family: String;
+
+    if SYSTEM_FONTS.contains(&value) {
+        size = S("medium");
+        family = S("sans-serif");
+    } else {
+        let parts = split_css_values(value);
+        let mut i = 0;
+
+        // up to four optional keywords may come before the size, `normal`
+        // resets whichever of them is left
+        while i < parts.len() {
+            let part = parts[i].as_str();
+            if FONT_SIZE_KEYWORDS.contains(&part.split('/').next().unwrap_or(""))
+                || (part.parse::<f64>().is_err() && is_length_value(part.split('/').next().unwrap_or("")))
+            {
+                break;
+            }
+
+            if part == "normal" {
+            } else if FONT_STYLES.contains(&part) {
+                style = part.to_string();
+            } else if part == "small-caps" {
+                variant = part.to_string();
+            } else if FONT_WEIGHTS.contains(&part) || part.parse::<f64>().is_ok() {
+                weight = part.to_string();
+            } else if FONT_STRETCHES.contains(&part) {
+                stretch = part.to_string();
+            } else {
+                return false;
+            }
+            i += 1;
+        }
+
+        if i >= parts.len() {
+            return false;
+        }
+
+        // the size may be written as `12px/1.5`, `12px /1.5` or `12px / 1.5`
+        let mut size_part = parts[i].clone();
+        i += 1;
+        while i < parts.len() && (size_part.ends_with('/') || parts[i].starts_with('/')) {
+            size_part += &parts[i];
+            i += 1;
+        }
+
+        let mut size_parts = size_part.splitn(2, '/');
+        size = size_parts.next().unwrap_or("").to_string();
+        match size_parts.next() {
+            Some(l) => line_height = l.to_string(),
+            None => {}
+        }
+
+        if i >= parts.len() {
+            return false;
+        }
+        family = parts[i..].join(" ");
+    }
+
+    declarations.push(KeyValue(S("font-style"), style));
+    declarations.push(KeyValue(S("font-variant"), variant));
+    declarations.push(KeyValue(S("font-weight"), weight));
+    declarations.push(KeyValue(S("font-stretch"), stretch));
+    declarations.push(KeyValue(S("font-size"), size));
+    declarations.push(KeyValue(S("line-height"), line_height));
+    declarations.push(KeyValue(S("font-family"), family));
+
+    return true;
+}
+
+const BACKGROUND_REPEATS: &[&str] = &["repeat-x", "repeat-y", "repeat", "space", "round", "no-repeat"];
+const BACKGROUND_ATTACHMENTS: &[&str] = &["scroll", "fixed", "local"];
+const BACKGROUND_BOXES: &[&str] = &["border-box", "padding-box", "content-box"];
+const BACKGROUND_POSITIONS: &[&str] = &["left", "center", "right", "top", "bottom"];
+
+fn expand_background(declarations: &mut Vec<KeyValue>, value: &str) -> bool {
+    let layers = split_css_list(value);
+    let mut images: Vec<String> = vec![];
+    let mut positions: Vec<String> = vec![];
+    let mut sizes: Vec<String> = vec![];
+    let mut repeats: Vec<String> = vec![];
+    let mut attachments: Vec<String> = vec![];
+    let mut origins: Vec<String> = vec![];
+    let mut clips: Vec<String> = vec![];
+    let mut color = S("transparent");
+
+    for (layer_index, layer) in layers.iter().enumerate() {
+        let mut image: Option<String> = None;
+        let mut position: Vec<String> = vec![];
+        let mut size: Vec<String> = vec![];
+        let mut repeat: Vec<String> = vec![];
+        let mut attachment: Option<String> = None;
+        let mut boxes: Vec<String> = vec![];
+        let mut capturing_size = false;
+
+        for part in split_css_values(layer) {
+            let part = part.as_str();
+            if part == "/" {
+                capturing_size = true;
+            } else if capturing_size {
+                if is_length_value(part) || part == "auto" || part == "cover" || part == "contain" {
+                    size.push(part.to_string());
+                } else {
+                    return false;
+                }
+                if size.len() == 2 {
+                    capturing_size = false;
+                }
+            } else if image.is_none() && is_image_value(part) {
+                image = Some(part.to_string());
+            } else if BACKGROUND_REPEATS.contains(&part) {
+                repeat.push(part.to_string());
+            } else if attachment.is_none() && BACKGROUND_ATTACHMENTS.contains(&part) {
+                attachment = Some(part.to_string());
+            } else if BACKGROUND_BOXES.contains(&part) {
+                boxes.push(part.to_string());
+            } else if BACKGROUND_POSITIONS.contains(&part) || is_length_value(part) {
+                position.push(part.to_string());
+            } else if layer_index == layers.len() - 1 && is_color(part) {
+                // only the final layer may carry the color
+                color = part.to_string();
+            } else if part.contains('/') {
+                let mut split = part.splitn(2, '/');
+                let pos = split.next().unwrap_or("");
+                let sz = split.next().unwrap_or("");
+                if pos != "" {
+                    position.push(pos.to_string());
+                }
+                if sz != "" {
+                    size.push(sz.to_string());
+                } else {
+                    capturing_size = true;
+                }
+            } else {
+                return false;
+            }
+        }
+
+        let origin = boxes.get(0).cloned().unwrap_or(S("padding-box"));
+        let clip = boxes.get(1).cloned().unwrap_or(boxes.get(0).cloned().unwrap_or(S("border-box")));
 
-        if capturing_unit {
-            unit.push(c);
+        images.push(image.unwrap_or(S("none")));
+        positions.push(if position.len() > 0 { position.join(" ") } else { S("0% 0%") });
+        sizes.push(if size.len() > 0 { size.join(" ") } else { S("auto") });
+        repeats.push(if repeat.len() > 0 { repeat.join(" ") } else { S("repeat") });
+        attachments.push(attachment.unwrap_or(S("scroll")));
+        origins.push(origin);
+        clips.push(clip);
+    }
+
+    declarations.push(KeyValue(S("background-color"), color));
+    declarations.push(KeyValue(S("background-image"), images.join(", ")));
+    declarations.push(KeyValue(S("background-position"), positions.join(", ")));
+    declarations.push(KeyValue(S("background-size"), sizes.join(", ")));
+    declarations.push(KeyValue(S("background-repeat"), repeats.join(", ")));
+    declarations.push(KeyValue(S("background-attachment"), attachments.join(", ")));
+    declarations.push(KeyValue(S("background-origin"), origins.join(", ")));
+    declarations.push(KeyValue(S("background-clip"), clips.join(", ")));
+
+    return true;
+}
+
+const LIST_STYLE_POSITIONS: &[&str] = &["inside", "outside"];
+
+fn expand_list_style(declarations: &mut Vec<KeyValue>, value: &str) -> bool {
+    let mut list_type: Option<String> = None;
+    let mut position: Option<String> = None;
+    let mut image: Option<String> = None;
+    let mut nones = 0;
+
+    for part in split_css_values(value) {
+        if part == "none" {
+            nones += 1;
+        } else if position.is_none() && LIST_STYLE_POSITIONS.contains(&part.as_str()) {
+            position = Some(part);
+        } else if image.is_none() && is_image_value(&part) {
+            image = Some(part);
+        } else if list_type.is_none() {
+            list_type = Some(part);
+        } else {
+            return false;
+        }
+    }
+
+    // `none` applies to whichever of type and image was not given
+    if nones > 0 && list_type.is_none() {
+        list_type = Some(S("none"));
+        nones -= 1;
+    }
+    if nones > 0 && image.is_none() {
+        image = Some(S("none"));
+        nones -= 1;
+    }
+    if nones > 0 {
+        return false;
+    }
+
+    declarations.push(KeyValue(S("list-style-type"), list_type.unwrap_or(S("disc"))));
+    declarations.push(KeyValue(S("list-style-position"), position.unwrap_or(S("outside"))));
+    declarations.push(KeyValue(S("list-style-image"), image.unwrap_or(S("none"))));
+
+    return true;
+}
+
+const TEXT_DECORATION_LINES: &[&str] = &["underline", "overline", "line-through", "blink"];
+const TEXT_DECORATION_STYLES: &[&str] = &["solid", "double", "dotted", "dashed", "wavy"];
+
+fn expand_text_decoration(declarations: &mut Vec<KeyValue>, value: &str) -> bool {
+    let mut lines: Vec<String> = vec![];
+    let mut style: Option<String> = None;
+    let mut color: Option<String> = None;
+    let mut thickness: Option<String> = None;
+
+    for part in split_css_values(value) {
+        if part == "none" && lines.len() == 0 {
+            lines.push(part);
+        } else if TEXT_DECORATION_LINES.contains(&part.as_str()) {
+            lines.push(part);
+        } else if style.is_none() && TEXT_DECORATION_STYLES.contains(&part.as_str()) {
+            style = Some(part);
+        } else if thickness.is_none() && (part == "auto" || part == "from-font" || is_length_value(&part)) {
+            thickness = Some(part);
+        } else if color.is_none() && is_color(&part) {
+            color = Some(part);
         } else {
-            val_str.push(c);
+            return false;
         }
     }
 
-    match val_str.parse() {
-        Ok(val) => {
-            if unit == "em" {
-                val * base_font_size
+    declarations.push(KeyValue(
+        S("text-decoration-line"),
+        if lines.len() > 0 { lines.join(" ") } else { S("none") },
+    ));
+    declarations.push(KeyValue(S("text-decoration-style"), style.unwrap_or(S("solid"))));
+    declarations.push(KeyValue(S("text-decoration-color"), color.unwrap_or(S("currentcolor"))));
+    declarations.push(KeyValue(S("text-decoration-thickness"), thickness.unwrap_or(S("auto"))));
+
+    return true;
+}
+
+fn expand_flex(declarations: &mut Vec<KeyValue>, value: &str) -> bool {
+    let (grow, shrink, basis) = match value {
+        "none" => (S("0"), S("0"), S("auto")),
+        "auto" => (S("1"), S("1"), S("auto")),
+        "initial" => (S("0"), S("1"), S("auto")),
+        _ => {
+            let mut numbers: Vec<String> = vec![];
+            let mut basis: Option<String> = None;
+
+            for part in split_css_values(value) {
+                if part.parse::<f64>().is_ok() && numbers.len() < 2 && (basis.is_none() || numbers.len() == 0) {
+                    numbers.push(part);
+                } else if basis.is_none() && (part == "auto" || part == "content" || is_length_value(&part)) {
+                    basis = Some(part);
+                } else {
+                    return false;
+                }
+            }
+
+            // a lone number is the grow factor, the basis then becomes 0
+            (
+                numbers.get(0).cloned().unwrap_or(S("1")),
+                numbers.get(1).cloned().unwrap_or(S("1")),
+                basis.unwrap_or(S("0%")),
+            )
+        }
+    };
+
+    declarations.push(KeyValue(S("flex-grow"), grow));
+    declarations.push(KeyValue(S("flex-shrink"), shrink));
+    declarations.push(KeyValue(S("flex-basis"), basis));
+
+    return true;
+}
+
+fn expand_flex_flow(declarations: &mut Vec<KeyValue>, value: &str) -> bool {
+    let mut direction: Option<String> = None;
+    let mut wrap: Option<String> = None;
+
+    for part in split_css_values(value) {
+        match part.as_str() {
+            "row" | "row-reverse" | "column" | "column-reverse" if direction.is_none() => {
+                direction = Some(part)
+            }
+            "nowrap" | "wrap" | "wrap-reverse" if wrap.is_none() => wrap = Some(part),
+            _ => return false,
+        }
+    }
+
+    declarations.push(KeyValue(S("flex-direction"), direction.unwrap_or(S("row"))));
+    declarations.push(KeyValue(S("flex-wrap"), wrap.unwrap_or(S("nowrap"))));
+
+    return true;
+}
+
+fn expand_grid_template(declarations: &mut Vec<KeyValue>, value: &str) -> bool {
+    let (rows, columns, areas) = if value == "none" {
+        (S("none"), S("none"), S("none"))
+    } else if !value.contains('"') && !value.contains('\'') {
+        // `<grid-template-rows> / <grid-template-columns>`
+        let mut split = value.splitn(2, '/');
+        let rows = split.next().unwrap_or("").trim().to_string();
+        let columns = match split.next() {
+            Some(c) => c.trim().to_string(),
+            None => return false,
+        };
+        (rows, columns, S("none"))
+    } else {
+        // `[ <line-names>? <string> <track-size>? <line-names>? ]+ [ / <explicit-track-list> ]?`
+        let mut split = value.splitn(2, '/');
+        let template = split.next().unwrap_or("");
+        let columns = split.next().map(|c| c.trim().to_string()).unwrap_or(S("none"));
+
+        let mut areas: Vec<String> = vec![];
+        let mut rows: Vec<String> = vec![];
+        let mut pending_row = false;
+
+        for part in split_css_values(template) {
+            if part.starts_with('"') || part.starts_with('\'') {
+                if pending_row {
+                    rows.push(S("auto"));
+                }
+                areas.push(part);
+                pending_row = true;
+            } else if part.starts_with('[') {
+                rows.push(part);
             } else {
-                val
+                rows.push(part);
+                pending_row = false;
             }
         }
-        Err(e) => {
-            println!("Error while parsing css value {}: {}", value, e);
-            0.0
+        if pending_row {
+            rows.push(S("auto"));
         }
+
+        (rows.join(" "), columns, areas.join(" "))
+    };
+
+    declarations.push(KeyValue(S("grid-template-rows"), rows));
+    declarations.push(KeyValue(S("grid-template-columns"), columns));
+    declarations.push(KeyValue(S("grid-template-areas"), areas));
+
+    return true;
+}
+
+// `<grid-line> [ / <grid-line> ]?`, a lone name stands for both ends.
+fn expand_grid_line(declarations: &mut Vec<KeyValue>, value: &str, start: &str, end: &str) -> bool {
+    let lines: Vec<String> = value.split('/').map(|l| l.trim().to_string()).collect();
+    if lines.len() > 2 || lines.iter().any(|l| l == "") {
+        return false;
+    }
+    let end_value = match lines.get(1) {
+        Some(l) => l.clone(),
+        None if is_custom_ident(&lines[0]) => lines[0].clone(),
+        None => S("auto"),
+    };
+
+    declarations.push(KeyValue(S(start), lines[0].clone()));
+    declarations.push(KeyValue(S(end), end_value));
+
+    return true;
+}
+
+// `<grid-line> [ / <grid-line> ]{0,3}` for the row start, column start, row
+// end and column end. Missing lines copy a name from the opposite side.
+fn expand_grid_area(declarations: &mut Vec<KeyValue>, value: &str) -> bool {
+    let mut lines: Vec<String> = value.split('/').map(|l| l.trim().to_string()).collect();
+    if lines.len() > 4 || lines.iter().any(|l| l == "") {
+        return false;
+    }
+    while lines.len() < 4 {
+        let opposite = lines[lines.len().saturating_sub(2)].clone();
+        lines.push(if is_custom_ident(&opposite) { opposite } else { S("auto") });
+    }
+
+    declarations.push(KeyValue(S("grid-row-start"), lines[0].clone()));
+    declarations.push(KeyValue(S("grid-column-start"), lines[1].clone()));
+    declarations.push(KeyValue(S("grid-row-end"), lines[2].clone()));
+    declarations.push(KeyValue(S("grid-column-end"), lines[3].clone()));
+
+    return true;
+}
+
+fn is_custom_ident(value: &str) -> bool {
+    if value == "auto" || value == "span" || value.contains(' ') {
+        return false;
+    }
+    let mut chars = value.chars();
+    match chars.next() {
+        Some('-') => chars.next().map_or(false, |c| !c.is_ascii_digit() && c != '.'),
+        Some(c) => c.is_alphabetic() || c == '_',
+        None => false,
+    }
+}
+
+fn expand_pair(declarations: &mut Vec<KeyValue>, value: &str, first: &str, second: &str) -> bool {
+    let values = split_css_values(value);
+
+    if values.len() == 0 || values.len() > 2 {
+        return false;
+    }
+
+    declarations.push(KeyValue(S(first), values[0].clone()));
+    declarations.push(KeyValue(S(second), values.get(1).unwrap_or(&values[0]).clone()));
+
+    return true;
+}
+
+pub fn longhands_of(shorthand: &str) -> Vec<String> {
+    let sides = |f: &dyn Fn(&str) -> String| SIDES.iter().map(|s| f(s)).collect::<Vec<String>>();
+
+    match shorthand {
+        "margin" => sides(&|s| format!("margin-{}", s)),
+        "padding" => sides(&|s| format!("padding-{}", s)),
+        "inset" => sides(&|s| s.to_string()),
+        "border-width" => sides(&|s| format!("border-{}-width", s)),
+        "border-style" => sides(&|s| format!("border-{}-style", s)),
+        "border-color" => sides(&|s| format!("border-{}-color", s)),
+        "border" => ["width", "style", "color"]
+            .iter()
+            .flat_map(|p| sides(&|s| format!("border-{}-{}", s, p)))
+            .collect(),
+        "border-top" | "border-right" | "border-bottom" | "border-left" => ["width", "style", "color"]
+            .iter()
+            .map(|p| format!("{}-{}", shorthand, p))
+            .collect(),
+        "border-radius" => ["top-left", "top-right", "bottom-right", "bottom-left"]
+            .iter()
+            .map(|c| format!("border-{}-radius", c))
+            .collect(),
+        "outline" => vec![S("outline-width"), S("outline-style"), S("outline-color")],
+        "font" => vec![
+            S("font-style"),
+            S("font-variant"),
+            S("font-weight"),
+            S("font-stretch"),
+            S("font-size"),
+            S("line-height"),
+            S("font-family"),
+        ],
+        "background" => vec![
+            S("background-color"),
+            S("background-image"),
+            S("background-position"),
+            S("background-size"),
+            S("background-repeat"),
+            S("background-attachment"),
+            S("background-origin"),
+            S("background-clip"),
+        ],
+        "list-style" => vec![S("list-style-type"), S("list-style-position"), S("list-style-image")],
+        "text-decoration" => vec![
+            S("text-decoration-line"),
+            S("text-decoration-style"),
+            S("text-decoration-color"),
+            S("text-decoration-thickness"),
+        ],
+        "flex" => vec![S("flex-grow"), S("flex-shrink"), S("flex-basis")],
+        "flex-flow" => vec![S("flex-direction"), S("flex-wrap")],
+        "grid-template" => vec![
+            S("grid-template-rows"),
+            S("grid-template-columns"),
+            S("grid-template-areas"),
+        ],
+        "grid-row" => vec![S("grid-row-start"), S("grid-row-end")],
+        "grid-column" => vec![S("grid-column-start"), S("grid-column-end")],
+        "grid-area" => vec![
+            S("grid-row-start"),
+            S("grid-column-start"),
+            S("grid-row-end"),
+            S("grid-column-end"),
+        ],
+        "gap" | "grid-gap" => vec![S("row-gap"), S("column-gap")],
+        "place-content" => vec![S("align-content"), S("justify-content")],
+        "place-items" => vec![S("align-items"), S("justify-items")],
+        "place-self" => vec![S("align-self"), S("justify-self")],
+        "overflow" => vec![S("overflow-x"), S("overflow-y")],
+        _ => vec![],
     }
 }
 
-pub fn parse_rect_like_value(value: &str) -> (&str, &str, &str, &str) {
-    let values: Vec<&str> = value.split(" ").collect();
-    let mut top = "0";
-    let mut right = "0";
-    let mut bottom = "0";
-    let mut left = "0";
+// Longhands of a shorthand containing `var()` hold the whole shorthand until
+// the substitution happens at computed-value time.
+const PENDING_SUBSTITUTION_PREFIX: &str = "\u{1}pending-substitution:";
+
+pub fn make_pending_substitution(shorthand: &str, value: &str) -> String {
+    format!("{}{}:{}", PENDING_SUBSTITUTION_PREFIX, shorthand, value)
+}
 
-    if values.len() == 1 {
-        top = values[0];
-        right = values[0];
-        bottom = values[0];
-        left = values[0];
-    } else if values.len() == 2 {
-        top = values[0];
-        right = values[1];
-        bottom = values[0];
-        left = values[1];
-    } else if values.len() == 3 {
-        top = values[0];
-        right = values[1];
-        bottom = values[2];
-        left = values[1];
-    } else if values.len() == 4 {
-        top = values[0];
-        right = values[1];
-        bottom = values[2];
-        left = values[3];
+pub fn is_pending_substitution(value: &str) -> bool {
+    value.starts_with(PENDING_SUBSTITUTION_PREFIX)
+}
+
+pub fn parse_pending_substitution(value: &str) -> Option<(String, String)> {
+    if !is_pending_substitution(value) {
+        return None;
     }
+    let rest = &value[PENDING_SUBSTITUTION_PREFIX.len()..];
+    let colon = rest.find(':')?;
+    Some((rest[..colon].to_string(), rest[colon + 1..].to_string()))
+}
 
-    return (top, right, bottom, left);
+pub fn is_shorthand(key: &str) -> bool {
+    longhands_of(key).len() > 0
 }
 
+// Expands a single shorthand declaration into its longhands. Returns false
+// when `key` is not a shorthand or `value` does not match its grammar.
+pub fn expand_shorthand(declarations: &mut Vec<KeyValue>, key: &str, value: &str) -> bool {
+    let longhands = longhands_of(key);
+    if longhands.len() == 0 {
+        return false;
+    }
+
+    // `!important` and the css-wide keywords apply to every longhand
+    let important = value.contains("!important");
+    let value = value.replace("!important", "");
+    let value = value.trim();
+
+    let mut expanded: Vec<KeyValue> = vec![];
+
+    let valid = if CSS_WIDE_KEYWORDS.contains(&value) {
+        for longhand in &longhands {
+            expanded.push(KeyValue(longhand.clone(), value.to_string()));
+        }
+        true
+    } else if value.contains("var(") {
+        // can only be expanded once the variables are known
+        for longhand in &longhands {
+            expanded.push(KeyValue(longhand.clone(), make_pending_substitution(key, value)));
+        }
+        true
+    } else {
+        match key {
+            "margin" | "padding" => push_rect_like(&mut expanded, value, |s| format!("{}-{}", key, s)),
+            "inset" => push_rect_like(&mut expanded, value, |s| s.to_string()),
+            "border-width" | "border-style" | "border-color" => {
+                let property = &key["border-".len()..];
+                push_rect_like(&mut expanded, value, |s| format!("border-{}-{}", s, property))
+            }
+            "border" => expand_border(&mut expanded, SIDES, value),
+            "border-top" | "border-right" | "border-bottom" | "border-left" => {
+                expand_border(&mut expanded, &[&key["border-".len()..]], value)
+            }
+            "border-radius" => expand_border_radius(&mut expanded, value),
+            "outline" => expand_outline(&mut expanded, value),
+            "font" => expand_font(&mut expanded, value),
+            "background" => expand_background(&mut expanded, value),
+            "list-style" => expand_list_style(&mut expanded, value),
+            "text-decoration" => expand_text_decoration(&mut expanded, value),
+            "flex" => expand_flex(&mut expanded, value),
+            "flex-flow" => expand_flex_flow(&mut expanded, value),
+            "grid-template" => expand_grid_template(&mut expanded, value),
+            "grid-row" => expand_grid_line(&mut expanded, value, "grid-row-start", "grid-row-end"),
+            "grid-column" => expand_grid_line(&mut expanded, value, "grid-column-start", "grid-column-end"),
+            "grid-area" => expand_grid_area(&mut expanded, value),
+            "gap" | "grid-gap" => expand_pair(&mut expanded, value, "row-gap", "column-gap"),
+            "place-content" => expand_pair(&mut expanded, value, "align-content", "justify-content"),
+            "place-items" => expand_pair(&mut expanded, value, "align-items", "justify-items"),
+            "place-self" => expand_pair(&mut expanded, value, "align-self", "justify-self"),
+            "overflow" => expand_pair(&mut expanded, value, "overflow-x", "overflow-y"),
+            _ => false,
+        }
+    };
+
+    if !valid {
+        println!("Invalid value for shorthand {}: {}", key, value);
+        return false;
+    }
+
+    for mut declaration in expanded {
+        if important {
+            declaration.1 += " !important";
+        }
+        declarations.push(declaration);
+    }
+
+    return true;
+}
+
+// Replaces every shorthand with its longhands at the same position, so a
+// longhand declared later still overrides the shorthand and vice versa.
 pub fn expand_shorthand_values(style_rule: &mut StyleRule) {
-  let mut expanded_declarations = Vec::new();
+    let mut expanded_declarations = Vec::new();
 
-  for declaration in &style_rule.declarations {
-      let key = &declaration.0;
-      let value = &declaration.1;
+    for declaration in &style_rule.declarations {
+        let key = &declaration.0;
+        let value = &declaration.1;
 
-      if key == "margin" {
-          let (top, right, bottom, left) = parse_rect_like_value(value);
+        if is_shorthand(key) {
+            // an invalid shorthand is dropped like any invalid declaration
+            expand_shorthand(&mut expanded_declarations, key, value);
+        } else {
+            expanded_declarations.push(declaration.clone());
+        }
+    }
 
-          expanded_declarations.push(KeyValue::new_values("margin-top", top));
-          expanded_declarations.push(KeyValue::new_values("margin-right", right));
-          expanded_declarations.push(KeyValue::new_values("margin-bottom", bottom));
-          expanded_declarations.push(KeyValue::new_values("margin-left", left));
-      } else if key == "padding" {
-          let (top, right, bottom, left) = parse_rect_like_value(value);
+    style_rule.declarations = expanded_declarations;
+}
 
-          expanded_declarations.push(KeyValue::new_values("padding-top", top));
-          expanded_declarations.push(KeyValue::new_values("padding-right", right));
-          expanded_declarations.push(KeyValue::new_values("padding-bottom", bottom));
-          expanded_declarations.push(KeyValue::new_values("padding-left", left));
-      }
-  }
+// Whether `value` is one the computed style of the longhand `key` can be
+// made from, with the same parsers. Longhands kept as they are written, with
+// no parser of their own, take any value.
+pub fn is_valid_longhand_value(key: &str, value: &str) -> bool {
+    let value = value.trim();
+    if CSS_WIDE_KEYWORDS.contains(&value) || value.contains("var(") {
+        return true;
+    }
+
+    let is_size = |v: &str| match v {
+        "auto" | "none" | "min-content" | "max-content" | "fit-content" => true,
+        _ => is_length_value(v),
+    };
+    let is_integer = |v: &str| v.parse::<i32>().is_ok();
 
-  style_rule.declarations.append(&mut expanded_declarations);
+    match key {
+        "color" | "background-color" | "border-top-color" | "border-right-color" | "border-bottom-color"
+        | "border-left-color" => is_color(value),
+        "margin-top" | "margin-right" | "margin-bottom" | "margin-left" => value == "auto" || is_length_value(value),
+        "padding-top" | "padding-right" | "padding-bottom" | "padding-left" => is_length_value(value),
+        "top" | "right" | "bottom" | "left" | "width" | "height" | "min-width" | "min-height" | "max-width"
+        | "max-height" => is_size(value),
+        "border-top-width" | "border-right-width" | "border-bottom-width" | "border-left-width" => {
+            is_line_width(value)
+        }
+        "border-top-style" | "border-right-style" | "border-bottom-style" | "border-left-style" => {
+            BORDER_STYLES.contains(&value)
+        }
+        "border-top-left-radius" | "border-top-right-radius" | "border-bottom-right-radius"
+        | "border-bottom-left-radius" => {
+            let radii = split_css_values(value);
+            radii.len() >= 1 && radii.len() <= 2 && radii.iter().all(|r| is_length_value(r))
+        }
+        "font-size" => FONT_SIZE_KEYWORDS.contains(&value) || is_length_value(value),
+        "font-weight" => match value {
+            "normal" | "bold" | "bolder" | "lighter" => true,
+            v => match parse_number(v) {
+                Ok(w) => w >= 1.0 && w <= 1000.0,
+                Err(_) => false,
+            },
+        },
+        "line-height" => match value.parse::<f64>() {
+            Ok(n) => n >= 0.0,
+            Err(_) => value == "normal" || is_length_value(value),
+        },
+        "opacity" => match value.strip_suffix('%') {
+            Some(p) => p.trim().parse::<f64>().is_ok(),
+            None => value.parse::<f64>().is_ok(),
+        },
+        "z-index" => value == "auto" || is_integer(value),
+        "order" => is_integer(value),
+        "flex-grow" | "flex-shrink" => match parse_number(value) {
+            Ok(n) => n >= 0.0,
+            Err(_) => false,
+        },
+        "overflow-x" | "overflow-y" => match value {
+            "visible" | "hidden" | "clip" | "scroll" | "auto" => true,
+            _ => false,
+        },
+        _ => true,
+    }
 }
 
 pub fn parse_css(css: &str) -> Vec<StyleRule> {
@@ -139,18 +1006,21 @@ pub fn parse_css(css: &str) -> Vec<StyleRule> {
     let mut is_capturing_selector = true;
     let mut inside_comment = false;
 
-    let chars = css.chars().enumerate();
+    let chars = css.chars().collect::<Vec<char>>();
+
+    for (i, c) in chars.iter().cloned().enumerate() {
+        let next = chars.get(i + 1).cloned().unwrap_or(' ');
 
-    for (i, c) in chars {
-        if (c == '/' || c == '*') && captured_code.ends_with("/") && !inside_comment {
+        if !inside_comment && c == '/' && next == '*' {
             inside_comment = true;
         } else if inside_comment {
-            if (c == '/' && captured_code.ends_with("*")) {
+            if c == '/' && captured_code.ends_with("*") && !captured_code.ends_with("/*") {
                 inside_comment = false;
-                println!("Comment: {}", captured_code);
                 captured_code = "".to_string();
-                continue;
+            } else {
+                captured_code.push(c);
             }
+            continue;
         }
 
         captured_code.push(c);
@@ -159,15 +1029,11 @@ pub fn parse_css(css: &str) -> Vec<StyleRule> {
             continue;
         }
 
-        if c == '/' {
-            continue;
-        }
-
         if c == '{' {
             style_rule.selector = captured_text.trim().to_string();
             captured_text = "".to_string();
             is_capturing_selector = false;
-        } else if c == ':' && !is_capturing_selector {
+        } else if c == ':' && !is_capturing_selector && declaration.0 == "" {
             declaration.0 = captured_text.trim().to_string();
             captured_text = "".to_string();
         } else if c == ';' || c == '}' {
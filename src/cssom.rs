@@ -0,0 +1,755 @@
+use crate::colors::*;
+use crate::css::*;
+use crate::html::*;
+use crate::length::*;
+use crate::media::*;
+use crate::properties::*;
+use crate::styles::*;
+use crate::utils::*;
+
+#[derive(Clone, Debug)]
+pub struct CssStyleDeclaration {
+    pub declarations: Vec<KeyValue>,
+}
+
+fn split_priority(value: &str) -> (String, bool) {
+    if value.contains("!important") {
+        (value.replace("!important", "").trim().to_string(), true)
+    } else {
+        (value.trim().to_string(), false)
+    }
+}
+
+// Shorthands tried when serializing, the ones covering more longhands first.
+const SERIALIZED_SHORTHANDS: &[&str] = &[
+    "border",
+    "font",
+    "background",
+    "margin",
+    "padding",
+    "inset",
+    "border-top",
+    "border-right",
+    "border-bottom",
+    "border-left",
+    "border-width",
+    "border-style",
+    "border-color",
+    "outline",
+    "list-style",
+    "text-decoration",
+    "flex",
+    "flex-flow",
+    "grid-template",
+    "gap",
+    "place-content",
+    "place-items",
+    "place-self",
+];
+
+// Writes a shorthand back from its longhands, or returns "" when the
+// longhands can't be represented by the shorthand.
+fn serialize_shorthand(name: &str, values: &Vec<String>) -> String {
+    let compress_rect = |v: &Vec<String>| -> String {
+        if v[1] == v[3] {
+            if v[0] == v[2] {
+                if v[0] == v[1] {
+                    return v[0].clone();
+                }
+                return format!("{} {}", v[0], v[1]);
+            }
+            return format!("{} {} {}", v[0], v[1], v[2]);
+        }
+        v.join(" ")
+    };
+
+    match name {
+        "margin" | "padding" | "inset" | "border-width" | "border-style" | "border-color" => {
+            compress_rect(values)
+        }
+        "border" => {
+            // the four sides of width, style and color
+            let groups = values.chunks(4).map(|c| c.to_vec()).collect::<Vec<Vec<String>>>();
+            if groups.iter().all(|group| group.iter().all(|v| *v == group[0])) {
+                format!("{} {} {}", groups[0][0], groups[1][0], groups[2][0])
+            } else {
+                "".to_string()
+            }
+        }
+        "gap" | "grid-gap" | "place-content" | "place-items" | "place-self" => {
+            if values[0] == values[1] {
+                values[0].clone()
+            } else {
+                values.join(" ")
+            }
+        }
+        "font" => {
+            let size = if values[5] == "normal" {
+                values[4].clone()
+            } else {
+                format!("{}/{}", values[4], values[5])
+            };
+            let mut parts = values[..4]
+                .iter()
+                .filter(|v| *v != "normal")
+                .cloned()
+                .collect::<Vec<String>>();
+            parts.push(size);
+            parts.push(values[6].clone());
+            parts.join(" ")
+        }
+        "background" => {
+            if values.iter().any(|v| split_css_list(v).len() > 1) {
+                return "".to_string();
+            }
+            let (color, layer) = (values[0].clone(), values[1..].to_vec());
+            let mut parts = vec![layer[0].clone(), layer[1].clone()];
+            if layer[2] != "auto" {
+                parts.push("/".to_string());
+                parts.push(layer[2].clone());
+            }
+            parts.extend(layer[3..].iter().cloned());
+            parts.push(color);
+            parts.join(" ")
+        }
+        "grid-template" => {
+            if values[2] != "none" {
+                return "".to_string();
+            }
+            if values[0] == "none" && values[1] == "none" {
+                return "none".to_string();
+            }
+            format!("{} / {}", values[0], values[1])
+        }
+        _ => values.join(" "),
+    }
+}
+
+impl CssStyleDeclaration {
+    pub fn new() -> CssStyleDeclaration {
+        CssStyleDeclaration {
+            declarations: vec![],
+        }
+    }
+
+    pub fn parse(css_text: &str) -> CssStyleDeclaration {
+        let mut declaration = CssStyleDeclaration::new();
+        declaration.set_css_text(css_text);
+        declaration
+    }
+
+    pub fn length(&self) -> usize {
+        self.declarations.len()
+    }
+
+    pub fn item(&self, index: usize) -> String {
+        match self.declarations.get(index) {
+            Some(d) => d.0.clone(),
+            None => "".to_string(),
+        }
+    }
+
+    fn get_longhand(&self, name: &str) -> Option<&String> {
+        // the last declaration wins, like in the cascade
+        self.declarations
+            .iter()
+            .rev()
+            .find(|d| d.0 == name)
+            .map(|d| &d.1)
+    }
+
+    pub fn get_property_value(&self, name: &str) -> String {
+        // custom property names are case-sensitive
+        let name = if is_custom_property(name.trim()) {
+            name.trim().to_string()
+        } else {
+            name.trim().to_lowercase()
+        };
+
+        if !is_shorthand(&name) {
+            return match self.get_longhand(&name) {
+                Some(v) => match parse_pending_substitution(v) {
+                    // a longhand of a shorthand with `var()` has no value of its own
+                    Some(_) => "".to_string(),
+                    None => split_priority(v).0,
+                },
+                None => "".to_string(),
+            };
+        }
+
+        let mut values: Vec<String> = vec![];
+        for longhand in longhands_of(&name) {
+            match self.get_longhand(&longhand) {
+                Some(v) => values.push(v.clone()),
+                None => return "".to_string(),
+            }
+        }
+
+        // a shorthand written with `var()` serializes to what was written
+        if let Some((shorthand, value)) = parse_pending_substitution(&values[0]) {
+            if shorthand == name && values.iter().all(|v| *v == values[0]) {
+                return split_priority(&value).0;
+            }
+            return "".to_string();
+        }
+
+        let values = values.iter().map(|v| split_priority(v).0).collect::<Vec<String>>();
+        serialize_shorthand(&name, &values)
+    }
+
+    pub fn get_property_priority(&self, name: &str) -> String {
+        let longhands = if is_shorthand(name) {
+            longhands_of(name)
+        } else {
+            vec![name.to_string()]
+        };
+
+        let important = longhands.iter().all(|l| match self.get_longhand(l) {
+            Some(v) => split_priority(v).1,
+            None => false,
+        });
+
+        if important {
+            "important".to_string()
+        } else {
+            "".to_string()
+        }
+    }
+
+    pub fn remove_property(&mut self, name: &str) -> String {
+        let value = self.get_property_value(name);
+
+        let longhands = if is_shorthand(name) {
+            longhands_of(name)
+        } else {
+            vec![name.to_string()]
+        };
+
+        self.declarations.retain(|d| !longhands.contains(&d.0));
+
+        value
+    }
+
+    // An empty value removes the property. Unknown properties, unknown
+    // priorities and values that don't match the property's grammar are
+    // ignored.
+    pub fn set_property(&mut self, name: &str, value: &str, priority: &str) {
+        // custom property names are case-sensitive
+        let name = if is_custom_property(name.trim()) {
+            name.trim().to_string()
+        } else {
+            name.trim().to_lowercase()
+        };
+        let name = name.as_str();
+        let value = value.trim();
+
+        if !is_shorthand(name) && !is_supported_property(name) && !is_custom_property(name) {
+            return;
+        }
+
+        let important = match priority.trim().to_lowercase().as_str() {
+            "" => false,
+            "important" => true,
+            _ => return,
+        };
+
+        if value == "" {
+            self.remove_property(name);
+            return;
+        }
+
+        if !is_shorthand(name) && !is_custom_property(name) && !is_valid_longhand_value(name, value) {
+            return;
+        }
+
+        let value = if important {
+            format!("{} !important", value)
+        } else {
+            value.to_string()
+        };
+
+        let mut expanded: Vec<KeyValue> = vec![];
+        if is_shorthand(name) {
+            if !expand_shorthand(&mut expanded, name, &value) {
+                return;
+            }
+        } else {
+            expanded.push(KeyValue::new_values(name, &value));
+        }
+
+        // replace in place so the declaration order stays the same
+        for declaration in expanded {
+            match self.declarations.iter_mut().find(|d| d.0 == declaration.0) {
+                Some(d) => d.1 = declaration.1,
+                None => self.declarations.push(declaration),
+            }
+        }
+    }
+
+    pub fn css_text(&self) -> String {
+        let mut parts: Vec<String> = vec![];
+        let mut serialized: Vec<String> = vec![];
+
+        for declaration in &self.declarations {
+            if serialized.contains(&declaration.0) {
+                continue;
+            }
+
+            if let Some((shorthand, value)) = parse_pending_substitution(&declaration.1) {
+                parts.push(format!("{}: {};", shorthand, value));
+                serialized.extend(longhands_of(&shorthand));
+                continue;
+            }
+
+            // use the first shorthand that can represent the longhands
+            let shorthand = SERIALIZED_SHORTHANDS.iter().find(|shorthand| {
+                let longhands = longhands_of(shorthand);
+                let important = self.get_property_priority(shorthand) == "important";
+                longhands.contains(&declaration.0)
+                    && self.get_property_value(shorthand) != ""
+                    && longhands.iter().all(|l| {
+                        self.get_longhand(l).map(|v| split_priority(v).1) == Some(important)
+                    })
+            });
+
+            match shorthand {
+                Some(shorthand) => {
+                    let priority = if self.get_property_priority(shorthand) == "important" {
+                        " !important"
+                    } else {
+                        ""
+                    };
+                    parts.push(format!(
+                        "{}: {}{};",
+                        shorthand,
+                        self.get_property_value(shorthand),
+                        priority
+                    ));
+                    serialized.extend(longhands_of(shorthand));
+                }
+                None => {
+                    parts.push(format!("{}: {};", declaration.0, declaration.1));
+                    serialized.push(declaration.0.clone());
+                }
+            }
+        }
+
+        parts.join(" ")
+    }
+
+    pub fn set_css_text(&mut self, css_text: &str) {
+        self.declarations = vec![];
+        for rule in parse_css(&format!("{{{}}}", css_text)) {
+            self.declarations.extend(rule.declarations);
+        }
+    }
+}
+
+impl StyleRule {
+    pub fn style(&self) -> CssStyleDeclaration {
+        CssStyleDeclaration {
+            declarations: self.declarations.clone(),
+        }
+    }
+
+    pub fn set_style(&mut self, style: CssStyleDeclaration) {
+        self.declarations = style.declarations;
+        self.css = self.css_text();
+    }
+
+    pub fn get_property_value(&self, name: &str) -> String {
+        self.style().get_property_value(name)
+    }
+
+    pub fn set_property(&mut self, name: &str, value: &str, priority: &str) {
+        let mut style = self.style();
+        style.set_property(name, value, priority);
+        self.set_style(style);
+    }
+
+    pub fn remove_property(&mut self, name: &str) -> String {
+        let mut style = self.style();
+        let value = style.remove_property(name);
+        self.set_style(style);
+        value
+    }
+
+    pub fn css_text(&self) -> String {
+        let declarations = self.style().css_text();
+        if declarations == "" {
+            format!("{} {{ }}", self.selector)
+        } else {
+            format!("{} {{ {} }}", self.selector, declarations)
+        }
+    }
+}
+
+impl StyleSheet {
+    pub fn css_rules(&self) -> &Vec<StyleRule> {
+        &self.rules
+    }
+
+    pub fn insert_rule(&mut self, rule: &str, index: usize) -> Result<usize, String> {
+        if index > self.rules.len() {
+            return Err(format!(
+                "IndexSizeError: index {} is larger than the number of rules ({})",
+                index,
+                self.rules.len()
+            ));
+        }
+
+        let mut rules = parse_css(rule);
+        if rules.len() != 1 || rules[0].selector == "" {
+            return Err(format!("SyntaxError: failed to parse the rule '{}'", rule));
+        }
+
+        self.rules.insert(index, rules.remove(0));
+        Ok(index)
+    }
+
+    pub fn delete_rule(&mut self, index: usize) -> Result<(), String> {
+        if index >= self.rules.len() {
+            return Err(format!(
+                "IndexSizeError: index {} is out of range ({} rules)",
+                index,
+                self.rules.len()
+            ));
+        }
+
+        self.rules.remove(index);
+        Ok(())
+    }
+
+    pub fn css_text(&self) -> String {
+        self.rules
+            .iter()
+            .map(|r| r.css_text())
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+impl DomElement {
+    // The `style` attribute as a declaration block.
+    pub fn style(&self) -> CssStyleDeclaration {
+        match self.attributes.get("style") {
+            Some(s) => CssStyleDeclaration::parse(s),
+            None => CssStyleDeclaration::new(),
+        }
+    }
+
+    pub fn set_style(&mut self, style: &CssStyleDeclaration) {
+        self.attributes.insert("style".to_string(), style.css_text());
+    }
+}
+
+pub struct Document {
+    pub url: String,
+    pub children: Vec<DomElement>,
+    pub style_sheets: Vec<StyleSheet>,
+    pub needs_restyle: bool,
+}
+
+fn find_element_by_id<'a>(tree: &'a mut Vec<DomElement>, id: &str) -> Option<&'a mut DomElement> {
+    for element in tree.iter_mut() {
+        if element.attributes.get("id").map(|i| i == id).unwrap_or(false) {
+            return Some(element);
+        }
+        if let Some(e) = find_element_by_id(&mut element.children, id) {
+            return Some(e);
+        }
+    }
+    return None;
+}
+
+impl Document {
+    pub fn new() -> Document {
+        Document {
+            url: "".to_string(),
+            children: vec![],
+            style_sheets: vec![],
+            needs_restyle: false,
+        }
+    }
+
+    pub fn style_sheets(&self) -> &Vec<StyleSheet> {
+        &self.style_sheets
+    }
+
+    // Any change made through the returned style sheets restyles the document.
+    pub fn style_sheets_mut(&mut self) -> &mut Vec<StyleSheet> {
+        self.needs_restyle = true;
+        &mut self.style_sheets
+    }
+
+    pub fn get_element_by_id_mut(&mut self, id: &str) -> Option<&mut DomElement> {
+        self.needs_restyle = true;
+        find_element_by_id(&mut self.children, id)
+    }
+
+    pub fn active_rules(&self, environment: &MediaEnvironment) -> Vec<StyleRule> {
+        get_active_rules(&self.style_sheets, environment)
+    }
+}
+
+pub struct ComputedStyleDeclaration {
+    pub style: CssStyleDeclaration,
+    // specified on the element but not understood by the engine
+    pub unsupported_properties: Vec<String>,
+}
+
+pub fn serialize_number(value: f64) -> String {
+    let value = format!("{:.4}", value);
+    let value = value.trim_end_matches('0').trim_end_matches('.');
+    if value == "-0" {
+        "0".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+pub fn serialize_px(value: f64) -> String {
+    format!("{}px", serialize_number(value))
+}
+
+pub fn serialize_color(color: Color) -> String {
+    let (r, g, b, a) = color.to_srgba();
+    let channel = |c: f64| (c * 255.0).round().max(0.0).min(255.0);
+    if a >= 1.0 {
+        format!("rgb({}, {}, {})", channel(r), channel(g), channel(b))
+    } else {
+        format!("rgba({}, {}, {}, {})", channel(r), channel(g), channel(b), serialize_number(a))
+    }
+}
+
+// The lengths in `value` in pixels, percentages and keywords as they are.
+fn resolve_lengths(computed_style: &ComputedStyle, value: &str) -> String {
+    let parts: Vec<String> = split_css_values(value)
+        .iter()
+        .map(|part| match parse_length_percentage(part) {
+            Ok(length) if !length.has_percentage() => serialize_px(length.resolve(&computed_style.length_context, 0.0)),
+            _ => part.clone(),
+        })
+        .collect();
+    return parts.join(" ");
+}
+
+fn resolve_property(element: &DomElement, computed_style: &ComputedStyle, name: &str) -> String {
+    let margin = &computed_style.margin;
+    let padding = &computed_style.padding;
+
+    match name {
+        "background-color" => serialize_color(computed_style.background_color),
+        "color" => serialize_color(computed_style.color),
+        "display" => computed_style.display.clone(),
+        "float" => computed_style.float.clone(),
+        "font-family" => computed_style.font_family.clone(),
+        "font-size" => serialize_px(computed_style.font_size),
+        "font-style" => computed_style.font_style.clone(),
+        "font-weight" => serialize_number(computed_style.font_weight),
+        "margin-top" => serialize_px(margin.top),
+        "margin-right" => serialize_px(margin.right),
+        "margin-bottom" => serialize_px(margin.bottom),
+        "margin-left" => serialize_px(margin.left),
+        "padding-top" => serialize_px(padding.top),
+        "padding-right" => serialize_px(padding.right),
+        "padding-bottom" => serialize_px(padding.bottom),
+        "padding-left" => serialize_px(padding.left),
+        "text-decoration-line" => computed_style.text_decoration.clone(),
+        "border-top-width" => serialize_px(computed_style.border.top.width),
+        "border-right-width" => serialize_px(computed_style.border.right.width),
+        "border-bottom-width" => serialize_px(computed_style.border.bottom.width),
+        "border-left-width" => serialize_px(computed_style.border.left.width),
+        "border-top-color" => serialize_color(computed_style.border.top.color),
+        "border-right-color" => serialize_color(computed_style.border.right.color),
+        "border-bottom-color" => serialize_color(computed_style.border.bottom.color),
+        "border-left-color" => serialize_color(computed_style.border.left.color),
+        "top" | "right" | "bottom" | "left" | "min-width" | "min-height" | "max-width" | "max-height"
+        | "column-gap" | "row-gap" | "flex-basis" | "border-spacing" | "vertical-align" => {
+            match computed_style.values.get(name) {
+                Some(v) => resolve_lengths(computed_style, v),
+                None => "".to_string(),
+            }
+        }
+        "border-top-left-radius" | "border-top-right-radius" | "border-bottom-right-radius"
+        | "border-bottom-left-radius" => match &element.computed_flow {
+            // percentages refer to the border box, which the flow is
+            Some(flow) if computed_style.display != "none" => {
+                let radius = &computed_style.border_radius;
+                let (x, y) = match name {
+                    "border-top-left-radius" => &radius.top_left,
+                    "border-top-right-radius" => &radius.top_right,
+                    "border-bottom-right-radius" => &radius.bottom_right,
+                    _ => &radius.bottom_left,
+                };
+                let context = &computed_style.length_context;
+                let x = serialize_px(x.resolve(context, flow.width).max(0.0));
+                let y = serialize_px(y.resolve(context, flow.height).max(0.0));
+                if x == y {
+                    x
+                } else {
+                    format!("{} {}", x, y)
+                }
+            }
+            _ => match computed_style.values.get(name) {
+                Some(v) => resolve_lengths(computed_style, v),
+                None => "".to_string(),
+            },
+        },
+        "line-height" => match computed_style.line_height {
+            LineHeight::Normal => "normal".to_string(),
+            LineHeight::Number(n) => serialize_px(n * computed_style.font_size),
+            LineHeight::Length(l) => serialize_px(l),
+        },
+        // the resolved value of the box size is the used content size, the
+        // flow is the border box
+        "width" | "height" => match &element.computed_flow {
+            Some(flow) if computed_style.display != "none" => {
+                let border = computed_style.border.widths();
+                if name == "width" {
+                    serialize_px(f64::max(0.0, flow.width - padding.left - padding.right - border.left - border.right))
+                } else {
+                    serialize_px(f64::max(0.0, flow.height - padding.top - padding.bottom - border.top - border.bottom))
+                }
+            }
+            // without a box it is the computed value
+            _ => match computed_style.values.get(name) {
+                Some(v) => resolve_lengths(computed_style, v),
+                None => "auto".to_string(),
+            },
+        },
+        _ => match computed_style.values.get(name) {
+            Some(v) => v.clone(),
+            None => "".to_string(),
+        },
+    }
+}
+
+// The resolved value of every supported property, serialized the way
+// `getComputedStyle` does it.
+pub fn get_computed_style(element: &DomElement) -> ComputedStyleDeclaration {
+    let mut declaration = ComputedStyleDeclaration {
+        style: CssStyleDeclaration::new(),
+        unsupported_properties: vec![],
+    };
+
+    let computed_style = match &element.computed_style {
+        Some(c) => c,
+        None => return declaration,
+    };
+
+    for property in PROPERTIES {
+        let value = resolve_property(element, computed_style, property.name);
+        declaration
+            .style
+            .declarations
+            .push(KeyValue::new_values(property.name, &value));
+    }
+
+    let mut custom_properties = computed_style.custom_properties.iter().collect::<Vec<_>>();
+    custom_properties.sort();
+    for (name, value) in custom_properties {
+        declaration
+            .style
+            .declarations
+            .push(KeyValue::new_values(name, value));
+    }
+
+    for name in element.style.keys() {
+        if !is_supported_property(name) && !is_custom_property(name) {
+            declaration.unsupported_properties.push(name.clone());
+        }
+    }
+    declaration.unsupported_properties.sort();
+
+    return declaration;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::colors::ColorScheme;
+    use crate::layout::compute_styles;
+
+    fn rule(css: &str) -> StyleRule {
+        return parse_css(css).remove(0);
+    }
+
+    #[test]
+    fn inserts_and_deletes_rules() {
+        let mut sheet = StyleSheet::new(vec![rule("p { color: red; }")]);
+        assert_eq!(sheet.insert_rule("div { width: 10px; }", 0), Ok(0));
+        assert_eq!(sheet.insert_rule("span { margin: 0 auto; }", 2), Ok(2));
+        assert_eq!(
+            sheet.css_text(),
+            "div { width: 10px; }\np { color: red; }\nspan { margin: 0 auto; }"
+        );
+
+        assert!(sheet.insert_rule("em { }", 4).is_err());
+        assert!(sheet.insert_rule("a { } b { }", 0).is_err());
+        assert!(sheet.delete_rule(3).is_err());
+
+        assert_eq!(sheet.delete_rule(1), Ok(()));
+        assert_eq!(sheet.css_text(), "div { width: 10px; }\nspan { margin: 0 auto; }");
+    }
+
+    #[test]
+    fn sets_and_removes_properties() {
+        let mut rule = rule("p { color: red; }");
+        rule.set_property("Width", "10px", "");
+        rule.set_property("padding", "1px 2px", "important");
+        rule.set_property("color", "blue", "");
+        assert_eq!(rule.css_text(), "p { color: blue; width: 10px; padding: 1px 2px !important; }");
+        assert_eq!(rule.get_property_value("padding-left"), "2px");
+        assert_eq!(rule.style().get_property_priority("padding"), "important");
+
+        assert_eq!(rule.remove_property("padding"), "1px 2px");
+        rule.set_property("width", "", "");
+        assert_eq!(rule.css_text(), "p { color: blue; }");
+    }
+
+    #[test]
+    fn ignores_invalid_declarations() {
+        let mut style = CssStyleDeclaration::parse("color: red");
+        style.set_property("colour", "blue", "");
+        style.set_property("color", "10px", "");
+        style.set_property("width", "red", "");
+        style.set_property("color", "blue", "!important");
+        style.set_property("margin", "1px 2px 3px 4px 5px", "");
+        assert_eq!(style.css_text(), "color: red;");
+
+        style.set_property("--Accent", "10px", "");
+        style.set_property("COLOR", "blue", "IMPORTANT");
+        assert_eq!(style.css_text(), "color: blue !important; --Accent: 10px;");
+    }
+
+    #[test]
+    fn restyles_after_changes() {
+        let mut document = Document::new();
+        document.children = parse_html(r#"<p id="a" style="height: 5px">x</p>"#);
+        document.style_sheets = vec![StyleSheet::new(vec![rule("p { width: 10px; }")])];
+
+        let computed_size = |document: &mut Document| {
+            let rules = document.active_rules(&MediaEnvironment::new(800.0, 600.0));
+            let length_context = LengthContext::new(800.0, 600.0);
+            compute_styles(&mut document.children, &rules, None, &length_context, ColorScheme::Light);
+            let element = document.get_element_by_id_mut("a").unwrap();
+            let style = get_computed_style(element).style;
+            return (style.get_property_value("width"), style.get_property_value("height"));
+        };
+        assert_eq!(computed_size(&mut document), (S("10px"), S("5px")));
+
+        document.needs_restyle = false;
+        document.style_sheets_mut()[0].insert_rule("p { width: 20px; }", 1).unwrap();
+        assert!(document.needs_restyle);
+        assert_eq!(computed_size(&mut document), (S("20px"), S("5px")));
+
+        document.needs_restyle = false;
+        let element = document.get_element_by_id_mut("a").unwrap();
+        let mut style = element.style();
+        style.set_property("height", "7px", "");
+        element.set_style(&style);
+        assert!(document.needs_restyle);
+        assert_eq!(computed_size(&mut document), (S("20px"), S("7px")));
+    }
+}
@@ -0,0 +1,305 @@
+use crate::html::*;
+
+// User interaction states matched by the dynamic pseudo-classes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ElementStateFlag {
+    Hover,
+    Active,
+    Focus,
+    FocusVisible,
+    FocusWithin,
+    Visited,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub struct ElementStates {
+    pub hover: bool,
+    pub active: bool,
+    pub focus: bool,
+    pub focus_visible: bool,
+    pub focus_within: bool,
+    pub visited: bool,
+}
+
+impl ElementStates {
+    pub fn new() -> ElementStates {
+        ElementStates::default()
+    }
+
+    pub fn get(&self, flag: ElementStateFlag) -> bool {
+        match flag {
+            ElementStateFlag::Hover => self.hover,
+            ElementStateFlag::Active => self.active,
+            ElementStateFlag::Focus => self.focus,
+            ElementStateFlag::FocusVisible => self.focus_visible,
+            ElementStateFlag::FocusWithin => self.focus_within,
+            ElementStateFlag::Visited => self.visited,
+        }
+    }
+
+    pub fn set(&mut self, flag: ElementStateFlag, value: bool) {
+        match flag {
+            ElementStateFlag::Hover => self.hover = value,
+            ElementStateFlag::Active => self.active = value,
+            ElementStateFlag::Focus => self.focus = value,
+            ElementStateFlag::FocusVisible => self.focus_visible = value,
+            ElementStateFlag::FocusWithin => self.focus_within = value,
+            ElementStateFlag::Visited => self.visited = value,
+        }
+    }
+
+    pub fn union(&self, other: &ElementStates) -> ElementStates {
+        ElementStates {
+            hover: self.hover || other.hover,
+            active: self.active || other.active,
+            focus: self.focus || other.focus,
+            focus_visible: self.focus_visible || other.focus_visible,
+            focus_within: self.focus_within || other.focus_within,
+            visited: self.visited || other.visited,
+        }
+    }
+}
+
+// Hover, active and focus-within also apply to the ancestors of the element.
+fn applies_to_ancestors(flag: ElementStateFlag) -> bool {
+    match flag {
+        ElementStateFlag::Hover | ElementStateFlag::Active | ElementStateFlag::FocusWithin => true,
+        _ => false,
+    }
+}
+
+pub fn get_element_by_path<'a>(tree: &'a Vec<DomElement>, path: &[usize]) -> Option<&'a DomElement> {
+    let mut element = tree.get(*path.first()?)?;
+    for i in &path[1..] {
+        element = element.children.get(*i)?;
+    }
+    Some(element)
+}
+
+pub fn get_element_by_path_mut<'a>(
+    tree: &'a mut Vec<DomElement>,
+    path: &[usize],
+) -> Option<&'a mut DomElement> {
+    let mut element = tree.get_mut(*path.first()?)?;
+    for i in &path[1..] {
+        element = element.children.get_mut(*i)?;
+    }
+    Some(element)
+}
+
+pub fn is_link(element: &DomElement) -> bool {
+    (element.tag_name == "A" || element.tag_name == "AREA") && element.attributes.contains_key("href")
+}
+
+pub fn is_form_control(element: &DomElement) -> bool {
+    match element.tag_name.as_str() {
+        "INPUT" | "BUTTON" | "SELECT" | "TEXTAREA" | "OPTION" | "OPTGROUP" | "FIELDSET" => true,
+        _ => false,
+    }
+}
+
+pub fn is_focusable(element: &DomElement) -> bool {
+    if element.node_type != NodeType::Element || element.pseudo_element.is_some() {
+        return false;
+    }
+    if element.attributes.contains_key("disabled") && is_form_control(element) {
+        return false;
+    }
+    match element.attributes.get("tabindex") {
+        Some(t) => return t.trim().parse::<i64>().is_ok(),
+        None => {}
+    }
+    match element.tag_name.as_str() {
+        "INPUT" | "BUTTON" | "SELECT" | "TEXTAREA" => true,
+        _ => is_link(element),
+    }
+}
+
+// Sets `flag` on the element at `path` and clears it everywhere else. Returns
+// the paths of the elements whose state changed.
+pub fn update_element_state(
+    tree: &mut Vec<DomElement>,
+    path: Option<&[usize]>,
+    flag: ElementStateFlag,
+) -> Vec<Vec<usize>> {
+    let mut changed: Vec<Vec<usize>> = vec![];
+    let mut current: Vec<usize> = vec![];
+    update_element_state_recursive(tree, path.unwrap_or(&[]), flag, &mut current, &mut changed);
+    return changed;
+}
+
+fn update_element_state_recursive(
+    tree: &mut Vec<DomElement>,
+    path: &[usize],
+    flag: ElementStateFlag,
+    current: &mut Vec<usize>,
+    changed: &mut Vec<Vec<usize>>,
+) {
+    for (i, element) in tree.iter_mut().enumerate() {
+        current.push(i);
+
+        let on_path = path.starts_with(current);
+        let value = if applies_to_ancestors(flag) {
+            on_path
+        } else {
+            on_path && path.len() == current.len()
+        };
+
+        if element.state.get(flag) != value {
+            element.state.set(flag, value);
+            changed.push(current.clone());
+        }
+
+        update_element_state_recursive(&mut element.children, path, flag, current, changed);
+        current.pop();
+    }
+}
+
+// The path of the first element in document order `predicate` is true for.
+pub fn find_element_path(tree: &Vec<DomElement>, predicate: &dyn Fn(&DomElement) -> bool) -> Option<Vec<usize>> {
+    for (i, element) in tree.iter().enumerate() {
+        if predicate(element) {
+            return Some(vec![i]);
+        }
+        match find_element_path(&element.children, predicate) {
+            Some(mut path) => {
+                path.insert(0, i);
+                return Some(path);
+            }
+            None => {}
+        }
+    }
+    return None;
+}
+
+// The nearest focusable element at or above `path`.
+pub fn get_focusable_path(tree: &Vec<DomElement>, path: &[usize]) -> Option<Vec<usize>> {
+    for len in (1..path.len() + 1).rev() {
+        match get_element_by_path(tree, &path[..len]) {
+            Some(element) if is_focusable(element) => return Some(path[..len].to_vec()),
+            _ => {}
+        }
+    }
+    return None;
+}
+
+fn collect_focusable_paths(tree: &Vec<DomElement>, current: &mut Vec<usize>, paths: &mut Vec<Vec<usize>>) {
+    for (i, element) in tree.iter().enumerate() {
+        current.push(i);
+        if is_focusable(element) {
+            paths.push(current.clone());
+        }
+        collect_focusable_paths(&element.children, current, paths);
+        current.pop();
+    }
+}
+
+// The element the Tab key moves the focus to, in document order.
+pub fn get_next_focusable_path(tree: &Vec<DomElement>, focused: Option<&[usize]>, backwards: bool) -> Option<Vec<usize>> {
+    let mut paths: Vec<Vec<usize>> = vec![];
+    collect_focusable_paths(tree, &mut vec![], &mut paths);
+    if paths.len() == 0 {
+        return None;
+    }
+
+    let position = focused.and_then(|f| paths.iter().position(|p| p.as_slice() == f));
+    let next = match (position, backwards) {
+        (Some(p), false) => (p + 1) % paths.len(),
+        (Some(p), true) => (p + paths.len() - 1) % paths.len(),
+        (None, false) => 0,
+        (None, true) => paths.len() - 1,
+    };
+
+    return Some(paths[next].clone());
+}
+
+// Moves the focus, `focus_visible` is set when the focus was moved with the
+// keyboard. Returns the paths of the elements whose state changed.
+pub fn focus_element(tree: &mut Vec<DomElement>, path: Option<&[usize]>, focus_visible: bool) -> Vec<Vec<usize>> {
+    let mut changed = update_element_state(tree, path, ElementStateFlag::Focus);
+    changed.extend(update_element_state(tree, path, ElementStateFlag::FocusWithin));
+    changed.extend(update_element_state(
+        tree,
+        if focus_visible { path } else { None },
+        ElementStateFlag::FocusVisible,
+    ));
+    return changed;
+}
+
+pub fn mark_visited_links(tree: &mut Vec<DomElement>, is_visited: &dyn Fn(&str) -> bool) -> Vec<Vec<usize>> {
+    let mut changed: Vec<Vec<usize>> = vec![];
+    mark_visited_links_recursive(tree, is_visited, &mut vec![], &mut changed);
+    return changed;
+}
+
+fn mark_visited_links_recursive(
+    tree: &mut Vec<DomElement>,
+    is_visited: &dyn Fn(&str) -> bool,
+    current: &mut Vec<usize>,
+    changed: &mut Vec<Vec<usize>>,
+) {
+    for (i, element) in tree.iter_mut().enumerate() {
+        current.push(i);
+        if is_link(element) {
+            let visited = is_visited(&element.attributes["href"]);
+            if element.state.visited != visited {
+                element.state.visited = visited;
+                changed.push(current.clone());
+            }
+        }
+        mark_visited_links_recursive(&mut element.children, is_visited, current, changed);
+        current.pop();
+    }
+}
+
+// Clicking a checkbox toggles it, clicking a radio button checks it and
+// unchecks the other buttons of its group.
+pub fn toggle_checked(tree: &mut Vec<DomElement>, path: &[usize]) -> Vec<Vec<usize>> {
+    let (input_type, name) = match get_element_by_path(tree, path) {
+        Some(element) if element.tag_name == "INPUT" && !element.attributes.contains_key("disabled") => (
+            element.attributes.get("type").map(|t| t.to_lowercase()).unwrap_or("".to_string()),
+            element.attributes.get("name").cloned(),
+        ),
+        _ => return vec![],
+    };
+
+    let mut changed: Vec<Vec<usize>> = vec![];
+
+    if input_type == "checkbox" {
+        let element = get_element_by_path_mut(tree, path).unwrap();
+        if element.attributes.remove("checked").is_none() {
+            element.attributes.insert("checked".to_string(), "".to_string());
+        }
+        changed.push(path.to_vec());
+    } else if input_type == "radio" {
+        if name.is_some() {
+            uncheck_radio_group(tree, name.as_ref().unwrap(), &mut vec![], &mut changed);
+        }
+        let element = get_element_by_path_mut(tree, path).unwrap();
+        if element.attributes.insert("checked".to_string(), "".to_string()).is_none() {
+            changed.push(path.to_vec());
+        }
+    }
+
+    return changed;
+}
+
+fn uncheck_radio_group(
+    tree: &mut Vec<DomElement>,
+    name: &str,
+    current: &mut Vec<usize>,
+    changed: &mut Vec<Vec<usize>>,
+) {
+    for (i, element) in tree.iter_mut().enumerate() {
+        current.push(i);
+        if element.tag_name == "INPUT"
+            && element.attributes.get("type").map(|t| t.to_lowercase()) == Some("radio".to_string())
+            && element.attributes.get("name").map(|n| n.as_str()) == Some(name)
+            && element.attributes.remove("checked").is_some()
+        {
+            changed.push(current.clone());
+        }
+        uncheck_radio_group(&mut element.children, name, current, changed);
+        current.pop();
+    }
+}
@@ -0,0 +1,959 @@
+use crate::floats::*;
+use crate::html::*;
+use crate::inline_layout::*;
+use crate::layout::*;
+use crate::length::*;
+use crate::overflow::is_scroll_container;
+use crate::utils::*;
+use std::ops::Range;
+
+pub fn is_flex_container(computed_style: &ComputedStyle) -> bool {
+    computed_style.display == "flex" || computed_style.display == "inline-flex"
+}
+
+pub struct FlexLayout {
+    // the content height
+    pub height: f64,
+    pub baseline: Option<f64>,
+}
+
+struct FlexItem {
+    // the children the item is made of, a run of them for text directly in
+    // the container
+    range: Range<usize>,
+    anonymous: bool,
+    order: i32,
+    grow: f64,
+    shrink: f64,
+    align: String,
+    margin: Margin,
+    // which margins are `auto`: top, right, bottom and left
+    auto_margins: [bool; 4],
+    // the padding and border
+    edges: Margin,
+    // content box sizes along the main axis
+    base_size: f64,
+    hypothetical_size: f64,
+    min_size: f64,
+    max_size: Option<f64>,
+    main_size: f64,
+    frozen: bool,
+    // the content width along the cross axis of a column
+    cross_width: f64,
+    // the content size along the cross axis that fills the line
+    stretched: Option<f64>,
+    // the border box size along the cross axis
+    cross_size: f64,
+    // from the top of the border box
+    baseline: Option<f64>,
+    // where the border box was laid out, and at which content size
+    laid_out: Option<(f64, f64, f64, Option<f64>)>,
+    // the offsets of the margin box from the content box of the container
+    main_position: f64,
+    cross_position: f64,
+}
+
+// The start and end of `margin` along the horizontal axis when `horizontal`,
+// along the vertical one otherwise.
+fn sides(margin: &Margin, horizontal: bool) -> (f64, f64) {
+    if horizontal {
+        (margin.left, margin.right)
+    } else {
+        (margin.top, margin.bottom)
+    }
+}
+
+impl FlexItem {
+    fn outer_edges(&self, horizontal: bool) -> f64 {
+        let (margin_start, margin_end) = sides(&self.margin, horizontal);
+        let (edge_start, edge_end) = sides(&self.edges, horizontal);
+        margin_start + margin_end + edge_start + edge_end
+    }
+
+    fn auto_sides(&self, horizontal: bool) -> (bool, bool) {
+        if horizontal {
+            (self.auto_margins[3], self.auto_margins[1])
+        } else {
+            (self.auto_margins[0], self.auto_margins[2])
+        }
+    }
+}
+
+pub fn get_value<'a>(computed_style: &'a ComputedStyle, name: &str, default: &'a str) -> &'a str {
+    computed_style.values.get(name).map(|v| v.as_str()).unwrap_or(default)
+}
+
+// A `normal` gap is none between flex items and grid tracks.
+pub fn resolve_gap(computed_style: &ComputedStyle, name: &str, basis: Option<f64>) -> f64 {
+    match get_value(computed_style, name, "normal") {
+        "normal" => 0.0,
+        value => match parse_length_percentage(value) {
+            Ok(gap) if !gap.has_percentage() || basis.is_some() => {
+                gap.resolve(&computed_style.length_context, basis.unwrap_or(0.0)).max(0.0)
+            }
+            _ => 0.0,
+        },
+    }
+}
+
+pub fn is_in_flow_child(element: &DomElement) -> bool {
+    element.node_type == NodeType::Element && is_displayed(element) && !is_out_of_flow(element)
+}
+
+// Text directly in the container is wrapped in an anonymous item, white
+// space alone isn't.
+pub fn has_text(tree: &[DomElement]) -> bool {
+    tree.iter().any(|c| c.node_type == NodeType::Text && c.node_value.trim() != "")
+}
+
+// The space before the first of `count` boxes and between each two, to give
+// out the `free` space the way `mode` asks, CSS Box Alignment §5.3. Spacing
+// that can't be done falls back to the start, or to the center.
+pub fn distribute_space(mode: &str, free: f64, count: usize) -> (f64, f64) {
+    let n = count as f64;
+    match mode {
+        "flex-end" | "end" | "right" => (free, 0.0),
+        "center" => (free / 2.0, 0.0),
+        "space-between" if free > 0.0 && count > 1 => (0.0, free / (n - 1.0)),
+        "space-around" if free > 0.0 => (free / n / 2.0, free / n),
+        "space-evenly" if free > 0.0 => (free / (n + 1.0), free / (n + 1.0)),
+        "space-around" | "space-evenly" => (free / 2.0, 0.0),
+        _ => (0.0, 0.0),
+    }
+}
+
+// Items laid out from the other end swap which end `flex-start` and
+// `flex-end` mean.
+fn flip_alignment(mode: &str, flip: bool) -> &str {
+    match mode {
+        "flex-start" if flip => "flex-end",
+        "flex-end" if flip => "flex-start",
+        _ => mode,
+    }
+}
+
+fn is_baseline(align: &str) -> bool {
+    align == "baseline" || align == "first baseline" || align == "last baseline"
+}
+
+fn collect_items(
+    tree: &mut Vec<DomElement>,
+    measure_text: &dyn Fn(String, f64, String) -> (f64, f64),
+    container: &InlineContainer,
+    align_items: &str,
+) -> Vec<FlexItem> {
+    let new_item = |range: Range<usize>, anonymous: bool| FlexItem {
+        range,
+        anonymous,
+        order: 0,
+        grow: 0.0,
+        shrink: 1.0,
+        align: align_items.to_string(),
+        margin: Margin::zero(),
+        auto_margins: [false; 4],
+        edges: Margin::zero(),
+        base_size: 0.0,
+        hypothetical_size: 0.0,
+        min_size: 0.0,
+        max_size: None,
+        main_size: 0.0,
+        frozen: false,
+        cross_width: 0.0,
+        stretched: None,
+        cross_size: 0.0,
+        baseline: None,
+        laid_out: None,
+        main_position: 0.0,
+        cross_position: 0.0,
+    };
+
+    let mut items = vec![];
+    let mut i = 0;
+    while i < tree.len() {
+        if !is_in_flow_child(&tree[i]) {
+            let start = i;
+            while i < tree.len() && !is_in_flow_child(&tree[i]) {
+                i += 1;
+            }
+            if has_text(&tree[start..i]) {
+                items.push(new_item(start..i, true));
+            } else {
+                // out-of-flow boxes keep the start of the container as
+                // their static position
+                let mut lines_container = container.clone();
+                lines_container.height = None;
+                layout_inline_content(tree, start..i, &lines_container, &mut FloatContext::new(), measure_text);
+            }
+            continue;
+        }
+
+        let computed_style = tree[i].computed_style.as_mut().unwrap();
+        resolve_box_edges(computed_style, container.width);
+        let computed_style = tree[i].computed_style.as_ref().unwrap();
+        let mut item = new_item(i..i + 1, false);
+        item.order = get_value(computed_style, "order", "0").parse().unwrap_or(0);
+        item.grow = get_value(computed_style, "flex-grow", "0").parse().unwrap_or(0.0);
+        item.shrink = get_value(computed_style, "flex-shrink", "1").parse().unwrap_or(1.0);
+        match get_value(computed_style, "align-self", "auto") {
+            "auto" => {}
+            "normal" => item.align = S("stretch"),
+            align => item.align = align.to_string(),
+        }
+        item.auto_margins = [
+            is_auto_margin(computed_style, "top"),
+            is_auto_margin(computed_style, "right"),
+            is_auto_margin(computed_style, "bottom"),
+            is_auto_margin(computed_style, "left"),
+        ];
+        item.margin = computed_style.margin.clone();
+        let border = computed_style.border.widths();
+        let padding = &computed_style.padding;
+        item.edges = Margin {
+            top: border.top + padding.top,
+            right: border.right + padding.right,
+            bottom: border.bottom + padding.bottom,
+            left: border.left + padding.left,
+        };
+        items.push(item);
+        i += 1;
+    }
+    return items;
+}
+
+// Lays out an item with its border box at `x` and `y`, `width` wide and with
+// a content height of `height` when given, or moves it there when it was
+// laid out at that size already. Sets its cross size and baseline.
+fn layout_item(
+    tree: &mut Vec<DomElement>,
+    item: &mut FlexItem,
+    measure_text: &dyn Fn(String, f64, String) -> (f64, f64),
+    container: &InlineContainer,
+    row: bool,
+    (x, y): (f64, f64),
+    width: f64,
+    height: Option<f64>,
+) -> f64 {
+    let (border_height, baseline) = match item.laid_out {
+        Some((old_x, old_y, old_width, old_height)) if old_width == width && old_height == height => {
+            for child in &mut tree[item.range.clone()] {
+                translate_element(child, x - old_x, y - old_y);
+            }
+            let height = height.map(|h| h + item.edges.top + item.edges.bottom);
+            (height.unwrap_or(item.cross_size), item.baseline)
+        }
+        _ if item.anonymous => {
+            let mut lines_container = container.clone();
+            lines_container.x = x;
+            lines_container.y = y;
+            lines_container.width = width;
+            lines_container.height = height;
+            let lines = layout_inline_content(
+                tree,
+                item.range.clone(),
+                &lines_container,
+                &mut FloatContext::new(),
+                measure_text,
+            );
+            (height.unwrap_or(lines.height), lines.last_baseline.map(|b| b - y))
+        }
+        _ => {
+            let block = layout_block_box(
+                &mut tree[item.range.start],
+                measure_text,
+                x,
+                y,
+                width,
+                container.height,
+                CollapsedMargin::empty(),
+                &mut FloatContext::new(),
+                Some(ItemSizing { height }),
+            );
+            (block.height, block.baseline.map(|b| b - y))
+        }
+    };
+    item.laid_out = Some((x, y, width, height));
+    item.baseline = baseline;
+    item.cross_size = if row {
+        border_height
+    } else {
+        width + item.edges.left + item.edges.right
+    };
+    return border_height;
+}
+
+// The content width a column gives an item: the width it asks for, the
+// width of the column when it stretches, or else fit to its content.
+fn get_column_item_width(
+    tree: &mut Vec<DomElement>,
+    item: &FlexItem,
+    measure_text: &dyn Fn(String, f64, String) -> (f64, f64),
+    container_width: f64,
+) -> f64 {
+    if item.anonymous {
+        return container_width;
+    }
+    let element = &mut tree[item.range.start];
+    let computed_style = element.computed_style.as_ref().unwrap();
+    let (width, min_width, max_width) = resolve_width(computed_style, container_width);
+    let (auto_start, auto_end) = item.auto_sides(true);
+    if width.is_none() && item.align == "stretch" && !auto_start && !auto_end {
+        let stretched = container_width - item.outer_edges(true);
+        return clamp_size(stretched.max(0.0), min_width, max_width);
+    }
+    return get_shrink_to_fit_width(element, measure_text, container_width);
+}
+
+// The flex base size and hypothetical main size of an item, CSS Flexbox
+// §9.2, and its minimum and maximum. An `auto` minimum keeps the item from
+// getting smaller than its content, §4.5, unless it is a scroll container.
+fn resolve_base_size(
+    tree: &mut Vec<DomElement>,
+    item: &mut FlexItem,
+    measure_text: &dyn Fn(String, f64, String) -> (f64, f64),
+    container: &InlineContainer,
+    row: bool,
+) {
+    // the smallest and largest the content makes the item along the main axis
+    let content_sizes = |tree: &mut Vec<DomElement>, item: &mut FlexItem| -> (f64, f64) {
+        if row && item.anonymous {
+            return get_inline_content_widths(tree, item.range.clone(), measure_text);
+        }
+        if row {
+            return get_content_widths(&mut tree[item.range.start], measure_text);
+        }
+        let width = item.cross_width;
+        let height = layout_item(tree, item, measure_text, container, row, (0.0, 0.0), width, None);
+        let content_height = height - item.edges.top - item.edges.bottom;
+        (content_height, content_height)
+    };
+
+    if item.anonymous {
+        let (min_content, max_content) = content_sizes(tree, item);
+        item.base_size = max_content;
+        item.hypothetical_size = max_content;
+        item.min_size = min_content;
+        return;
+    }
+
+    let computed_style = tree[item.range.start].computed_style.as_ref().unwrap();
+    let (size, min_size, max_size) = if row {
+        resolve_width(computed_style, container.width)
+    } else {
+        resolve_height(computed_style, container.height)
+    };
+    let main_basis = if row { Some(container.width) } else { container.height };
+    let (edge_start, edge_end) = sides(&item.edges, row);
+    let basis = match get_value(computed_style, "flex-basis", "auto") {
+        "auto" => size,
+        "content" => None,
+        value => match parse_length_percentage(value) {
+            Ok(basis) => resolve_size(computed_style, &Some(basis), main_basis, edge_start + edge_end),
+            Err(_) => size,
+        },
+    };
+
+    // a scroll container can get as small as it likes
+    let scrolls = is_scroll_container(computed_style);
+    let content = if basis.is_none() || (min_size.is_none() && !scrolls) {
+        Some(content_sizes(tree, item))
+    } else {
+        None
+    };
+    item.base_size = basis.unwrap_or_else(|| content.unwrap().1);
+    item.max_size = max_size;
+    item.min_size = match min_size {
+        Some(min_size) => min_size,
+        None if scrolls => 0.0,
+        None => {
+            let content_min = content.unwrap().0;
+            let suggestion = size.map_or(content_min, |s| s.min(content_min));
+            max_size.map_or(suggestion, |m| suggestion.min(m))
+        }
+    };
+    item.hypothetical_size = clamp_size(item.base_size, Some(item.min_size), item.max_size);
+}
+
+// Flexes the items of a line to fill `available` along the main axis, CSS
+// Flexbox §9.7: grow factors share out the free space, shrink factors
+// weighted by the base sizes take back what overflows, and items that hit
+// their minimum or maximum are frozen there while the rest try again.
+fn resolve_flexible_lengths(items: &mut Vec<FlexItem>, line: &[usize], available: f64, gap: f64, row: bool) {
+    let gaps = gap * line.len().saturating_sub(1) as f64;
+    let hypothetical: f64 = line
+        .iter()
+        .map(|i| items[*i].hypothetical_size + items[*i].outer_edges(row))
+        .sum();
+    let growing = hypothetical + gaps < available;
+    for i in line {
+        let item = &mut items[*i];
+        item.main_size = item.hypothetical_size;
+        let factor = if growing { item.grow } else { item.shrink };
+        item.frozen = factor == 0.0
+            || (growing && item.base_size > item.hypothetical_size)
+            || (!growing && item.base_size < item.hypothetical_size);
+    }
+
+    let get_free_space = |items: &Vec<FlexItem>| -> f64 {
+        let used: f64 = line
+            .iter()
+            .map(|i| {
+                let item = &items[*i];
+                item.outer_edges(row) + if item.frozen { item.main_size } else { item.base_size }
+            })
+            .sum();
+        available - gaps - used
+    };
+    let initial_free_space = get_free_space(items);
+
+    loop {
+        let unfrozen: Vec<usize> = line.iter().cloned().filter(|i| !items[*i].frozen).collect();
+        if unfrozen.len() == 0 {
+            break;
+        }
+        let mut free_space = get_free_space(items);
+        let factors: f64 = unfrozen
+            .iter()
+            .map(|i| if growing { items[*i].grow } else { items[*i].shrink })
+            .sum();
+        // factors adding up to less than one take only part of the space
+        if factors < 1.0 && (initial_free_space * factors).abs() < free_space.abs() {
+            free_space = initial_free_space * factors;
+        }
+
+        let scaled_shrink: f64 = unfrozen.iter().map(|i| items[*i].shrink * items[*i].base_size).sum();
+        for i in &unfrozen {
+            let item = &mut items[*i];
+            item.main_size = if growing {
+                item.base_size + free_space * item.grow / factors
+            } else if scaled_shrink > 0.0 {
+                item.base_size + free_space * item.shrink * item.base_size / scaled_shrink
+            } else {
+                item.base_size
+            };
+        }
+
+        let mut violations = vec![];
+        let mut total_violation = 0.0;
+        for i in &unfrozen {
+            let item = &mut items[*i];
+            let clamped = clamp_size(item.main_size, Some(item.min_size), item.max_size);
+            violations.push(clamped - item.main_size);
+            total_violation += clamped - item.main_size;
+            item.main_size = clamped;
+        }
+        for (i, violation) in unfrozen.iter().zip(violations) {
+            if total_violation == 0.0
+                || (total_violation > 0.0 && violation > 0.0)
+                || (total_violation < 0.0 && violation < 0.0)
+            {
+                items[*i].frozen = true;
+            }
+        }
+    }
+}
+
+// Lays out the children of a flex container in the content box `container`,
+// CSS Flexbox §9. Items are put in lines along the main axis, flexed to fill
+// them and aligned within them, and the lines are stacked along the cross
+// axis. Returns the content height and the first baseline.
+pub fn layout_flex_container(
+    element: &mut DomElement,
+    measure_text: &dyn Fn(String, f64, String) -> (f64, f64),
+    container: &InlineContainer,
+    min_height: Option<f64>,
+    max_height: Option<f64>,
+) -> FlexLayout {
+    let computed_style = element.computed_style.as_ref().unwrap();
+    let direction = get_value(computed_style, "flex-direction", "row");
+    let row = !direction.starts_with("column");
+    let reverse = direction.ends_with("-reverse");
+    let wrap = get_value(computed_style, "flex-wrap", "nowrap").to_string();
+    let justify_content = get_value(computed_style, "justify-content", "normal").to_string();
+    let align_items = match get_value(computed_style, "align-items", "normal") {
+        "normal" => "stretch",
+        align => align,
+    }
+    .to_string();
+    let align_content = get_value(computed_style, "align-content", "normal").to_string();
+    let column_gap = resolve_gap(computed_style, "column-gap", Some(container.width));
+    let row_gap = resolve_gap(computed_style, "row-gap", container.height);
+    let (main_gap, cross_gap) = if row { (column_gap, row_gap) } else { (row_gap, column_gap) };
+
+    let tree = &mut element.children;
+    let mut items = collect_items(tree, measure_text, container, &align_items);
+    items.sort_by_key(|i| i.order);
+    for item in items.iter_mut() {
+        if !row {
+            item.cross_width = get_column_item_width(tree, item, measure_text, container.width);
+        }
+        resolve_base_size(tree, item, measure_text, container, row);
+    }
+
+    // lines break where the next item would overflow the main size
+    let available_main = if row { Some(container.width) } else { container.height.or(max_height) };
+    let mut lines: Vec<Vec<usize>> = vec![];
+    let mut line_size = 0.0;
+    for (i, item) in items.iter().enumerate() {
+        let outer_size = item.hypothetical_size + item.outer_edges(row);
+        match (lines.last_mut(), available_main) {
+            (Some(line), Some(available)) if wrap == "nowrap" || line_size + main_gap + outer_size <= available => {
+                line.push(i);
+                line_size += main_gap + outer_size;
+            }
+            (Some(line), None) => {
+                line.push(i);
+                line_size += main_gap + outer_size;
+            }
+            _ => {
+                lines.push(vec![i]);
+                line_size = outer_size;
+            }
+        }
+    }
+
+    let container_main = if row {
+        container.width
+    } else {
+        match container.height {
+            Some(height) => height,
+            None => {
+                let longest = lines
+                    .iter()
+                    .map(|line| {
+                        let sizes: f64 = line.iter().map(|i| items[*i].hypothetical_size + items[*i].outer_edges(row)).sum();
+                        sizes + main_gap * (line.len() - 1) as f64
+                    })
+                    .fold(0.0, f64::max);
+                clamp_size(longest, min_height, max_height)
+            }
+        }
+    };
+    for line in &lines {
+        resolve_flexible_lengths(&mut items, line, container_main, main_gap, row);
+    }
+
+    // the cross size each item takes at its main size
+    for item in items.iter_mut() {
+        if row {
+            let width = item.main_size;
+            layout_item(tree, item, measure_text, container, row, (0.0, 0.0), width, None);
+        } else {
+            let (width, height) = (item.cross_width, Some(item.main_size));
+            layout_item(tree, item, measure_text, container, row, (0.0, 0.0), width, height);
+        }
+    }
+
+    // lines are as tall as their items, the baseline-aligned ones lined up,
+    // and a single line fills a known cross size
+    let definite_cross = if row { container.height } else { Some(container.width) };
+    let baseline_offset = |item: &FlexItem| item.margin.top + item.baseline.unwrap_or(item.cross_size);
+    let is_baseline_aligned = |item: &FlexItem| {
+        let (auto_start, auto_end) = item.auto_sides(!row);
+        row && is_baseline(&item.align) && !auto_start && !auto_end
+    };
+    let mut line_crosses: Vec<f64> = vec![];
+    let mut line_baselines: Vec<f64> = vec![];
+    for line in &lines {
+        let mut cross: f64 = 0.0;
+        let mut above: f64 = 0.0;
+        let mut below: f64 = 0.0;
+        for i in line {
+            let item = &items[*i];
+            let outer_cross = item.cross_size + sides(&item.margin, !row).0 + sides(&item.margin, !row).1;
+            if is_baseline_aligned(item) {
+                above = above.max(baseline_offset(item));
+                below = below.max(outer_cross - baseline_offset(item));
+            } else {
+                cross = cross.max(outer_cross);
+            }
+        }
+        line_crosses.push(cross.max(above + below));
+        line_baselines.push(above);
+    }
+    if wrap == "nowrap" && lines.len() > 0 {
+        match definite_cross {
+            Some(cross) => line_crosses[0] = cross,
+            None => {}
+        }
+    }
+
+    let cross_gaps = cross_gap * lines.len().saturating_sub(1) as f64;
+    let container_cross = match definite_cross {
+        Some(cross) => cross,
+        None => {
+            let lines_cross: f64 = line_crosses.iter().sum();
+            clamp_size(lines_cross + cross_gaps, min_height, max_height)
+        }
+    };
+
+    // `align-content` gives out the cross space the lines leave
+    let wrap_reverse = wrap == "wrap-reverse";
+    let free_cross = container_cross - line_crosses.iter().sum::<f64>() - cross_gaps;
+    let (mut line_position, line_spacing) = if wrap == "nowrap" {
+        (0.0, 0.0)
+    } else if align_content == "normal" || align_content == "stretch" {
+        if free_cross > 0.0 {
+            let extra = free_cross / lines.len().max(1) as f64;
+            for cross in line_crosses.iter_mut() {
+                *cross += extra;
+            }
+        }
+        (0.0, 0.0)
+    } else {
+        distribute_space(flip_alignment(&align_content, wrap_reverse), free_cross, lines.len())
+    };
+
+    // lines go from the cross end with `wrap-reverse`, items from the main
+    // end in reversed directions
+    let line_order: Vec<usize> = if wrap_reverse {
+        (0..lines.len()).rev().collect()
+    } else {
+        (0..lines.len()).collect()
+    };
+    for l in line_order {
+        let line = &lines[l];
+        let line_cross = line_crosses[l];
+
+        // stretched items fill the line along the cross axis
+        for i in line {
+            let item = &mut items[*i];
+            let (auto_start, auto_end) = item.auto_sides(!row);
+            if item.anonymous || auto_start || auto_end || item.align != "stretch" {
+                continue;
+            }
+            let computed_style = tree[item.range.start].computed_style.as_ref().unwrap();
+            let (size, min_size, max_size) = if row {
+                resolve_height(computed_style, container.height)
+            } else {
+                resolve_width(computed_style, container.width)
+            };
+            if size.is_some() {
+                continue;
+            }
+            let (edge_start, edge_end) = sides(&item.edges, !row);
+            let (margin_start, margin_end) = sides(&item.margin, !row);
+            let stretched = (line_cross - margin_start - margin_end - edge_start - edge_end).max(0.0);
+            let stretched = clamp_size(stretched, min_size, max_size);
+            item.stretched = Some(stretched);
+            item.cross_size = stretched + edge_start + edge_end;
+        }
+
+        let used: f64 = line
+            .iter()
+            .map(|i| items[*i].main_size + items[*i].outer_edges(row))
+            .sum::<f64>()
+            + main_gap * (line.len() - 1) as f64;
+        let mut free_main = container_main - used;
+        // `auto` margins take the free space before `justify-content` does
+        let auto_count: usize = line
+            .iter()
+            .map(|i| {
+                let (start, end) = items[*i].auto_sides(row);
+                start as usize + end as usize
+            })
+            .sum();
+        let auto_share = if free_main > 0.0 && auto_count > 0 {
+            let share = free_main / auto_count as f64;
+            free_main = 0.0;
+            share
+        } else {
+            0.0
+        };
+        let justify = match justify_content.as_str() {
+            "start" | "left" if reverse => "flex-end",
+            "end" | "right" if reverse => "flex-start",
+            "normal" | "stretch" => flip_alignment("flex-start", reverse),
+            mode => flip_alignment(mode, reverse),
+        };
+        let (start, spacing) = distribute_space(justify, free_main, line.len());
+
+        let order: Vec<usize> = if reverse { line.iter().rev().cloned().collect() } else { line.clone() };
+        let mut position = start;
+        for i in order {
+            let item = &mut items[i];
+            let (auto_start, auto_end) = item.auto_sides(row);
+            if auto_start {
+                position += auto_share;
+            }
+            item.main_position = position;
+            position += item.main_size + item.outer_edges(row) + main_gap + spacing;
+            if auto_end {
+                position += auto_share;
+            }
+
+            let (margin_start, margin_end) = sides(&item.margin, !row);
+            let free = line_cross - item.cross_size - margin_start - margin_end;
+            let offset = match item.auto_sides(!row) {
+                (true, true) => free.max(0.0) / 2.0,
+                (true, false) => free.max(0.0),
+                (false, true) => 0.0,
+                _ if is_baseline_aligned(item) => line_baselines[l] - baseline_offset(item),
+                _ => match flip_alignment(item.align.as_str(), wrap_reverse) {
+                    "flex-end" | "end" | "self-end" => free,
+                    "center" => free / 2.0,
+                    _ => 0.0,
+                },
+            };
+            item.cross_position = line_position + offset;
+        }
+        line_position += line_cross + cross_gap + line_spacing;
+    }
+
+    // with everything decided, the items are laid out in place
+    let mut first_baseline: Option<f64> = None;
+    let mut aligned_baseline: Option<f64> = None;
+    for (index, item) in items.iter_mut().enumerate() {
+        let (main_start, _) = sides(&item.margin, row);
+        let (cross_start, _) = sides(&item.margin, !row);
+        let main = container_main_offset(row, container) + item.main_position + main_start;
+        let cross = container_cross_offset(row, container) + item.cross_position + cross_start;
+        let (x, y) = if row { (main, cross) } else { (cross, main) };
+        let (width, height) = if row {
+            (item.main_size, item.stretched)
+        } else {
+            (item.stretched.unwrap_or(item.cross_width), Some(item.main_size))
+        };
+        layout_item(tree, item, measure_text, container, row, (x, y), width, height);
+
+        // the container's baseline is the first line's, from its first item
+        // aligned by baseline, or else its first item
+        if lines[0].contains(&index) {
+            match item.baseline {
+                Some(b) if is_baseline_aligned(item) => aligned_baseline = aligned_baseline.or(Some(y + b)),
+                Some(b) => first_baseline = first_baseline.or(Some(y + b)),
+                None => {}
+            }
+        }
+    }
+
+    FlexLayout {
+        height: if row { container_cross } else { container_main },
+        baseline: aligned_baseline.or(first_baseline),
+    }
+}
+
+fn container_main_offset(row: bool, container: &InlineContainer) -> f64 {
+    if row {
+        container.x
+    } else {
+        container.y
+    }
+}
+
+fn container_cross_offset(row: bool, container: &InlineContainer) -> f64 {
+    if row {
+        container.y
+    } else {
+        container.x
+    }
+}
+
+// The narrowest and widest the content box of a flex container gets, from
+// the contributions of its items, CSS Flexbox §9.9. A row that doesn't wrap
+// is never narrower than all its items side by side.
+pub fn get_flex_content_widths(
+    element: &mut DomElement,
+    measure_text: &dyn Fn(String, f64, String) -> (f64, f64),
+) -> (f64, f64) {
+    let computed_style = element.computed_style.as_ref().unwrap();
+    let row = !get_value(computed_style, "flex-direction", "row").starts_with("column");
+    let wraps = get_value(computed_style, "flex-wrap", "nowrap") != "nowrap";
+    let gap = resolve_gap(computed_style, "column-gap", None);
+
+    let tree = &mut element.children;
+    let mut contributions = vec![];
+    let mut i = 0;
+    while i < tree.len() {
+        if is_in_flow_child(&tree[i]) {
+            contributions.push(get_intrinsic_widths(&mut tree[i], measure_text));
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < tree.len() && !is_in_flow_child(&tree[i]) {
+            i += 1;
+        }
+        if has_text(&tree[start..i]) {
+            contributions.push(get_inline_content_widths(tree, start..i, measure_text));
+        }
+    }
+
+    let largest = contributions
+        .iter()
+        .fold((0.0, 0.0), |(min, max): (f64, f64), c| (min.max(c.0), max.max(c.1)));
+    if !row {
+        return largest;
+    }
+    let gaps = gap * contributions.len().saturating_sub(1) as f64;
+    let min_sum: f64 = contributions.iter().map(|c| c.0).sum();
+    let max_sum: f64 = contributions.iter().map(|c| c.1).sum();
+    if wraps {
+        return (largest.0, max_sum + gaps);
+    }
+    return (min_sum + gaps, max_sum + gaps);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::colors::ColorScheme;
+    use crate::element_state::*;
+    use crate::styles::get_user_agent_rules;
+
+    fn measure_text(text: String, font_size: f64, _font: String) -> (f64, f64) {
+        return (text.chars().count() as f64 * font_size * 0.5, font_size);
+    }
+
+    // Lays out the flex container `#f` of `html` in a content box at the
+    // origin, `width` wide and `height` tall when it is given.
+    fn layout(html: &str, width: f64, height: Option<f64>) -> (DomElement, FlexLayout) {
+        let mut tree = parse_html(html);
+        let length_context = LengthContext::new(800.0, 600.0);
+        compute_styles(&mut tree, get_user_agent_rules(), None, &length_context, ColorScheme::Light);
+        let path = find_element_path(&tree, &|e| e.attributes.get("id").map(|id| id.as_str()) == Some("f")).unwrap();
+        let element = get_element_by_path_mut(&mut tree, &path).unwrap();
+        let computed_style = element.computed_style.clone();
+        let mut container = InlineContainer::new(0.0, 0.0, width, computed_style.as_ref());
+        container.height = height;
+        let flex = layout_flex_container(element, &measure_text, &container, None, None);
+        return (element.clone(), flex);
+    }
+
+    // The border box of the child `i` of `element`: x, y, width and height.
+    fn child_rect(element: &DomElement, i: usize) -> (f64, f64, f64, f64) {
+        let flow = element.children[i].computed_flow.as_ref().unwrap();
+        return (flow.x, flow.y, flow.width, flow.height);
+    }
+
+    #[test]
+    fn wraps_items_that_overflow_the_line() {
+        let (element, flex) = layout(
+            r#"<div id="f" style="display: flex; flex-wrap: wrap"><div style="width: 120px; height: 10px"></div><div style="width: 120px; height: 20px"></div><div style="width: 120px; height: 10px"></div></div>"#,
+            300.0,
+            None,
+        );
+        assert_eq!(child_rect(&element, 0), (0.0, 0.0, 120.0, 10.0));
+        assert_eq!(child_rect(&element, 1), (120.0, 0.0, 120.0, 20.0));
+        assert_eq!(child_rect(&element, 2), (0.0, 20.0, 120.0, 10.0));
+        assert_eq!(flex.height, 30.0);
+    }
+
+    #[test]
+    fn shrinks_items_on_a_single_line_without_wrap() {
+        let (element, flex) = layout(
+            r#"<div id="f" style="display: flex"><div style="width: 300px; height: 10px"></div><div style="width: 300px; height: 10px; flex-shrink: 3"></div></div>"#,
+            400.0,
+            None,
+        );
+        // the 200px overflow is taken in proportion to shrink times base size
+        assert_eq!(child_rect(&element, 0), (0.0, 0.0, 250.0, 10.0));
+        assert_eq!(child_rect(&element, 1), (250.0, 0.0, 150.0, 10.0));
+        assert_eq!(flex.height, 10.0);
+    }
+
+    #[test]
+    fn grows_items_by_their_flex_grow() {
+        let (element, _) = layout(
+            r#"<div id="f" style="display: flex"><div style="flex: 1 1 0; height: 10px"></div><div style="flex: 3 1 0; height: 10px"></div><div style="width: 40px; height: 10px"></div></div>"#,
+            400.0,
+            None,
+        );
+        assert_eq!(child_rect(&element, 0), (0.0, 0.0, 90.0, 10.0));
+        assert_eq!(child_rect(&element, 1), (90.0, 0.0, 270.0, 10.0));
+        assert_eq!(child_rect(&element, 2), (360.0, 0.0, 40.0, 10.0));
+    }
+
+    #[test]
+    fn clamps_flexed_sizes_to_max_width() {
+        let (element, _) = layout(
+            r#"<div id="f" style="display: flex"><div style="flex-grow: 1; max-width: 100px; height: 10px"></div><div style="flex-grow: 1; height: 10px"></div></div>"#,
+            400.0,
+            None,
+        );
+        assert_eq!(child_rect(&element, 0), (0.0, 0.0, 100.0, 10.0));
+        assert_eq!(child_rect(&element, 1), (100.0, 0.0, 300.0, 10.0));
+    }
+
+    #[test]
+    fn aligns_items_on_both_axes() {
+        let (element, _) = layout(
+            r#"<div id="f" style="display: flex; justify-content: center; align-items: center"><div style="width: 100px; height: 20px"></div><div style="width: 100px; height: 40px; align-self: flex-end"></div><div style="width: 100px"></div></div>"#,
+            400.0,
+            Some(100.0),
+        );
+        assert_eq!(child_rect(&element, 0), (50.0, 40.0, 100.0, 20.0));
+        assert_eq!(child_rect(&element, 1), (150.0, 60.0, 100.0, 40.0));
+        // a centered item without a height isn't stretched
+        assert_eq!(child_rect(&element, 2), (250.0, 50.0, 100.0, 0.0));
+    }
+
+    #[test]
+    fn stretches_items_to_the_line() {
+        let (element, _) = layout(
+            r#"<div id="f" style="display: flex"><div style="width: 100px; height: 30px"></div><div style="width: 100px"></div></div>"#,
+            400.0,
+            None,
+        );
+        assert_eq!(child_rect(&element, 1), (100.0, 0.0, 100.0, 30.0));
+    }
+
+    #[test]
+    fn places_items_by_order_and_reverse_direction() {
+        let (element, _) = layout(
+            r#"<div id="f" style="display: flex"><div style="width: 10px; height: 10px; order: 2"></div><div style="width: 20px; height: 10px; order: -1"></div><div style="width: 30px; height: 10px"></div></div>"#,
+            400.0,
+            None,
+        );
+        assert_eq!(child_rect(&element, 1).0, 0.0);
+        assert_eq!(child_rect(&element, 2).0, 20.0);
+        assert_eq!(child_rect(&element, 0).0, 50.0);
+
+        let (element, _) = layout(
+            r#"<div id="f" style="display: flex; flex-direction: row-reverse"><div style="width: 10px; height: 10px"></div><div style="width: 20px; height: 10px"></div></div>"#,
+            400.0,
+            None,
+        );
+        assert_eq!(child_rect(&element, 0).0, 390.0);
+        assert_eq!(child_rect(&element, 1).0, 370.0);
+    }
+
+    #[test]
+    fn puts_gaps_between_items_and_lines() {
+        let (element, flex) = layout(
+            r#"<div id="f" style="display: flex; flex-wrap: wrap; column-gap: 10px; row-gap: 5px"><div style="width: 100px; height: 10px"></div><div style="width: 100px; height: 10px"></div><div style="width: 100px; height: 10px"></div></div>"#,
+            250.0,
+            None,
+        );
+        assert_eq!(child_rect(&element, 0), (0.0, 0.0, 100.0, 10.0));
+        assert_eq!(child_rect(&element, 1), (110.0, 0.0, 100.0, 10.0));
+        assert_eq!(child_rect(&element, 2), (0.0, 15.0, 100.0, 10.0));
+        assert_eq!(flex.height, 25.0);
+    }
+
+    #[test]
+    fn lays_out_columns() {
+        let (element, flex) = layout(
+            r#"<div id="f" style="display: flex; flex-direction: column; row-gap: 10px"><div style="height: 20px"></div><div style="height: 30px; width: 50px; align-self: flex-end"></div></div>"#,
+            400.0,
+            None,
+        );
+        assert_eq!(child_rect(&element, 0), (0.0, 0.0, 400.0, 20.0));
+        assert_eq!(child_rect(&element, 1), (350.0, 30.0, 50.0, 30.0));
+        assert_eq!(flex.height, 60.0);
+    }
+
+    #[test]
+    fn lays_out_a_nested_flex_container() {
+        let (element, flex) = layout(
+            r#"<div id="f" style="display: flex"><div style="width: 100px; height: 10px"></div><div style="display: flex; flex-grow: 1; padding: 5px"><div style="flex-grow: 1; height: 20px"></div><div style="flex-grow: 1; height: 20px"></div></div></div>"#,
+            400.0,
+            None,
+        );
+        assert_eq!(child_rect(&element, 0), (0.0, 0.0, 100.0, 10.0));
+        assert_eq!(child_rect(&element, 1), (100.0, 0.0, 300.0, 30.0));
+        let nested = &element.children[1];
+        assert_eq!(child_rect(nested, 0), (105.0, 5.0, 145.0, 20.0));
+        assert_eq!(child_rect(nested, 1), (250.0, 5.0, 145.0, 20.0));
+        assert_eq!(flex.height, 30.0);
+    }
+}
@@ -0,0 +1,107 @@
+use crate::layout::Rect;
+
+#[derive(Clone, Debug)]
+pub struct PlacedFloat {
+    pub left: bool,
+    // the margin box
+    pub rect: Rect,
+}
+
+// The floats of a block formatting context, in document coordinates. Line
+// boxes and the border boxes of formatting context roots in it go around
+// them, CSS 2.1 §9.5.
+#[derive(Clone, Debug)]
+pub struct FloatContext {
+    pub floats: Vec<PlacedFloat>,
+    // a float is never above the ones before it
+    last_top: f64,
+}
+
+impl FloatContext {
+    pub fn new() -> FloatContext {
+        FloatContext {
+            floats: vec![],
+            last_top: f64::NEG_INFINITY,
+        }
+    }
+
+    fn intersecting<'a>(&'a self, y: f64, height: f64) -> impl Iterator<Item = &'a PlacedFloat> {
+        // a band without height still meets the floats it starts in
+        let bottom = y + height.max(0.01);
+        self.floats
+            .iter()
+            .filter(move |f| f.rect.height > 0.0 && f.rect.y < bottom && f.rect.y + f.rect.height > y)
+    }
+
+    // The left and right edges of the space next to the floats in the band
+    // from `y` down `height`, within the `width` wide container at `x`.
+    pub fn get_available_space(&self, y: f64, height: f64, x: f64, width: f64) -> (f64, f64) {
+        let mut left = x;
+        let mut right = x + width;
+        for float in self.intersecting(y, height) {
+            if float.left {
+                left = left.max(float.rect.x + float.rect.width);
+            } else {
+                right = right.min(float.rect.x);
+            }
+        }
+        return (left, right.max(left));
+    }
+
+    // The first float bottom below `y`, where the space may widen.
+    pub fn get_next_bottom(&self, y: f64) -> Option<f64> {
+        self.floats
+            .iter()
+            .map(|f| f.rect.y + f.rect.height)
+            .filter(|b| *b > y)
+            .fold(None, |m: Option<f64>, b| Some(m.map_or(b, |m| m.min(b))))
+    }
+
+    // Where the border box of an element with `clear` has to start, below
+    // the floats it clears.
+    pub fn get_clearance(&self, clear: &str) -> Option<f64> {
+        let (left, right) = match clear {
+            "left" | "inline-start" => (true, false),
+            "right" | "inline-end" => (false, true),
+            "both" => (true, true),
+            _ => return None,
+        };
+        self.floats
+            .iter()
+            .filter(|f| (f.left && left) || (!f.left && right))
+            .map(|f| f.rect.y + f.rect.height)
+            .fold(None, |m: Option<f64>, b| Some(m.map_or(b, |m| m.max(b))))
+    }
+
+    // The bottom of the lowest float, that formatting context roots grow
+    // to contain.
+    pub fn get_bottom(&self) -> Option<f64> {
+        self.get_clearance("both")
+    }
+
+    // Finds the highest position at or below `y` where a float with a
+    // `width` by `height` margin box fits, as far left or right as it goes.
+    pub fn find_position(&self, left: bool, width: f64, height: f64, y: f64, x: f64, container_width: f64) -> (f64, f64) {
+        let mut y = y.max(self.last_top);
+        loop {
+            let (start, end) = self.get_available_space(y, height, x, container_width);
+            let is_free = start == x && end == x + container_width;
+            if end - start >= width || is_free {
+                return (if left { start } else { end - width }, y);
+            }
+            match self.get_next_bottom(y) {
+                Some(bottom) => y = bottom,
+                None => return (if left { start } else { end - width }, y),
+            }
+        }
+    }
+
+    pub fn add(&mut self, left: bool, rect: Rect) {
+        self.last_top = self.last_top.max(rect.y);
+        self.floats.push(PlacedFloat { left, rect });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.floats.len() == 0
+    }
+}
@@ -0,0 +1,433 @@
+use crate::css::*;
+use crate::utils::*;
+use std::collections::HashMap;
+
+pub const PSEUDO_ELEMENTS: &[&str] = &["before", "after", "marker", "first-letter", "first-line"];
+
+// Splits `q::before` into `q` and `before`. The single colon syntax is
+// accepted for the pseudo-elements defined in CSS 2.
+pub fn split_pseudo_element(selector: &str) -> (&str, Option<&str>) {
+    if let Some(i) = selector.find("::") {
+        return (&selector[..i], Some(&selector[i + 2..]));
+    }
+
+    for name in &["before", "after", "first-letter", "first-line"] {
+        let legacy = format!(":{}", name);
+        if selector.ends_with(&legacy) {
+            return (&selector[..selector.len() - legacy.len()], Some(name));
+        }
+    }
+
+    return (selector, None);
+}
+
+// Counter values are kept within 32 bits, as browsers do, so adding to them
+// can't overflow.
+pub fn clamp_counter_value(value: i64) -> i64 {
+    value.max(i32::MIN as i64).min(i32::MAX as i64)
+}
+
+#[derive(Clone, Debug)]
+pub struct Counter {
+    pub value: i64,
+    // reversed counters count down, see `reversed(list-item)`
+    pub reversed: bool,
+}
+
+// Counters in scope at the current position of a document order traversal.
+// Each name maps to a stack of nested counter instances.
+#[derive(Clone, Debug)]
+pub struct CounterState {
+    pub counters: HashMap<String, Vec<Counter>>,
+    pub quote_depth: usize,
+}
+
+impl CounterState {
+    pub fn new() -> CounterState {
+        CounterState {
+            counters: HashMap::new(),
+            quote_depth: 0,
+        }
+    }
+
+    // Creates a new counter instance. The caller pops it once the scope,
+    // which is the element's following siblings and their descendants, ends.
+    pub fn reset(&mut self, name: &str, value: i64, reversed: bool) {
+        self.counters
+            .entry(name.to_string())
+            .or_insert(vec![])
+            .push(Counter { value, reversed });
+    }
+
+    pub fn set(&mut self, name: &str, value: i64) -> bool {
+        match self.counters.get_mut(name).and_then(|c| c.last_mut()) {
+            Some(c) => {
+                c.value = value;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn increment(&mut self, name: &str, by: i64) -> bool {
+        match self.counters.get_mut(name).and_then(|c| c.last_mut()) {
+            Some(c) => {
+                c.value = clamp_counter_value(c.value.saturating_add(by));
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn is_reversed(&self, name: &str) -> bool {
+        self.counters
+            .get(name)
+            .and_then(|c| c.last())
+            .map(|c| c.reversed)
+            .unwrap_or(false)
+    }
+
+    pub fn pop(&mut self, name: &str) {
+        match self.counters.get_mut(name) {
+            Some(c) => {
+                c.pop();
+            }
+            None => {}
+        }
+    }
+
+    pub fn value(&self, name: &str) -> i64 {
+        self.counters
+            .get(name)
+            .and_then(|c| c.last())
+            .map(|c| c.value)
+            .unwrap_or(0)
+    }
+
+    pub fn values(&self, name: &str) -> Vec<i64> {
+        match self.counters.get(name) {
+            Some(c) if c.len() > 0 => c.iter().map(|c| c.value).collect(),
+            _ => vec![0],
+        }
+    }
+}
+
+// Parses `counter-reset` like values: `a 1 b` is `[(a, Some(1)), (b, None)]`.
+pub fn parse_counter_list(value: &str) -> Vec<(String, Option<i64>)> {
+    let mut list: Vec<(String, Option<i64>)> = vec![];
+
+    if value.trim() == "none" {
+        return list;
+    }
+
+    for part in split_css_values(value) {
+        match part.parse::<i64>() {
+            Ok(n) => match list.last_mut() {
+                Some(last) => last.1 = Some(clamp_counter_value(n)),
+                None => {}
+            },
+            Err(_) => list.push((part, None)),
+        }
+    }
+
+    return list;
+}
+
+// `reversed(name)` in `counter-reset`
+pub fn parse_reversed_counter_name(name: &str) -> (String, bool) {
+    if name.starts_with("reversed(") && name.ends_with(')') {
+        (name["reversed(".len()..name.len() - 1].trim().to_string(), true)
+    } else {
+        (name.to_string(), false)
+    }
+}
+
+fn format_alphabetic(value: i64, alphabet: &[char]) -> Option<String> {
+    if value < 1 {
+        return None;
+    }
+
+    let base = alphabet.len() as i64;
+    let mut value = value;
+    let mut result: Vec<char> = vec![];
+    while value > 0 {
+        value -= 1;
+        result.insert(0, alphabet[(value % base) as usize]);
+        value /= base;
+    }
+
+    Some(result.into_iter().collect())
+}
+
+fn format_roman(value: i64) -> Option<String> {
+    if value < 1 || value > 3999 {
+        return None;
+    }
+
+    let numerals = [
+        (1000, "m"),
+        (900, "cm"),
+        (500, "d"),
+        (400, "cd"),
+        (100, "c"),
+        (90, "xc"),
+        (50, "l"),
+        (40, "xl"),
+        (10, "x"),
+        (9, "ix"),
+        (5, "v"),
+        (4, "iv"),
+        (1, "i"),
+    ];
+
+    let mut value = value;
+    let mut result = "".to_string();
+    for (n, numeral) in numerals.iter() {
+        while value >= *n {
+            result += numeral;
+            value -= n;
+        }
+    }
+
+    Some(result)
+}
+
+fn format_additive_hebrew(value: i64) -> Option<String> {
+    if value < 1 || value > 999 {
+        return None;
+    }
+
+    let hundreds = ["", "ק", "ר", "ש", "ת", "תק", "תר", "תש", "תת", "תתק"];
+    let tens = ["", "י", "כ", "ל", "מ", "נ", "ס", "ע", "פ", "צ"];
+    let ones = ["", "א", "ב", "ג", "ד", "ה", "ו", "ז", "ח", "ט"];
+
+    let rest = value % 100;
+    let below_hundred = match rest {
+        // written as 9 + 6 and 9 + 7 to avoid spelling the name of god
+        15 => "טו".to_string(),
+        16 => "טז".to_string(),
+        _ => tens[(rest / 10) as usize].to_string() + ones[(rest % 10) as usize],
+    };
+
+    Some(hundreds[(value / 100) as usize].to_string() + &below_hundred)
+}
+
+const LATIN: &[char] = &[
+    'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i', 'j', 'k', 'l', 'm', 'n', 'o', 'p', 'q', 'r', 's',
+    't', 'u', 'v', 'w', 'x', 'y', 'z',
+];
+
+const GREEK: &[char] = &[
+    'α', 'β', 'γ', 'δ', 'ε', 'ζ', 'η', 'θ', 'ι', 'κ', 'λ', 'μ', 'ν', 'ξ', 'ο', 'π', 'ρ', 'σ', 'τ',
+    'υ', 'φ', 'χ', 'ψ', 'ω',
+];
+
+pub fn is_bullet_style(style: &str) -> bool {
+    match style {
+        "disc" | "circle" | "square" | "disclosure-open" | "disclosure-closed" => true,
+        _ => false,
+    }
+}
+
+// Formats a counter value in one of the predefined counter styles. Values a
+// style can't represent and unknown styles fall back to decimal.
+pub fn format_counter(value: i64, style: &str) -> String {
+    let formatted = match style {
+        "none" => Some("".to_string()),
+        "disc" => Some("•".to_string()),
+        "circle" => Some("◦".to_string()),
+        "square" => Some("▪".to_string()),
+        "disclosure-open" => Some("▾".to_string()),
+        "disclosure-closed" => Some("▸".to_string()),
+        "decimal-leading-zero" => Some(if value < 0 {
+            format!("-{:02}", value.unsigned_abs())
+        } else {
+            format!("{:02}", value)
+        }),
+        "lower-alpha" | "lower-latin" => format_alphabetic(value, LATIN),
+        "upper-alpha" | "upper-latin" => format_alphabetic(value, LATIN).map(|v| v.to_uppercase()),
+        "lower-greek" => format_alphabetic(value, GREEK),
+        "lower-roman" => format_roman(value),
+        "upper-roman" => format_roman(value).map(|v| v.to_uppercase()),
+        "hebrew" => format_additive_hebrew(value),
+        _ => None,
+    };
+
+    formatted.unwrap_or(value.to_string())
+}
+
+// The text of a `::marker` with `content: normal`.
+pub fn format_marker(counters: &CounterState, list_style_type: &str) -> Option<String> {
+    let list_style_type = list_style_type.trim();
+
+    if list_style_type == "none" {
+        return None;
+    }
+    if is_css_string(list_style_type) {
+        return Some(parse_css_string(list_style_type));
+    }
+    if is_bullet_style(list_style_type) {
+        return Some(format_counter(0, list_style_type) + " ");
+    }
+
+    Some(format_counter(counters.value("list-item"), list_style_type) + ". ")
+}
+
+// Resolves backslash escapes like `\201C` or `\"` in a CSS string.
+pub fn unescape_css_string(value: &str) -> String {
+    let mut result = "".to_string();
+    let mut chars = value.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+
+        let mut hex = "".to_string();
+        while hex.len() < 6 && chars.peek().map(|c| c.is_ascii_hexdigit()).unwrap_or(false) {
+            hex.push(chars.next().unwrap());
+        }
+
+        if hex.len() > 0 {
+            // a single whitespace after a hex escape belongs to the escape
+            if chars.peek().map(|c| c.is_whitespace()).unwrap_or(false) {
+                chars.next();
+            }
+            let code = u32::from_str_radix(&hex, 16).unwrap_or(0xFFFD);
+            result.push(std::char::from_u32(code).unwrap_or('\u{FFFD}'));
+        } else if let Some(next) = chars.next() {
+            if next != '\n' {
+                result.push(next);
+            }
+        }
+    }
+
+    return result;
+}
+
+pub fn is_css_string(value: &str) -> bool {
+    value.len() >= 2
+        && ((value.starts_with('"') && value.ends_with('"'))
+            || (value.starts_with('\'') && value.ends_with('\'')))
+}
+
+pub fn parse_css_string(value: &str) -> String {
+    unescape_css_string(&value[1..value.len() - 1])
+}
+
+const DEFAULT_QUOTES: &[(&str, &str)] = &[("\u{201C}", "\u{201D}"), ("\u{2018}", "\u{2019}")];
+
+pub fn parse_quotes(value: &str) -> Vec<(String, String)> {
+    match value.trim() {
+        "none" => vec![],
+        "auto" | "" => DEFAULT_QUOTES
+            .iter()
+            .map(|q| (q.0.to_string(), q.1.to_string()))
+            .collect(),
+        v => {
+            let strings = split_css_values(v)
+                .iter()
+                .filter(|s| is_css_string(s))
+                .map(|s| parse_css_string(s))
+                .collect::<Vec<String>>();
+            strings
+                .chunks(2)
+                .filter(|c| c.len() == 2)
+                .map(|c| (c[0].clone(), c[1].clone()))
+                .collect()
+        }
+    }
+}
+
+fn get_function_arguments<'a>(value: &'a str, name: &str) -> Option<Vec<String>> {
+    if value.starts_with(&format!("{}(", name)) && value.ends_with(')') {
+        let inner = &value[name.len() + 1..value.len() - 1];
+        Some(split_css_list(inner))
+    } else {
+        None
+    }
+}
+
+// Builds the text of a `content` value. Returns None for `none` and `normal`,
+// which generate no box for `::before` and `::after`.
+pub fn generate_content(
+    content: &str,
+    attributes: &HashMap<String, String>,
+    counters: &mut CounterState,
+    quotes: &Vec<(String, String)>,
+) -> Option<String> {
+    let content = content.replace("!important", "");
+    let content = content.trim();
+
+    if content == "none" || content == "normal" || content == "" {
+        return None;
+    }
+
+    let mut text = "".to_string();
+
+    // the alternative text after `/` is for accessibility only
+    let parts = split_css_values(content);
+    let parts = match parts.iter().position(|p| p == "/") {
+        Some(i) => parts[..i].to_vec(),
+        None => parts,
+    };
+
+    for part in parts {
+        if is_css_string(&part) {
+            text += &parse_css_string(&part);
+        } else if let Some(args) = get_function_arguments(&part, "attr") {
+            let name = args.get(0).map(|a| split_css_values(a)[0].clone()).unwrap_or(S(""));
+            match attributes.get(&name) {
+                Some(v) => text += v,
+                None => match args.get(1) {
+                    Some(fallback) if is_css_string(fallback) => text += &parse_css_string(fallback),
+                    _ => {}
+                },
+            }
+        } else if let Some(args) = get_function_arguments(&part, "counter") {
+            let name = args.get(0).cloned().unwrap_or(S(""));
+            let style = args.get(1).cloned().unwrap_or(S("decimal"));
+            text += &format_counter(counters.value(&name), &style);
+        } else if let Some(args) = get_function_arguments(&part, "counters") {
+            let name = args.get(0).cloned().unwrap_or(S(""));
+            let separator = args
+                .get(1)
+                .filter(|s| is_css_string(s))
+                .map(|s| parse_css_string(s))
+                .unwrap_or(S(""));
+            let style = args.get(2).cloned().unwrap_or(S("decimal"));
+            text += &counters
+                .values(&name)
+                .iter()
+                .map(|v| format_counter(*v, &style))
+                .collect::<Vec<String>>()
+                .join(&separator);
+        } else if part == "open-quote" {
+            if quotes.len() > 0 {
+                let depth = usize::min(counters.quote_depth, quotes.len() - 1);
+                text += &quotes[depth].0;
+            }
+            counters.quote_depth += 1;
+        } else if part == "close-quote" {
+            if counters.quote_depth > 0 {
+                counters.quote_depth -= 1;
+                if quotes.len() > 0 {
+                    let depth = usize::min(counters.quote_depth, quotes.len() - 1);
+                    text += &quotes[depth].1;
+                }
+            }
+        } else if part == "no-open-quote" {
+            counters.quote_depth += 1;
+        } else if part == "no-close-quote" {
+            if counters.quote_depth > 0 {
+                counters.quote_depth -= 1;
+            }
+        } else {
+            // images and unknown values are not supported
+            println!("Unsupported content value {}", part);
+        }
+    }
+
+    return Some(text);
+}
@@ -0,0 +1,1272 @@
+use crate::css::*;
+use crate::flex_layout::*;
+use crate::floats::*;
+use crate::html::*;
+use crate::inline_layout::*;
+use crate::layout::*;
+use crate::length::*;
+use crate::overflow::is_scroll_container;
+use crate::utils::*;
+use std::collections::HashMap;
+use std::ops::Range;
+
+// The most times `repeat()` repeats its tracks, so a huge count can't take
+// all the memory.
+const MAX_REPETITIONS: usize = 1000;
+
+// How far line numbers and spans go, as in browsers, so a huge one can't
+// make a grid of that many tracks.
+const MAX_LINE: i32 = 10000;
+
+pub fn is_grid_container(computed_style: &ComputedStyle) -> bool {
+    computed_style.display == "grid" || computed_style.display == "inline-grid"
+}
+
+pub struct GridLayout {
+    // the content height
+    pub height: f64,
+    pub baseline: Option<f64>,
+}
+
+#[derive(Clone, Debug)]
+enum Breadth {
+    Length(LengthPercentage),
+    Flex(f64),
+    Auto,
+    MinContent,
+    MaxContent,
+    FitContent(LengthPercentage),
+}
+
+#[derive(Clone, Debug)]
+struct TrackSize {
+    min: Breadth,
+    max: Breadth,
+}
+
+#[derive(Clone, Debug)]
+enum TrackEntry {
+    Names(Vec<String>),
+    Track(TrackSize),
+    // `repeat(auto-fill, ...)`, or `repeat(auto-fit, ...)` when true
+    AutoRepeat(bool, Vec<TrackEntry>),
+}
+
+// A track of the implicit grid as it gets sized, CSS Grid §11.4.
+#[derive(Clone, Debug)]
+struct Track {
+    size: TrackSize,
+    base: f64,
+    limit: f64,
+    // an empty `auto-fit` repetition, which takes no room and no gaps
+    collapsed: bool,
+}
+
+impl Track {
+    fn new(size: TrackSize) -> Track {
+        Track {
+            size,
+            base: 0.0,
+            limit: 0.0,
+            collapsed: false,
+        }
+    }
+
+    fn flex(&self) -> Option<f64> {
+        match self.size.max {
+            Breadth::Flex(f) if !self.collapsed => Some(f),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum GridLine {
+    Auto,
+    Line(i32),
+    Span(i32),
+    Named(String),
+}
+
+// Where an item goes along an axis, between two lines counted from the
+// start of the explicit grid, or how many tracks it spans when it is placed
+// automatically.
+#[derive(Clone, Copy, Debug)]
+enum AxisPlacement {
+    Definite(i32, i32),
+    Auto(i32),
+}
+
+struct GridItem {
+    // the children the item is made of, a run of them for text directly in
+    // the container
+    range: Range<usize>,
+    anonymous: bool,
+    order: i32,
+    justify: String,
+    align: String,
+    // the tracks it spans, once placed
+    columns: (usize, usize),
+    rows: (usize, usize),
+}
+
+// The explicit tracks of an axis and the names of the lines around them,
+// and the tracks an `auto-fit` repetition gave.
+struct ExplicitTracks {
+    tracks: Vec<TrackSize>,
+    names: Vec<Vec<String>>,
+    auto_fit: Option<Range<usize>>,
+}
+
+fn get_function_arguments<'a>(value: &'a str, name: &str) -> Option<&'a str> {
+    if value.starts_with(name) && value[name.len()..].starts_with('(') && value.ends_with(')') {
+        return Some(&value[name.len() + 1..value.len() - 1]);
+    }
+    return None;
+}
+
+fn parse_breadth(value: &str) -> Option<Breadth> {
+    match value {
+        "auto" => Some(Breadth::Auto),
+        "min-content" => Some(Breadth::MinContent),
+        "max-content" => Some(Breadth::MaxContent),
+        _ if value.ends_with("fr") => match value[..value.len() - 2].parse::<f64>() {
+            Ok(f) if f >= 0.0 => Some(Breadth::Flex(f)),
+            _ => None,
+        },
+        _ => parse_length_percentage(value).ok().map(|l| Breadth::Length(l)),
+    }
+}
+
+fn parse_track_size(value: &str) -> Option<TrackSize> {
+    match get_function_arguments(value, "minmax") {
+        Some(arguments) => {
+            let arguments = split_css_list(arguments);
+            if arguments.len() != 2 {
+                return None;
+            }
+            let min = match parse_breadth(&arguments[0])? {
+                // a flexible minimum is invalid
+                Breadth::Flex(_) => return None,
+                min => min,
+            };
+            return Some(TrackSize {
+                min,
+                max: parse_breadth(&arguments[1])?,
+            });
+        }
+        None => {}
+    }
+    match get_function_arguments(value, "fit-content") {
+        Some(argument) => {
+            return Some(TrackSize {
+                min: Breadth::Auto,
+                max: Breadth::FitContent(parse_length_percentage(argument.trim()).ok()?),
+            });
+        }
+        None => {}
+    }
+    match parse_breadth(value)? {
+        // `1fr` is `minmax(auto, 1fr)`
+        Breadth::Flex(f) => Some(TrackSize {
+            min: Breadth::Auto,
+            max: Breadth::Flex(f),
+        }),
+        breadth => Some(TrackSize {
+            min: breadth.clone(),
+            max: breadth,
+        }),
+    }
+}
+
+// Parses `grid-template-columns`, `grid-template-rows` and the
+// `grid-auto-*` lists, with the integer repetitions written out.
+fn parse_track_list(value: &str) -> Option<Vec<TrackEntry>> {
+    if value == "none" {
+        return Some(vec![]);
+    }
+    let mut entries = vec![];
+    // line names are in brackets, split where there are spaces between them
+    let mut names: Option<Vec<String>> = None;
+    for part in split_css_values(value) {
+        if part.starts_with('[') || names.is_some() {
+            let line_names = names.get_or_insert(vec![]);
+            for name in part.trim_start_matches('[').trim_end_matches(']').split_whitespace() {
+                line_names.push(name.to_string());
+            }
+            if part.ends_with(']') {
+                entries.push(TrackEntry::Names(names.take().unwrap()));
+            }
+            continue;
+        }
+        match get_function_arguments(&part, "repeat") {
+            Some(arguments) => {
+                let arguments = split_css_list(arguments);
+                if arguments.len() != 2 {
+                    return None;
+                }
+                let repeated = parse_track_list(&arguments[1])?;
+                match arguments[0].as_str() {
+                    "auto-fill" => entries.push(TrackEntry::AutoRepeat(false, repeated)),
+                    "auto-fit" => entries.push(TrackEntry::AutoRepeat(true, repeated)),
+                    count => {
+                        let count: usize = count.parse().ok()?;
+                        for _ in 0..count.min(MAX_REPETITIONS) {
+                            entries.extend(repeated.iter().cloned());
+                        }
+                    }
+                }
+            }
+            None => entries.push(TrackEntry::Track(parse_track_size(&part)?)),
+        }
+    }
+    return Some(entries);
+}
+
+fn get_track_list(computed_style: &ComputedStyle, name: &str) -> Vec<TrackEntry> {
+    let value = get_value(computed_style, name, "none");
+    match parse_track_list(value) {
+        Some(entries) => entries,
+        None => {
+            println!("Invalid value for {}: {}", name, value);
+            vec![]
+        }
+    }
+}
+
+fn resolve_breadth(breadth: &Breadth, context: &LengthContext, basis: Option<f64>) -> Option<f64> {
+    match breadth {
+        Breadth::Length(l) if !l.has_percentage() || basis.is_some() => {
+            Some(l.resolve(context, basis.unwrap_or(0.0)).max(0.0))
+        }
+        _ => None,
+    }
+}
+
+// Writes out the track list, with an automatic repetition repeated as many
+// times as fits in `available`, CSS Grid §7.2.3.2, or once when the space
+// isn't known.
+fn expand_track_list(
+    entries: &Vec<TrackEntry>,
+    available: Option<f64>,
+    gap: f64,
+    context: &LengthContext,
+) -> ExplicitTracks {
+    let mut explicit = ExplicitTracks {
+        tracks: vec![],
+        names: vec![vec![]],
+        auto_fit: None,
+    };
+    let add_entries = |explicit: &mut ExplicitTracks, entries: &Vec<TrackEntry>| {
+        for entry in entries {
+            match entry {
+                TrackEntry::Names(names) => explicit.names.last_mut().unwrap().extend(names.iter().cloned()),
+                TrackEntry::Track(size) => {
+                    explicit.tracks.push(size.clone());
+                    explicit.names.push(vec![]);
+                }
+                TrackEntry::AutoRepeat(_, _) => {}
+            }
+        }
+    };
+
+    // a track counts with its fixed maximum, or its fixed minimum
+    let fixed_size = |size: &TrackSize| {
+        resolve_breadth(&size.max, context, available)
+            .or(resolve_breadth(&size.min, context, available))
+            .unwrap_or(0.0)
+    };
+    let tracks_of = |entries: &Vec<TrackEntry>| -> Vec<TrackSize> {
+        entries
+            .iter()
+            .filter_map(|e| match e {
+                TrackEntry::Track(size) => Some(size.clone()),
+                _ => None,
+            })
+            .collect()
+    };
+    let fixed_tracks = tracks_of(entries);
+    let fixed_total: f64 = fixed_tracks.iter().map(|t| fixed_size(t)).sum();
+
+    for entry in entries {
+        match entry {
+            TrackEntry::AutoRepeat(fit, repeated) => {
+                let repeated_tracks = tracks_of(repeated);
+                let repeated_total: f64 = repeated_tracks.iter().map(|t| fixed_size(t)).sum();
+                let total = |count: usize| {
+                    let track_count = fixed_tracks.len() + count * repeated_tracks.len();
+                    fixed_total + count as f64 * repeated_total + gap * track_count.saturating_sub(1) as f64
+                };
+                let mut count = 1;
+                match available {
+                    Some(available) if repeated_total + gap > 0.0 => {
+                        while count < MAX_REPETITIONS && total(count + 1) <= available {
+                            count += 1;
+                        }
+                    }
+                    _ => {}
+                }
+                let start = explicit.tracks.len();
+                for _ in 0..count {
+                    add_entries(&mut explicit, repeated);
+                }
+                if *fit {
+                    explicit.auto_fit = Some(start..explicit.tracks.len());
+                }
+            }
+            _ => add_entries(&mut explicit, &vec![entry.clone()]),
+        }
+    }
+    return explicit;
+}
+
+// The rows of cell names of `grid-template-areas`, `None` for the cells
+// without one. Rows of different lengths make the value invalid.
+fn parse_template_areas(value: &str) -> Vec<Vec<Option<String>>> {
+    if value == "none" {
+        return vec![];
+    }
+    let mut rows = vec![];
+    for part in split_css_values(value) {
+        let row: Vec<Option<String>> = part
+            .trim_matches(|c| c == '"' || c == '\'')
+            .split_whitespace()
+            .map(|cell| if cell.chars().all(|c| c == '.') { None } else { Some(cell.to_string()) })
+            .collect();
+        rows.push(row);
+    }
+    if rows.iter().any(|r: &Vec<Option<String>>| r.len() != rows[0].len() || r.len() == 0) {
+        println!("Invalid value for grid-template-areas: {}", value);
+        return vec![];
+    }
+    return rows;
+}
+
+// The rows and columns each named area spans.
+fn get_named_areas(areas: &Vec<Vec<Option<String>>>) -> HashMap<String, ((usize, usize), (usize, usize))> {
+    let mut named: HashMap<String, ((usize, usize), (usize, usize))> = HashMap::new();
+    for (row, cells) in areas.iter().enumerate() {
+        for (column, cell) in cells.iter().enumerate() {
+            let name = match cell {
+                Some(n) => n,
+                None => continue,
+            };
+            let area = named.entry(name.clone()).or_insert(((row, row + 1), (column, column + 1)));
+            (area.0).0 = (area.0).0.min(row);
+            (area.0).1 = (area.0).1.max(row + 1);
+            (area.1).0 = (area.1).0.min(column);
+            (area.1).1 = (area.1).1.max(column + 1);
+        }
+    }
+    return named;
+}
+
+fn parse_grid_line(value: &str) -> GridLine {
+    let parts = split_css_values(value);
+    match parts.iter().map(|p| p.as_str()).collect::<Vec<&str>>().as_slice() {
+        ["auto"] => GridLine::Auto,
+        ["span", n] | [n, "span"] => GridLine::Span(n.parse::<i32>().unwrap_or(1).max(1).min(MAX_LINE)),
+        [n] => match n.parse::<i32>() {
+            Ok(0) => GridLine::Auto,
+            Ok(n) => GridLine::Line(n.max(-MAX_LINE).min(MAX_LINE)),
+            Err(_) => GridLine::Named(n.to_string()),
+        },
+        // the nth line of a name counts as its first
+        [a, b] => match (a.parse::<i32>(), b.parse::<i32>()) {
+            (Ok(_), Err(_)) => GridLine::Named(b.to_string()),
+            (Err(_), Ok(_)) => GridLine::Named(a.to_string()),
+            _ => GridLine::Auto,
+        },
+        _ => GridLine::Auto,
+    }
+}
+
+// The line a name refers to on the `side` of an area: the implicit
+// `name-start` or `name-end` line of a named area, or else the first line
+// with that name.
+fn find_named_line(names: &Vec<Vec<String>>, name: &str, side: &str) -> Option<i32> {
+    let area_line = format!("{}-{}", name, side);
+    names
+        .iter()
+        .position(|n| n.contains(&area_line))
+        .or(names.iter().position(|n| n.iter().any(|n| n == name)))
+        .map(|i| i as i32)
+}
+
+fn resolve_axis_placement(start: &GridLine, end: &GridLine, names: &Vec<Vec<String>>) -> AxisPlacement {
+    let explicit_lines = names.len() as i32;
+    let line = |l: &GridLine, side: &str| -> Option<i32> {
+        match l {
+            GridLine::Line(n) if *n > 0 => Some(n - 1),
+            // negative lines count back from the end of the explicit grid
+            GridLine::Line(n) => Some(explicit_lines + n),
+            GridLine::Named(name) => find_named_line(names, name, side),
+            _ => None,
+        }
+    };
+    let span = |l: &GridLine| match l {
+        GridLine::Span(n) => *n,
+        _ => 1,
+    };
+    match (line(start, "start"), line(end, "end")) {
+        (Some(a), Some(b)) if a == b => AxisPlacement::Definite(a, a + 1),
+        (Some(a), Some(b)) => AxisPlacement::Definite(a.min(b), a.max(b)),
+        (Some(a), None) => AxisPlacement::Definite(a, a + span(end)),
+        (None, Some(b)) => AxisPlacement::Definite(b - span(start), b),
+        (None, None) => AxisPlacement::Auto(match start {
+            GridLine::Span(n) => *n,
+            _ => span(end),
+        }),
+    }
+}
+
+fn overlaps(a: (i32, i32), b: (i32, i32)) -> bool {
+    a.0 < b.1 && b.0 < a.1
+}
+
+// Places the items on the grid, CSS Grid §8.5. The flow goes along the
+// minor axis and starts new tracks along the major one; items fixed on both
+// axes go first, then those fixed on the major axis, then the rest fill the
+// free cells after the cursor, or from the start when `dense`.
+fn place_items(
+    placements: &Vec<(AxisPlacement, AxisPlacement)>,
+    minor_count: i32,
+    dense: bool,
+) -> Vec<((i32, i32), (i32, i32))> {
+    let mut placed: Vec<Option<((i32, i32), (i32, i32))>> = vec![None; placements.len()];
+    let mut occupied: Vec<((i32, i32), (i32, i32))> = vec![];
+    let fits = |occupied: &Vec<((i32, i32), (i32, i32))>, minor: (i32, i32), major: (i32, i32)| {
+        !occupied.iter().any(|(m, n)| overlaps(*m, minor) && overlaps(*n, major))
+    };
+
+    // the minor tracks the flow goes through
+    let mut minor_start = 0;
+    let mut minor_end = minor_count;
+    let mut major_start = 0;
+    for (minor, major) in placements {
+        match minor {
+            AxisPlacement::Definite(a, b) => {
+                minor_start = minor_start.min(*a);
+                minor_end = minor_end.max(*b);
+            }
+            AxisPlacement::Auto(span) => minor_end = minor_end.max(minor_start + span),
+        }
+        match major {
+            AxisPlacement::Definite(a, _) => major_start = major_start.min(*a),
+            _ => {}
+        }
+    }
+
+    for (i, placement) in placements.iter().enumerate() {
+        match placement {
+            (AxisPlacement::Definite(a, b), AxisPlacement::Definite(c, d)) => {
+                placed[i] = Some(((*a, *b), (*c, *d)));
+                occupied.push(((*a, *b), (*c, *d)));
+            }
+            _ => {}
+        }
+    }
+
+    let mut cursors: HashMap<i32, i32> = HashMap::new();
+    for (i, placement) in placements.iter().enumerate() {
+        match placement {
+            (AxisPlacement::Auto(span), AxisPlacement::Definite(c, d)) => {
+                let cursor = cursors.entry(*c).or_insert(minor_start);
+                let mut start = if dense { minor_start } else { *cursor };
+                while !fits(&occupied, (start, start + span), (*c, *d)) {
+                    start += 1;
+                }
+                *cursor = start + span;
+                placed[i] = Some(((start, start + span), (*c, *d)));
+                occupied.push(((start, start + span), (*c, *d)));
+            }
+            _ => {}
+        }
+    }
+
+    let mut cursor = (major_start, minor_start);
+    for (i, placement) in placements.iter().enumerate() {
+        if placed[i].is_some() {
+            continue;
+        }
+        if dense {
+            cursor = (major_start, minor_start);
+        }
+        let area = match placement.0 {
+            AxisPlacement::Definite(a, b) => {
+                if a < cursor.1 && !dense {
+                    cursor.0 += 1;
+                }
+                cursor.1 = a;
+                let span = placement.1.span();
+                while !fits(&occupied, (a, b), (cursor.0, cursor.0 + span)) {
+                    cursor.0 += 1;
+                }
+                ((a, b), (cursor.0, cursor.0 + span))
+            }
+            AxisPlacement::Auto(minor_span) => {
+                let span = placement.1.span();
+                loop {
+                    // an item wider than the grid goes at its start
+                    let overflows = cursor.1 + minor_span > minor_end && cursor.1 > minor_start;
+                    if overflows {
+                        cursor = (cursor.0 + 1, minor_start);
+                        continue;
+                    }
+                    let minor = (cursor.1, cursor.1 + minor_span);
+                    let major = (cursor.0, cursor.0 + span);
+                    if fits(&occupied, minor, major) {
+                        break (minor, major);
+                    }
+                    cursor.1 += 1;
+                }
+            }
+        };
+        cursor.1 = (area.0).1;
+        placed[i] = Some(area);
+        occupied.push(area);
+    }
+    return placed.into_iter().map(|p| p.unwrap()).collect();
+}
+
+impl AxisPlacement {
+    fn span(&self) -> i32 {
+        match self {
+            AxisPlacement::Definite(a, b) => b - a,
+            AxisPlacement::Auto(span) => *span,
+        }
+    }
+}
+
+fn get_self_alignment(computed_style: Option<&ComputedStyle>, name: &str, default: &str) -> String {
+    let value = computed_style.map_or("auto", |c| get_value(c, name, "auto"));
+    match value {
+        "auto" => default.to_string(),
+        value => value.to_string(),
+    }
+}
+
+fn collect_items(
+    tree: &mut Vec<DomElement>,
+    measure_text: &dyn Fn(String, f64, String) -> (f64, f64),
+    container: &InlineContainer,
+    container_style: &ComputedStyle,
+) -> Vec<GridItem> {
+    // `legacy` and `normal` stretch the items
+    let default_alignment = |value: &str| match value {
+        "legacy" | "normal" => S("stretch"),
+        value => value.to_string(),
+    };
+    let justify_items = default_alignment(get_value(container_style, "justify-items", "legacy"));
+    let align_items = default_alignment(get_value(container_style, "align-items", "normal"));
+
+    let mut items = vec![];
+    let mut i = 0;
+    while i < tree.len() {
+        if !is_in_flow_child(&tree[i]) {
+            let start = i;
+            while i < tree.len() && !is_in_flow_child(&tree[i]) {
+                i += 1;
+            }
+            if has_text(&tree[start..i]) {
+                items.push(GridItem {
+                    range: start..i,
+                    anonymous: true,
+                    order: 0,
+                    justify: justify_items.clone(),
+                    align: align_items.clone(),
+                    columns: (0, 0),
+                    rows: (0, 0),
+                });
+            } else {
+                // out-of-flow boxes keep the start of the container as
+                // their static position
+                let mut lines_container = container.clone();
+                lines_container.height = None;
+                layout_inline_content(tree, start..i, &lines_container, &mut FloatContext::new(), measure_text);
+            }
+            continue;
+        }
+
+        let computed_style = tree[i].computed_style.as_mut().unwrap();
+        resolve_box_edges(computed_style, container.width);
+        let computed_style = tree[i].computed_style.as_ref();
+        items.push(GridItem {
+            range: i..i + 1,
+            anonymous: false,
+            order: computed_style.map_or(0, |c| get_value(c, "order", "0").parse().unwrap_or(0)),
+            justify: default_alignment(&get_self_alignment(computed_style, "justify-self", &justify_items)),
+            align: default_alignment(&get_self_alignment(computed_style, "align-self", &align_items)),
+            columns: (0, 0),
+            rows: (0, 0),
+        });
+        i += 1;
+    }
+    items.sort_by_key(|i| i.order);
+    return items;
+}
+
+// Sets up the grid of a container whose content box is `width` by `height`
+// where known: the explicit tracks from the templates, the items placed
+// on them, and the implicit tracks around. Returns the columns and rows.
+fn build_grid(
+    tree: &Vec<DomElement>,
+    container_style: &ComputedStyle,
+    items: &mut Vec<GridItem>,
+    width: Option<f64>,
+    height: Option<f64>,
+) -> (Vec<Track>, Vec<Track>) {
+    let context = &container_style.length_context;
+    let column_gap = resolve_gap(container_style, "column-gap", width);
+    let row_gap = resolve_gap(container_style, "row-gap", height);
+    let mut columns = expand_track_list(
+        &get_track_list(container_style, "grid-template-columns"),
+        width,
+        column_gap,
+        context,
+    );
+    let mut rows = expand_track_list(&get_track_list(container_style, "grid-template-rows"), height, row_gap, context);
+    let auto_track = |name: &str| -> Vec<TrackSize> {
+        let sizes: Vec<TrackSize> = get_track_list(container_style, name)
+            .into_iter()
+            .filter_map(|e| match e {
+                TrackEntry::Track(size) => Some(size),
+                _ => None,
+            })
+            .collect();
+        if sizes.len() == 0 {
+            return vec![parse_track_size("auto").unwrap()];
+        }
+        sizes
+    };
+    let auto_columns = auto_track("grid-auto-columns");
+    let auto_rows = auto_track("grid-auto-rows");
+
+    // named areas add to the explicit grid and name the lines around them
+    let areas = parse_template_areas(get_value(container_style, "grid-template-areas", "none"));
+    let area_columns = areas.get(0).map_or(0, |r| r.len());
+    for (explicit, count, auto) in [(&mut columns, area_columns, &auto_columns), (&mut rows, areas.len(), &auto_rows)].iter_mut() {
+        while explicit.tracks.len() < *count {
+            explicit.tracks.push(auto[0].clone());
+            explicit.names.push(vec![]);
+        }
+    }
+    for (name, ((row_start, row_end), (column_start, column_end))) in get_named_areas(&areas) {
+        rows.names[row_start].push(format!("{}-start", name));
+        rows.names[row_end].push(format!("{}-end", name));
+        columns.names[column_start].push(format!("{}-start", name));
+        columns.names[column_end].push(format!("{}-end", name));
+    }
+
+    let flow = get_value(container_style, "grid-auto-flow", "row");
+    let column_flow = flow.contains("column");
+    let dense = flow.contains("dense");
+    let placements: Vec<(AxisPlacement, AxisPlacement)> = items
+        .iter()
+        .map(|item| {
+            if item.anonymous {
+                return (AxisPlacement::Auto(1), AxisPlacement::Auto(1));
+            }
+            let computed_style = tree[item.range.start].computed_style.as_ref().unwrap();
+            let line = |name: &str| parse_grid_line(get_value(computed_style, name, "auto"));
+            let column = resolve_axis_placement(&line("grid-column-start"), &line("grid-column-end"), &columns.names);
+            let row = resolve_axis_placement(&line("grid-row-start"), &line("grid-row-end"), &rows.names);
+            if column_flow {
+                (row, column)
+            } else {
+                (column, row)
+            }
+        })
+        .collect();
+    let minor_count = if column_flow { rows.tracks.len() } else { columns.tracks.len() };
+    let areas: Vec<((i32, i32), (i32, i32))> = place_items(&placements, minor_count as i32, dense)
+        .into_iter()
+        .map(|(minor, major)| if column_flow { (major, minor) } else { (minor, major) })
+        .collect();
+
+    // the implicit grid reaches every item, with tracks before the explicit
+    // ones when an item asks for lines before the first
+    let column_range = areas.iter().fold((0, columns.tracks.len() as i32), |(s, e), a| (s.min((a.0).0), e.max((a.0).1)));
+    let row_range = areas.iter().fold((0, rows.tracks.len() as i32), |(s, e), a| (s.min((a.1).0), e.max((a.1).1)));
+    for (item, area) in items.iter_mut().zip(areas.iter()) {
+        item.columns = (((area.0).0 - column_range.0) as usize, ((area.0).1 - column_range.0) as usize);
+        item.rows = (((area.1).0 - row_range.0) as usize, ((area.1).1 - row_range.0) as usize);
+    }
+
+    let make_tracks = |explicit: &ExplicitTracks, auto: &Vec<TrackSize>, (start, end): (i32, i32)| -> Vec<Track> {
+        let explicit_count = explicit.tracks.len() as i32;
+        let count = auto.len() as i32;
+        (start..end)
+            .map(|t| {
+                let size = if t < 0 {
+                    auto[((count - (-t) % count) % count) as usize].clone()
+                } else if t >= explicit_count {
+                    auto[((t - explicit_count) % count) as usize].clone()
+                } else {
+                    explicit.tracks[t as usize].clone()
+                };
+                let mut track = Track::new(size);
+                track.collapsed = t >= 0 && explicit.auto_fit.as_ref().map_or(false, |r| r.contains(&(t as usize)));
+                track
+            })
+            .collect()
+    };
+    let mut column_tracks = make_tracks(&columns, &auto_columns, column_range);
+    let mut row_tracks = make_tracks(&rows, &auto_rows, row_range);
+
+    // an `auto-fit` track only stays when an item is in it
+    for (tracks, used) in [
+        (&mut column_tracks, items.iter().map(|i| i.columns).collect::<Vec<(usize, usize)>>()),
+        (&mut row_tracks, items.iter().map(|i| i.rows).collect::<Vec<(usize, usize)>>()),
+    ]
+    .iter_mut()
+    {
+        for (t, track) in tracks.iter_mut().enumerate() {
+            if track.collapsed && used.iter().any(|(s, e)| *s <= t && t < *e) {
+                track.collapsed = false;
+            }
+        }
+    }
+    return (column_tracks, row_tracks);
+}
+
+// Sizes the tracks of an axis, CSS Grid §11.4 to §11.8. Each item spans
+// `tracks[span.0..span.1]` and contributes its outer size at its minimum
+// size, for `auto` minimums, and its smallest and largest outer size.
+// Without `available` space the tracks take their largest size when
+// `maximize`, for a max-content constraint, and stay at their smallest
+// otherwise.
+fn size_tracks(
+    tracks: &mut Vec<Track>,
+    contributions: &Vec<((usize, usize), f64, f64, f64)>,
+    available: Option<f64>,
+    gap: f64,
+    context: &LengthContext,
+    maximize: bool,
+    stretch: bool,
+) {
+    let resolve = |b: &Breadth| resolve_breadth(b, context, available);
+    let is_intrinsic_min = |t: &Track| resolve(&t.size.min).is_none();
+    let is_intrinsic_max = |t: &Track| t.flex().is_none() && resolve(&t.size.max).is_none();
+
+    for track in tracks.iter_mut() {
+        if track.collapsed {
+            track.base = 0.0;
+            track.limit = 0.0;
+            continue;
+        }
+        track.base = resolve(&track.size.min).unwrap_or(0.0);
+        track.limit = match track.size.max {
+            Breadth::Flex(_) => track.base,
+            _ => resolve(&track.size.max).unwrap_or(f64::INFINITY),
+        };
+        track.limit = track.limit.max(track.base);
+    }
+    let gaps_between = |tracks: &[Track]| gap * tracks.iter().filter(|t| !t.collapsed).count().saturating_sub(1) as f64;
+
+    // items that span no flexible track size the intrinsic tracks, those
+    // spanning fewer tracks first
+    let flexible: Vec<bool> = tracks.iter().map(|t| t.flex().is_some()).collect();
+    let spans_flexible = |span: &(usize, usize)| flexible[span.0..span.1].iter().any(|f| *f);
+    let mut sorted: Vec<&((usize, usize), f64, f64, f64)> =
+        contributions.iter().filter(|c| !spans_flexible(&c.0)).collect();
+    sorted.sort_by_key(|c| (c.0).1 - (c.0).0);
+    for ((start, end), minimum_contribution, min_contribution, max_contribution) in sorted {
+        let span = &mut tracks[*start..*end];
+        let span_gaps = gaps_between(span);
+        let min_size = |track: &Track| match track.size.min {
+            Breadth::MinContent => *min_contribution,
+            Breadth::MaxContent => *max_contribution,
+            _ => *minimum_contribution,
+        };
+        let max_size = |track: &Track| match &track.size.max {
+            Breadth::MinContent => *min_contribution,
+            Breadth::FitContent(limit) => {
+                let limit = limit.resolve(context, available.unwrap_or(0.0));
+                min_contribution.max(limit.min(*max_contribution))
+            }
+            _ => *max_contribution,
+        };
+
+        if span.len() == 1 {
+            let track = &mut span[0];
+            if is_intrinsic_min(track) {
+                track.base = track.base.max(min_size(track));
+            }
+            if is_intrinsic_max(track) {
+                let size = max_size(track);
+                track.limit = if track.limit.is_infinite() { size } else { track.limit.max(size) };
+            }
+            track.limit = track.limit.max(track.base);
+            continue;
+        }
+
+        // what a spanning item needs beyond the tracks goes to them equally
+        let intrinsic: Vec<usize> = (0..span.len()).filter(|i| is_intrinsic_min(&span[*i])).collect();
+        if intrinsic.len() > 0 {
+            let needed = min_size(&span[intrinsic[0]]) - span.iter().map(|t| t.base).sum::<f64>() - span_gaps;
+            if needed > 0.0 {
+                for i in &intrinsic {
+                    span[*i].base += needed / intrinsic.len() as f64;
+                }
+            }
+        }
+        let intrinsic: Vec<usize> = (0..span.len()).filter(|i| is_intrinsic_max(&span[*i])).collect();
+        if intrinsic.len() > 0 {
+            let limits: f64 = span.iter().map(|t| if t.limit.is_infinite() { t.base } else { t.limit }).sum();
+            let needed = (max_size(&span[intrinsic[0]]) - limits - span_gaps).max(0.0);
+            for i in &intrinsic {
+                let track = &mut span[*i];
+                let limit = if track.limit.is_infinite() { track.base } else { track.limit };
+                track.limit = limit + needed / intrinsic.len() as f64;
+            }
+        }
+        for track in span.iter_mut() {
+            track.limit = track.limit.max(track.base);
+        }
+    }
+
+    // items in flexible tracks keep them from getting smaller than their
+    // content
+    for ((start, end), minimum_contribution, _, _) in contributions.iter().filter(|c| spans_flexible(&c.0)) {
+        let span = &mut tracks[*start..*end];
+        let needed = minimum_contribution - span.iter().map(|t| t.base).sum::<f64>() - gaps_between(span);
+        let flexible: Vec<usize> = (0..span.len())
+            .filter(|i| span[*i].flex().is_some() && is_intrinsic_min(&span[*i]))
+            .collect();
+        if needed > 0.0 && flexible.len() > 0 {
+            for i in &flexible {
+                span[*i].base += needed / flexible.len() as f64;
+            }
+        }
+    }
+    for track in tracks.iter_mut() {
+        if track.limit.is_infinite() {
+            track.limit = track.base;
+        }
+        if track.flex().is_some() {
+            track.limit = track.base;
+        }
+    }
+
+    // the tracks grow towards their limits into the free space
+    let all_gaps = gaps_between(tracks);
+    match available {
+        Some(available) => {
+            let mut free = available - all_gaps - tracks.iter().map(|t| t.base).sum::<f64>();
+            loop {
+                let growable: Vec<usize> = (0..tracks.len()).filter(|i| tracks[*i].base < tracks[*i].limit).collect();
+                if free <= 0.01 || growable.len() == 0 {
+                    break;
+                }
+                let share = free / growable.len() as f64;
+                for i in growable {
+                    let track = &mut tracks[i];
+                    let growth = share.min(track.limit - track.base);
+                    track.base += growth;
+                    free -= growth;
+                }
+            }
+        }
+        None if maximize => {
+            for track in tracks.iter_mut() {
+                track.base = track.limit;
+            }
+        }
+        None => {}
+    }
+
+    // flexible tracks share what is left by their flex factors, CSS Grid
+    // §11.7, and never get smaller than they are
+    if tracks.iter().any(|t| t.flex().is_some()) {
+        let fr = match available {
+            Some(available) => find_fr_size(tracks, 0..tracks.len(), available - all_gaps),
+            None if maximize => {
+                let mut fr: f64 = 0.0;
+                for track in tracks.iter() {
+                    match track.flex() {
+                        Some(f) if f > 1.0 => fr = fr.max(track.base / f),
+                        Some(_) => fr = fr.max(track.base),
+                        None => {}
+                    }
+                }
+                for ((start, end), _, _, max_contribution) in contributions.iter().filter(|c| spans_flexible(&c.0)) {
+                    let space = max_contribution - gaps_between(&tracks[*start..*end]);
+                    fr = fr.max(find_fr_size(tracks, *start..*end, space));
+                }
+                fr
+            }
+            None => 0.0,
+        };
+        for track in tracks.iter_mut() {
+            match track.flex() {
+                Some(f) => track.base = track.base.max(fr * f),
+                None => {}
+            }
+        }
+    }
+
+    // `auto` tracks stretch into the rest
+    match available {
+        Some(available) if stretch => {
+            let free = available - all_gaps - tracks.iter().map(|t| t.base).sum::<f64>();
+            let stretchy: Vec<usize> = (0..tracks.len())
+                .filter(|i| !tracks[*i].collapsed && matches!(tracks[*i].size.max, Breadth::Auto))
+                .collect();
+            if free > 0.0 && stretchy.len() > 0 {
+                for i in &stretchy {
+                    tracks[*i].base += free / stretchy.len() as f64;
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+// The size of `1fr` that fills `space` with the tracks in `range`. Tracks
+// whose base size is more than their share keep it and leave the rest to
+// the others.
+fn find_fr_size(tracks: &Vec<Track>, range: Range<usize>, space: f64) -> f64 {
+    let mut inflexible = vec![false; tracks.len()];
+    loop {
+        let mut leftover = space;
+        let mut flex_sum = 0.0;
+        for i in range.clone() {
+            match tracks[i].flex() {
+                Some(f) if !inflexible[i] => flex_sum += f,
+                _ => leftover -= tracks[i].base,
+            }
+        }
+        // factors adding up to less than one don't take all the space
+        let fr = leftover.max(0.0) / f64::max(flex_sum, 1.0);
+        let mut changed = false;
+        for i in range.clone() {
+            match tracks[i].flex() {
+                Some(f) if !inflexible[i] && tracks[i].base > fr * f => {
+                    inflexible[i] = true;
+                    changed = true;
+                }
+                _ => {}
+            }
+        }
+        if !changed {
+            return fr;
+        }
+    }
+}
+
+// Where each track starts, with the content distributed along the axis by
+// `mode` when there is room left.
+fn get_track_positions(tracks: &Vec<Track>, start: f64, gap: f64, available: Option<f64>, mode: &str) -> Vec<f64> {
+    let visible = tracks.iter().filter(|t| !t.collapsed).count();
+    let used = tracks.iter().map(|t| t.base).sum::<f64>() + gap * visible.saturating_sub(1) as f64;
+    let free = available.map_or(0.0, |a| a - used);
+    let (offset, spacing) = match mode {
+        "normal" | "stretch" => (0.0, 0.0),
+        mode => distribute_space(mode, free, visible),
+    };
+
+    let mut positions = vec![];
+    let mut position = start + offset;
+    let mut first = true;
+    for track in tracks {
+        if !track.collapsed {
+            if !first {
+                position += gap + spacing;
+            }
+            first = false;
+        }
+        positions.push(position);
+        position += track.base;
+    }
+    return positions;
+}
+
+// Lays out an item with its border box at `x` and `y`, `width` wide and with
+// a content height of `height` when given. Returns the border box height and
+// the baseline.
+fn layout_grid_item(
+    tree: &mut Vec<DomElement>,
+    item: &GridItem,
+    measure_text: &dyn Fn(String, f64, String) -> (f64, f64),
+    container: &InlineContainer,
+    (x, y): (f64, f64),
+    width: f64,
+    height: Option<f64>,
+) -> (f64, Option<f64>) {
+    if item.anonymous {
+        let mut lines_container = container.clone();
+        lines_container.x = x;
+        lines_container.y = y;
+        lines_container.width = width;
+        lines_container.height = height;
+        let lines = layout_inline_content(tree, item.range.clone(), &lines_container, &mut FloatContext::new(), measure_text);
+        return (height.unwrap_or(lines.height), lines.last_baseline);
+    }
+    let block = layout_block_box(
+        &mut tree[item.range.start],
+        measure_text,
+        x,
+        y,
+        width,
+        container.height,
+        CollapsedMargin::empty(),
+        &mut FloatContext::new(),
+        Some(ItemSizing { height }),
+    );
+    return (block.height, block.baseline);
+}
+
+fn is_stretch(align: &str) -> bool {
+    align == "stretch" || align == "normal"
+}
+
+// The offset of a box `size` long in `space` along an axis, aligned as
+// `align` says.
+fn align_offset(align: &str, free: f64) -> f64 {
+    match align {
+        "end" | "flex-end" | "self-end" | "right" => free,
+        "center" => free / 2.0,
+        _ => 0.0,
+    }
+}
+
+// The content width of an item in a grid area `area_width` wide, and its
+// left and right margins: it stretches across the area unless it has a
+// width, `auto` margins or another alignment, and then fits its content.
+fn get_item_width(
+    tree: &mut Vec<DomElement>,
+    item: &GridItem,
+    measure_text: &dyn Fn(String, f64, String) -> (f64, f64),
+    area_width: f64,
+) -> (f64, f64, f64) {
+    if item.anonymous {
+        return (area_width, 0.0, 0.0);
+    }
+    let element = &mut tree[item.range.start];
+    let computed_style = element.computed_style.as_ref().unwrap();
+    let margin = computed_style.margin.clone();
+    let border = computed_style.border.widths();
+    let padding = &computed_style.padding;
+    let edges = border.left + padding.left + padding.right + border.right;
+    let (width, min_width, max_width) = resolve_width(computed_style, area_width);
+    let auto_left = is_auto_margin(computed_style, "left");
+    let auto_right = is_auto_margin(computed_style, "right");
+
+    let width = match width {
+        Some(width) => clamp_size(width, min_width, max_width),
+        None if is_stretch(&item.justify) && !auto_left && !auto_right => {
+            clamp_size((area_width - margin.left - margin.right - edges).max(0.0), min_width, max_width)
+        }
+        None => get_shrink_to_fit_width(element, measure_text, area_width),
+    };
+    let free = area_width - width - edges - margin.left - margin.right;
+    let left = match (auto_left, auto_right) {
+        (true, true) => margin.left + free.max(0.0) / 2.0,
+        (true, false) => margin.left + free.max(0.0),
+        (false, true) => margin.left,
+        _ => margin.left + align_offset(&item.justify, free),
+    };
+    return (width, left, margin.right);
+}
+
+// The outer width an item contributes to the columns at its minimum width,
+// and at its smallest and largest.
+fn get_width_contributions(
+    tree: &mut Vec<DomElement>,
+    item: &GridItem,
+    measure_text: &dyn Fn(String, f64, String) -> (f64, f64),
+    container_width: Option<f64>,
+) -> (f64, f64, f64) {
+    if item.anonymous {
+        let (min, max) = get_inline_content_widths(tree, item.range.clone(), measure_text);
+        return (min, min, max);
+    }
+    let element = &mut tree[item.range.start];
+    let computed_style = element.computed_style.as_ref().unwrap();
+    let (width, min_width, max_width) = resolve_width(computed_style, container_width.unwrap_or(0.0));
+    let margin = &computed_style.margin;
+    let border = computed_style.border.widths();
+    let padding = &computed_style.padding;
+    let outer = margin.left + border.left + padding.left + padding.right + border.right + margin.right;
+    // the automatic minimum size of a scroll container is 0, §6.6
+    let minimum = if is_scroll_container(computed_style) {
+        Some(min_width.unwrap_or(0.0) + outer)
+    } else {
+        None
+    };
+    let (min, max) = match width {
+        Some(width) if container_width.is_some() || !computed_style.width.as_ref().unwrap().has_percentage() => {
+            let width = clamp_size(width, min_width, max_width) + outer;
+            (width, width)
+        }
+        _ => get_intrinsic_widths(element, measure_text),
+    };
+    return (minimum.unwrap_or(min), min, max);
+}
+
+// Lays out the children of a grid container in the content box `container`,
+// CSS Grid §12: the items are placed on the grid, the columns sized to
+// them, then the rows to the items at their column widths, and each item
+// is laid out in its area. Returns the content height and the baseline.
+pub fn layout_grid_container(
+    element: &mut DomElement,
+    measure_text: &dyn Fn(String, f64, String) -> (f64, f64),
+    container: &InlineContainer,
+    min_height: Option<f64>,
+    max_height: Option<f64>,
+) -> GridLayout {
+    let container_style = element.computed_style.clone().unwrap();
+    let context = &container_style.length_context;
+    let column_gap = resolve_gap(&container_style, "column-gap", Some(container.width));
+    let row_gap = resolve_gap(&container_style, "row-gap", container.height);
+    let justify_content = get_value(&container_style, "justify-content", "normal");
+    let align_content = get_value(&container_style, "align-content", "normal");
+
+    let tree = &mut element.children;
+    let mut items = collect_items(tree, measure_text, container, &container_style);
+    let (mut columns, mut rows) = build_grid(tree, &container_style, &mut items, Some(container.width), container.height);
+
+    let contributions: Vec<((usize, usize), f64, f64, f64)> = items
+        .iter()
+        .map(|item| {
+            let (minimum, min, max) = get_width_contributions(tree, item, measure_text, Some(container.width));
+            (item.columns, minimum, min, max)
+        })
+        .collect();
+    size_tracks(
+        &mut columns,
+        &contributions,
+        Some(container.width),
+        column_gap,
+        context,
+        true,
+        is_stretch(justify_content),
+    );
+    let column_positions = get_track_positions(&columns, container.x, column_gap, Some(container.width), justify_content);
+    let area_width = |item: &GridItem| {
+        let last = item.columns.1 - 1;
+        column_positions[last] + columns[last].base - column_positions[item.columns.0]
+    };
+
+    // the rows fit the items as wide as they are in their areas
+    let mut contributions = vec![];
+    for item in &items {
+        let (width, _, _) = get_item_width(tree, item, measure_text, area_width(item));
+        let (height, _) = layout_grid_item(tree, item, measure_text, container, (0.0, 0.0), width, None);
+        let (minimum, outer) = if item.anonymous {
+            (height, height)
+        } else {
+            let computed_style = tree[item.range.start].computed_style.as_ref().unwrap();
+            let margin = &computed_style.margin;
+            let minimum = if is_scroll_container(computed_style) {
+                let (_, min_height, _) = resolve_height(computed_style, container.height);
+                let border = computed_style.border.widths();
+                let padding = &computed_style.padding;
+                let edges = border.top + padding.top + padding.bottom + border.bottom;
+                margin.top + min_height.unwrap_or(0.0) + edges + margin.bottom
+            } else {
+                margin.top + height + margin.bottom
+            };
+            (minimum, margin.top + height + margin.bottom)
+        };
+        contributions.push((item.rows, minimum, outer, outer));
+    }
+    size_tracks(&mut rows, &contributions, container.height, row_gap, context, true, is_stretch(align_content));
+    let row_positions = get_track_positions(&rows, container.y, row_gap, container.height, align_content);
+
+    let mut baseline: Option<(usize, f64)> = None;
+    for item in &items {
+        let area_x = column_positions[item.columns.0];
+        let area_y = row_positions[item.rows.0];
+        let last_row = item.rows.1 - 1;
+        let area_height = row_positions[last_row] + rows[last_row].base - area_y;
+        let (width, margin_left, _) = get_item_width(tree, item, measure_text, area_width(item));
+
+        let (margin_top, margin_bottom, auto_top, auto_bottom, height) = if item.anonymous {
+            (0.0, 0.0, false, false, None)
+        } else {
+            let computed_style = tree[item.range.start].computed_style.as_ref().unwrap();
+            let margin = &computed_style.margin;
+            let auto_top = is_auto_margin(computed_style, "top");
+            let auto_bottom = is_auto_margin(computed_style, "bottom");
+            let (height, min_height, max_height) = resolve_height(computed_style, Some(area_height));
+            let border = computed_style.border.widths();
+            let padding = &computed_style.padding;
+            let edges = border.top + padding.top + padding.bottom + border.bottom;
+            let height = match height {
+                Some(h) => Some(h),
+                None if is_stretch(&item.align) && !auto_top && !auto_bottom => {
+                    let stretched = area_height - margin.top - margin.bottom - edges;
+                    Some(clamp_size(stretched.max(0.0), min_height, max_height))
+                }
+                None => None,
+            };
+            (margin.top, margin.bottom, auto_top, auto_bottom, height)
+        };
+
+        let x = area_x + margin_left;
+        let y = area_y + margin_top;
+        let (border_height, item_baseline) =
+            layout_grid_item(tree, item, measure_text, container, (x, y), width, height);
+        let free = area_height - margin_top - border_height - margin_bottom;
+        let dy = match (auto_top, auto_bottom) {
+            (true, true) => free.max(0.0) / 2.0,
+            (true, false) => free.max(0.0),
+            (false, true) => 0.0,
+            _ => align_offset(&item.align, free),
+        };
+        if dy != 0.0 {
+            for child in &mut tree[item.range.clone()] {
+                translate_element(child, 0.0, dy);
+            }
+        }
+
+        // the baseline comes from the first item in the first row
+        match (item_baseline, baseline) {
+            (Some(b), None) => baseline = Some((item.rows.0, b + dy)),
+            (Some(b), Some((row, _))) if item.rows.0 < row => baseline = Some((item.rows.0, b + dy)),
+            _ => {}
+        }
+    }
+
+    let rows_height = match (row_positions.last(), rows.last()) {
+        (Some(position), Some(row)) => position + row.base - container.y,
+        _ => 0.0,
+    };
+    GridLayout {
+        height: container.height.unwrap_or(clamp_size(rows_height, min_height, max_height)),
+        baseline: baseline.map(|(_, b)| b),
+    }
+}
+
+// The narrowest and widest the content box of a grid container gets: its
+// columns sized under a min-content and a max-content constraint.
+pub fn get_grid_content_widths(
+    element: &mut DomElement,
+    measure_text: &dyn Fn(String, f64, String) -> (f64, f64),
+) -> (f64, f64) {
+    let container_style = element.computed_style.clone().unwrap();
+    let column_gap = resolve_gap(&container_style, "column-gap", None);
+    let tree = &mut element.children;
+    let container = InlineContainer::new(0.0, 0.0, 0.0, Some(&container_style));
+    let mut items = collect_items(tree, measure_text, &container, &container_style);
+    let (columns, _) = build_grid(tree, &container_style, &mut items, None, None);
+    let contributions: Vec<((usize, usize), f64, f64, f64)> = items
+        .iter()
+        .map(|item| {
+            let (minimum, min, max) = get_width_contributions(tree, item, measure_text, None);
+            (item.columns, minimum, min, max)
+        })
+        .collect();
+
+    let mut widths = [0.0, 0.0];
+    for (i, maximize) in [false, true].iter().enumerate() {
+        let mut tracks = columns.clone();
+        let context = &container_style.length_context;
+        size_tracks(&mut tracks, &contributions, None, column_gap, context, *maximize, false);
+        let visible = tracks.iter().filter(|t| !t.collapsed).count();
+        widths[i] = tracks.iter().map(|t| t.base).sum::<f64>() + column_gap * visible.saturating_sub(1) as f64;
+    }
+    return (widths[0], widths[1]);
+}
@@ -1,5 +1,9 @@
-use crate::colors::ColorTupleA;
+use crate::colors::{Color, ColorScheme};
+use crate::css::CssValue;
+use crate::element_state::*;
+use crate::generated_content::CounterState;
 use crate::layout::*;
+use crate::length::*;
 use crate::utils::*;
 use std::collections::HashMap;
 use std::hash::Hash;
@@ -28,25 +32,183 @@ pub struct Margin {
   pub left: f64,
 }
 
+impl Margin {
+  pub fn zero() -> Margin {
+    Margin {
+      top: 0.0,
+      right: 0.0,
+      bottom: 0.0,
+      left: 0.0,
+    }
+  }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MarginLength {
+  pub top: LengthPercentage,
+  pub right: LengthPercentage,
+  pub bottom: LengthPercentage,
+  pub left: LengthPercentage,
+}
+
+impl MarginLength {
+  // Percentages on every side refer to the containing block's width.
+  pub fn resolve(&self, context: &LengthContext, containing_width: f64) -> Margin {
+    Margin {
+      top: self.top.resolve(context, containing_width),
+      right: self.right.resolve(context, containing_width),
+      bottom: self.bottom.resolve(context, containing_width),
+      left: self.left.resolve(context, containing_width),
+    }
+  }
+}
+
+// A side of the border. `width` is the used width, zero when the style is
+// `none` or `hidden`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BorderSide {
+  pub width: f64,
+  pub style: String,
+  pub color: Color,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Border {
+  pub top: BorderSide,
+  pub right: BorderSide,
+  pub bottom: BorderSide,
+  pub left: BorderSide,
+}
+
+impl Border {
+  pub fn widths(&self) -> Margin {
+    Margin {
+      top: self.top.width,
+      right: self.right.width,
+      bottom: self.bottom.width,
+      left: self.left.width,
+    }
+  }
+
+  pub fn is_visible(&self) -> bool {
+    [&self.top, &self.right, &self.bottom, &self.left]
+      .iter()
+      .any(|s| s.width > 0.0 && !s.color.is_transparent())
+  }
+}
+
+// The horizontal and vertical radius of each corner. Percentages refer to
+// the width and the height of the border box.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BorderRadius {
+  pub top_left: (LengthPercentage, LengthPercentage),
+  pub top_right: (LengthPercentage, LengthPercentage),
+  pub bottom_right: (LengthPercentage, LengthPercentage),
+  pub bottom_left: (LengthPercentage, LengthPercentage),
+}
+
+// The `top`, `right`, `bottom` and `left` of a positioned box, `None` for
+// `auto`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Inset {
+  pub top: Option<LengthPercentage>,
+  pub right: Option<LengthPercentage>,
+  pub bottom: Option<LengthPercentage>,
+  pub left: Option<LengthPercentage>,
+}
+
+// The computed `line-height`. Numbers are inherited as numbers, lengths and
+// percentages as pixels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LineHeight {
+  Normal,
+  Number(f64),
+  Length(f64),
+}
+
 #[derive(Clone, Debug)]
 pub struct ComputedStyle {
   pub margin: Margin,
   pub padding: Margin,
-  pub background_color: ColorTupleA,
-  pub color: ColorTupleA,
+  pub margin_length: MarginLength,
+  pub padding_length: MarginLength,
+  // `None` is `auto`, or `none` for the maximums
+  pub width: Option<LengthPercentage>,
+  pub height: Option<LengthPercentage>,
+  pub min_width: Option<LengthPercentage>,
+  pub min_height: Option<LengthPercentage>,
+  pub max_width: Option<LengthPercentage>,
+  pub max_height: Option<LengthPercentage>,
+  pub border: Border,
+  pub border_radius: BorderRadius,
+  pub length_context: LengthContext,
+  pub background_color: Color,
+  pub color: Color,
+  pub opacity: f64,
+  // the used color scheme, see `get_used_color_scheme`
+  pub color_scheme: ColorScheme,
   pub font_size: f64,
+  pub line_height: LineHeight,
   pub font_path: String,
+  pub font_family: String,
+  pub font_weight: f64,
+  pub font_style: String,
   pub text_decoration: String,
   pub display: String,
   pub float: String,
-  pub hoverable: bool,
+  pub position: String,
+  pub inset: Inset,
+  // `None` is `auto`
+  pub z_index: Option<i32>,
+  pub overflow_x: String,
+  pub overflow_y: String,
+  // the states this element's style depends on, see `RuleIndex::match_element`
+  pub state_dependencies: ElementStates,
+  pub custom_properties: HashMap<String, String>,
+  pub values: HashMap<String, String>,
+  // what the element was styled with, to restyle it without its ancestors
+  pub inherited_declarations: HashMap<String, CssValue>,
+  pub parent_length_context: LengthContext,
+  pub counters: CounterState,
 }
 
+// The part of a text node that landed on one line box. `y` is the top of the
+// content area and `baseline` where the glyphs sit.
 #[derive(Clone, Debug)]
 pub struct TextLine {
   pub text: String,
   pub x: f64,
   pub y: f64,
+  pub width: f64,
+  pub height: f64,
+  pub baseline: f64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TextSegmentKind {
+  Word,
+  // lines may wrap after a space, unless `white-space` keeps them from it
+  Space,
+  // a preserved newline
+  Break,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextSegment {
+  pub kind: TextSegmentKind,
+  pub text: String,
+  pub width: f64,
+}
+
+// The last measurement of a text node, reused while the text, font and
+// white space handling stay the same.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextMeasurement {
+  pub text: String,
+  pub font_size: f64,
+  pub font_path: String,
+  pub white_space: String,
+  pub segments: Vec<TextSegment>,
 }
 
 #[derive(Clone, Debug)]
@@ -55,9 +217,47 @@ pub struct ComputedFlow {
   pub y: f64,
   pub width: f64,
   pub height: f64,
-  pub adjacent_margin_bottom: f64,
   pub hover_rect: Rect,
-  // pub text_lines: Vec<TextLine>,
+  pub text_measurement: Option<TextMeasurement>,
+  pub text_lines: Vec<TextLine>,
+  // the boxes of an inline element, one per line it spans
+  pub fragments: Vec<Rect>,
+  // the baseline of the last line box inside, for inline-blocks
+  pub baseline: Option<f64>,
+}
+
+impl ComputedFlow {
+  pub fn empty() -> ComputedFlow {
+    ComputedFlow {
+      x: 0.0,
+      y: 0.0,
+      width: 0.0,
+      height: 0.0,
+      hover_rect: Rect {
+        x: 0.0,
+        y: 0.0,
+        width: 0.0,
+        height: 0.0,
+      },
+      text_measurement: None,
+      text_lines: vec![],
+      fragments: vec![],
+      baseline: None,
+    }
+  }
+}
+
+// A box generated by a `::before`, `::after`, `::marker`, `::first-letter`
+// or `::first-line` rule. It lives in the tree like a regular element so it
+// is laid out and painted as an inline box.
+#[derive(Clone, Debug)]
+pub struct PseudoElement {
+  pub name: String,
+  pub declarations: Vec<KeyValue>,
+  pub originating_attributes: HashMap<String, String>,
+  // for the box that takes what doesn't fit on the first line, how many
+  // nodes at its start are the rest of nodes split where the line ended
+  pub split_depth: usize,
 }
 
 #[derive(Clone, Debug)]
@@ -71,9 +271,13 @@ pub struct DomElement {
   pub outer_html: String,
   pub tag_name: String,
   pub style: HashMap<String, String>,
-  pub is_hovered: bool,
+  pub state: ElementStates,
   pub computed_flow: Option<ComputedFlow>,
   pub computed_style: Option<ComputedStyle>,
+  pub pseudo_element: Option<PseudoElement>,
+  // how far the content of a scroll container is scrolled
+  pub scroll_x: f64,
+  pub scroll_y: f64,
 }
 
 impl DomElement {
@@ -90,9 +294,25 @@ impl DomElement {
       style: HashMap::new(),
       computed_flow: None,
       computed_style: None,
-      is_hovered: false,
+      state: ElementStates::new(),
+      pseudo_element: None,
+      scroll_x: 0.0,
+      scroll_y: 0.0,
     }
   }
+
+  pub fn new_text(text: &str) -> DomElement {
+    let mut element = DomElement::new(NodeType::Text);
+    element.node_value = text.to_string();
+    element
+  }
+
+  pub fn new_pseudo_element(pseudo_element: PseudoElement) -> DomElement {
+    let mut element = DomElement::new(NodeType::Element);
+    element.tag_name = format!("::{}", pseudo_element.name);
+    element.pseudo_element = Some(pseudo_element);
+    element
+  }
 }
 
 const SELF_CLOSING_TAGS: &[&str] = &[
@@ -153,14 +373,21 @@ pub fn tokenize(html: String) -> Vec<String> {
   let mut code_block = false;
 
   for (i, c) in chars {
-    if (!code_block && c == '\n') || c == '\r' || c == '\t' {
+    if c == '\r' {
       continue;
     }
+    // white space in text is kept for layout to collapse, in tags it only
+    // separates attributes
+    let c = if (c == '\n' || c == '\t') && !code_block && captured_text.starts_with('<') {
+      ' '
+    } else {
+      c
+    };
     if (c == '<' || (code_block && c == '\n' && c != '<')) && !ignore {
       if capturing {
         captured_text = captured_text.to_string();
         if captured_text != "" {
-          tokens.push(captured_text.clone().trim().to_string());
+          tokens.push(captured_text.clone());
           if code_block && c == '\n' {
             tokens.push("<br/>".to_string());
           }
@@ -226,7 +453,7 @@ pub fn tokenize(html: String) -> Vec<String> {
       captured_text = String::from("");
       capturing = true;
     }
-    if capturing && (c != ' ' || (c == ' ' && captured_text != "")) {
+    if capturing {
       captured_text.push(c);
 
       if captured_text == "<!--" {
@@ -0,0 +1,1267 @@
+use crate::element_state::*;
+use crate::floats::*;
+use crate::html::*;
+use crate::layout::*;
+use crate::length::*;
+use crate::utils::*;
+use std::collections::HashMap;
+use std::ops::Range;
+
+// Times New Roman's metrics per unit of font size. They are the only fonts
+// we ship, so they stand in for every font.
+const ASCENT: f64 = 0.891;
+const DESCENT: f64 = 0.216;
+const LINE_GAP: f64 = 0.042;
+const X_HEIGHT: f64 = 0.448;
+
+// The vertical metrics of an inline box.
+#[derive(Clone, Debug)]
+pub struct BoxMetrics {
+    pub font_size: f64,
+    pub ascent: f64,
+    pub descent: f64,
+    pub line_height: f64,
+}
+
+impl BoxMetrics {
+    pub fn from_style(computed_style: &ComputedStyle) -> BoxMetrics {
+        let font_size = computed_style.font_size;
+        let line_height = match computed_style.line_height {
+            LineHeight::Normal => font_size * (ASCENT + DESCENT + LINE_GAP),
+            LineHeight::Number(n) => font_size * n,
+            LineHeight::Length(l) => l,
+        };
+        BoxMetrics {
+            font_size,
+            ascent: font_size * ASCENT,
+            descent: font_size * DESCENT,
+            line_height,
+        }
+    }
+
+    // what `line-height` adds above and under the content area, it may be
+    // negative
+    fn half_leading(&self) -> f64 {
+        (self.line_height - self.ascent - self.descent) / 2.0
+    }
+}
+
+// The block container the lines are laid out in. Its style makes the root
+// inline box, whose strut every line box starts with.
+#[derive(Clone, Debug)]
+pub struct InlineContainer {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    // the content height, when it doesn't depend on the content
+    pub height: Option<f64>,
+    pub metrics: BoxMetrics,
+    pub text_align: String,
+}
+
+impl InlineContainer {
+    pub fn new(x: f64, y: f64, width: f64, computed_style: Option<&ComputedStyle>) -> InlineContainer {
+        let (metrics, text_align) = match computed_style {
+            Some(c) => (
+                BoxMetrics::from_style(c),
+                c.values.get("text-align").cloned().unwrap_or(S("start")),
+            ),
+            None => (
+                BoxMetrics {
+                    font_size: 16.0,
+                    ascent: 16.0 * ASCENT,
+                    descent: 16.0 * DESCENT,
+                    line_height: 16.0 * (ASCENT + DESCENT + LINE_GAP),
+                },
+                S("start"),
+            ),
+        };
+        InlineContainer {
+            x,
+            y,
+            width,
+            height: None,
+            metrics,
+            text_align,
+        }
+    }
+}
+
+pub struct InlineLayout {
+    pub height: f64,
+    pub last_baseline: Option<f64>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum ItemKind {
+    Text,
+    Space,
+    // the start and end edges of an inline box
+    Open,
+    Close,
+    // an inline-block or anything else laid out as one box
+    Atomic,
+    // a float, placed next to the line it comes in or below it
+    Float,
+    // where an absolutely positioned box would have been
+    OutOfFlow,
+    Break,
+    // where the first line ends, after the `::first-line` box once what
+    // didn't fit on the line moved out of it
+    FirstLineEnd,
+}
+
+#[derive(Clone, Debug)]
+struct InlineItem {
+    kind: ItemKind,
+    // the node the item comes from, relative to the laid out children
+    path: Vec<usize>,
+    text: String,
+    width: f64,
+    metrics: BoxMetrics,
+    // from `vertical-align`, upwards is positive
+    shift: f64,
+    collapsible: bool,
+    // whether the line may wrap after a space or around an atomic box
+    wrap: bool,
+    // outside list markers hang in front of the line
+    hanging: bool,
+    // the margin box height and baseline of atomic boxes and floats
+    height: f64,
+    baseline: f64,
+}
+
+// What a line box needs to know of an inline element it holds.
+#[derive(Clone, Debug)]
+struct InlineBox {
+    metrics: BoxMetrics,
+    shift: f64,
+    // the padding and border above and below the content area
+    top_edge: f64,
+    bottom_edge: f64,
+    start_margin: f64,
+    end_margin: f64,
+}
+
+struct ItemCollector {
+    items: Vec<InlineItem>,
+    boxes: HashMap<Vec<usize>, InlineBox>,
+    // a collapsible space right before swallows the next ones, even across
+    // element boundaries
+    after_collapsible_space: bool,
+    containing_width: f64,
+}
+
+fn decode_text(text: &str) -> String {
+    text.replace("&nbsp;", "\u{a0}")
+        .replace("&gt;", ">")
+        .replace("&lt;", "<")
+        .replace("&amp;", "&")
+}
+
+// Splits a text node at its soft wrap opportunities and measures the pieces.
+pub fn get_text_segments(
+    text: &str,
+    white_space: &str,
+    font_size: f64,
+    font_path: &str,
+    measure_text: &dyn Fn(String, f64, String) -> (f64, f64),
+) -> Vec<TextSegment> {
+    let preserve_spaces = white_space == "pre" || white_space == "pre-wrap" || white_space == "break-spaces";
+    let preserve_newlines = preserve_spaces || white_space == "pre-line";
+
+    let mut pieces: Vec<(TextSegmentKind, String)> = vec![];
+    let mut word = S("");
+    for c in decode_text(text).chars() {
+        let kind = match c {
+            '\r' => continue,
+            '\n' if preserve_newlines => TextSegmentKind::Break,
+            '\n' | ' ' | '\t' => TextSegmentKind::Space,
+            _ => {
+                word.push(c);
+                continue;
+            }
+        };
+        if word != "" {
+            pieces.push((TextSegmentKind::Word, word.clone()));
+            word = S("");
+        }
+
+        if kind == TextSegmentKind::Break {
+            // collapsible spaces around a preserved newline are removed
+            if !preserve_spaces && pieces.last().map(|p| &p.0) == Some(&TextSegmentKind::Space) {
+                pieces.pop();
+            }
+            pieces.push((kind, S("\n")));
+        } else if preserve_spaces {
+            // tab stops are eight spaces apart
+            pieces.push((kind, if c == '\t' { S("        ") } else { S(" ") }));
+        } else if pieces.last().map(|p| &p.0) != Some(&TextSegmentKind::Space)
+            && pieces.last().map(|p| &p.0) != Some(&TextSegmentKind::Break)
+        {
+            pieces.push((kind, S(" ")));
+        }
+    }
+    if word != "" {
+        pieces.push((TextSegmentKind::Word, word));
+    }
+
+    let space_width = measure_text(S(" "), font_size, font_path.to_string()).0;
+    return pieces
+        .into_iter()
+        .map(|(kind, text)| {
+            let width = match kind {
+                TextSegmentKind::Word => measure_text(text.replace('\u{a0}', " "), font_size, font_path.to_string()).0,
+                TextSegmentKind::Space => space_width * text.chars().count() as f64,
+                TextSegmentKind::Break => 0.0,
+            };
+            TextSegment { kind, text, width }
+        })
+        .collect();
+}
+
+// Measures a text node, or reuses its last measurement.
+fn measure_text_node(
+    element: &mut DomElement,
+    measure_text: &dyn Fn(String, f64, String) -> (f64, f64),
+) -> Vec<TextSegment> {
+    let computed_style = element.computed_style.as_ref().unwrap();
+    let white_space = computed_style.values.get("white-space").cloned().unwrap_or(S("normal"));
+
+    let previous_measurement = element
+        .computed_flow
+        .as_ref()
+        .and_then(|f| f.text_measurement.as_ref())
+        .filter(|m| {
+            m.text == element.node_value
+                && m.font_size == computed_style.font_size
+                && m.font_path == computed_style.font_path
+                && m.white_space == white_space
+        });
+    match previous_measurement {
+        Some(m) => return m.segments.clone(),
+        None => {}
+    }
+
+    let segments = get_text_segments(
+        &element.node_value,
+        &white_space,
+        computed_style.font_size,
+        &computed_style.font_path,
+        measure_text,
+    );
+    let measurement = TextMeasurement {
+        text: element.node_value.clone(),
+        font_size: computed_style.font_size,
+        font_path: computed_style.font_path.clone(),
+        white_space,
+        segments: segments.clone(),
+    };
+    match element.computed_flow.as_mut() {
+        Some(f) => f.text_measurement = Some(measurement),
+        None => {
+            let mut flow = ComputedFlow::empty();
+            flow.text_measurement = Some(measurement);
+            element.computed_flow = Some(flow);
+        }
+    }
+    return segments;
+}
+
+fn allows_wrapping(white_space: &str) -> bool {
+    white_space != "nowrap" && white_space != "pre"
+}
+
+// How far `vertical-align` raises a box above its parent's baseline. `above`
+// and `below` are how far the box reaches over and under its own baseline.
+fn get_baseline_shift(
+    computed_style: &ComputedStyle,
+    metrics: &BoxMetrics,
+    parent: &BoxMetrics,
+    above: f64,
+    below: f64,
+) -> f64 {
+    let vertical_align = computed_style
+        .values
+        .get("vertical-align")
+        .map(|v| v.trim().to_string())
+        .unwrap_or(S("baseline"));
+    match vertical_align.as_str() {
+        "baseline" => 0.0,
+        "sub" => -parent.font_size * 0.2,
+        "super" => parent.font_size * 0.34,
+        // line-relative alignment is approximated with the parent's text
+        "text-top" | "top" => parent.ascent - above,
+        "text-bottom" | "bottom" => below - parent.descent,
+        "middle" => parent.font_size * X_HEIGHT / 2.0 - (above - below) / 2.0,
+        v => match parse_length_percentage(v) {
+            // percentages refer to the box's own line height
+            Ok(l) => l.resolve(&computed_style.length_context, metrics.line_height),
+            Err(e) => {
+                println!("{}", e);
+                0.0
+            }
+        },
+    }
+}
+
+fn is_atomic_inline(computed_style: &ComputedStyle) -> bool {
+    computed_style.display != "inline"
+}
+
+fn is_pseudo_element(element: &DomElement, name: &str) -> bool {
+    element.pseudo_element.as_ref().map(|p| p.name.as_str()) == Some(name)
+}
+
+fn collect_items(
+    tree: &mut Vec<DomElement>,
+    range: Range<usize>,
+    prefix: &Vec<usize>,
+    parent: &BoxMetrics,
+    parent_shift: f64,
+    hanging: bool,
+    collector: &mut ItemCollector,
+    measure_text: &dyn Fn(String, f64, String) -> (f64, f64),
+) {
+    let range_start = range.start;
+    for i in range {
+        let mut path = prefix.clone();
+        path.push(i);
+
+        if i > range_start && is_pseudo_element(&tree[i], "after-first-line") && tree[i].children.len() > 0 {
+            let mut first_line_path = prefix.clone();
+            first_line_path.push(i - 1);
+            collector.items.push(InlineItem {
+                kind: ItemKind::FirstLineEnd,
+                path: first_line_path,
+                text: S(""),
+                width: 0.0,
+                metrics: parent.clone(),
+                shift: parent_shift,
+                collapsible: false,
+                wrap: true,
+                hanging: false,
+                height: 0.0,
+                baseline: 0.0,
+            });
+        }
+
+        let element = &mut tree[i];
+        if element.computed_style.is_none() || !is_displayed(element) {
+            clear_computed_flow(element);
+            continue;
+        }
+
+        match element.node_type {
+            NodeType::Text => {
+                let segments = measure_text_node(element, measure_text);
+                let computed_style = element.computed_style.as_ref().unwrap();
+                let metrics = BoxMetrics::from_style(computed_style);
+                let white_space = computed_style.values.get("white-space").cloned().unwrap_or(S("normal"));
+                let collapsible = !(white_space == "pre" || white_space == "pre-wrap" || white_space == "break-spaces");
+                let wrap = allows_wrapping(&white_space);
+
+                for segment in segments {
+                    let kind = match segment.kind {
+                        TextSegmentKind::Word => ItemKind::Text,
+                        TextSegmentKind::Space => ItemKind::Space,
+                        TextSegmentKind::Break => ItemKind::Break,
+                    };
+                    if kind == ItemKind::Space && collapsible && collector.after_collapsible_space {
+                        continue;
+                    }
+                    collector.after_collapsible_space = match kind {
+                        ItemKind::Space => collapsible,
+                        ItemKind::Break => true,
+                        _ => false,
+                    };
+                    collector.items.push(InlineItem {
+                        kind,
+                        path: path.clone(),
+                        text: segment.text.replace('\u{a0}', " "),
+                        width: segment.width,
+                        metrics: metrics.clone(),
+                        shift: parent_shift,
+                        collapsible,
+                        wrap,
+                        hanging,
+                        height: 0.0,
+                        baseline: 0.0,
+                    });
+                }
+            }
+            NodeType::Element => {
+                resolve_box_edges(element.computed_style.as_mut().unwrap(), collector.containing_width);
+                let computed_style = element.computed_style.as_ref().unwrap();
+                let metrics = BoxMetrics::from_style(computed_style);
+                let white_space = computed_style.values.get("white-space").cloned().unwrap_or(S("normal"));
+
+                if element.tag_name == "BR" {
+                    collector.after_collapsible_space = true;
+                    collector.items.push(InlineItem {
+                        kind: ItemKind::Break,
+                        path: path.clone(),
+                        text: S(""),
+                        width: 0.0,
+                        metrics: metrics.clone(),
+                        shift: parent_shift,
+                        collapsible: false,
+                        wrap: true,
+                        hanging,
+                        height: 0.0,
+                        baseline: 0.0,
+                    });
+                    continue;
+                }
+
+                if is_out_of_flow(element) {
+                    collector.items.push(InlineItem {
+                        kind: ItemKind::OutOfFlow,
+                        path: path.clone(),
+                        text: S(""),
+                        width: 0.0,
+                        metrics: parent.clone(),
+                        shift: 0.0,
+                        collapsible: false,
+                        wrap: false,
+                        hanging: false,
+                        height: 0.0,
+                        baseline: 0.0,
+                    });
+                    continue;
+                }
+
+                if computed_style.float != "none" {
+                    collector.items.push(InlineItem {
+                        kind: ItemKind::Float,
+                        path: path.clone(),
+                        text: S(""),
+                        width: 0.0,
+                        metrics: parent.clone(),
+                        shift: 0.0,
+                        collapsible: false,
+                        wrap: true,
+                        hanging: false,
+                        height: 0.0,
+                        baseline: 0.0,
+                    });
+                    continue;
+                }
+
+                if is_atomic_inline(computed_style) {
+                    // sized and shifted once it is laid out
+                    collector.after_collapsible_space = false;
+                    collector.items.push(InlineItem {
+                        kind: ItemKind::Atomic,
+                        path: path.clone(),
+                        text: S(""),
+                        width: 0.0,
+                        metrics: parent.clone(),
+                        shift: parent_shift,
+                        collapsible: false,
+                        wrap: allows_wrapping(&white_space),
+                        hanging,
+                        height: 0.0,
+                        baseline: 0.0,
+                    });
+                    continue;
+                }
+
+                let shift = parent_shift
+                    + get_baseline_shift(computed_style, &metrics, parent, metrics.ascent, metrics.descent);
+                let hanging = hanging || is_outside_marker(element);
+                let margin = computed_style.margin.clone();
+                let padding = computed_style.padding.clone();
+                let border = computed_style.border.widths();
+                let start_edge = margin.left + border.left + padding.left;
+                let end_edge = padding.right + border.right + margin.right;
+                collector.boxes.insert(
+                    path.clone(),
+                    InlineBox {
+                        metrics: metrics.clone(),
+                        shift,
+                        top_edge: border.top + padding.top,
+                        bottom_edge: padding.bottom + border.bottom,
+                        start_margin: margin.left,
+                        end_margin: margin.right,
+                    },
+                );
+
+                let edge = |kind: ItemKind, width: f64| InlineItem {
+                    kind,
+                    path: path.clone(),
+                    text: S(""),
+                    width,
+                    metrics: metrics.clone(),
+                    shift,
+                    collapsible: false,
+                    wrap: false,
+                    hanging,
+                    height: 0.0,
+                    baseline: 0.0,
+                };
+                collector.items.push(edge(ItemKind::Open, start_edge));
+                if start_edge > 0.0 {
+                    collector.after_collapsible_space = false;
+                }
+                let children_len = element.children.len();
+                collect_items(
+                    &mut element.children,
+                    0..children_len,
+                    &path,
+                    &metrics,
+                    shift,
+                    hanging,
+                    collector,
+                    measure_text,
+                );
+                collector.items.push(edge(ItemKind::Close, end_edge));
+                if end_edge > 0.0 {
+                    collector.after_collapsible_space = false;
+                }
+            }
+            _ => clear_computed_flow(element),
+        }
+    }
+}
+
+// Whether the item keeps a line from being empty.
+fn is_content(item: &InlineItem) -> bool {
+    if item.hanging {
+        return false;
+    }
+    match item.kind {
+        ItemKind::Space => !item.collapsible,
+        ItemKind::Open | ItemKind::Close => item.width > 0.0,
+        ItemKind::Float | ItemKind::OutOfFlow | ItemKind::FirstLineEnd => false,
+        _ => true,
+    }
+}
+
+// Greedily fills the line starting at `items[start]`, `width` wide, with
+// the items between soft wrap opportunities. Collapsible spaces at the start
+// of a line are dropped. Floats go to `place_float` with the width the line
+// has taken so far, which returns the new width of the line when the float
+// went next to it. Returns the line, where the next one starts and the width
+// the line takes.
+fn break_line(
+    items: &Vec<InlineItem>,
+    start: usize,
+    width: f64,
+    place_float: &mut dyn FnMut(usize, f64) -> Option<f64>,
+) -> (Vec<usize>, usize, f64) {
+    let mut width = width;
+    let mut line: Vec<usize> = vec![];
+    let mut line_width = 0.0;
+    let mut chunk: Vec<usize> = vec![];
+
+    let advance = |i: &usize| if items[*i].hanging { 0.0 } else { items[*i].width };
+    // moves the chunk to the line, unless the line is full
+    let flush = |chunk: &mut Vec<usize>, line: &mut Vec<usize>, line_width: &mut f64, width: f64| -> bool {
+        if chunk.len() == 0 {
+            return true;
+        }
+        let chunk_width: f64 = chunk.iter().map(advance).sum();
+        // spaces at the end of a line hang, they don't have to fit
+        let trailing_spaces: f64 = chunk
+            .iter()
+            .rev()
+            .take_while(|i| items[**i].kind == ItemKind::Space)
+            .map(advance)
+            .sum();
+        // what takes no room fits even after spaces that hang over the end
+        let line_has_content = line.iter().any(|i| is_content(&items[*i]));
+        if line_has_content
+            && chunk_width - trailing_spaces > 0.0
+            && *line_width + chunk_width - trailing_spaces > width + 0.01
+        {
+            return false;
+        }
+        for i in chunk.drain(..) {
+            let at_line_start = !line.iter().any(|i| is_content(&items[*i]));
+            if at_line_start && items[i].kind == ItemKind::Space && items[i].collapsible {
+                continue;
+            }
+            *line_width += advance(&i);
+            line.push(i);
+        }
+        return true;
+    };
+
+    for i in start..items.len() {
+        let item = &items[i];
+        let fits = match item.kind {
+            ItemKind::Break | ItemKind::FirstLineEnd => {
+                if flush(&mut chunk, &mut line, &mut line_width, width) {
+                    line.push(i);
+                    return (line, i + 1, line_width);
+                }
+                false
+            }
+            ItemKind::Float => {
+                let chunk_width: f64 = chunk.iter().map(advance).sum();
+                match place_float(i, line_width + chunk_width) {
+                    Some(w) => width = w,
+                    None => {}
+                }
+                true
+            }
+            ItemKind::Atomic if item.wrap => {
+                flush(&mut chunk, &mut line, &mut line_width, width) && {
+                    chunk.push(i);
+                    flush(&mut chunk, &mut line, &mut line_width, width)
+                }
+            }
+            ItemKind::Space if item.wrap => {
+                chunk.push(i);
+                flush(&mut chunk, &mut line, &mut line_width, width)
+            }
+            _ => {
+                chunk.push(i);
+                true
+            }
+        };
+        if !fits {
+            return (line, chunk[0], line_width);
+        }
+    }
+    if !flush(&mut chunk, &mut line, &mut line_width, width) {
+        return (line, chunk[0], line_width);
+    }
+
+    return (line, items.len(), line_width);
+}
+
+#[derive(Default)]
+struct NodeFragments {
+    text_lines: Vec<TextLine>,
+    // the line each text line is on, to join the words of a line
+    line_indices: Vec<usize>,
+    fragments: Vec<Rect>,
+}
+
+fn get_bounding_rect(rects: &Vec<Rect>) -> Option<Rect> {
+    let first = rects.first()?;
+    let mut left = first.x;
+    let mut top = first.y;
+    let mut right = first.x + first.width;
+    let mut bottom = first.y + first.height;
+    for rect in rects {
+        left = left.min(rect.x);
+        top = top.min(rect.y);
+        right = right.max(rect.x + rect.width);
+        bottom = bottom.max(rect.y + rect.height);
+    }
+    Some(Rect {
+        x: left,
+        y: top,
+        width: right - left,
+        height: bottom - top,
+    })
+}
+
+fn is_left_float(computed_style: &ComputedStyle) -> bool {
+    computed_style.float == "left" || computed_style.float == "inline-start"
+}
+
+// Puts a float that didn't go next to a line at `y` or below, and moves it
+// there from the origin it was laid out at.
+fn place_float_below(
+    tree: &mut Vec<DomElement>,
+    floats: &mut FloatContext,
+    item: &InlineItem,
+    y: f64,
+    container: &InlineContainer,
+) {
+    let element = get_element_by_path_mut(tree, &item.path).unwrap();
+    let computed_style = element.computed_style.as_ref().unwrap();
+    let left = is_left_float(computed_style);
+    let clear = computed_style.values.get("clear").cloned().unwrap_or(S("none"));
+    let y = floats.get_clearance(&clear).map_or(y, |c| c.max(y));
+    let (x, y) = floats.find_position(left, item.width, item.height, y, container.x, container.width);
+    floats.add(
+        left,
+        Rect {
+            x,
+            y,
+            width: item.width,
+            height: item.height,
+        },
+    );
+    translate_element(element, x, y);
+}
+
+// The byte offset in `text` after its first `words` words and the white
+// space after them, with words told apart as `get_text_segments` does.
+fn get_text_split(text: &str, words: usize) -> usize {
+    let mut count = 0;
+    let mut in_word = false;
+    for (i, c) in text.char_indices() {
+        let is_space = c == '\n' || c == ' ' || c == '\t';
+        if !is_space && !in_word {
+            if count == words {
+                return i;
+            }
+            count += 1;
+        }
+        in_word = !is_space;
+    }
+    return text.len();
+}
+
+// Takes the nodes of `children` from where the second line starts, at
+// `path`, on. `shared` is how many levels of `path` have content on the first
+// line and `words` how many words of the text node at `path` do. A node with
+// content on both lines is split in two. Returns the nodes taken and how many
+// levels were split.
+fn split_children(children: &mut Vec<DomElement>, path: &[usize], shared: usize, words: usize) -> (Vec<DomElement>, usize) {
+    let child = path[0].min(children.len());
+    if shared == 0 || child == children.len() {
+        return (children.drain(child..).collect(), 0);
+    }
+
+    let mut moved: Vec<DomElement> = vec![];
+    let mut depth = 0;
+    let node = &mut children[child];
+    if node.node_type == NodeType::Text {
+        let split = get_text_split(&node.node_value, words);
+        if split < node.node_value.len() {
+            let mut second = DomElement::new_text(&node.node_value[split..]);
+            second.computed_style = node.computed_style.clone();
+            node.node_value.truncate(split);
+            moved.push(second);
+            depth = 1;
+        }
+    } else if path.len() > 1 {
+        let (inner, inner_depth) = split_children(&mut node.children, &path[1..], shared - 1, words);
+        if inner.len() > 0 {
+            let first_children = std::mem::take(&mut node.children);
+            let mut second = node.clone();
+            node.children = first_children;
+            second.children = inner;
+            moved.push(second);
+            depth = inner_depth + 1;
+        }
+    }
+    moved.extend(children.drain(child + 1..));
+    return (moved, depth);
+}
+
+// Moves what comes from the second line on out of the `::first-line` box in
+// `tree` into the box after it, restyled as if the block was its parent.
+// `path`, `shared` and `words` tell where the second line starts, as for
+// `split_children`. Returns whether anything moved.
+fn split_first_line(tree: &mut Vec<DomElement>, path: &Vec<usize>, shared: usize, words: usize) -> bool {
+    if path.len() < 2 || shared == 0 || path[0] + 1 >= tree.len() {
+        return false;
+    }
+    let (before, after) = tree.split_at_mut(path[0] + 1);
+    let first_line = &mut before[path[0]];
+    let rest = &mut after[0];
+    if !is_pseudo_element(first_line, "first-line") || !is_pseudo_element(rest, "after-first-line") {
+        return false;
+    }
+
+    let (mut moved, depth) = split_children(&mut first_line.children, &path[1..], shared - 1, words);
+    if moved.len() == 0 {
+        return false;
+    }
+    let computed_style = first_line.computed_style.as_ref().unwrap();
+    for element in moved.iter_mut() {
+        inherit_styles(element, &computed_style.inherited_declarations, &computed_style.parent_length_context);
+    }
+
+    // what moved before was split off the end of what moves now
+    let pseudo_element = rest.pseudo_element.as_mut().unwrap();
+    join_split_children(&mut moved, rest.children.drain(..).collect(), pseudo_element.split_depth);
+    pseudo_element.split_depth = depth;
+    rest.children = moved;
+    return true;
+}
+
+// Lays out `tree[range]`, a run of inline-level siblings, in line boxes
+// stacked from the top of `container`. The line boxes are shortened to go
+// around the floats of the formatting context, which the floats in the run
+// join.
+pub fn layout_inline_content(
+    tree: &mut Vec<DomElement>,
+    range: Range<usize>,
+    container: &InlineContainer,
+    floats: &mut FloatContext,
+    measure_text: &dyn Fn(String, f64, String) -> (f64, f64),
+) -> InlineLayout {
+    // `::first-line` styles what ends up on the first line, which is only
+    // known after the lines are broken. What didn't fit moves out of it and
+    // the lines are broken again.
+    let floats_before = match tree.get(range.start) {
+        Some(first) if is_pseudo_element(first, "first-line") => Some(floats.clone()),
+        _ => None,
+    };
+    let (layout, second_line) = layout_lines(tree, range.clone(), container, floats, measure_text);
+    match (floats_before, second_line) {
+        (Some(floats_before), Some((path, shared, words))) if split_first_line(tree, &path, shared, words) => {
+            *floats = floats_before;
+            return layout_lines(tree, range, container, floats, measure_text).0;
+        }
+        _ => return layout,
+    }
+}
+
+// Lays out the lines of `layout_inline_content`. Also returns where the
+// second line starts: the node, how many levels of its path have content on
+// the first line and how many of its words are on the first line.
+fn layout_lines(
+    tree: &mut Vec<DomElement>,
+    range: Range<usize>,
+    container: &InlineContainer,
+    floats: &mut FloatContext,
+    measure_text: &dyn Fn(String, f64, String) -> (f64, f64),
+) -> (InlineLayout, Option<(Vec<usize>, usize, usize)>) {
+    let mut collector = ItemCollector {
+        items: vec![],
+        boxes: HashMap::new(),
+        after_collapsible_space: true,
+        containing_width: container.width,
+    };
+    collect_items(
+        tree,
+        range,
+        &vec![],
+        &container.metrics,
+        0.0,
+        false,
+        &mut collector,
+        measure_text,
+    );
+    let boxes = collector.boxes;
+    let mut items = collector.items;
+
+    // the side and `clear` of each float
+    let mut float_styles: HashMap<usize, (bool, String)> = HashMap::new();
+    for (i, item) in items.iter_mut().enumerate() {
+        if item.kind != ItemKind::Atomic && item.kind != ItemKind::Float {
+            continue;
+        }
+        let element = get_element_by_path_mut(tree, &item.path).unwrap();
+        let (width, height, baseline) = layout_atomic_inline(element, measure_text, container);
+        item.width = width;
+        item.height = height;
+        item.baseline = baseline;
+        if item.kind == ItemKind::Float {
+            let computed_style = element.computed_style.as_ref().unwrap();
+            let clear = computed_style.values.get("clear").cloned().unwrap_or(S("none"));
+            float_styles.insert(i, (is_left_float(computed_style), clear));
+            continue;
+        }
+        let computed_style = element.computed_style.as_ref().unwrap();
+        item.shift += get_baseline_shift(
+            computed_style,
+            &BoxMetrics::from_style(computed_style),
+            &item.metrics,
+            baseline,
+            height - baseline,
+        );
+    }
+
+    let mut nodes: HashMap<Vec<usize>, NodeFragments> = HashMap::new();
+    let mut y = container.y;
+    let mut last_baseline: Option<f64> = None;
+    // the space next to floats is looked for over the height of the strut
+    let strut_height = container.metrics.line_height.max(0.0);
+    let mut handled = vec![false; items.len()];
+    let mut deferred: Vec<usize> = vec![];
+    let mut start = 0;
+    let mut line_index = 0;
+    let mut static_positions: Vec<(Vec<usize>, f64, f64)> = vec![];
+    let mut second_line: Option<(Vec<usize>, usize, usize)> = None;
+
+    loop {
+        for i in deferred.drain(..) {
+            place_float_below(tree, floats, &items[i], y, container);
+        }
+        if start >= items.len() {
+            break;
+        }
+
+        let (left, right) = floats.get_available_space(y, strut_height, container.x, container.width);
+        let mut placed: Vec<(usize, f64, f64)> = vec![];
+        let mut handled_count = 0;
+        let (line, next, line_width) = {
+            // a float goes next to the line when it fits at its top beside
+            // what the line has so far
+            let mut place_float = |i: usize, used_width: f64| -> Option<f64> {
+                if handled[i] {
+                    return None;
+                }
+                handled[i] = true;
+                handled_count += 1;
+                let item = &items[i];
+                let (is_left, clear) = &float_styles[&i];
+                let min_y = floats.get_clearance(clear).map_or(y, |c| c.max(y));
+                let (float_x, float_y) =
+                    floats.find_position(*is_left, item.width, item.height, min_y, container.x, container.width);
+                let (start, end) = floats.get_available_space(y, strut_height, container.x, container.width);
+                if float_y > y || (used_width > 0.0 && used_width + item.width > end - start + 0.01) {
+                    deferred.push(i);
+                    return None;
+                }
+                floats.add(
+                    *is_left,
+                    Rect {
+                        x: float_x,
+                        y: float_y,
+                        width: item.width,
+                        height: item.height,
+                    },
+                );
+                placed.push((i, float_x, float_y));
+                let (start, end) = floats.get_available_space(y, strut_height, container.x, container.width);
+                Some(end - start)
+            };
+            break_line(&items, start, right - left, &mut place_float)
+        };
+        for (i, float_x, float_y) in placed {
+            let element = get_element_by_path_mut(tree, &items[i].path).unwrap();
+            translate_element(element, float_x, float_y);
+        }
+
+        let (left, right) = floats.get_available_space(y, strut_height, container.x, container.width);
+        // a line that doesn't fit beside the floats goes below them
+        if line_width > right - left + 0.01 && right - left < container.width && handled_count == 0 {
+            match floats.get_next_bottom(y) {
+                Some(bottom) => {
+                    y = bottom;
+                    continue;
+                }
+                None => {}
+            }
+        }
+        if line.len() == 0 {
+            start = next;
+            continue;
+        }
+
+        // collapsible spaces at the end of a line are removed
+        let mut end = line.len();
+        for j in (0..line.len()).rev() {
+            let item = &items[line[j]];
+            if item.kind == ItemKind::Space && item.collapsible {
+                end = j;
+            } else if item.kind != ItemKind::Close
+                && item.kind != ItemKind::Break
+                && item.kind != ItemKind::FirstLineEnd
+            {
+                break;
+            }
+        }
+        let line_items: Vec<usize> = line
+            .iter()
+            .enumerate()
+            .filter(|(j, i)| *j < end || !(items[**i].kind == ItemKind::Space && items[**i].collapsible))
+            .map(|(_, i)| *i)
+            .collect();
+
+        let used_width: f64 = line_items.iter().filter(|i| !items[**i].hanging).map(|i| items[*i].width).sum();
+        let free_space = (right - left - used_width).max(0.0);
+        let is_last_line = next >= items.len()
+            || line.last().map(|i| items[*i].kind == ItemKind::Break) == Some(true);
+
+        let mut justify_space = 0.0;
+        let mut offset = match container.text_align.as_str() {
+            "right" | "end" => free_space,
+            "center" => free_space / 2.0,
+            _ => 0.0,
+        };
+        if container.text_align == "justify" && !is_last_line {
+            let spaces = line_items.iter().filter(|i| items[**i].kind == ItemKind::Space).count();
+            if spaces > 0 {
+                justify_space = free_space / spaces as f64;
+            }
+            offset = 0.0;
+        }
+
+        // positions along the line
+        let hanging_width: f64 = line_items.iter().filter(|i| items[**i].hanging).map(|i| items[*i].width).sum();
+        let mut hanging_x = left - hanging_width;
+        let mut x = left + offset;
+        let mut positions: HashMap<usize, f64> = HashMap::new();
+        for i in &line_items {
+            let item = &items[*i];
+            let width = if item.kind == ItemKind::Space { item.width + justify_space } else { item.width };
+            if item.hanging {
+                positions.insert(*i, hanging_x);
+                hanging_x += width;
+            } else {
+                positions.insert(*i, x);
+                x += width;
+            }
+        }
+
+        // the line box is tall enough for the strut and every box on it,
+        // each aligned on its baseline
+        let strut = &container.metrics;
+        let mut top = -(strut.ascent + strut.half_leading());
+        let mut bottom = strut.descent + strut.half_leading();
+        for i in &line_items {
+            let item = &items[*i];
+            let (item_top, item_bottom) = match item.kind {
+                ItemKind::Atomic => (-item.baseline, item.height - item.baseline),
+                _ => (
+                    -(item.metrics.ascent + item.metrics.half_leading()),
+                    item.metrics.descent + item.metrics.half_leading(),
+                ),
+            };
+            top = top.min(item_top - item.shift);
+            bottom = bottom.max(item_bottom - item.shift);
+        }
+        let baseline = y - top;
+        // a line with nothing but a list marker or out-of-flow boxes takes no
+        // room
+        let line_height = if line_items.iter().any(|i| is_content(&items[*i])) {
+            bottom - top
+        } else {
+            0.0
+        };
+
+        last_baseline = Some(baseline);
+
+        if line_index == 0 && next < items.len() {
+            let path = &items[next].path;
+            let words = items[..next]
+                .iter()
+                .filter(|i| i.kind == ItemKind::Text && &i.path == path)
+                .count();
+            let shared = items[..next]
+                .iter()
+                .map(|i| i.path.iter().zip(path.iter()).take_while(|(a, b)| a == b).count())
+                .max()
+                .unwrap_or(0);
+            second_line = Some((path.clone(), shared, words));
+        }
+
+        for i in &line_items {
+            let item = &items[*i];
+            let item_x = positions[i];
+            let item_baseline = baseline - item.shift;
+
+            match item.kind {
+                ItemKind::Text | ItemKind::Space => {
+                    let node = nodes.entry(item.path.clone()).or_insert(NodeFragments::default());
+                    let width = if item.kind == ItemKind::Space { item.width + justify_space } else { item.width };
+                    let joins = node.line_indices.last() == Some(&line_index)
+                        && justify_space == 0.0
+                        && node.text_lines.last().map(|l| (l.x + l.width - item_x).abs() < 0.01) == Some(true);
+                    if joins {
+                        let text_line = node.text_lines.last_mut().unwrap();
+                        text_line.text += &item.text;
+                        text_line.width += width;
+                    } else {
+                        node.text_lines.push(TextLine {
+                            text: item.text.clone(),
+                            x: item_x,
+                            y: item_baseline - item.metrics.ascent,
+                            width,
+                            height: item.metrics.ascent + item.metrics.descent,
+                            baseline: item_baseline,
+                        });
+                        node.line_indices.push(line_index);
+                    }
+                }
+                ItemKind::Atomic => {
+                    let element = get_element_by_path_mut(tree, &item.path).unwrap();
+                    translate_element(element, item_x, item_baseline - item.baseline);
+                }
+                ItemKind::OutOfFlow => static_positions.push((item.path.clone(), item_x, y)),
+                // a line break element sits at the end of its line
+                ItemKind::Break if item.text == "" => {
+                    let node = nodes.entry(item.path.clone()).or_insert(NodeFragments::default());
+                    node.fragments.push(Rect {
+                        x: item_x,
+                        y: item_baseline - item.metrics.ascent,
+                        width: 0.0,
+                        height: item.metrics.ascent + item.metrics.descent,
+                    });
+                    node.line_indices.push(line_index);
+                }
+                _ => {}
+            }
+
+            // the fragments of the inline elements the item is in
+            for depth in 1..=item.path.len() {
+                let box_path = &item.path[..depth];
+                let inline_box = match boxes.get(box_path) {
+                    Some(b) => b,
+                    None => continue,
+                };
+                let (left, right) = match item.kind {
+                    ItemKind::Open if depth == item.path.len() => {
+                        (item_x + inline_box.start_margin, item_x + item.width)
+                    }
+                    ItemKind::Close if depth == item.path.len() => {
+                        (item_x, item_x + item.width - inline_box.end_margin)
+                    }
+                    _ => (item_x, item_x + item.width),
+                };
+                let box_baseline = baseline - inline_box.shift;
+                let rect = Rect {
+                    x: left,
+                    y: box_baseline - inline_box.metrics.ascent - inline_box.top_edge,
+                    width: right - left,
+                    height: inline_box.metrics.ascent
+                        + inline_box.metrics.descent
+                        + inline_box.top_edge
+                        + inline_box.bottom_edge,
+                };
+                let node = nodes.entry(box_path.to_vec()).or_insert(NodeFragments::default());
+                if node.line_indices.last() == Some(&line_index) {
+                    let fragment = node.fragments.last_mut().unwrap();
+                    let right = (fragment.x + fragment.width).max(rect.x + rect.width);
+                    fragment.x = fragment.x.min(rect.x);
+                    fragment.width = right - fragment.x;
+                } else {
+                    node.fragments.push(rect);
+                    node.line_indices.push(line_index);
+                }
+            }
+        }
+
+        y += line_height;
+        line_index += 1;
+        start = next;
+    }
+
+    let mut placed: Vec<Vec<usize>> = nodes.keys().cloned().collect();
+    for (path, x, y) in static_positions {
+        let element = get_element_by_path_mut(tree, &path).unwrap();
+        let mut flow = ComputedFlow::empty();
+        flow.x = x;
+        flow.y = y;
+        set_computed_flow(element, flow);
+        placed.push(path);
+    }
+    for (path, node) in nodes {
+        let element = get_element_by_path_mut(tree, &path).unwrap();
+        let rects = if element.node_type == NodeType::Text {
+            node.text_lines
+                .iter()
+                .map(|l| Rect {
+                    x: l.x,
+                    y: l.y,
+                    width: l.width,
+                    height: l.height,
+                })
+                .collect()
+        } else {
+            node.fragments.clone()
+        };
+        let rect = get_bounding_rect(&rects).unwrap();
+        let mut flow = ComputedFlow::empty();
+        flow.x = rect.x;
+        flow.y = rect.y;
+        flow.width = rect.width;
+        flow.height = rect.height;
+        flow.text_lines = node.text_lines;
+        if element.node_type == NodeType::Element {
+            flow.fragments = node.fragments;
+        }
+        set_computed_flow(element, flow);
+    }
+
+    // boxes that ended up on no line, like collapsed white space
+    for item in &items {
+        if item.kind == ItemKind::Atomic || item.kind == ItemKind::Float || placed.contains(&item.path) {
+            continue;
+        }
+        let element = get_element_by_path_mut(tree, &item.path).unwrap();
+        let mut flow = ComputedFlow::empty();
+        flow.x = container.x;
+        flow.y = container.y;
+        set_computed_flow(element, flow);
+        placed.push(item.path.clone());
+    }
+
+    let layout = InlineLayout {
+        height: y - container.y,
+        last_baseline,
+    };
+    return (layout, second_line);
+}
+
+// The narrowest and widest the content of the run can be laid out, the
+// longest unbreakable piece and the longest line when only forced breaks
+// end lines.
+pub fn get_inline_content_widths(
+    tree: &mut Vec<DomElement>,
+    range: Range<usize>,
+    measure_text: &dyn Fn(String, f64, String) -> (f64, f64),
+) -> (f64, f64) {
+    let mut collector = ItemCollector {
+        items: vec![],
+        boxes: HashMap::new(),
+        after_collapsible_space: true,
+        containing_width: 0.0,
+    };
+    let metrics = InlineContainer::new(0.0, 0.0, 0.0, None).metrics;
+    collect_items(tree, range, &vec![], &metrics, 0.0, false, &mut collector, measure_text);
+
+    let mut items = collector.items;
+    let mut atomic_widths: Vec<(f64, f64)> = vec![];
+    for item in items.iter_mut().filter(|i| i.kind == ItemKind::Atomic || i.kind == ItemKind::Float) {
+        let element = get_element_by_path_mut(tree, &item.path).unwrap();
+        atomic_widths.push(get_intrinsic_widths(element, measure_text));
+    }
+
+    let mut min_width: f64 = 0.0;
+    let mut max_width: f64 = 0.0;
+    let mut chunk = 0.0;
+    let mut line = 0.0;
+    // collapsible spaces at the end of a line don't count
+    let mut trailing_space = 0.0;
+    let mut atomic_index = 0;
+    for item in &items {
+        if item.hanging {
+            continue;
+        }
+        match item.kind {
+            ItemKind::Break => {
+                min_width = min_width.max(chunk);
+                max_width = max_width.max(line - trailing_space);
+                chunk = 0.0;
+                line = 0.0;
+                trailing_space = 0.0;
+            }
+            ItemKind::Atomic | ItemKind::Float => {
+                let (atomic_min, atomic_max) = atomic_widths[atomic_index];
+                atomic_index += 1;
+                if item.wrap {
+                    min_width = min_width.max(chunk);
+                    min_width = min_width.max(atomic_min);
+                    chunk = 0.0;
+                } else {
+                    chunk += atomic_min;
+                }
+                line += atomic_max;
+            }
+            ItemKind::Space if item.wrap => {
+                min_width = min_width.max(chunk);
+                chunk = 0.0;
+                if !(item.collapsible && line == 0.0) {
+                    line += item.width;
+                    trailing_space = if item.collapsible { item.width } else { 0.0 };
+                }
+                continue;
+            }
+            _ => {
+                chunk += item.width;
+                line += item.width;
+            }
+        }
+        if item.kind != ItemKind::Close && item.kind != ItemKind::FirstLineEnd {
+            trailing_space = 0.0;
+        }
+    }
+    min_width = min_width.max(chunk);
+    max_width = max_width.max(line - trailing_space);
+
+    return (min_width, max_width);
+}
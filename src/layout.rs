@@ -1,10 +1,24 @@
+use crate::borders::*;
 use crate::colors::*;
 use crate::css::*;
+use crate::element_state::*;
+use crate::flex_layout::*;
+use crate::floats::*;
+use crate::generated_content::*;
+use crate::grid_layout::*;
 use crate::html::*;
+use crate::inline_layout::*;
+use crate::length::*;
+use crate::lisia_colors::get_system_color;
+use crate::overflow::*;
+use crate::properties::*;
+use crate::rule_index::*;
+use crate::selectors::*;
 use crate::styles::*;
+use crate::table_layout::*;
 use crate::utils::*;
 use std::collections::HashMap;
-use std::time::Instant;
+use std::ops::Range;
 
 #[derive(Clone, Debug)]
 pub struct Rect {
@@ -21,11 +35,22 @@ pub struct RenderItem {
     pub width: f64,
     pub height: f64,
     pub text: String,
+    // where the glyphs of `text` sit
+    pub baseline: f64,
     pub font_size: f64,
     pub font_path: String,
-    pub background_color: ColorTupleA,
-    pub color: ColorTupleA,
+    pub background_color: Color,
+    pub color: Color,
     pub underline: bool,
+    // a convex polygon filled with `background_color` instead of the rect
+    pub polygon: Vec<[f64; 2]>,
+    // inside a fixed box, painted without scrolling
+    pub fixed: bool,
+    // the part of the page it is painted in, cut by the boxes around it
+    pub clip: Option<Rect>,
+    // the element hit testing finds here, on the items that stand for it in
+    // the paint order and aren't painted
+    pub path: Option<Vec<usize>>,
 }
 
 impl RenderItem {
@@ -36,22 +61,20 @@ impl RenderItem {
             width: 0.0,
             height: 0.0,
             font_size: 16.0,
-            color: (0.0, 0.0, 0.0, 1.0),
-            background_color: (0.0, 0.0, 0.0, 0.0),
+            color: Color::BLACK,
+            background_color: Color::TRANSPARENT,
             font_path: S(""),
             text: S(""),
+            baseline: 0.0,
             underline: false,
+            polygon: vec![],
+            fixed: false,
+            clip: None,
+            path: None,
         }
     }
 }
 
-pub fn hit_test_element(element: &DomElement, x: f64, y: f64) -> bool {
-    if element.computed_flow.is_none() {
-        return false;
-    }
-    return rect_contains(&element.computed_flow.as_ref().unwrap().hover_rect, x, y);
-}
-
 pub fn rect_contains(rect: &Rect, x: f64, y: f64) -> bool {
     return x >= rect.x && x <= rect.x + rect.width && y >= rect.y && y <= rect.y + rect.height;
 }
@@ -63,156 +86,705 @@ pub fn is_in_viewport(rect: &Rect, viewport: &Rect) -> bool {
         && rect.y <= viewport.y + viewport.height;
 }
 
+// Updates the hovered elements to the element at `path`, the one under the
+// mouse, and restyles the ones whose style depends on it.
 pub fn should_rerender(
-    mouse_x: f64,
-    mouse_y: f64,
+    path: Option<&[usize]>,
     tree: &mut Vec<DomElement>,
     style: &Vec<StyleRule>,
-) -> bool {
-    let mut dirty = false;
+    preferred_color_scheme: ColorScheme,
+) -> StyleChange {
+    let changed = update_element_state(tree, path, ElementStateFlag::Hover);
 
-    let elements_len = tree.len();
+    return restyle_changed_elements(tree, &changed, &[ElementStateFlag::Hover], style, preferred_color_scheme);
+}
+
+// The path of child indices to the element painted on top at `x`, `y` in
+// the window, in the items of `get_render_array` painted with the page
+// scrolled to `viewport`. Hit testing goes down the paint order, so it sees
+// fixed, sticky and scrolled boxes where they are painted and the layers in
+// their stacking order. Hovered elements are hit tested with the rect they
+// had when they were last painted, so a hover style that moves them doesn't
+// make them flicker.
+pub fn get_element_path_at(render_array: &Vec<RenderItem>, viewport: &Rect, x: f64, y: f64) -> Option<Vec<usize>> {
+    for item in render_array.iter().rev() {
+        let path = match &item.path {
+            Some(p) => p,
+            None => continue,
+        };
+        // fixed boxes don't scroll with the page
+        let (x, y) = if item.fixed { (x, y) } else { (x + viewport.x, y + viewport.y) };
+        let rect = Rect {
+            x: item.x,
+            y: item.y,
+            width: item.width,
+            height: item.height,
+        };
+        let clipped = match &item.clip {
+            Some(clip) => !rect_contains(clip, x, y),
+            None => false,
+        };
+        if rect_contains(&rect, x, y) && !clipped {
+            return Some(path.clone());
+        }
+    }
 
-    for i in 0..elements_len {
-        let mut hovered = false;
-        let mut element = &mut tree[i];
+    return None;
+}
 
-        if element.computed_style.is_none() {
-            continue;
+// Attributes that map to CSS, they lose against any author rule.
+fn get_presentational_hints(element: &DomElement) -> Vec<KeyValue> {
+    let mut hints: Vec<KeyValue> = vec![];
+
+    match element.attributes.get("type") {
+        Some(list_type) if element.tag_name == "OL" || element.tag_name == "UL" || element.tag_name == "LI" => {
+            let list_style_type = match list_type.as_str() {
+                "1" => "decimal",
+                "a" => "lower-alpha",
+                "A" => "upper-alpha",
+                "i" => "lower-roman",
+                "I" => "upper-roman",
+                t if t.eq_ignore_ascii_case("disc") => "disc",
+                t if t.eq_ignore_ascii_case("circle") => "circle",
+                t if t.eq_ignore_ascii_case("square") => "square",
+                _ => "",
+            };
+            if list_style_type != "" {
+                hints.push(KeyValue::new_values("list-style-type", list_style_type));
+            }
         }
+        _ => {}
+    }
 
-        if element.computed_style.as_ref().unwrap().hoverable {
-            hovered = hit_test_element(&element, mouse_x, mouse_y);
+    // the table attributes, as in the rendering section of the HTML standard
+    let tag_name = element.tag_name.as_str();
+    let is_cell = tag_name == "TD" || tag_name == "TH";
+    let mut push_dimension = |name: &str, property: &str| match element.attributes.get(name) {
+        Some(value) => match get_dimension_value(value) {
+            Some(value) => hints.push(KeyValue::new_values(property, &value)),
+            None => {}
+        },
+        None => {}
+    };
+    match tag_name {
+        "TABLE" => {
+            push_dimension("width", "width");
+            push_dimension("height", "height");
+            push_dimension("cellspacing", "border-spacing");
         }
-       
-        if hovered != element.is_hovered {
-            element.is_hovered = hovered;
-            dirty = true;
+        "COL" | "COLGROUP" => push_dimension("width", "width"),
+        _ if is_cell => {
+            push_dimension("width", "width");
+            push_dimension("height", "height");
         }
-
-        if element.children.len() > 0 {
-            if should_rerender(mouse_x, mouse_y, &mut element.children, style) {
-                return true;
+        _ => {}
+    }
+    let is_row_part = match tag_name {
+        "TR" | "THEAD" | "TBODY" | "TFOOT" => true,
+        _ => is_cell,
+    };
+    if tag_name == "TABLE" || is_row_part {
+        match element.attributes.get("bgcolor") {
+            Some(color) => hints.push(KeyValue::new_values("background-color", color.trim())),
+            None => {}
+        }
+    }
+    if is_row_part {
+        match element.attributes.get("valign").map(|v| v.trim().to_ascii_lowercase()) {
+            Some(valign) if valign == "top" || valign == "middle" || valign == "bottom" || valign == "baseline" => {
+                hints.push(KeyValue::new_values("vertical-align", &valign));
             }
+            _ => {}
+        }
+        match element.attributes.get("align").map(|v| v.trim().to_ascii_lowercase()) {
+            Some(align) if align == "left" || align == "right" || align == "center" || align == "justify" => {
+                hints.push(KeyValue::new_values("text-align", &align));
+            }
+            _ => {}
+        }
+    }
+    if is_cell && element.attributes.contains_key("nowrap") {
+        hints.push(KeyValue::new_values("white-space", "nowrap"));
+    }
+    if tag_name == "TABLE" {
+        match element.attributes.get("border").map(|b| b.trim().parse::<u32>().unwrap_or(1)) {
+            Some(width) => {
+                for side in ["top", "right", "bottom", "left"].iter() {
+                    hints.push(KeyValue::new_values(&format!("border-{}-width", side), &format!("{}px", width)));
+                    hints.push(KeyValue::new_values(&format!("border-{}-style", side), "outset"));
+                }
+            }
+            None => {}
         }
     }
 
-    return dirty;
+    return hints;
 }
 
-pub fn element_matches_hover_selector(element: &DomElement, selector: &str) -> bool {
-    selector.to_uppercase() == element.tag_name.clone() + ":HOVER"
+// A length attribute like `width="100"` or `width="50%"`.
+fn get_dimension_value(value: &str) -> Option<String> {
+    let value = value.trim();
+    let digits = value.trim_end_matches('%');
+    match digits.parse::<f64>() {
+        Ok(n) if n >= 0.0 && digits.len() < value.len() => Some(format!("{}%", n)),
+        Ok(n) if n >= 0.0 => Some(format!("{}px", n)),
+        _ => None,
+    }
 }
 
-pub fn element_matches_single_selector(element: &DomElement, selector: &str, pseudo: &str) -> bool {
-    if selector == "*".to_string() + pseudo {
-        return true;
+fn has_generated_content(declarations: &Vec<KeyValue>) -> bool {
+    match declarations.iter().rev().find(|d| d.0 == "content") {
+        Some(d) => d.1 != "none" && d.1 != "normal",
+        None => false,
     }
+}
 
-    if selector.to_uppercase() == element.tag_name.to_string() + pseudo {
-        return true;
+// Undoes what `generate_pseudo_elements` did to a list of children. Returns
+// the removed boxes.
+fn remove_generated_content(children: &mut Vec<DomElement>) -> Vec<DomElement> {
+    let mut removed: Vec<DomElement> = vec![];
+    let mut unwrapped: Vec<DomElement> = vec![];
+    for mut child in children.drain(..) {
+        match &child.pseudo_element {
+            Some(p) if p.name == "first-line" || p.name == "after-first-line" => {
+                let children = child.children.drain(..).collect();
+                join_split_children(&mut unwrapped, children, p.split_depth);
+                removed.push(child);
+            }
+            _ => unwrapped.push(child),
+        }
     }
 
-    if selector.starts_with('#') && element.attributes.contains_key("id") {
-        return element.attributes.get("id").unwrap().to_string() + pseudo == &selector[1..];
+    let mut result: Vec<DomElement> = vec![];
+    let mut first_letter: Option<String> = None;
+    for mut child in unwrapped {
+        match &child.pseudo_element {
+            Some(p) if p.name == "first-letter" => {
+                first_letter = Some(child.children.iter().map(|c| c.node_value.clone()).collect());
+                removed.push(child);
+                continue;
+            }
+            Some(_) => {
+                removed.push(child);
+                continue;
+            }
+            None => {}
+        }
+
+        if let Some(letter) = first_letter.take() {
+            if child.node_type == NodeType::Text {
+                child.node_value = letter + &child.node_value;
+            } else {
+                result.push(DomElement::new_text(&letter));
+            }
+        }
+        result.push(child);
     }
+    if let Some(letter) = first_letter {
+        result.push(DomElement::new_text(&letter));
+    }
+
+    *children = result;
+    return removed;
+}
 
-    if selector.starts_with(".") && element.attributes.contains_key("class") {
-        let selector_classes = selector.split(".").collect::<Vec<&str>>()[1..].to_vec();
-        let classes = element.attributes.get("class").unwrap().split(" ").map(|c| c.to_string() + pseudo).collect::<Vec<String>>();
-        // println!("{:?}", classes);
-        return selector_classes.iter().all(|c| classes.contains(&c.to_string()));
+// Appends `children` to `target`, joining the `depth` nodes at the seam that
+// were split in two where the first line ended.
+pub fn join_split_children(target: &mut Vec<DomElement>, children: Vec<DomElement>, depth: usize) {
+    let mut children = children.into_iter();
+    if depth > 0 {
+        match (target.last_mut(), children.next()) {
+            (Some(last), Some(first)) if last.node_type == NodeType::Text => last.node_value += &first.node_value,
+            (Some(last), Some(first)) => join_split_children(&mut last.children, first.children, depth - 1),
+            (_, Some(first)) => target.push(first),
+            _ => {}
+        }
     }
+    target.extend(children);
+}
 
-    return false;
+fn get_children_signature(children: &Vec<DomElement>) -> Vec<(String, String)> {
+    children
+        .iter()
+        .map(|c| (c.tag_name.clone(), c.node_value.clone()))
+        .collect()
 }
 
-pub fn element_matches_selector(element: &DomElement, selector: &str, pseudo: &str) -> bool {
-    let selectors = selector.split(",").collect::<Vec<&str>>();
+// The first letter together with the punctuation around it.
+// White space before the letter is left out, it would collapse anyway.
+fn split_first_letter(text: &str) -> Option<(String, String)> {
+    let text = text.trim_start();
+    let mut end = 0;
+    let mut found_letter = false;
+    for (i, c) in text.char_indices() {
+        if c.is_alphanumeric() {
+            if found_letter {
+                break;
+            }
+            found_letter = true;
+        } else if !(c.is_ascii_punctuation() || "«»“”‘’„".contains(c)) || found_letter && !c.is_ascii_punctuation() {
+            break;
+        }
+        end = i + c.len_utf8();
+    }
 
-    for s in selectors {
-        if element_matches_single_selector(element, s.trim(), pseudo) {
-            return true;
+    if !found_letter {
+        return None;
+    }
+    Some((text[..end].to_string(), text[end..].to_string()))
+}
+
+// A generated box. One that is generated again keeps what it had in
+// `removed`, so restyling it can tell what changed.
+fn make_pseudo_element(
+    removed: &mut Vec<DomElement>,
+    attributes: &HashMap<String, String>,
+    pseudo_declarations: &HashMap<String, Vec<KeyValue>>,
+    name: &str,
+) -> DomElement {
+    let mut declarations = vec![KeyValue::new_values("display", "inline")];
+    declarations.extend(pseudo_declarations.get(name).cloned().unwrap_or(vec![]));
+    let mut pseudo_element = DomElement::new_pseudo_element(PseudoElement {
+        name: name.to_string(),
+        declarations,
+        originating_attributes: attributes.clone(),
+        split_depth: 0,
+    });
+    match removed
+        .iter()
+        .position(|r| r.pseudo_element.as_ref().map(|p| p.name.as_str()) == Some(name))
+    {
+        Some(i) => {
+            let previous = removed.remove(i);
+            pseudo_element.computed_style = previous.computed_style;
+            pseudo_element.computed_flow = previous.computed_flow;
+            pseudo_element.children = previous.children;
         }
+        None => {}
     }
+    pseudo_element
+}
 
-    return false;
+fn is_block_container(display: &str) -> bool {
+    display == "block"
+        || display == "list-item"
+        || display == "inline-block"
+        || display == "table-cell"
+        || display == "table-caption"
 }
 
-pub fn get_element_at(
-    tree: &Vec<DomElement>,
-    x: f64,
-    y: f64,
-) -> Option<&DomElement> {
-    let elements_len = tree.len();
+// Generates the `::first-letter`, `::marker`, `::before` and `::after` boxes
+// of an element whose generated content was removed into `removed`.
+fn generate_pseudo_elements(
+    element: &mut DomElement,
+    display: &str,
+    pseudo_declarations: &HashMap<String, Vec<KeyValue>>,
+    removed: &mut Vec<DomElement>,
+) {
+    if display == "none" || element.node_type != NodeType::Element {
+        return;
+    }
 
-    for i in 0..elements_len {
-        let element = &tree[i];
-        let computed_flow = element.computed_flow.as_ref();
-        if computed_flow.is_none() {
-            continue;
+    let attributes = element.attributes.clone();
+    let mut make_pseudo_element =
+        |name: &str| make_pseudo_element(removed, &attributes, pseudo_declarations, name);
+
+    // `::first-letter` takes its letter from the first text child
+    if is_block_container(display) && pseudo_declarations.contains_key("first-letter") {
+        let first_text = element
+            .children
+            .iter()
+            .position(|c| c.node_type == NodeType::Text && c.node_value.trim() != "");
+        match first_text {
+            Some(i) => match split_first_letter(&element.children[i].node_value.clone()) {
+                Some((letter, rest)) => {
+                    let mut first_letter = make_pseudo_element("first-letter");
+                    if get_children_signature(&first_letter.children) != vec![(S(""), letter.clone())] {
+                        first_letter.children = vec![DomElement::new_text(&letter)];
+                    }
+                    element.children[i].node_value = rest;
+                    element.children.insert(i, first_letter);
+                }
+                None => {}
+            },
+            None => {}
         }
-        let computed_flow = computed_flow.unwrap();
-        let rect = Rect {
-            x: computed_flow.x,
-            y: computed_flow.y,
-            width: computed_flow.width,
-            height: computed_flow.height,
-        };
+    }
+
+    let mut prefix: Vec<DomElement> = vec![];
+    if display == "list-item" {
+        prefix.push(make_pseudo_element("marker"));
+    }
+    match pseudo_declarations.get("before") {
+        Some(d) if has_generated_content(d) => prefix.push(make_pseudo_element("before")),
+        _ => {}
+    }
+    for (i, pseudo) in prefix.into_iter().enumerate() {
+        element.children.insert(i, pseudo);
+    }
 
-        let element = &tree[i];
+    match pseudo_declarations.get("after") {
+        Some(d) if has_generated_content(d) => element.children.push(make_pseudo_element("after")),
+        _ => {}
+    }
+}
 
-        if element.children.len() > 0 {
-            let child = get_element_at(&element.children, x, y);
-            if child.is_some() {
-                return child;
-            } else if rect_contains(&rect, x, y) {
-                return Some(element);
+// Wraps the inline content at the start of a block container with a
+// `::first-line` rule, before the first block or line break, in the
+// `::first-line` box once the children are styled and their display is
+// known. The wrapped children are restyled as its content. What doesn't fit
+// on the first line moves into the box after it once the lines are broken.
+fn generate_first_line(
+    element: &mut DomElement,
+    display: &str,
+    pseudo_declarations: &HashMap<String, Vec<KeyValue>>,
+    removed: &mut Vec<DomElement>,
+    context: &StyleContext,
+    inherit_declarations: &HashMap<String, CssValue>,
+    length_context: &LengthContext,
+    counters: &CounterState,
+) -> StyleChange {
+    if !is_block_container(display) || !pseudo_declarations.contains_key("first-line") {
+        return StyleChange::None;
+    }
+    let mut end = 0;
+    while end < element.children.len() {
+        let child = &element.children[end];
+        let child_display = match &child.computed_style {
+            Some(c) if child.node_type == NodeType::Element => c.display.as_str(),
+            _ => "inline",
+        };
+        if child.tag_name == "BR" || child_display == "block" || child_display == "list-item" || child_display == "table"
+        {
+            break;
+        }
+        end += 1;
+    }
+    if end == 0 {
+        return StyleChange::None;
+    }
+
+    let mut change = StyleChange::None;
+    let mut boxes: Vec<DomElement> = vec![];
+    for name in &["first-line", "after-first-line"] {
+        let mut pseudo_element = make_pseudo_element(removed, &element.attributes, pseudo_declarations, name);
+        // content split off the first line last time is joined again
+        if pseudo_element.children.len() > 0 && *name == "after-first-line" {
+            change = StyleChange::Layout;
+        }
+        pseudo_element.children = vec![];
+        let shared = cascade_element_style(
+            &mut pseudo_element,
+            context,
+            &vec![],
+            vec![],
+            inherit_declarations,
+            length_context,
+        );
+        let mut computed_style = shared.computed_style;
+        computed_style.counters = counters.clone();
+        change = change.max(match &pseudo_element.computed_style {
+            Some(previous_style) => compare_computed_styles(previous_style, &computed_style),
+            None => StyleChange::Layout,
+        });
+        if *name == "first-line" {
+            pseudo_element.children = element.children.drain(..end).collect();
+            for child in pseudo_element.children.iter_mut() {
+                inherit_styles(child, &shared.new_inherit_declarations, &computed_style.length_context);
             }
-        } else if rect_contains(&rect, x, y) {
-            return Some(element);
         }
+        pseudo_element.computed_style = Some(computed_style);
+        boxes.push(pseudo_element);
     }
+    element.children.splice(0..0, boxes);
+    return change;
+}
 
-    return None;
+// How much work a style change causes.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum StyleChange {
+    None,
+    Paint,
+    Layout,
+}
+
+impl StyleChange {
+    pub fn max(self, other: StyleChange) -> StyleChange {
+        if other > self {
+            other
+        } else {
+            self
+        }
+    }
+}
+
+// Properties that don't change the size or position of any box.
+const PAINT_PROPERTIES: &[&str] = &[
+    "background-color",
+    "border-bottom-color",
+    "border-bottom-left-radius",
+    "border-bottom-right-radius",
+    "border-left-color",
+    "border-right-color",
+    "border-top-color",
+    "border-top-left-radius",
+    "border-top-right-radius",
+    "color",
+    "color-scheme",
+    "opacity",
+    "text-decoration-line",
+    "z-index",
+];
+
+// `normal` pages only support the light scheme, others get the preferred
+// scheme when they list it and their first scheme otherwise.
+pub fn get_used_color_scheme(color_scheme: &str, preferred: ColorScheme) -> ColorScheme {
+    let schemes: Vec<ColorScheme> = color_scheme
+        .split_whitespace()
+        .filter_map(|s| match s {
+            "light" => Some(ColorScheme::Light),
+            "dark" => Some(ColorScheme::Dark),
+            _ => None,
+        })
+        .collect();
+    if schemes.contains(&preferred) && !color_scheme.split_whitespace().any(|s| s == "only") {
+        return preferred;
+    }
+    return schemes.first().cloned().unwrap_or(ColorScheme::Light);
+}
+
+// Percentages and `em` refer to the element's own font size.
+pub fn get_line_height(value: &str, length_context: &LengthContext) -> LineHeight {
+    let value = value.trim();
+    if value == "normal" {
+        return LineHeight::Normal;
+    }
+    match value.parse::<f64>() {
+        Ok(n) if n >= 0.0 => return LineHeight::Number(n),
+        Ok(_) => return LineHeight::Normal,
+        Err(_) => {}
+    }
+    match parse_length_percentage(value) {
+        Ok(l) => LineHeight::Length(l.resolve(length_context, length_context.font_size).max(0.0)),
+        Err(e) => {
+            println!("{}", e);
+            LineHeight::Normal
+        }
+    }
+}
+
+fn compare_computed_styles(old: &ComputedStyle, new: &ComputedStyle) -> StyleChange {
+    let layout_changed = old.display != new.display
+        || old.float != new.float
+        || old.font_size != new.font_size
+        || old.font_path != new.font_path
+        || old.margin_length != new.margin_length
+        || old.padding_length != new.padding_length
+        || new
+            .values
+            .iter()
+            .any(|(k, v)| !PAINT_PROPERTIES.contains(&k.as_str()) && old.values.get(k) != Some(v));
+    if layout_changed {
+        return StyleChange::Layout;
+    }
+
+    if old.color != new.color
+        || old.color_scheme != new.color_scheme
+        || old.background_color != new.background_color
+        || old.border != new.border
+        || old.border_radius != new.border_radius
+        || old.opacity != new.opacity
+        || old.z_index != new.z_index
+        || old.text_decoration != new.text_decoration
+        || old.values != new.values
+    {
+        return StyleChange::Paint;
+    }
+
+    return StyleChange::None;
+}
+
+// Everything styling needs that stays the same during a traversal.
+pub struct StyleContext<'a> {
+    pub rules: &'a Vec<StyleRule>,
+    pub rule_index: RuleIndex,
+    pub registrations: HashMap<String, PropertyRegistration>,
+    // the scheme the user prefers, used by elements whose `color-scheme` allows it
+    pub preferred_color_scheme: ColorScheme,
+}
+
+impl<'a> StyleContext<'a> {
+    pub fn new(rules: &'a Vec<StyleRule>, preferred_color_scheme: ColorScheme) -> StyleContext<'a> {
+        StyleContext {
+            rules,
+            rule_index: RuleIndex::new(rules),
+            registrations: get_property_registrations(rules),
+            preferred_color_scheme,
+        }
+    }
+}
+
+struct StyleTraversal {
+    ancestors: Vec<AncestorElement>,
+    filter: AncestorFilter,
+    counters: CounterState,
+    // states of the ancestors, by depth, that descendant styles depend on
+    ancestor_dependencies: Vec<(usize, ElementStates)>,
+}
+
+impl StyleTraversal {
+    fn new(counters: CounterState) -> StyleTraversal {
+        StyleTraversal {
+            ancestors: vec![],
+            filter: AncestorFilter::new(),
+            counters,
+            ancestor_dependencies: vec![],
+        }
+    }
+
+    fn push_ancestor(&mut self, element: &DomElement) {
+        let ancestor = AncestorElement::new(element);
+        self.filter.push(&ancestor);
+        self.ancestors.push(ancestor);
+    }
+
+    fn pop_ancestor(&mut self) {
+        match self.ancestors.pop() {
+            Some(ancestor) => self.filter.pop(&ancestor),
+            None => {}
+        }
+    }
+}
+
+// Siblings with the same matched rules share the cascaded and computed style.
+#[derive(PartialEq, Eq, Hash)]
+struct StyleSharingKey {
+    tag_name: String,
+    matched_rules: Vec<(usize, Option<String>)>,
+    hints: Vec<(String, String)>,
+}
+
+#[derive(Clone)]
+struct SharedStyle {
+    style: HashMap<String, String>,
+    pseudo_declarations: HashMap<String, Vec<KeyValue>>,
+    computed_style: ComputedStyle,
+    new_inherit_declarations: HashMap<String, CssValue>,
 }
 
 pub fn compute_styles(
     tree: &mut Vec<DomElement>,
     style: &Vec<StyleRule>,
     inherit_declarations: Option<HashMap<String, CssValue>>,
+    length_context: &LengthContext,
+    preferred_color_scheme: ColorScheme,
 ) {
-    let elements_len = tree.len();
+    let context = StyleContext::new(style, preferred_color_scheme);
+    let mut traversal = StyleTraversal::new(CounterState::new());
+    compute_styles_with_traversal(
+        tree,
+        &context,
+        &mut traversal,
+        &inherit_declarations.unwrap_or(HashMap::new()),
+        length_context,
+    );
+}
 
-    let inherit_declarations = inherit_declarations.unwrap_or(HashMap::new());
+fn compute_styles_with_traversal(
+    tree: &mut Vec<DomElement>,
+    context: &StyleContext,
+    traversal: &mut StyleTraversal,
+    inherit_declarations: &HashMap<String, CssValue>,
+    length_context: &LengthContext,
+) -> StyleChange {
+    // counters created at this level go out of scope with the parent
+    let mut scoped_counters: Vec<String> = vec![];
+    let mut sharing_cache: HashMap<StyleSharingKey, SharedStyle> = HashMap::new();
+
+    let mut change = StyleChange::None;
+
+    for element in tree.iter_mut() {
+        change = change.max(compute_element_styles(
+            element,
+            context,
+            traversal,
+            inherit_declarations,
+            length_context,
+            &mut scoped_counters,
+            &mut sharing_cache,
+        ));
+    }
 
-    for i in 0..elements_len {
-        let mut new_inherit_declarations = inherit_declarations.clone();
+    for name in scoped_counters {
+        traversal.counters.pop(&name);
+    }
 
-        let mut element = &mut tree[i];
-        let mut hoverable = false;
-        element.style = HashMap::new();
-        {
-            for style_rule in style {
-                if element_matches_selector(&element, &style_rule.selector, "") {
-                    for declaration in &style_rule.declarations {
-                        element
-                            .style
-                            .insert(declaration.0.clone(), declaration.1.clone());
-                    }
-                }
+    return change;
+}
 
-                if element_matches_selector(&element, &style_rule.selector, ":HOVER") {
-                    hoverable = true;
-                    if element.is_hovered {
-                        for declaration in &style_rule.declarations {
-                            element
-                                .style
-                                .insert(declaration.0.clone(), declaration.1.clone());
-                        }
-                    }
+// Orders declarations so that applying them in order leaves the winning one:
+// normal user agent, user and author declarations, then important author,
+// user and user agent declarations. Source order is kept within each group.
+fn sort_by_cascade_origin(mut declarations: Vec<(CascadeOrigin, KeyValue)>) -> Vec<KeyValue> {
+    declarations.sort_by_key(|(origin, declaration)| {
+        let important = declaration.1.replace(' ', "").ends_with("!important");
+        match (origin, important) {
+            (CascadeOrigin::UserAgent, false) => 0,
+            (CascadeOrigin::User, false) => 1,
+            (CascadeOrigin::Author, false) => 2,
+            (CascadeOrigin::Author, true) => 3,
+            (CascadeOrigin::User, true) => 4,
+            (CascadeOrigin::UserAgent, true) => 5,
+        }
+    });
+    return declarations.into_iter().map(|(_, d)| d).collect();
+}
+
+// The cascaded and computed style of an element, everything that only
+// depends on the matched rules and what the parent passes down.
+fn cascade_element_style(
+    element: &mut DomElement,
+    context: &StyleContext,
+    matched_rules: &Vec<MatchedRule>,
+    hints: Vec<KeyValue>,
+    inherit_declarations: &HashMap<String, CssValue>,
+    length_context: &LengthContext,
+) -> SharedStyle {
+    let mut pseudo_declarations: HashMap<String, Vec<KeyValue>> = HashMap::new();
+
+    element.style = HashMap::new();
+    if let Some(pseudo_element) = &element.pseudo_element {
+        for declaration in &pseudo_element.declarations {
+            element
+                .style
+                .insert(declaration.0.clone(), declaration.1.clone());
+        }
+    } else {
+        let mut declarations: Vec<(CascadeOrigin, KeyValue)> = vec![];
+        let mut pseudo_origin_declarations: HashMap<String, Vec<(CascadeOrigin, KeyValue)>> = HashMap::new();
+
+        // presentational hints are author declarations that come before the author rules
+        let mut hints = Some(hints);
+
+        for matched_rule in matched_rules {
+            let style_rule = &context.rules[matched_rule.rule];
+            if style_rule.origin == CascadeOrigin::Author {
+                for declaration in hints.take().unwrap_or(vec![]) {
+                    declarations.push((CascadeOrigin::Author, declaration));
                 }
             }
+
+            let target = match &matched_rule.pseudo_element {
+                Some(pseudo) => pseudo_origin_declarations.entry(pseudo.clone()).or_insert(vec![]),
+                None => &mut declarations,
+            };
+            for declaration in &style_rule.declarations {
+                target.push((style_rule.origin, declaration.clone()));
+            }
+        }
+
+        for declaration in hints.take().unwrap_or(vec![]) {
+            declarations.push((CascadeOrigin::Author, declaration));
         }
 
         // parse style attribute
@@ -222,414 +794,2084 @@ pub fn compute_styles(
             let rules = parse_css(&style_attribute);
             for rule in rules {
                 for declaration in &rule.declarations {
-
-                element.style.insert(declaration.0.clone(), declaration.1.clone());
+                    declarations.push((CascadeOrigin::Author, declaration.clone()));
                 }
             }
         }
 
-        let get_inherit_value = |k: &str, d: CssValue| {
-            get_inheritable_declaration_value(&element.style, &inherit_declarations, k, d)
+        for declaration in sort_by_cascade_origin(declarations) {
+            element.style.insert(declaration.0, declaration.1);
+        }
+        for (pseudo, declarations) in pseudo_origin_declarations {
+            pseudo_declarations.insert(pseudo, sort_by_cascade_origin(declarations));
+        }
+    }
+
+    let custom_properties =
+        compute_custom_properties(&element.style, inherit_declarations, &context.registrations);
+    substitute_var_references(&mut element.style, &custom_properties);
+
+    return compute_element_style(
+        element,
+        context.preferred_color_scheme,
+        pseudo_declarations,
+        custom_properties,
+        inherit_declarations,
+        length_context,
+    );
+}
+
+// The computed style of an element from its cascaded declarations, after its
+// custom properties were substituted.
+fn compute_element_style(
+    element: &DomElement,
+    preferred_color_scheme: ColorScheme,
+    pseudo_declarations: HashMap<String, Vec<KeyValue>>,
+    custom_properties: HashMap<String, String>,
+    inherit_declarations: &HashMap<String, CssValue>,
+    length_context: &LengthContext,
+) -> SharedStyle {
+    let mut new_inherit_declarations = inherit_declarations.clone();
+    new_inherit_declarations.retain(|k, _| !is_custom_property(k));
+    for (key, value) in &custom_properties {
+        new_inherit_declarations.insert(key.clone(), CssValue::String(value.clone()));
+    }
+
+    // computed values of the properties without a dedicated field
+    let mut values: HashMap<String, String> = HashMap::new();
+    for property in PROPERTIES {
+        let inherited = inherit_declarations.get(property.name).map(|v| v.to_css_string());
+        let initial = S(property.initial_value);
+        let value = match element.style.get(property.name) {
+            Some(v) => {
+                let v = v.replace("!important", "").trim().to_string();
+                match v.as_str() {
+                    "inherit" => inherited.unwrap_or(initial),
+                    "initial" => initial,
+                    "unset" | "revert" | "revert-layer" if property.inherited => {
+                        inherited.unwrap_or(initial)
+                    }
+                    "unset" | "revert" | "revert-layer" => initial,
+                    _ => v,
+                }
+            }
+            None if property.inherited => inherited.unwrap_or(initial),
+            None => initial,
         };
 
-        let display = get_declaration_value(&element.style, "display", "inline-block");
+        // children only take the others when they say `inherit`
+        new_inherit_declarations.insert(S(property.name), CssValue::String(value.clone()));
+        values.insert(S(property.name), value);
+    }
+
+    let get_inherit_value = |k: &str, d: CssValue| {
+        get_inheritable_declaration_value(&element.style, &inherit_declarations, k, d)
+    };
+
+    let display = get_declaration_value(&element.style, "display", "inline");
+
+    let background_color_css =
+        get_declaration_value(&element.style, "background-color", "none");
+
+    let parent_font_weight = match inherit_declarations.get("font-weight") {
+        Some(w) => parse_font_weight(w.to_string(), 400.0),
+        None => 400.0,
+    };
+    let font_weight = match element.style.get("font-weight") {
+        Some(w) if w != "inherit" => parse_font_weight(w, parent_font_weight),
+        _ => parent_font_weight,
+    };
+    let font_weight_css = CssValue::String(font_weight.to_string());
+
+    let font_style_css = get_inherit_value("font-style", css_string("normal"));
+    let font_style = font_style_css.to_string();
+
+    let font_family_css = get_inherit_value("font-family", css_string("Times New Roman"));
+    let font_family = font_family_css.to_string();
 
-        let background_color_css =
-            get_declaration_value(&element.style, "background-color", "none");
+    let text_decoration_css = get_inherit_value("text-decoration-line", css_string("none"));
+    let text_decoration = text_decoration_css.to_string();
 
-        let font_weight_css = get_inherit_value("font-weight", css_string("normal"));
-        let font_weight = font_weight_css.to_string();
+    let color_css = get_inherit_value("color", css_string("canvastext"));
 
-        let font_style_css = get_inherit_value("font-style", css_string("normal"));
-        let font_style = font_style_css.to_string();
+    let color_scheme = get_used_color_scheme(&values["color-scheme"], preferred_color_scheme);
 
-        let font_family_css = get_inherit_value("font-family", css_string("Times New Roman"));
-        let font_family = font_family_css.to_string();
+    // `currentColor` in `color` is the inherited color
+    let parent_color = match inherit_declarations.get("color") {
+        Some(CssValue::Color(c)) => *c,
+        Some(CssValue::String(c)) => {
+            parse_css_color_with_current(c, &Color::BLACK, color_scheme).unwrap_or(Color::BLACK)
+        }
+        _ => Color::BLACK,
+    };
+    let color = match color_css.clone() {
+        CssValue::Color(c) => c,
+        CssValue::String(c) => match parse_css_color_with_current(&c, &parent_color, color_scheme) {
+            Ok(c) => c,
+            Err(e) => {
+                println!("{}", e);
+                Color::BLACK
+            }
+        },
+    };
+
+    let background_color = if background_color_css == "none" || background_color_css == "transparent" {
+        Color::TRANSPARENT
+    } else {
+        match parse_css_color_with_current(&background_color_css, &color, color_scheme) {
+            Ok(c) => c,
+            Err(e) => {
+                println!("{}", e);
+                Color::TRANSPARENT
+            }
+        }
+    };
+
+    let opacity_css = get_declaration_value(&element.style, "opacity", "1");
+    let opacity = match opacity_css.strip_suffix('%') {
+        Some(p) => p.trim().parse::<f64>().map(|p| p / 100.0),
+        None => opacity_css.trim().parse::<f64>(),
+    }
+    .unwrap_or(1.0)
+    .max(0.0)
+    .min(1.0);
+
+    let mut font_path: String = "Times New Roman 400.ttf".to_string();
+
+    new_inherit_declarations.insert(S("font-family"), font_family_css.clone());
+    new_inherit_declarations.insert(S("font-weight"), font_weight_css.clone());
+    new_inherit_declarations.insert(S("font-style"), font_style_css.clone());
+    new_inherit_declarations.insert(S("text-decoration-line"), text_decoration_css.clone());
+    // system colors compute to themselves, so descendants with another color
+    // scheme resolve them again
+    let inherited_color = match &color_css {
+        CssValue::String(c) if get_system_color(c.trim(), color_scheme).is_some() => color_css.clone(),
+        _ => CssValue::Color(color),
+    };
+    new_inherit_declarations.insert(S("color"), inherited_color);
+
+    let font_families = split_css_list(&font_family);
+    let primary_font_family = font_families
+        .first()
+        .map(|f| f.trim_matches(|c| c == '"' || c == '\'').to_lowercase())
+        .unwrap_or(S(""));
+
+    if primary_font_family == "times new roman" {
+        if font_weight >= 600.0 {
+            if font_style == "italic" {
+                font_path = "Times New Roman Italique 700.ttf".to_string();
+            } else {
+                font_path = "Times New Roman 700.ttf".to_string();
+            }
+        } else {
+            if font_style == "italic" {
+                font_path = "Times New Roman Italique 400.ttf".to_string();
+            } else {
+                font_path = "Times New Roman 400.ttf".to_string();
+            }
+        }
+    }
 
-        let text_decoration_css = get_inherit_value("text-decoration", css_string("none"));
-        let text_decoration = text_decoration_css.to_string();
+    let font_size = match element.style.get("font-size") {
+        Some(f) if f != "inherit" => parse_font_size(f, length_context),
+        _ => length_context.font_size,
+    };
 
-        let color_css = get_inherit_value("color", css_string("#000"));
+    let mut element_length_context = length_context.with_font_size(font_size);
+    if element.tag_name == "HTML" {
+        element_length_context.root_font_size = font_size;
+    }
 
-        let color = match color_css {
-            CssValue::Color(c) => c,
-            CssValue::String(c) => match parse_css_color(&c) {
-                Ok(c) => c,
+    let line_height = get_line_height(&values["line-height"], &element_length_context);
+    let line_height_css = match line_height {
+        LineHeight::Normal => S("normal"),
+        LineHeight::Number(n) => n.to_string(),
+        LineHeight::Length(l) => format!("{}px", l),
+    };
+    new_inherit_declarations.insert(S("line-height"), CssValue::String(line_height_css.clone()));
+    values.insert(S("line-height"), line_height_css);
+
+    let get_length_declaration_value = |k: &str| -> LengthPercentage {
+        let value = get_declaration_value(&element.style, k, "0");
+        if value == "auto" {
+            return LengthPercentage::zero();
+        }
+        match parse_length_percentage(&value) {
+            Ok(v) => v,
+            Err(e) => {
+                println!("{}", e);
+                LengthPercentage::zero()
+            }
+        }
+    };
+
+    let margin_length = MarginLength {
+        top: get_length_declaration_value("margin-top"),
+        right: get_length_declaration_value("margin-right"),
+        bottom: get_length_declaration_value("margin-bottom"),
+        left: get_length_declaration_value("margin-left"),
+    };
+
+    let padding_length = MarginLength {
+        top: get_length_declaration_value("padding-top"),
+        right: get_length_declaration_value("padding-right"),
+        bottom: get_length_declaration_value("padding-bottom"),
+        left: get_length_declaration_value("padding-left"),
+    };
+
+    let get_size_declaration_value = |k: &str| -> Option<LengthPercentage> {
+        let value = values[k].as_str();
+        match value {
+            "auto" | "none" | "min-content" | "max-content" | "fit-content" => None,
+            _ => match parse_length_percentage(value) {
+                Ok(v) => Some(v),
                 Err(e) => {
                     println!("{}", e);
-                    (0.0, 0.0, 0.0, 1.0)
+                    None
                 }
             },
-            CssValue::Number(_) => (0.0, 0.0, 0.0, 1.0),
-        };
-
-        let background_color = if background_color_css == "none" {
-            (0.0, 0.0, 0.0, 0.0)
-        } else {
-            match parse_css_color(&background_color_css) {
-                Ok(c) => c,
+        }
+    };
+
+    let inset = Inset {
+        top: get_size_declaration_value("top"),
+        right: get_size_declaration_value("right"),
+        bottom: get_size_declaration_value("bottom"),
+        left: get_size_declaration_value("left"),
+    };
+    let position = values["position"].clone();
+    let z_index = match values["z-index"].trim().parse::<i32>() {
+        Ok(z) => Some(z),
+        Err(_) => None,
+    };
+    let (overflow_x, overflow_y) = get_computed_overflow(&values["overflow-x"], &values["overflow-y"]);
+    // absolutely positioned boxes don't float
+    let float = match position.as_str() {
+        "absolute" | "fixed" => S("none"),
+        _ => get_declaration_value(&element.style, "float", "none"),
+    };
+
+    let width = get_size_declaration_value("width");
+    let height = get_size_declaration_value("height");
+    let min_width = get_size_declaration_value("min-width");
+    let min_height = get_size_declaration_value("min-height");
+    let max_width = get_size_declaration_value("max-width");
+    let max_height = get_size_declaration_value("max-height");
+
+    let border = get_computed_border(&values, &element_length_context, &color, color_scheme);
+
+    let get_radius_value = |corner: &str| -> (LengthPercentage, LengthPercentage) {
+        let value = &values[&format!("border-{}-radius", corner)];
+        let radii: Vec<LengthPercentage> = split_css_values(value)
+            .iter()
+            .map(|r| match parse_length_percentage(r) {
+                Ok(r) => r,
                 Err(e) => {
                     println!("{}", e);
-                    (0.0, 0.0, 0.0, 0.0)
+                    LengthPercentage::zero()
                 }
+            })
+            .collect();
+        match radii.len() {
+            0 => (LengthPercentage::zero(), LengthPercentage::zero()),
+            1 => (radii[0].clone(), radii[0].clone()),
+            _ => (radii[0].clone(), radii[1].clone()),
+        }
+    };
+
+    let border_radius = BorderRadius {
+        top_left: get_radius_value("top-left"),
+        top_right: get_radius_value("top-right"),
+        bottom_right: get_radius_value("bottom-right"),
+        bottom_left: get_radius_value("bottom-left"),
+    };
+
+    // percentages are resolved again against the containing block in reflow
+    let margin = margin_length.resolve(&element_length_context, length_context.viewport_width);
+    let padding = padding_length.resolve(&element_length_context, length_context.viewport_width);
+
+    let computed_style = ComputedStyle {
+        margin: margin,
+        padding: padding,
+        margin_length: margin_length,
+        padding_length: padding_length,
+        width: width,
+        height: height,
+        min_width: min_width,
+        min_height: min_height,
+        max_width: max_width,
+        max_height: max_height,
+        border: border,
+        border_radius: border_radius,
+        length_context: element_length_context,
+        background_color: background_color,
+        color: color,
+        opacity: opacity,
+        color_scheme: color_scheme,
+        font_size: font_size,
+        line_height: line_height,
+        font_path: font_path.to_string(),
+        font_family: font_family.to_string(),
+        font_weight: font_weight,
+        font_style: font_style.to_string(),
+        text_decoration: text_decoration.to_string(),
+        display: display.to_string(),
+        float: float,
+        position: position,
+        inset: inset,
+        z_index: z_index,
+        overflow_x: overflow_x,
+        overflow_y: overflow_y,
+        state_dependencies: ElementStates::new(),
+        custom_properties: custom_properties,
+        values: values,
+        inherited_declarations: inherit_declarations.clone(),
+        parent_length_context: length_context.clone(),
+        counters: CounterState::new(),
+    };
+
+
+    SharedStyle {
+        style: element.style.clone(),
+        pseudo_declarations,
+        computed_style,
+        new_inherit_declarations,
+    }
+}
+
+fn get_border_side(
+    values: &HashMap<String, String>,
+    side: &str,
+    length_context: &LengthContext,
+    color: &Color,
+    color_scheme: ColorScheme,
+) -> BorderSide {
+    let style = values[&format!("border-{}-style", side)].clone();
+    let width = match values[&format!("border-{}-width", side)].as_str() {
+        _ if style == "none" || style == "hidden" => 0.0,
+        "thin" => 1.0,
+        "medium" => 3.0,
+        "thick" => 5.0,
+        w => match parse_length(w) {
+            Ok(l) => l.to_px(length_context).max(0.0),
+            Err(e) => {
+                println!("{}", e);
+                3.0
             }
-        };
+        },
+    };
+    let color = match parse_css_color_with_current(&values[&format!("border-{}-color", side)], color, color_scheme) {
+        Ok(c) => c,
+        Err(e) => {
+            println!("{}", e);
+            *color
+        }
+    };
+    BorderSide { width, style, color }
+}
+
+// The border from the computed values of its longhands. Tables change the
+// `border` of a computed style as they lay out, this is what it was.
+pub fn get_computed_border(
+    values: &HashMap<String, String>,
+    length_context: &LengthContext,
+    color: &Color,
+    color_scheme: ColorScheme,
+) -> Border {
+    Border {
+        top: get_border_side(values, "top", length_context, color, color_scheme),
+        right: get_border_side(values, "right", length_context, color, color_scheme),
+        bottom: get_border_side(values, "bottom", length_context, color, color_scheme),
+        left: get_border_side(values, "left", length_context, color, color_scheme),
+    }
+}
+
+// `visible` and `clip` only stay when the other axis is `visible` or `clip`
+// too, a box can't scroll in one direction and overflow in the other.
+fn get_computed_overflow(overflow_x: &str, overflow_y: &str) -> (String, String) {
+    let is_valid = |o: &str| match o {
+        "visible" | "hidden" | "clip" | "scroll" | "auto" => true,
+        _ => false,
+    };
+    let overflow_x = if is_valid(overflow_x.trim()) { overflow_x.trim() } else { "visible" };
+    let overflow_y = if is_valid(overflow_y.trim()) { overflow_y.trim() } else { "visible" };
+    let is_clipping = |o: &str| o == "visible" || o == "clip";
+    if is_clipping(overflow_x) == is_clipping(overflow_y) {
+        return (S(overflow_x), S(overflow_y));
+    }
+    let scrolling = |o: &str| match o {
+        "visible" => S("auto"),
+        "clip" => S("hidden"),
+        o => S(o),
+    };
+    return (scrolling(overflow_x), scrolling(overflow_y));
+}
 
-        let mut base_font_size = 16.0;
-        let mut font_size: f64 = base_font_size;
-        let mut font_path: String = "Times New Roman 400.ttf".to_string();
-
-        new_inherit_declarations.insert(S("font-family"), font_family_css.clone());
-        new_inherit_declarations.insert(S("font-weight"), font_weight_css.clone());
-        new_inherit_declarations.insert(S("font-style"), font_style_css.clone());
-        new_inherit_declarations.insert(S("text-decoration"), text_decoration_css.clone());
-        new_inherit_declarations.insert(S("color"), CssValue::Color(color));
-
-        if font_family.to_lowercase() == "times new roman" {
-            if font_weight == "bold" {
-                if font_style == "italic" {
-                    font_path = "Times New Roman Italique 700.ttf".to_string();
-                } else {
-                    font_path = "Times New Roman 700.ttf".to_string();
+fn compute_element_styles(
+    element: &mut DomElement,
+    context: &StyleContext,
+    traversal: &mut StyleTraversal,
+    inherit_declarations: &HashMap<String, CssValue>,
+    length_context: &LengthContext,
+    scoped_counters: &mut Vec<String>,
+    sharing_cache: &mut HashMap<StyleSharingKey, SharedStyle>,
+) -> StyleChange {
+    let mut change = StyleChange::None;
+    let counters_before = traversal.counters.clone();
+
+    let mut matched_rules: Vec<MatchedRule> = vec![];
+    let mut hints: Vec<KeyValue> = vec![];
+    let mut state_dependencies = ElementStates::new();
+    if element.pseudo_element.is_none() {
+        let result = context
+            .rule_index
+            .match_element(element, &traversal.ancestors, Some(&traversal.filter));
+        matched_rules = result.matched_rules;
+        state_dependencies = result.state_dependencies;
+        traversal.ancestor_dependencies.extend(result.ancestor_dependencies);
+        hints = get_presentational_hints(element);
+    }
+
+    let sharing_key = if element.pseudo_element.is_none() && !element.attributes.contains_key("style") {
+        Some(StyleSharingKey {
+            tag_name: element.tag_name.clone(),
+            matched_rules: matched_rules
+                .iter()
+                .map(|m| (m.rule, m.pseudo_element.clone()))
+                .collect(),
+            hints: hints.iter().map(|h| (h.0.clone(), h.1.clone())).collect(),
+        })
+    } else {
+        None
+    };
+
+    let shared = match sharing_key.as_ref().and_then(|k| sharing_cache.get(k)) {
+        Some(shared) => {
+            element.style = shared.style.clone();
+            shared.clone()
+        }
+        None => {
+            let shared = cascade_element_style(
+                element,
+                context,
+                &matched_rules,
+                hints,
+                inherit_declarations,
+                length_context,
+            );
+            match sharing_key {
+                Some(key) => {
+                    sharing_cache.insert(key, shared.clone());
                 }
-            } else {
-                if font_style == "italic" {
-                    font_path = "Times New Roman Italique 400.ttf".to_string();
-                } else {
-                    font_path = "Times New Roman 400.ttf".to_string();
+                None => {}
+            }
+            shared
+        }
+    };
+
+    let mut computed_style = shared.computed_style;
+    computed_style.counters = counters_before;
+
+    {
+        let values = &computed_style.values;
+        let counters = &mut traversal.counters;
+
+        // counters are reset, then incremented, then set
+        let list_items = element.children.iter().filter(|c| c.tag_name == "LI").count() as i64;
+        for (name, value) in parse_counter_list(&values["counter-reset"]) {
+            let (name, mut reversed) = parse_reversed_counter_name(&name);
+            let mut value = value;
+            if name == "list-item" && element.tag_name == "OL" {
+                reversed = reversed || element.attributes.contains_key("reversed");
+                let start = element.attributes.get("start").and_then(|s| s.trim().parse::<i64>().ok());
+                match start.map(clamp_counter_value) {
+                    Some(start) if reversed => value = Some(start.saturating_add(1)),
+                    Some(start) => value = Some(start.saturating_sub(1)),
+                    None => {}
                 }
             }
+            let value = match value {
+                Some(v) => v,
+                None if reversed => list_items + 1,
+                None => 0,
+            };
+            counters.reset(&name, value, reversed);
+            scoped_counters.push(name);
         }
 
-        // TODO: simplify
-        {
-            let font_size_inherit = inherit_declarations.get("font-size");
-            let font_size_str = element.style.get("font-size");
-
-            match font_size_str {
-                Some(f) => {
-                    match font_size_inherit {
-                        Some(f_i) => {
-                            base_font_size = (*f_i).to_number();
-                        }
-                        None => {}
-                    }
+        let mut increments = parse_counter_list(&values["counter-increment"]);
+        if values["display"] == "list-item" && !increments.iter().any(|c| c.0 == "list-item") {
+            increments.push((S("list-item"), None));
+        }
+        for (name, value) in increments {
+            let value = value.unwrap_or(if counters.is_reversed(&name) { -1 } else { 1 });
+            if !counters.increment(&name, value) {
+                counters.reset(&name, value, false);
+                scoped_counters.push(name);
+            }
+        }
 
-                    font_size = parse_numeric_css_value(&f, base_font_size);
-                }
-                None => match font_size_inherit {
-                    Some(f_i) => {
-                        font_size = (*f_i).to_number();
-                    }
-                    None => {}
-                },
+        let mut sets = parse_counter_list(&values["counter-set"]);
+        if element.tag_name == "LI" {
+            match element.attributes.get("value").and_then(|s| s.trim().parse::<i64>().ok()) {
+                Some(v) => sets.push((S("list-item"), Some(clamp_counter_value(v)))),
+                None => {}
             }
+        }
+        for (name, value) in sets {
+            let value = value.unwrap_or(0);
+            if !counters.set(&name, value) {
+                counters.reset(&name, value, false);
+                scoped_counters.push(name);
+            }
+        }
 
-            new_inherit_declarations.insert("font-size".to_string(), CssValue::Number(font_size));
+        if let Some(pseudo_element) = &element.pseudo_element {
+            let quotes = parse_quotes(&values["quotes"]);
+            let content = &values["content"];
+
+            let text = if pseudo_element.name == "marker" && content == "normal" {
+                format_marker(counters, &values["list-style-type"])
+            } else if pseudo_element.name == "first-letter"
+                || pseudo_element.name == "first-line"
+                || pseudo_element.name == "after-first-line"
+            {
+                None
+            } else {
+                generate_content(content, &pseudo_element.originating_attributes, counters, &quotes)
+            };
+
+            match text {
+                Some(text) if get_children_signature(&element.children) != vec![(S(""), text.clone())] => {
+                    element.children = vec![DomElement::new_text(&text)];
+                    change = StyleChange::Layout;
+                }
+                _ => {}
+            }
         }
+    }
 
-        let get_numeric_declaration_value = |k: &str| -> f64 {
-            parse_numeric_css_value(
-                &get_declaration_value(&element.style, k, "0"),
-                base_font_size,
-            )
-        };
+    let signature = get_children_signature(&element.children);
+    let mut removed: Vec<DomElement> = vec![];
+    if element.pseudo_element.is_none() {
+        removed = remove_generated_content(&mut element.children);
+        generate_pseudo_elements(element, &computed_style.display, &shared.pseudo_declarations, &mut removed);
+    }
 
-        let margin_top = get_numeric_declaration_value("margin-top");
-        let margin_bottom = get_numeric_declaration_value("margin-bottom");
-        let margin_left = get_numeric_declaration_value("margin-left");
-        let margin_right = get_numeric_declaration_value("margin-right");
+    if element.children.len() > 0 && element.tag_name != "SCRIPT" && element.tag_name != "STYLE" {
+        traversal.push_ancestor(element);
+        change = change.max(compute_styles_with_traversal(
+            &mut element.children,
+            context,
+            traversal,
+            &shared.new_inherit_declarations,
+            &computed_style.length_context,
+        ));
+        traversal.pop_ancestor();
+    }
 
-        let padding_top = get_numeric_declaration_value("padding-top");
-        let padding_bottom = get_numeric_declaration_value("padding-bottom");
-        let padding_left = get_numeric_declaration_value("padding-left");
-        let padding_right = get_numeric_declaration_value("padding-right");
+    if element.pseudo_element.is_none() {
+        change = change.max(generate_first_line(
+            element,
+            &computed_style.display,
+            &shared.pseudo_declarations,
+            &mut removed,
+            context,
+            &shared.new_inherit_declarations,
+            &computed_style.length_context,
+            &traversal.counters,
+        ));
+    }
+    if signature != get_children_signature(&element.children) {
+        change = StyleChange::Layout;
+    }
 
-        let element = &mut tree[i];
+    // descendants whose selectors depend on a state of this element
+    let depth = traversal.ancestors.len();
+    traversal.ancestor_dependencies.retain(|(d, states)| {
+        if *d == depth {
+            state_dependencies = state_dependencies.union(states);
+            false
+        } else {
+            true
+        }
+    });
+    computed_style.state_dependencies = state_dependencies;
 
-        if element.children.len() > 0 && element.tag_name != "SCRIPT" && element.tag_name != "STYLE" {
-            compute_styles(&mut element.children, style, Some(new_inherit_declarations));
+    let own_change = match &element.computed_style {
+        Some(previous_style) => compare_computed_styles(previous_style, &computed_style),
+        None => StyleChange::Layout,
+    };
+    element.computed_style = Some(computed_style);
+
+    return change.max(own_change);
+}
+
+// Restyles the element at `path` and its descendants with what its parent
+// passed down last time. Counter changes don't reach the following siblings.
+fn restyle_element_with_context(tree: &mut Vec<DomElement>, path: &[usize], context: &StyleContext) -> StyleChange {
+    let mut traversal = StyleTraversal::new(CounterState::new());
+    for len in 1..path.len() {
+        match get_element_by_path(tree, &path[..len]) {
+            Some(ancestor) => traversal.push_ancestor(ancestor),
+            None => return StyleChange::None,
         }
+    }
 
-        element.computed_style = Some(ComputedStyle {
-            margin: Margin {
-                top: margin_top,
-                right: margin_right,
-                bottom: margin_bottom,
-                left: margin_left,
-            },
-            padding: Margin {
-                top: padding_top,
-                right: padding_right,
-                bottom: padding_bottom,
-                left: padding_left,
+    let element = match get_element_by_path_mut(tree, path) {
+        Some(e) => e,
+        None => return StyleChange::None,
+    };
+    let computed_style = match element.computed_style.as_ref() {
+        Some(c) => c,
+        None => return StyleChange::Layout,
+    };
+
+    let inherit_declarations = computed_style.inherited_declarations.clone();
+    let length_context = computed_style.parent_length_context.clone();
+    traversal.counters = computed_style.counters.clone();
+
+    return compute_element_styles(
+        element,
+        context,
+        &mut traversal,
+        &inherit_declarations,
+        &length_context,
+        &mut vec![],
+        &mut HashMap::new(),
+    );
+}
+
+// Computes the style of `element` and its descendants again from what they
+// cascaded last time, with what a new parent passes down. Content that moves
+// out of the `::first-line` box after line breaking is restyled this way.
+pub fn inherit_styles(
+    element: &mut DomElement,
+    inherit_declarations: &HashMap<String, CssValue>,
+    length_context: &LengthContext,
+) {
+    let previous = match element.computed_style.take() {
+        Some(c) => c,
+        None => return,
+    };
+    // custom properties and `var()` were substituted with what the parent
+    // passed down, and the used color scheme is the preferred one whenever
+    // that matters
+    let shared = compute_element_style(
+        element,
+        previous.color_scheme,
+        HashMap::new(),
+        previous.custom_properties.clone(),
+        inherit_declarations,
+        length_context,
+    );
+    let mut computed_style = shared.computed_style;
+    computed_style.counters = previous.counters;
+    computed_style.state_dependencies = previous.state_dependencies;
+    for child in element.children.iter_mut() {
+        inherit_styles(child, &shared.new_inherit_declarations, &computed_style.length_context);
+    }
+    element.computed_style = Some(computed_style);
+}
+
+// Restyles the subtrees of the changed elements whose style depends on one of
+// `flags`, the invalidation set of those states. Without flags they are
+// restyled unconditionally. Returns what has to be done after restyling.
+pub fn restyle_changed_elements(
+    tree: &mut Vec<DomElement>,
+    changed: &Vec<Vec<usize>>,
+    flags: &[ElementStateFlag],
+    style: &Vec<StyleRule>,
+    preferred_color_scheme: ColorScheme,
+) -> StyleChange {
+    let mut paths = changed
+        .iter()
+        .filter(|path| match get_element_by_path(tree, path) {
+            Some(element) if flags.len() > 0 => match element.computed_style.as_ref() {
+                Some(c) => flags.iter().any(|f| c.state_dependencies.get(*f)),
+                None => false,
             },
-            background_color: background_color,
-            color: color,
-            font_size: font_size,
-            font_path: font_path.to_string(),
-            text_decoration: text_decoration.to_string(),
-            display: display.to_string(),
-            float: get_declaration_value(&element.style, "float", "none"),
-            hoverable: hoverable,
-        });
+            Some(_) => true,
+            None => false,
+        })
+        .cloned()
+        .collect::<Vec<Vec<usize>>>();
+    paths.sort();
+
+    // a restyled element takes its descendants along
+    let context = StyleContext::new(style, preferred_color_scheme);
+    let mut restyled: Vec<Vec<usize>> = vec![];
+    let mut change = StyleChange::None;
+    for path in paths {
+        if restyled.iter().any(|r| path.starts_with(r)) {
+            continue;
+        }
+        change = change.max(restyle_element_with_context(tree, &path, &context));
+        restyled.push(path);
     }
+
+    return change;
 }
 
 #[derive(Clone, Debug)]
 pub struct ReflowContext {
     pub x: f64,
     pub y: f64,
-    pub adjacent_margin_bottom: f64,
+    pub containing_width: f64,
 }
 
-fn is_horizontal_layout(computed_style: &ComputedStyle) -> bool {
-    return computed_style.display == "inline-block" || computed_style.display == "inline" || computed_style.float != "none";
+pub fn is_outside_marker(element: &DomElement) -> bool {
+    match (&element.pseudo_element, &element.computed_style) {
+        (Some(pseudo_element), Some(computed_style)) => {
+            pseudo_element.name == "marker"
+                && computed_style.values.get("list-style-position").map(|p| p.as_str()) == Some("outside")
+        }
+        _ => false,
+    }
 }
 
-pub fn reflow(
-    tree: &mut Vec<DomElement>,
+pub fn is_displayed(element: &DomElement) -> bool {
+    match element.computed_style.as_ref() {
+        Some(computed_style) => computed_style.display != "none",
+        None => false,
+    }
+}
+
+// Absolutely positioned boxes are laid out after the flow, see
+// `layout_positioned_elements`.
+pub fn is_out_of_flow(element: &DomElement) -> bool {
+    match element.computed_style.as_ref() {
+        Some(c) => element.node_type == NodeType::Element && (c.position == "absolute" || c.position == "fixed"),
+        None => false,
+    }
+}
+
+// Block-level boxes stack vertically, everything else goes in line boxes.
+// Floats are placed from the line they come in, and out-of-flow boxes
+// leave their static position there.
+pub fn is_block_level(element: &DomElement) -> bool {
+    if element.node_type != NodeType::Element || !is_displayed(element) || is_out_of_flow(element) {
+        return false;
+    }
+    let computed_style = element.computed_style.as_ref().unwrap();
+    return computed_style.float == "none" && !computed_style.display.starts_with("inline");
+}
+
+// Percentages of margins and paddings refer to the containing block's width.
+pub fn resolve_box_edges(computed_style: &mut ComputedStyle, containing_width: f64) {
+    computed_style.margin = computed_style
+        .margin_length
+        .resolve(&computed_style.length_context, containing_width);
+    computed_style.padding = computed_style
+        .padding_length
+        .resolve(&computed_style.length_context, containing_width);
+    if is_collapsing_table(computed_style) {
+        computed_style.padding = Margin::zero();
+    }
+}
+
+// Hovered elements keep the hover rect they had before they were hovered,
+// text nodes keep their measurement.
+pub fn set_computed_flow(element: &mut DomElement, mut flow: ComputedFlow) {
+    let previous = element.computed_flow.take();
+    flow.hover_rect = match &previous {
+        Some(p) if element.state.hover && element.computed_style.is_some() => p.hover_rect.clone(),
+        _ => Rect {
+            x: flow.x,
+            y: flow.y,
+            width: flow.width,
+            height: flow.height,
+        },
+    };
+    if flow.text_measurement.is_none() {
+        flow.text_measurement = previous.and_then(|p| p.text_measurement);
+    }
+    element.computed_flow = Some(flow);
+}
+
+pub fn clear_computed_flow(element: &mut DomElement) {
+    element.computed_flow = None;
+}
+
+pub fn translate_element(element: &mut DomElement, dx: f64, dy: f64) {
+    let is_hovered = element.state.hover;
+    match element.computed_flow.as_mut() {
+        Some(computed_flow) => {
+            computed_flow.x += dx;
+            computed_flow.y += dy;
+            if !is_hovered {
+                computed_flow.hover_rect.x += dx;
+                computed_flow.hover_rect.y += dy;
+            }
+            for line in &mut computed_flow.text_lines {
+                line.x += dx;
+                line.y += dy;
+                line.baseline += dy;
+            }
+            for fragment in &mut computed_flow.fragments {
+                fragment.x += dx;
+                fragment.y += dy;
+            }
+            computed_flow.baseline = computed_flow.baseline.map(|b| b + dy);
+        }
+        None => {}
+    }
+    for child in &mut element.children {
+        translate_element(child, dx, dy);
+    }
+}
+
+pub fn has_laid_out_children(element: &DomElement) -> bool {
+    element.tag_name != "SCRIPT" && element.tag_name != "STYLE"
+}
+
+// The narrowest and widest the content box of `element` can get without
+// overflowing or wrapping more than it has to.
+pub fn get_content_widths(
+    element: &mut DomElement,
     measure_text: &dyn Fn(String, f64, String) -> (f64, f64),
-    context: Option<ReflowContext>,
-) {
-    let elements_len = tree.len();
+) -> (f64, f64) {
+    if !has_laid_out_children(element) {
+        return (0.0, 0.0);
+    }
+    match element.computed_style.as_ref() {
+        Some(c) if is_flex_container(c) => return get_flex_content_widths(element, measure_text),
+        Some(c) if is_grid_container(c) => return get_grid_content_widths(element, measure_text),
+        Some(c) if is_table(c) => return get_table_content_widths(element, measure_text),
+        _ => {}
+    }
+    let len = element.children.len();
+    return get_block_content_widths(&mut element.children, 0..len, measure_text);
+}
 
-    let mut context = context.unwrap_or(ReflowContext {
-        x: 0.0,
-        y: 0.0,
-        adjacent_margin_bottom: 0.0,
-    });
+// Like `get_content_widths`, for the children in `range` of a block
+// container.
+pub fn get_block_content_widths(
+    children: &mut Vec<DomElement>,
+    range: Range<usize>,
+    measure_text: &dyn Fn(String, f64, String) -> (f64, f64),
+) -> (f64, f64) {
+    let mut min_width: f64 = 0.0;
+    let mut max_width: f64 = 0.0;
+    let mut i = range.start;
+    while i < range.end {
+        let widths = if is_internal_table_box(&children[i]) {
+            let start = i;
+            i = get_table_run_end(children, start, range.end);
+            get_anonymous_table_widths(children, start..i, measure_text)
+        } else if is_block_level(&children[i]) {
+            i += 1;
+            get_intrinsic_widths(&mut children[i - 1], measure_text)
+        } else {
+            let start = i;
+            while i < range.end && !is_block_level(&children[i]) {
+                i += 1;
+            }
+            get_inline_content_widths(children, start..i, measure_text)
+        };
+        min_width = min_width.max(widths.0);
+        max_width = max_width.max(widths.1);
+    }
+    return (min_width, max_width);
+}
 
-    let x_base = context.x;
-    let y_base = context.y;
+// Like `get_content_widths`, for the margin box.
+pub fn get_intrinsic_widths(
+    element: &mut DomElement,
+    measure_text: &dyn Fn(String, f64, String) -> (f64, f64),
+) -> (f64, f64) {
+    let (min_width, max_width) = get_content_widths(element, measure_text);
+    let computed_style = element.computed_style.as_ref().unwrap();
+    // percentages depend on the size being computed, they count as zero
+    let margin = computed_style.margin_length.resolve(&computed_style.length_context, 0.0);
+    let padding = computed_style.padding_length.resolve(&computed_style.length_context, 0.0);
+    let border = computed_style.border.widths();
+    let edges = margin.left + margin.right + padding.left + padding.right + border.left + border.right;
+    return (min_width + edges, max_width + edges);
+}
 
-    let mut reserved_block_y = y_base;
+// The content box size `size` asks for, or `None` when it is `auto` or a
+// percentage of a size that isn't known. `edges` are the padding and
+// borders `box-sizing: border-box` takes out.
+pub fn resolve_size(
+    computed_style: &ComputedStyle,
+    size: &Option<LengthPercentage>,
+    basis: Option<f64>,
+    edges: f64,
+) -> Option<f64> {
+    let size = size.as_ref()?;
+    if size.has_percentage() && basis.is_none() {
+        return None;
+    }
+    let value = size.resolve(&computed_style.length_context, basis.unwrap_or(0.0));
+    if computed_style.values.get("box-sizing").map(|b| b.as_str()) == Some("border-box") {
+        return Some(f64::max(0.0, value - edges));
+    }
+    return Some(f64::max(0.0, value));
+}
 
-    let mut last_element: Option<usize> = None;
+// A maximum is applied before a minimum, so the minimum wins.
+pub fn clamp_size(value: f64, min: Option<f64>, max: Option<f64>) -> f64 {
+    let value = match max {
+        Some(max) => value.min(max),
+        None => value,
+    };
+    match min {
+        Some(min) => value.max(min),
+        None => value,
+    }
+}
 
-    for i in 0..elements_len {
-        let mut x = x_base;
-        let mut y = y_base;
+pub fn resolve_width(computed_style: &ComputedStyle, containing_width: f64) -> (Option<f64>, Option<f64>, Option<f64>) {
+    let padding = &computed_style.padding;
+    let border = computed_style.border.widths();
+    let edges = padding.left + padding.right + border.left + border.right;
+    let basis = Some(containing_width);
+    (
+        resolve_size(computed_style, &computed_style.width, basis, edges),
+        resolve_size(computed_style, &computed_style.min_width, basis, edges),
+        resolve_size(computed_style, &computed_style.max_width, basis, edges),
+    )
+}
 
-        let mut width = 0.0;
-        let mut height = 0.0;
+pub fn resolve_height(
+    computed_style: &ComputedStyle,
+    containing_height: Option<f64>,
+) -> (Option<f64>, Option<f64>, Option<f64>) {
+    let padding = &computed_style.padding;
+    let border = computed_style.border.widths();
+    let edges = padding.top + padding.bottom + border.top + border.bottom;
+    (
+        resolve_size(computed_style, &computed_style.height, containing_height, edges),
+        resolve_size(computed_style, &computed_style.min_height, containing_height, edges),
+        resolve_size(computed_style, &computed_style.max_height, containing_height, edges),
+    )
+}
 
-        let computed_style = &tree[i].computed_style.as_ref().unwrap();
+pub fn is_auto_margin(computed_style: &ComputedStyle, side: &str) -> bool {
+    computed_style.values.get(&format!("margin-{}", side)).map(|m| m.as_str()) == Some("auto")
+}
 
-        if computed_style.display == "none" {
-            continue;
+// The content width and the left and right margins of a block-level box in
+// normal flow, CSS 2.1 §10.3.3. An `auto` width fills the containing block,
+// or is `auto_width` when given, and `auto` margins share what a set width
+// leaves.
+pub fn get_block_width(computed_style: &ComputedStyle, containing_width: f64, auto_width: Option<f64>) -> (f64, f64, f64) {
+    let margin = &computed_style.margin;
+    let padding = &computed_style.padding;
+    let border = computed_style.border.widths();
+    let edges = padding.left + padding.right + border.left + border.right;
+    let (width, min_width, max_width) = resolve_width(computed_style, containing_width);
+    let width = width.or(auto_width);
+
+    let used_width = |width: Option<f64>| -> (f64, f64, f64) {
+        match width {
+            Some(width) => {
+                let auto_left = is_auto_margin(computed_style, "left");
+                let auto_right = is_auto_margin(computed_style, "right");
+                let left = if auto_left { 0.0 } else { margin.left };
+                let right = if auto_right { 0.0 } else { margin.right };
+                let remaining = containing_width - width - edges - left - right;
+                // an over-constrained right margin gives way
+                match (auto_left, auto_right) {
+                    (true, true) if remaining > 0.0 => (width, remaining / 2.0, remaining / 2.0),
+                    (true, false) => (width, remaining, right),
+                    _ => (width, left, right + remaining),
+                }
+            }
+            None => {
+                let width = f64::max(0.0, containing_width - margin.left - margin.right - edges);
+                (width, margin.left, margin.right)
+            }
         }
+    };
 
-        let previous_margin_bottom = context.adjacent_margin_bottom;
+    let (tentative_width, _, _) = used_width(width);
+    let clamped_width = clamp_size(tentative_width, min_width, max_width);
+    if clamped_width != tentative_width {
+        return used_width(Some(clamped_width));
+    }
+    return used_width(width);
+}
 
-        if last_element.is_some() {
-            let previous_element = &tree[last_element.unwrap()];
-            let prev_computed_style = previous_element.computed_style.as_ref().unwrap();
-            let prev_computed_flow = previous_element.computed_flow.as_ref().unwrap();
+// Tables in normal flow with an `auto` width are as wide as their content
+// wants within the containing block, CSS 2.1 §17.5.2, other boxes fill it.
+fn get_auto_width(
+    element: &mut DomElement,
+    measure_text: &dyn Fn(String, f64, String) -> (f64, f64),
+    containing_width: f64,
+) -> Option<f64> {
+    match element.computed_style.as_ref() {
+        Some(c) if is_table(c) && c.width.is_none() => {
+            Some(get_shrink_to_fit_width(element, measure_text, containing_width))
+        }
+        _ => None,
+    }
+}
 
-            if is_horizontal_layout(prev_computed_style) {
-                y = prev_computed_flow.y;
-            }
+// The content width of an inline-block or float, CSS 2.1 §10.3.5 and
+// §10.3.9: the set width, or as wide as the content wants within the space
+// there is.
+pub fn get_shrink_to_fit_width(
+    element: &mut DomElement,
+    measure_text: &dyn Fn(String, f64, String) -> (f64, f64),
+    available_width: f64,
+) -> f64 {
+    let computed_style = element.computed_style.as_ref().unwrap();
+    let margin = computed_style.margin.clone();
+    let padding = computed_style.padding.clone();
+    let border = computed_style.border.widths();
+    let (width, min_width, max_width) = resolve_width(computed_style, available_width);
+
+    let width = match width {
+        Some(width) => width,
+        None => {
+            let (min_content, max_content) = get_content_widths(element, measure_text);
+            let edges = padding.left + padding.right + border.left + border.right;
+            let available = available_width - margin.left - margin.right - edges;
+            min_content.max(available).min(max_content)
+        }
+    };
+    return clamp_size(width, min_width, max_width);
+}
 
-            let should_continue_horizontal_layout = is_horizontal_layout(computed_style)
-                && is_horizontal_layout(prev_computed_style);
+// Adjoining vertical margins, CSS 2.1 §8.3.1. They collapse into the
+// largest positive margin plus the most negative one.
+#[derive(Clone, Copy, Debug)]
+pub struct CollapsedMargin {
+    pub positive: f64,
+    pub negative: f64,
+}
 
-            if is_horizontal_layout(computed_style) && should_continue_horizontal_layout {
-                // Horizontal layout
-                x = prev_computed_flow.x + prev_computed_flow.width;
-                context.adjacent_margin_bottom = 0.0;
-            } else {
-                // Vertical layout
-                y = reserved_block_y;
-                y += f64::max(computed_style.margin.bottom, computed_style.margin.top);
+impl CollapsedMargin {
+    pub fn new(margin: f64) -> CollapsedMargin {
+        CollapsedMargin {
+            positive: margin.max(0.0),
+            negative: margin.min(0.0),
+        }
+    }
 
-                // context.adjacent_margin_bottom = prev_computed_flow.adjacent_margin_bottom;
-            }
+    pub fn empty() -> CollapsedMargin {
+        CollapsedMargin::new(0.0)
+    }
 
-            x += computed_style.margin.right;
-        } else {
-            y += f64::max(0.0, computed_style.margin.top - previous_margin_bottom);
+    pub fn adjoin(&self, other: &CollapsedMargin) -> CollapsedMargin {
+        CollapsedMargin {
+            positive: self.positive.max(other.positive),
+            negative: self.negative.min(other.negative),
         }
+    }
 
-        last_element = Some(i);
+    pub fn resolve(&self) -> f64 {
+        self.positive + self.negative
+    }
+}
 
-        let element = &mut tree[i];
-        let computed_style = element.computed_style.as_ref().unwrap();
+// Boxes whose margins don't collapse with the margins of their children.
+pub fn establishes_formatting_context(element: &DomElement) -> bool {
+    let computed_style = match element.computed_style.as_ref() {
+        Some(c) => c,
+        None => return false,
+    };
+    element.tag_name == "HTML"
+        || computed_style.float != "none"
+        || computed_style.position == "absolute"
+        || computed_style.position == "fixed"
+        || is_scroll_container(computed_style)
+        || match computed_style.display.as_str() {
+            "inline-block" | "flow-root" | "table" | "inline-table" | "table-cell" | "table-caption" | "flex"
+            | "inline-flex" | "grid" | "inline-grid" => true,
+            _ => false,
+        }
+}
 
-        x += computed_style.margin.left;
+pub struct BlockBoxLayout {
+    // the border box height
+    pub height: f64,
+    pub baseline: Option<f64>,
+    // the margins above the box, its top border edge is at the `y` it was
+    // laid out at plus these
+    pub top_margin: CollapsedMargin,
+    // the margins below the box, still open to collapse with what follows
+    pub bottom_margin: CollapsedMargin,
+    // an empty box, whose top and bottom margins collapse together
+    pub collapsed_through: bool,
+}
 
-        let mut adjacent_margin_bottom = 0.0;
+// What a flex or grid container decides for one of its items, which are formatting
+// context roots whatever their display.
+#[derive(Clone, Copy, Debug)]
+pub struct ItemSizing {
+    // the content height, in place of the one the item asks for
+    pub height: Option<f64>,
+}
 
-        width = computed_style.padding.left + computed_style.padding.right;
-        height = computed_style.padding.top + computed_style.padding.bottom;
+pub struct BlockChildrenLayout {
+    // where the last in-flow content ends, before `end_margin`
+    pub end_y: f64,
+    pub end_margin: CollapsedMargin,
+    // the margins that were open when the first in-flow content was placed
+    pub top_margin: Option<CollapsedMargin>,
+    pub baseline: Option<f64>,
+}
 
-        if element.children.len() > 0
-            && element.tag_name != "SCRIPT"
-            && element.tag_name != "STYLE"
-        {
-            context.x = x + computed_style.padding.left;
-            context.y = y + computed_style.padding.top;
+// Lays out a block container whose content box is `content_width` wide, below
+// `y` and the still open margins `margin`, which include its own top margin.
+// Its top margin collapses with its first child's unless padding or a new
+// formatting context keeps them apart, and the same goes for the bottom. A
+// new formatting context has its own floats and grows to contain them.
+pub fn layout_block_box(
+    element: &mut DomElement,
+    measure_text: &dyn Fn(String, f64, String) -> (f64, f64),
+    x: f64,
+    y: f64,
+    content_width: f64,
+    containing_height: Option<f64>,
+    margin: CollapsedMargin,
+    floats: &mut FloatContext,
+    item: Option<ItemSizing>,
+) -> BlockBoxLayout {
+    let computed_style = element.computed_style.as_ref().unwrap();
+    let padding = computed_style.padding.clone();
+    let border = computed_style.border.widths();
+    let margin_bottom = CollapsedMargin::new(computed_style.margin.bottom);
+    let (height, min_height, max_height) = resolve_height(computed_style, containing_height);
+    let height = item.and_then(|i| i.height).or(height);
+    let is_flex = is_flex_container(computed_style);
+    let is_grid = is_grid_container(computed_style);
+    let is_table = is_table(computed_style);
+    // tables and their cells grow to fit their content whatever height they
+    // ask for
+    let grows_to_content = is_table || computed_style.display == "table-cell";
+    let is_formatting_context = item.is_some() || establishes_formatting_context(element);
+    let mut own_floats = FloatContext::new();
+    let floats = if is_formatting_context { &mut own_floats } else { floats };
+    let collapses_top = !is_formatting_context && padding.top == 0.0 && border.top == 0.0;
+    let collapses_bottom =
+        !is_formatting_context && padding.bottom == 0.0 && border.bottom == 0.0 && height.is_none();
+
+    let (children_y, children_margin) = if collapses_top {
+        (y, margin)
+    } else {
+        (y + margin.resolve() + border.top + padding.top, CollapsedMargin::empty())
+    };
+    let mut container = InlineContainer::new(x + border.left + padding.left, children_y, content_width, Some(computed_style));
+    container.height = height.map(|h| clamp_size(h, min_height, max_height));
+    let mut used_width = content_width;
+    // the margin boxes of the captions above and below a table
+    let mut captions = (0.0, 0.0);
+    let children = if has_laid_out_children(element) && is_flex {
+        let flex = layout_flex_container(element, measure_text, &container, min_height, max_height);
+        BlockChildrenLayout {
+            end_y: children_y + flex.height,
+            end_margin: CollapsedMargin::empty(),
+            top_margin: Some(CollapsedMargin::empty()),
+            baseline: flex.baseline,
+        }
+    } else if has_laid_out_children(element) && is_grid {
+        let grid = layout_grid_container(element, measure_text, &container, min_height, max_height);
+        BlockChildrenLayout {
+            end_y: children_y + grid.height,
+            end_margin: CollapsedMargin::empty(),
+            top_margin: Some(CollapsedMargin::empty()),
+            baseline: grid.baseline,
+        }
+    } else if has_laid_out_children(element) && is_table {
+        let table = layout_table_box(element, measure_text, &container, min_height);
+        used_width = table.width;
+        captions = (table.captions_above, table.captions_below);
+        BlockChildrenLayout {
+            end_y: children_y + table.height,
+            end_margin: CollapsedMargin::empty(),
+            top_margin: Some(CollapsedMargin::empty()),
+            baseline: table.baseline,
+        }
+    } else if has_laid_out_children(element) {
+        let len = element.children.len();
+        layout_block_children(&mut element.children, 0..len, measure_text, &container, children_margin, floats)
+    } else {
+        BlockChildrenLayout {
+            end_y: children_y,
+            end_margin: children_margin,
+            top_margin: None,
+            baseline: None,
+        }
+    };
+
+    let has_content = children.top_margin.is_some();
+    let top_margin = match (collapses_top, children.top_margin) {
+        (false, _) => margin,
+        (true, Some(m)) => m,
+        // without content the margins from above all end at the top
+        (true, None) => children.end_margin,
+    };
+    let top = y + top_margin.resolve();
+    let content_top = top + border.top + padding.top;
+    let end_margin = if has_content || !collapses_top {
+        children.end_margin
+    } else {
+        CollapsedMargin::empty()
+    };
+    let mut content_end = if collapses_bottom {
+        children.end_y.max(content_top)
+    } else {
+        children.end_y.max(content_top) + end_margin.resolve()
+    };
+    if is_formatting_context {
+        content_end = content_end.max(floats.get_bottom().unwrap_or(content_end));
+    }
 
-            reflow(
-                &mut element.children,
-                measure_text,
-                Some(context.clone()),
-            );
+    // content taller than a set height overflows
+    let content_height = if grows_to_content {
+        clamp_size(height.unwrap_or(0.0), min_height, max_height).max(content_end - content_top)
+    } else {
+        clamp_size(height.unwrap_or(content_end - content_top), min_height, max_height)
+    };
+    let collapsed_through = collapses_top && collapses_bottom && !has_content && content_height == 0.0;
+
+    let border_height = border.top + padding.top + content_height + padding.bottom + border.bottom;
+    let mut flow = ComputedFlow::empty();
+    flow.x = x;
+    flow.y = top;
+    flow.width = border.left + padding.left + used_width + padding.right + border.right;
+    flow.height = border_height;
+    flow.baseline = children.baseline;
+    set_computed_flow(element, flow);
+    // the table box goes below the captions above it
+    if captions.0 != 0.0 {
+        translate_element(element, 0.0, captions.0);
+    }
+
+    BlockBoxLayout {
+        height: captions.0 + border_height + captions.1,
+        baseline: children.baseline.map(|b| b + captions.0),
+        top_margin,
+        bottom_margin: if collapsed_through {
+            children.end_margin.adjoin(&margin_bottom)
+        } else if collapses_bottom {
+            end_margin.adjoin(&margin_bottom)
+        } else {
+            margin_bottom
+        },
+        collapsed_through,
+    }
+}
+
+// Lays out an inline-block, or a block inside an inline, with its margin box
+// at the origin. Returns the size of the margin box and the distance from
+// its top to the baseline.
+pub fn layout_atomic_inline(
+    element: &mut DomElement,
+    measure_text: &dyn Fn(String, f64, String) -> (f64, f64),
+    container: &InlineContainer,
+) -> (f64, f64, f64) {
+    let (content_width, margin_left, margin_right) = if is_block_level(element) {
+        let auto_width = get_auto_width(element, measure_text, container.width);
+        get_block_width(element.computed_style.as_ref().unwrap(), container.width, auto_width)
+    } else {
+        // `auto` margins of inline-blocks are zero, and so are their
+        // margin_length values
+        let computed_style = element.computed_style.as_ref().unwrap();
+        let (left, right) = (computed_style.margin.left, computed_style.margin.right);
+        (get_shrink_to_fit_width(element, measure_text, container.width), left, right)
+    };
+    let computed_style = element.computed_style.as_ref().unwrap();
+    let padding = computed_style.padding.clone();
+    let border = computed_style.border.widths();
+    let (margin_top, margin_bottom) = (computed_style.margin.top, computed_style.margin.bottom);
+
+    let block = layout_block_box(
+        element,
+        measure_text,
+        margin_left,
+        0.0,
+        content_width,
+        container.height,
+        CollapsedMargin::new(margin_top),
+        &mut FloatContext::new(),
+        None,
+    );
+    let (height, baseline) = (block.height, block.baseline);
+    let margin_box_height = margin_top + height + margin_bottom;
+
+    // without a line inside, the bottom margin edge sits on the baseline
+    return (
+        margin_left + border.left + padding.left + content_width + padding.right + border.right + margin_right,
+        margin_box_height,
+        baseline.unwrap_or(margin_box_height),
+    );
+}
+
+// Stacks the block-level children in `range` and puts runs of inline-level
+// ones in line boxes, like an anonymous block box would. `margin` holds the
+// margins still open above the first child.
+pub fn layout_block_children(
+    tree: &mut Vec<DomElement>,
+    range: Range<usize>,
+    measure_text: &dyn Fn(String, f64, String) -> (f64, f64),
+    container: &InlineContainer,
+    margin: CollapsedMargin,
+    floats: &mut FloatContext,
+) -> BlockChildrenLayout {
+    let mut y = container.y;
+    let mut margin = margin;
+    let mut top_margin: Option<CollapsedMargin> = None;
+    let mut last_baseline: Option<f64> = None;
+
+    let mut i = range.start;
+    while i < range.end {
+        if !is_block_level(&tree[i]) {
+            let start = i;
+            while i < range.end && !is_block_level(&tree[i]) {
+                i += 1;
+            }
+            let mut lines_container = container.clone();
+            lines_container.y = y + margin.resolve();
+            let lines = layout_inline_content(tree, start..i, &lines_container, floats, measure_text);
+            // a run of collapsed white space makes no line box and the
+            // margins around it still adjoin
+            if lines.height > 0.0 {
+                top_margin = top_margin.or(Some(margin));
+                y = lines_container.y + lines.height;
+                margin = CollapsedMargin::empty();
+                last_baseline = lines.last_baseline;
+            }
+            continue;
+        }
+
+        if is_internal_table_box(&tree[i]) {
+            let start = i;
+            i = get_table_run_end(tree, start, range.end);
+            let (min_width, max_width) = get_anonymous_table_widths(tree, start..i, measure_text);
+            let mut table_container = container.clone();
+            table_container.y = y + margin.resolve();
+            table_container.width = min_width.max(container.width).min(max_width);
+            table_container.height = None;
+            let table = layout_anonymous_table(tree, start..i, measure_text, &table_container);
+            top_margin = top_margin.or(Some(margin));
+            y = table_container.y + table.captions_above + table.height + table.captions_below;
+            margin = CollapsedMargin::empty();
+            if table.baseline.is_some() {
+                last_baseline = table.baseline;
+            }
+            continue;
+        }
+
+        let element = &mut tree[i];
+        i += 1;
+        let computed_style = element.computed_style.as_mut().unwrap();
+        resolve_box_edges(computed_style, container.width);
+        let margin_top = CollapsedMargin::new(computed_style.margin.top);
+        let clear = computed_style.values.get("clear").cloned().unwrap_or(S("none"));
+
+        // clearance puts the border box below the floats it clears, and
+        // the margins above it no longer collapse through it
+        let hypothetical_top = y + margin.adjoin(&margin_top).resolve();
+        let mut forced_top = match floats.get_clearance(&clear) {
+            Some(clear_y) if clear_y > hypothetical_top => Some(clear_y),
+            _ => None,
+        };
 
-            for el in &element.children {
-                let el_computed_flow = el.computed_flow.as_ref();
-                let el_computed_style = el.computed_style.as_ref();
-                if el_computed_flow.is_none() || el_computed_style.is_none() {
-                    continue;
+        let block = if establishes_formatting_context(element) && !floats.is_empty() {
+            // the border box of a formatting context root goes beside the
+            // floats, or below them where it doesn't fit
+            let mut top = forced_top.unwrap_or(hypothetical_top);
+            loop {
+                let (left, right) = floats.get_available_space(top, 0.0, container.x, container.width);
+                let auto_width = get_auto_width(element, measure_text, right - left);
+                let computed_style = element.computed_style.as_ref().unwrap();
+                let (content_width, margin_left, margin_right) =
+                    get_block_width(computed_style, right - left, auto_width);
+                let block_top = if top == hypothetical_top { None } else { Some(top) };
+                let block = match block_top {
+                    Some(top) => layout_block_box(
+                        element,
+                        measure_text,
+                        left + margin_left,
+                        top,
+                        content_width,
+                        container.height,
+                        CollapsedMargin::empty(),
+                        floats,
+                        None,
+                    ),
+                    None => layout_block_box(
+                        element,
+                        measure_text,
+                        left + margin_left,
+                        y,
+                        content_width,
+                        container.height,
+                        margin.adjoin(&margin_top),
+                        floats,
+                        None,
+                    ),
+                };
+                let margin_box_width = element.computed_flow.as_ref().unwrap().width + margin_left + margin_right;
+                let (fit_left, fit_right) =
+                    floats.get_available_space(top, block.height, container.x, container.width);
+                let fits = margin_box_width <= fit_right - fit_left + 0.01 && right - left > 0.0;
+                match floats.get_next_bottom(top) {
+                    Some(bottom) if !fits => top = bottom,
+                    _ => {
+                        forced_top = block_top;
+                        break block;
+                    }
                 }
-                let el_computed_flow = el_computed_flow.unwrap();
-                let el_computed_style = el_computed_style.unwrap();
-                width = f64::max(
-                    el_computed_flow.width + (el_computed_flow.x - x) + el_computed_style.margin.right + computed_style.padding.right,
-                    width,
-                );
-                
-                height = f64::max(
-                    el_computed_flow.height + (el_computed_flow.y - y) + el_computed_style.margin.bottom + computed_style.padding.bottom,
-                    height,
-                );
-            }
-
-            adjacent_margin_bottom = computed_style.margin.bottom;
-
-            for el in &element.children {
-                let computed_flow = el.computed_flow.as_ref();
-                if computed_flow.is_none() {
-                    continue;
+            }
+        } else {
+            let auto_width = get_auto_width(element, measure_text, container.width);
+            let computed_style = element.computed_style.as_ref().unwrap();
+            let (content_width, margin_left, _) = get_block_width(computed_style, container.width, auto_width);
+            let (block_y, block_margin) = match forced_top {
+                Some(top) => (top, CollapsedMargin::empty()),
+                None => (y, margin.adjoin(&margin_top)),
+            };
+            layout_block_box(
+                element,
+                measure_text,
+                container.x + margin_left,
+                block_y,
+                content_width,
+                container.height,
+                block_margin,
+                floats,
+                None,
+            )
+        };
+        match forced_top {
+            Some(top) => {
+                top_margin = top_margin.or(Some(margin));
+                y = top + block.top_margin.resolve() + block.height;
+                margin = block.bottom_margin;
+                if block.baseline.is_some() {
+                    last_baseline = block.baseline;
                 }
-                let computed_flow = computed_flow.unwrap();
-                adjacent_margin_bottom =
-                    f64::max(adjacent_margin_bottom, computed_flow.adjacent_margin_bottom);
-                break;
+                continue;
             }
+            None => {}
         }
+        margin = block.bottom_margin;
+        if block.collapsed_through {
+            continue;
+        }
+        top_margin = top_margin.or(Some(block.top_margin));
+        y += block.top_margin.resolve() + block.height;
+        if block.baseline.is_some() {
+            last_baseline = block.baseline;
+        }
+    }
 
-        match element.node_type {
-            NodeType::Text => {
-                element.node_value = element
-                    .node_value
-                    .replace("&nbsp;", " ")
-                    .replace("&gt;", ">")
-                    .replace("&lt;", "<");
-
+    BlockChildrenLayout {
+        end_y: y,
+        end_margin: margin,
+        top_margin,
+        baseline: last_baseline,
+    }
+}
 
-                // text wrapping
+fn resolve_inset(computed_style: &ComputedStyle, containing_block: &Rect) -> (Option<f64>, Option<f64>, Option<f64>, Option<f64>) {
+    let context = &computed_style.length_context;
+    let inset = &computed_style.inset;
+    let horizontal = |l: &Option<LengthPercentage>| l.as_ref().map(|l| l.resolve(context, containing_block.width));
+    let vertical = |l: &Option<LengthPercentage>| l.as_ref().map(|l| l.resolve(context, containing_block.height));
+    (
+        vertical(&inset.top),
+        horizontal(&inset.right),
+        vertical(&inset.bottom),
+        horizontal(&inset.left),
+    )
+}
 
+// The box inside the borders, and inside the padding too unless
+// `padding_box`.
+pub fn get_inner_rect(element: &DomElement, padding_box: bool) -> Rect {
+    let computed_flow = element.computed_flow.as_ref().unwrap();
+    let computed_style = element.computed_style.as_ref().unwrap();
+    let mut edges = computed_style.border.widths();
+    if !padding_box {
+        let padding = &computed_style.padding;
+        edges.top += padding.top;
+        edges.right += padding.right;
+        edges.bottom += padding.bottom;
+        edges.left += padding.left;
+    }
+    Rect {
+        x: computed_flow.x + edges.left,
+        y: computed_flow.y + edges.top,
+        width: (computed_flow.width - edges.left - edges.right).max(0.0),
+        height: (computed_flow.height - edges.top - edges.bottom).max(0.0),
+    }
+}
 
-                let size = measure_text(
-                    element.node_value.clone(),
-                    computed_style.font_size,
-                    computed_style.font_path.to_string(),
-                );
-                width = size.0;
-                height = size.1;
+// Lays out an absolutely positioned box in `containing_block`, CSS 2.1
+// §10.3.7 and §10.6.4. Its flow holds its static position, where `auto`
+// insets leave it.
+fn layout_absolute_box(
+    element: &mut DomElement,
+    measure_text: &dyn Fn(String, f64, String) -> (f64, f64),
+    containing_block: &Rect,
+) {
+    let (static_x, static_y) = match element.computed_flow.as_ref() {
+        Some(f) => (f.x, f.y),
+        None => (containing_block.x, containing_block.y),
+    };
+    let computed_style = element.computed_style.as_mut().unwrap();
+    resolve_box_edges(computed_style, containing_block.width);
+    let computed_style = element.computed_style.as_ref().unwrap();
+    let margin = computed_style.margin.clone();
+    let padding = computed_style.padding.clone();
+    let border = computed_style.border.widths();
+    let (top, right, bottom, left) = resolve_inset(computed_style, containing_block);
+    let edges = padding.left + padding.right + border.left + border.right;
+    let (width, min_width, max_width) = resolve_width(computed_style, containing_block.width);
+
+    let (content_width, margin_left, margin_right) = match (width, left, right) {
+        (Some(width), Some(l), Some(r)) => {
+            let width = clamp_size(width, min_width, max_width);
+            let remaining = containing_block.width - l - r - width - edges - margin.left - margin.right;
+            // `auto` margins center the box between the insets
+            match (is_auto_margin(computed_style, "left"), is_auto_margin(computed_style, "right")) {
+                (true, true) if remaining > 0.0 => (width, remaining / 2.0, remaining / 2.0),
+                (true, false) => (width, margin.left + remaining, margin.right),
+                _ => (width, margin.left, margin.right),
             }
-            _ => {}
         }
+        (Some(width), _, _) => (clamp_size(width, min_width, max_width), margin.left, margin.right),
+        (None, Some(l), Some(r)) => {
+            let width = containing_block.width - l - r - margin.left - margin.right - edges;
+            (clamp_size(width.max(0.0), min_width, max_width), margin.left, margin.right)
+        }
+        (None, _, _) => {
+            let available = containing_block.width - left.unwrap_or(0.0) - right.unwrap_or(0.0);
+            (get_shrink_to_fit_width(element, measure_text, available), margin.left, margin.right)
+        }
+    };
+    let border_width = content_width + edges;
+    let x = match (left, right) {
+        (Some(l), _) => containing_block.x + l + margin_left,
+        (None, Some(r)) => containing_block.x + containing_block.width - r - margin_right - border_width,
+        (None, None) => static_x + margin_left,
+    };
+
+    let computed_style = element.computed_style.as_ref().unwrap();
+    let has_height = computed_style.height.is_some();
+    let (_, min_height, max_height) = resolve_height(computed_style, Some(containing_block.height));
+    let y = match top {
+        Some(t) => containing_block.y + t,
+        None => static_y,
+    };
+    layout_block_box(
+        element,
+        measure_text,
+        x,
+        y,
+        content_width,
+        Some(containing_block.height),
+        CollapsedMargin::new(margin.top),
+        &mut FloatContext::new(),
+        None,
+    );
+
+    let computed_flow = element.computed_flow.as_mut().unwrap();
+    let vertical_edges = border.top + padding.top + padding.bottom + border.bottom;
+    match (top, bottom) {
+        // an `auto` height stretches between the insets
+        (Some(t), Some(b)) if !has_height => {
+            let content_height =
+                containing_block.height - t - b - margin.top - margin.bottom - vertical_edges;
+            computed_flow.height = clamp_size(content_height.max(0.0), min_height, max_height) + vertical_edges;
+            computed_flow.hover_rect.height = computed_flow.height;
+        }
+        (None, Some(b)) => {
+            let bottom_y = containing_block.y + containing_block.height - b - margin.bottom;
+            let dy = bottom_y - (computed_flow.y + computed_flow.height);
+            translate_element(element, 0.0, dy);
+        }
+        _ => {}
+    }
+}
 
-        match element.node_type {
-            NodeType::Comment => {}
-            _ => {
-                reserved_block_y = f64::max(height + y, reserved_block_y);
+// Places the positioned boxes once the flow is laid out: relative ones are
+// offset from where they are, absolute ones laid out in the padding box of
+// their nearest positioned ancestor, and fixed ones in the viewport. Sticky
+// ones move when painted.
+fn layout_positioned_elements(
+    tree: &mut Vec<DomElement>,
+    measure_text: &dyn Fn(String, f64, String) -> (f64, f64),
+    containing_block: &Rect,
+    viewport: &Rect,
+    parent_content: &Rect,
+) {
+    for element in tree.iter_mut() {
+        if element.node_type != NodeType::Element || element.computed_flow.is_none() || !is_displayed(element) {
+            continue;
+        }
+        let position = element.computed_style.as_ref().unwrap().position.clone();
+        match position.as_str() {
+            "absolute" => layout_absolute_box(element, measure_text, containing_block),
+            "fixed" => layout_absolute_box(element, measure_text, viewport),
+            "relative" => {
+                let computed_style = element.computed_style.as_ref().unwrap();
+                let (top, right, bottom, left) = resolve_inset(computed_style, parent_content);
+                let dx = left.or(right.map(|r| -r)).unwrap_or(0.0);
+                let dy = top.or(bottom.map(|b| -b)).unwrap_or(0.0);
+                translate_element(element, dx, dy);
             }
+            _ => {}
         }
 
-        element.computed_flow = Some(ComputedFlow {
-            x: x,
-            y: y,
-            width: width,
-            height: height,
-            adjacent_margin_bottom: adjacent_margin_bottom,
-            hover_rect: if element.is_hovered && element.computed_style.is_some() {
-                element.computed_flow.as_ref().unwrap().hover_rect.clone()
-            } else {
-                Rect {
-                    x: x,
-                    y: y,
-                    width: width,
-                    height: height,
-                }
-            },
-        });
+        if !has_laid_out_children(element) {
+            continue;
+        }
+        let padding_box = get_inner_rect(element, true);
+        let content_box = get_inner_rect(element, false);
+        let containing_block = if position == "static" { containing_block } else { &padding_box };
+        layout_positioned_elements(&mut element.children, measure_text, containing_block, viewport, &content_box);
     }
 }
 
-pub fn get_render_array(
+pub fn reflow(
     tree: &mut Vec<DomElement>,
-    viewport: &Rect,
-) -> Vec<RenderItem> {
-    let mut array: Vec<RenderItem> = vec![];
+    measure_text: &dyn Fn(String, f64, String) -> (f64, f64),
+    context: Option<ReflowContext>,
+) {
+    let context = context.unwrap_or(ReflowContext {
+        x: 0.0,
+        y: 0.0,
+        containing_width: 0.0,
+    });
 
-    for i in 0..tree.len() {
-        let element = &mut tree[i];
-        let computed_flow = element.computed_flow.as_ref();
-        if computed_flow.is_none() {
-            continue;
+    // the initial containing block is the viewport
+    let mut container = InlineContainer::new(context.x, context.y, context.containing_width, None);
+    container.height = tree
+        .iter()
+        .find_map(|e| e.computed_style.as_ref())
+        .map(|c| c.length_context.viewport_height);
+    resolve_table_borders(tree);
+    let len = tree.len();
+    layout_block_children(tree, 0..len, measure_text, &container, CollapsedMargin::empty(), &mut FloatContext::new());
+
+    // the initial containing block has the size of the viewport
+    let viewport = Rect {
+        x: context.x,
+        y: context.y,
+        width: context.containing_width,
+        height: container.height.unwrap_or(0.0),
+    };
+    layout_positioned_elements(tree, measure_text, &viewport, &viewport, &viewport);
+}
+
+// The color of the area behind the document: the background of the root
+// element, or of the body when the root has none, or the `Canvas` system
+// color of the root's color scheme.
+pub fn get_canvas_color(tree: &Vec<DomElement>) -> Color {
+    let root = match tree.iter().find(|e| e.tag_name == "HTML") {
+        Some(r) => r,
+        None => return Color::WHITE,
+    };
+    let root_style = match root.computed_style.as_ref() {
+        Some(c) => c,
+        None => return Color::WHITE,
+    };
+    let canvas = get_system_color("canvas", root_style.color_scheme).unwrap_or(Color::WHITE);
+
+    let body_background = root
+        .children
+        .iter()
+        .find(|e| e.tag_name == "BODY")
+        .and_then(|b| b.computed_style.as_ref())
+        .map(|c| c.background_color)
+        .unwrap_or(Color::TRANSPARENT);
+    let background = if !root_style.background_color.is_transparent() {
+        root_style.background_color
+    } else {
+        body_background
+    };
+
+    return background.over(&canvas);
+}
+
+// What painting an element takes from its ancestors. Colors keep their
+// alpha, faded by the opacities, and the window blends them with what is
+// painted under them.
+#[derive(Clone)]
+struct PaintContext {
+    // the product of the ancestors' opacities
+    opacity: f64,
+    // the content box of the parent, that sticky boxes stay in
+    parent_content: Rect,
+    // the part of the document in view, in the coordinates of the boxes
+    viewport: Rect,
+    // inside a fixed box, painted without scrolling
+    fixed: bool,
+    // where the boxes around let the content paint, see `RenderItem`
+    clip: Option<Rect>,
+    // how far scrolling and sticky positioning moved the boxes from where
+    // they were laid out
+    offset: (f64, f64),
+    // the clip, offset and viewport the content of the nearest positioned
+    // ancestor has, that absolute boxes take instead of their parent's
+    absolute_clip: Option<Rect>,
+    absolute_offset: (f64, f64),
+    absolute_viewport: Rect,
+    // the path of the element whose children are painted
+    path: Vec<usize>,
+}
+
+// A positioned box and its descendants, painted in the stacking context of
+// an ancestor by `z-index`.
+struct PaintLayer {
+    z_index: i32,
+    items: Vec<RenderItem>,
+}
+
+// The root and positioned boxes paint in layers. Positioned boxes with an
+// `auto` z-index are treated like stacking contexts of level zero.
+fn creates_stacking_context(element: &DomElement) -> bool {
+    if element.node_type != NodeType::Element {
+        return false;
+    }
+    match element.computed_style.as_ref() {
+        Some(c) => element.tag_name == "HTML" || c.position != "static",
+        None => false,
+    }
+}
+
+// Stacking order, simplified from CSS 2.1 Appendix E: the box's own
+// background and borders, the layers below zero, the rest of its
+// descendants, then the other layers in order. Layers on the same level
+// keep the document order.
+fn stack_layers(own: Vec<RenderItem>, descendants: Vec<RenderItem>, mut layers: Vec<PaintLayer>) -> Vec<RenderItem> {
+    layers.sort_by_key(|l| l.z_index);
+    let mut items = own;
+    let mut above = vec![];
+    for layer in layers {
+        if layer.z_index < 0 {
+            items.extend(layer.items);
+        } else {
+            above.extend(layer.items);
         }
-        let computed_flow = computed_flow.unwrap();
-        let rect = Rect {
-            x: computed_flow.x,
-            y: computed_flow.y,
-            width: computed_flow.width,
-            height: computed_flow.height,
-        };
+    }
+    items.extend(descendants);
+    items.extend(above);
+    return items;
+}
 
-        let is_in_viewport = is_in_viewport(viewport, &rect);
-        if element.children.len() > 0
-            && element.tag_name != "SCRIPT"
-            && element.tag_name != "STYLE"
-            && is_in_viewport
-        {
-            let children_render_items = get_render_array(
-                &mut element.children,
-                viewport,
-            );
-            array.extend(children_render_items);
+// The items of the whole document in paint order, the unpainted ones for hit
+// testing included.
+pub fn get_render_array(tree: &mut Vec<DomElement>, viewport: &Rect) -> Vec<RenderItem> {
+    let context = PaintContext {
+        opacity: 1.0,
+        parent_content: viewport.clone(),
+        viewport: viewport.clone(),
+        fixed: false,
+        clip: None,
+        offset: (0.0, 0.0),
+        absolute_clip: None,
+        absolute_offset: (0.0, 0.0),
+        absolute_viewport: viewport.clone(),
+        path: vec![],
+    };
+    let mut layers: Vec<PaintLayer> = vec![];
+    let items = paint_children(tree, &context, &mut layers);
+    return stack_layers(vec![], items, layers);
+}
+
+// How far a sticky box moves to stay `top` or `bottom` from the edges of
+// the viewport, without leaving the content box of its parent.
+fn get_sticky_offset(element: &DomElement, context: &PaintContext) -> f64 {
+    let computed_flow = element.computed_flow.as_ref().unwrap();
+    let computed_style = element.computed_style.as_ref().unwrap();
+    let viewport = &context.viewport;
+    let (top, _, bottom, _) = resolve_inset(computed_style, viewport);
+    let box_top = computed_flow.y;
+    let box_bottom = computed_flow.y + computed_flow.height;
+
+    let mut dy = 0.0;
+    match top {
+        Some(t) if box_top < viewport.y + t => dy = viewport.y + t - box_top,
+        _ => {}
+    }
+    match bottom {
+        Some(b) if box_bottom + dy > viewport.y + viewport.height - b => {
+            dy = viewport.y + viewport.height - b - box_bottom
         }
+        _ => {}
+    }
 
-        let element = &tree[i];
-        let computed_flow = element.computed_flow.as_ref().unwrap();
-        let computed_style = element.computed_style.as_ref();
-        if computed_style.is_none() {
+    let parent = &context.parent_content;
+    let margin = &computed_style.margin;
+    if dy > 0.0 {
+        dy = dy.min((parent.y + parent.height - box_bottom - margin.bottom).max(0.0));
+    } else if dy < 0.0 {
+        dy = dy.max((parent.y - box_top + margin.top).min(0.0));
+    }
+    return dy;
+}
+
+fn translate_render_item(item: &mut RenderItem, dx: f64, dy: f64) {
+    item.x += dx;
+    item.y += dy;
+    item.baseline += dy;
+    for point in &mut item.polygon {
+        point[0] += dx;
+        point[1] += dy;
+    }
+}
+
+fn paint_stacking_context(element: &DomElement, path: &Vec<usize>, context: &PaintContext) -> Vec<RenderItem> {
+    let computed_style = element.computed_style.as_ref().unwrap();
+    let mut context = context.clone();
+    // absolute boxes are only clipped and scrolled with their containing block
+    if computed_style.position == "absolute" {
+        context.clip = context.absolute_clip.clone();
+        context.offset = context.absolute_offset;
+        context.viewport = context.absolute_viewport.clone();
+    }
+    // fixed boxes are laid out in the viewport as if it wasn't scrolled
+    if computed_style.position == "fixed" && !context.fixed {
+        context.fixed = true;
+        context.viewport.x = 0.0;
+        context.viewport.y = 0.0;
+        context.clip = None;
+        context.offset = (0.0, 0.0);
+    }
+    if computed_style.position == "sticky" {
+        let dy = get_sticky_offset(element, &context);
+        context.viewport.y -= dy;
+        context.offset.1 += dy;
+    }
+
+    let mut layers: Vec<PaintLayer> = vec![];
+    let (own, descendants) = paint_element(element, path, &context, &mut layers);
+    return stack_layers(own, descendants, layers);
+}
+
+// Paints the elements in document order, and the positioned ones in layers
+// of the stacking context they are in.
+fn paint_children(tree: &Vec<DomElement>, context: &PaintContext, layers: &mut Vec<PaintLayer>) -> Vec<RenderItem> {
+    let mut items: Vec<RenderItem> = vec![];
+    for (i, element) in tree.iter().enumerate() {
+        if element.computed_flow.is_none() {
             continue;
         }
-        let computed_style = computed_style.unwrap();
-
-        let has_something_to_render = element.node_value != ""
-            || computed_style.background_color != (0.0, 0.0, 0.0, 0.0);
-        // The element has nothing to render
-        if !has_something_to_render
-            || !is_in_viewport
-            || computed_style.display == "none"
-        {
+        let mut path = context.path.clone();
+        path.push(i);
+        if creates_stacking_context(element) {
+            let z_index = element.computed_style.as_ref().unwrap().z_index.unwrap_or(0);
+            layers.push(PaintLayer {
+                z_index,
+                items: paint_stacking_context(element, &path, context),
+            });
             continue;
         }
+        let (own, descendants) = paint_element(element, &path, context, layers);
+        items.extend(own);
+        items.extend(descendants);
+    }
+    return items;
+}
+
+// The items of the element itself, and those of its descendants.
+fn paint_element(
+    element: &DomElement,
+    path: &Vec<usize>,
+    context: &PaintContext,
+    layers: &mut Vec<PaintLayer>,
+) -> (Vec<RenderItem>, Vec<RenderItem>) {
+    let computed_flow = element.computed_flow.as_ref().unwrap();
+    let rect = Rect {
+        x: computed_flow.x,
+        y: computed_flow.y,
+        width: computed_flow.width,
+        height: computed_flow.height,
+    };
+
+    let is_in_viewport = is_in_viewport(&context.viewport, &rect);
+
+    // what hit testing finds, under the descendants like the element's own
+    // items
+    let mut hit_items: Vec<RenderItem> = vec![];
+    if is_in_viewport {
+        let hover_rect = &computed_flow.hover_rect;
+        let mut item = RenderItem::new();
+        item.x = hover_rect.x + context.offset.0;
+        item.y = hover_rect.y + context.offset.1;
+        item.width = hover_rect.width;
+        item.height = hover_rect.height;
+        item.baseline = item.y + item.height;
+        item.fixed = context.fixed;
+        item.clip = context.clip.clone();
+        item.path = Some(path.clone());
+        hit_items.push(item);
+    }
+
+    let (opacity, background_color) = match element.computed_style.as_ref() {
+        Some(c) => {
+            let opacity = context.opacity * c.opacity;
+            (opacity, c.background_color.with_opacity(opacity))
+        }
+        None => (context.opacity, Color::TRANSPARENT),
+    };
+
+    let mut descendants: Vec<RenderItem> = vec![];
+    if element.children.len() > 0 && has_laid_out_children(element) && is_in_viewport {
+        let mut children_context = context.clone();
+        children_context.path = path.clone();
+        children_context.opacity = opacity;
+        if element.node_type == NodeType::Element && element.computed_style.is_some() {
+            children_context.parent_content = get_inner_rect(element, false);
+        }
+        // the content of a clipping box paints inside its padding box, and
+        // scroll containers move it by their scroll offset
+        match get_clip_rect(element) {
+            Some(clip) => {
+                let (scroll_x, scroll_y) = get_scroll_offset(element);
+                let mut painted_clip = clip.clone();
+                painted_clip.x += context.offset.0;
+                painted_clip.y += context.offset.1;
+                children_context.clip = match &context.clip {
+                    Some(outer) => Some(intersect_rects(outer, &painted_clip)),
+                    None => Some(painted_clip),
+                };
+                children_context.offset = (context.offset.0 - scroll_x, context.offset.1 - scroll_y);
+                let mut viewport = intersect_rects(&context.viewport, &clip);
+                viewport.x += scroll_x;
+                viewport.y += scroll_y;
+                children_context.viewport = viewport;
+                // sticky boxes stay in all of the content, not just what is in view
+                if is_scroll_container(element.computed_style.as_ref().unwrap()) {
+                    let (width, height) = get_scrollable_size(element);
+                    let padding = &element.computed_style.as_ref().unwrap().padding;
+                    let content = &mut children_context.parent_content;
+                    content.width = content.width.max(width - padding.left - padding.right);
+                    content.height = content.height.max(height - padding.top - padding.bottom);
+                }
+            }
+            None => {}
+        }
+        match element.computed_style.as_ref() {
+            Some(c) if element.node_type == NodeType::Element && c.position != "static" => {
+                children_context.absolute_clip = children_context.clip.clone();
+                children_context.absolute_offset = children_context.offset;
+                children_context.absolute_viewport = children_context.viewport.clone();
+            }
+            _ => {}
+        }
+        descendants = paint_children(&element.children, &children_context, layers);
+    }
+
+    // scrollbars go over the content
+    match element.computed_style.as_ref() {
+        Some(c) if element.node_type == NodeType::Element && is_scroll_container(c) && is_in_viewport => {
+            for mut item in get_scrollbar_items(element, context.offset) {
+                item.fixed = context.fixed;
+                item.clip = context.clip.clone();
+                descendants.push(item);
+            }
+        }
+        _ => {}
+    }
+
+    let computed_style = element.computed_style.as_ref();
+    if computed_style.is_none() {
+        return (hit_items, descendants);
+    }
+    let computed_style = computed_style.unwrap();
+
+    let has_something_to_render = computed_flow.text_lines.len() > 0
+        || !background_color.is_transparent()
+        || computed_style.border.is_visible();
+    // The element has nothing to render
+    if !has_something_to_render
+        || !is_in_viewport
+        || computed_style.display == "none"
+    {
+        return (hit_items, descendants);
+    }
 
-        match element.node_type {
-            NodeType::Comment => {}
-            _ => {
+    let color = computed_style.color.with_opacity(opacity);
+    let underline = computed_style.text_decoration.split_whitespace().any(|l| l == "underline");
+    let mut items: Vec<RenderItem> = vec![];
+    match element.node_type {
+        NodeType::Comment => {}
+        NodeType::Text => {
+            for line in &computed_flow.text_lines {
+                items.push(RenderItem {
+                    x: line.x,
+                    y: line.y,
+                    width: line.width,
+                    height: line.height,
+                    background_color: Color::TRANSPARENT,
+                    text: line.text.clone(),
+                    baseline: line.baseline,
+                    font_size: computed_style.font_size,
+                    font_path: computed_style.font_path.clone(),
+                    color: color,
+                    underline: underline,
+                    polygon: vec![],
+                    fixed: context.fixed,
+                    clip: context.clip.clone(),
+                    path: None,
+                });
+            }
+        }
+        _ => {
+            // an inline element paints its background and borders on
+            // each line, without borders where it breaks
+            let rects = if computed_flow.fragments.len() > 0 {
+                computed_flow.fragments.clone()
+            } else {
+                vec![rect]
+            };
+            let last = rects.len() - 1;
+            for (j, rect) in rects.into_iter().enumerate() {
+                let radii = get_corner_radii(computed_style, &rect);
+                let (border, radii) = slice_border(&computed_style.border, &radii, j == 0, j == last);
                 let item = RenderItem {
-                    x: computed_flow.x,
-                    y: computed_flow.y,
-                    width: computed_flow.width,
-                    height: computed_flow.height,
-                    background_color: computed_style.background_color,
-                    text: element.node_value.clone(),
+                    x: rect.x,
+                    y: rect.y,
+                    width: rect.width,
+                    height: rect.height,
+                    background_color: background_color,
+                    text: S(""),
+                    baseline: rect.y + rect.height,
                     font_size: computed_style.font_size,
                     font_path: computed_style.font_path.clone(),
-                    color: computed_style.color,
-                    underline: element.node_value != ""
-                        && computed_style.text_decoration == "underline",
+                    color: color,
+                    underline: false,
+                    polygon: vec![],
+                    fixed: context.fixed,
+                    clip: context.clip.clone(),
+                    path: None,
                 };
-                array.insert(0, item);
+                if !background_color.is_transparent() {
+                    items.push(RenderItem {
+                        polygon: if has_radius(&radii) {
+                            get_rounded_rect(&rect, &radii)
+                        } else {
+                            vec![]
+                        },
+                        ..item.clone()
+                    });
+                }
+                for (polygon, border_color) in get_border_polygons(&rect, &border, &radii) {
+                    items.push(RenderItem {
+                        background_color: border_color.with_opacity(opacity),
+                        polygon: polygon,
+                        ..item.clone()
+                    });
+                }
             }
         }
     }
 
-    return array;
+    if context.offset != (0.0, 0.0) {
+        for item in &mut items {
+            translate_render_item(item, context.offset.0, context.offset.1);
+        }
+    }
+    hit_items.extend(items);
+    return (hit_items, descendants);
 }
@@ -0,0 +1,587 @@
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LengthUnit {
+    Px,
+    Cm,
+    Mm,
+    Q,
+    In,
+    Pt,
+    Pc,
+    Em,
+    Rem,
+    Ex,
+    Ch,
+    Cap,
+    Ic,
+    Lh,
+    Rlh,
+    Vw,
+    Vh,
+    Vmin,
+    Vmax,
+}
+
+impl LengthUnit {
+    pub fn from_str(unit: &str) -> Option<LengthUnit> {
+        let unit = unit.to_lowercase();
+        let unit = match unit.as_str() {
+            "px" => LengthUnit::Px,
+            "cm" => LengthUnit::Cm,
+            "mm" => LengthUnit::Mm,
+            "q" => LengthUnit::Q,
+            "in" => LengthUnit::In,
+            "pt" => LengthUnit::Pt,
+            "pc" => LengthUnit::Pc,
+            "em" => LengthUnit::Em,
+            "rem" => LengthUnit::Rem,
+            "ex" => LengthUnit::Ex,
+            "ch" => LengthUnit::Ch,
+            "cap" => LengthUnit::Cap,
+            "ic" => LengthUnit::Ic,
+            "lh" => LengthUnit::Lh,
+            "rlh" => LengthUnit::Rlh,
+            // small, large and dynamic viewport units are the same thing
+            // for a window without collapsible browser chrome
+            "vw" | "svw" | "lvw" | "dvw" | "vi" | "svi" | "lvi" | "dvi" => LengthUnit::Vw,
+            "vh" | "svh" | "lvh" | "dvh" | "vb" | "svb" | "lvb" | "dvb" => LengthUnit::Vh,
+            "vmin" | "svmin" | "lvmin" | "dvmin" => LengthUnit::Vmin,
+            "vmax" | "svmax" | "lvmax" | "dvmax" => LengthUnit::Vmax,
+            _ => return None,
+        };
+        Some(unit)
+    }
+}
+
+// Everything a length needs to be turned into pixels, except the size of the
+// containing block which is only known during layout.
+#[derive(Clone, Debug)]
+pub struct LengthContext {
+    pub font_size: f64,
+    pub root_font_size: f64,
+    pub viewport_width: f64,
+    pub viewport_height: f64,
+}
+
+impl LengthContext {
+    pub fn new(viewport_width: f64, viewport_height: f64) -> LengthContext {
+        LengthContext {
+            font_size: 16.0,
+            root_font_size: 16.0,
+            viewport_width,
+            viewport_height,
+        }
+    }
+
+    pub fn with_font_size(&self, font_size: f64) -> LengthContext {
+        LengthContext {
+            font_size,
+            ..self.clone()
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Length {
+    pub value: f64,
+    pub unit: LengthUnit,
+}
+
+impl Length {
+    pub fn px(value: f64) -> Length {
+        Length {
+            value,
+            unit: LengthUnit::Px,
+        }
+    }
+
+    pub fn to_px(&self, context: &LengthContext) -> f64 {
+        let v = self.value;
+        match self.unit {
+            LengthUnit::Px => v,
+            LengthUnit::Cm => v * 96.0 / 2.54,
+            LengthUnit::Mm => v * 96.0 / 25.4,
+            LengthUnit::Q => v * 96.0 / 101.6,
+            LengthUnit::In => v * 96.0,
+            LengthUnit::Pt => v * 96.0 / 72.0,
+            LengthUnit::Pc => v * 16.0,
+            LengthUnit::Em => v * context.font_size,
+            LengthUnit::Rem => v * context.root_font_size,
+            // no font metrics are available, use the usual fallbacks
+            LengthUnit::Ex => v * context.font_size * 0.5,
+            LengthUnit::Ch => v * context.font_size * 0.5,
+            LengthUnit::Cap => v * context.font_size * 0.7,
+            LengthUnit::Ic => v * context.font_size,
+            LengthUnit::Lh => v * context.font_size * 1.2,
+            LengthUnit::Rlh => v * context.root_font_size * 1.2,
+            LengthUnit::Vw => v * context.viewport_width / 100.0,
+            LengthUnit::Vh => v * context.viewport_height / 100.0,
+            LengthUnit::Vmin => {
+                v * f64::min(context.viewport_width, context.viewport_height) / 100.0
+            }
+            LengthUnit::Vmax => {
+                v * f64::max(context.viewport_width, context.viewport_height) / 100.0
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LengthPercentage {
+    Length(Length),
+    Percentage(f64),
+    Calc(Box<CalcNode>),
+}
+
+impl LengthPercentage {
+    pub fn zero() -> LengthPercentage {
+        LengthPercentage::Length(Length::px(0.0))
+    }
+
+    // `basis` is the size percentages refer to, usually the width of the
+    // containing block.
+    pub fn resolve(&self, context: &LengthContext, basis: f64) -> f64 {
+        match self {
+            LengthPercentage::Length(l) => l.to_px(context),
+            LengthPercentage::Percentage(p) => p / 100.0 * basis,
+            LengthPercentage::Calc(node) => node.evaluate(context, basis),
+        }
+    }
+
+    pub fn has_percentage(&self) -> bool {
+        match self {
+            LengthPercentage::Length(_) => false,
+            LengthPercentage::Percentage(_) => true,
+            LengthPercentage::Calc(node) => node.has_percentage(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CalcType {
+    Number,
+    Length,
+    Percentage,
+    LengthPercentage,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum CalcNode {
+    Number(f64),
+    Length(Length),
+    Percentage(f64),
+    Sum(Box<CalcNode>, Box<CalcNode>),
+    Difference(Box<CalcNode>, Box<CalcNode>),
+    Product(Box<CalcNode>, Box<CalcNode>),
+    Quotient(Box<CalcNode>, Box<CalcNode>),
+    Min(Vec<CalcNode>),
+    Max(Vec<CalcNode>),
+    Clamp(Box<CalcNode>, Box<CalcNode>, Box<CalcNode>),
+}
+
+fn add_calc_types(a: CalcType, b: CalcType) -> Result<CalcType, String> {
+    match (a, b) {
+        (a, b) if a == b => Ok(a),
+        (CalcType::Number, _) | (_, CalcType::Number) => {
+            Err(format!("Cannot add {:?} and {:?}", a, b))
+        }
+        _ => Ok(CalcType::LengthPercentage),
+    }
+}
+
+impl CalcNode {
+    pub fn calc_type(&self) -> Result<CalcType, String> {
+        match self {
+            CalcNode::Number(_) => Ok(CalcType::Number),
+            CalcNode::Length(_) => Ok(CalcType::Length),
+            CalcNode::Percentage(_) => Ok(CalcType::Percentage),
+            CalcNode::Sum(a, b) | CalcNode::Difference(a, b) => {
+                add_calc_types(a.calc_type()?, b.calc_type()?)
+            }
+            CalcNode::Product(a, b) => match (a.calc_type()?, b.calc_type()?) {
+                (CalcType::Number, t) | (t, CalcType::Number) => Ok(t),
+                (a, b) => Err(format!("Cannot multiply {:?} by {:?}", a, b)),
+            },
+            CalcNode::Quotient(a, b) => match b.calc_type()? {
+                CalcType::Number => a.calc_type(),
+                t => Err(format!("Cannot divide by {:?}", t)),
+            },
+            CalcNode::Min(args) | CalcNode::Max(args) => {
+                let mut t = args[0].calc_type()?;
+                for arg in &args[1..] {
+                    t = add_calc_types(t, arg.calc_type()?)?;
+                }
+                Ok(t)
+            }
+            CalcNode::Clamp(min, val, max) => {
+                let t = add_calc_types(min.calc_type()?, val.calc_type()?)?;
+                add_calc_types(t, max.calc_type()?)
+            }
+        }
+    }
+
+    pub fn evaluate(&self, context: &LengthContext, basis: f64) -> f64 {
+        match self {
+            CalcNode::Number(n) => *n,
+            CalcNode::Length(l) => l.to_px(context),
+            CalcNode::Percentage(p) => p / 100.0 * basis,
+            CalcNode::Sum(a, b) => a.evaluate(context, basis) + b.evaluate(context, basis),
+            CalcNode::Difference(a, b) => a.evaluate(context, basis) - b.evaluate(context, basis),
+            CalcNode::Product(a, b) => a.evaluate(context, basis) * b.evaluate(context, basis),
+            CalcNode::Quotient(a, b) => a.evaluate(context, basis) / b.evaluate(context, basis),
+            CalcNode::Min(args) => args
+                .iter()
+                .map(|a| a.evaluate(context, basis))
+                .fold(f64::INFINITY, f64::min),
+            CalcNode::Max(args) => args
+                .iter()
+                .map(|a| a.evaluate(context, basis))
+                .fold(f64::NEG_INFINITY, f64::max),
+            CalcNode::Clamp(min, val, max) => {
+                let min = min.evaluate(context, basis);
+                let val = val.evaluate(context, basis);
+                let max = max.evaluate(context, basis);
+                f64::max(min, f64::min(val, max))
+            }
+        }
+    }
+
+    pub fn has_percentage(&self) -> bool {
+        match self {
+            CalcNode::Number(_) | CalcNode::Length(_) => false,
+            CalcNode::Percentage(_) => true,
+            CalcNode::Sum(a, b)
+            | CalcNode::Difference(a, b)
+            | CalcNode::Product(a, b)
+            | CalcNode::Quotient(a, b) => a.has_percentage() || b.has_percentage(),
+            CalcNode::Min(args) | CalcNode::Max(args) => args.iter().any(|a| a.has_percentage()),
+            CalcNode::Clamp(a, b, c) => {
+                a.has_percentage() || b.has_percentage() || c.has_percentage()
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum CalcToken {
+    Dimension(f64, String),
+    Percentage(f64),
+    Operator(char),
+    Function(String),
+    OpenParen,
+    CloseParen,
+    Comma,
+}
+
+// Splits a numeric token like `1.5e2px` into its number and unit. Returns the
+// number of bytes consumed by the number so callers can continue scanning.
+pub fn split_number(value: &str) -> Option<(f64, usize)> {
+    let bytes = value.as_bytes();
+    let mut i = 0;
+
+    if i < bytes.len() && (bytes[i] == b'+' || bytes[i] == b'-') {
+        i += 1;
+    }
+
+    let digits_start = i;
+    while i < bytes.len() && bytes[i].is_ascii_digit() {
+        i += 1;
+    }
+    let mut has_digits = i > digits_start;
+
+    if i + 1 < bytes.len() && bytes[i] == b'.' && bytes[i + 1].is_ascii_digit() {
+        i += 1;
+        while i < bytes.len() && bytes[i].is_ascii_digit() {
+            i += 1;
+        }
+        has_digits = true;
+    }
+
+    if !has_digits {
+        return None;
+    }
+
+    // an `e` only starts an exponent when digits follow, otherwise it is the
+    // start of a unit like `em` or `ex`
+    if i < bytes.len() && (bytes[i] == b'e' || bytes[i] == b'E') {
+        let mut j = i + 1;
+        if j < bytes.len() && (bytes[j] == b'+' || bytes[j] == b'-') {
+            j += 1;
+        }
+        if j < bytes.len() && bytes[j].is_ascii_digit() {
+            while j < bytes.len() && bytes[j].is_ascii_digit() {
+                j += 1;
+            }
+            i = j;
+        }
+    }
+
+    match value[..i].parse::<f64>() {
+        Ok(n) => Some((n, i)),
+        Err(_) => None,
+    }
+}
+
+fn tokenize_calc(value: &str) -> Result<Vec<CalcToken>, String> {
+    let mut tokens: Vec<CalcToken> = vec![];
+    let mut i = 0;
+
+    while i < value.len() {
+        let rest = &value[i..];
+        let c = rest.chars().next().unwrap();
+
+        if c.is_whitespace() {
+            i += c.len_utf8();
+            continue;
+        }
+
+        let previous_is_operand = match tokens.last() {
+            Some(CalcToken::Dimension(_, _)) | Some(CalcToken::Percentage(_)) => true,
+            Some(CalcToken::CloseParen) => true,
+            _ => false,
+        };
+
+        let starts_number = c.is_ascii_digit()
+            || (c == '.' && rest.len() > 1 && rest.as_bytes()[1].is_ascii_digit())
+            || ((c == '+' || c == '-') && !previous_is_operand && rest.len() > 1 && {
+                let next = rest.as_bytes()[1];
+                next.is_ascii_digit() || next == b'.'
+            });
+
+        if starts_number {
+            let (number, len) = match split_number(rest) {
+                Some(n) => n,
+                None => return Err(format!("Invalid number in {}", value)),
+            };
+            i += len;
+            let unit: String = value[i..]
+                .chars()
+                .take_while(|c| c.is_alphabetic() || *c == '%')
+                .collect();
+            i += unit.len();
+            if unit == "%" {
+                tokens.push(CalcToken::Percentage(number));
+            } else {
+                tokens.push(CalcToken::Dimension(number, unit));
+            }
+        } else if c == '+' || c == '-' || c == '*' || c == '/' {
+            tokens.push(CalcToken::Operator(c));
+            i += 1;
+        } else if c == '(' {
+            tokens.push(CalcToken::OpenParen);
+            i += 1;
+        } else if c == ')' {
+            tokens.push(CalcToken::CloseParen);
+            i += 1;
+        } else if c == ',' {
+            tokens.push(CalcToken::Comma);
+            i += 1;
+        } else if c.is_alphabetic() || c == '-' {
+            let name: String = rest
+                .chars()
+                .take_while(|c| c.is_alphanumeric() || *c == '-')
+                .collect();
+            i += name.len();
+            if value[i..].starts_with('(') {
+                i += 1;
+                tokens.push(CalcToken::Function(name.to_lowercase()));
+            } else {
+                return Err(format!("Unexpected identifier {} in {}", name, value));
+            }
+        } else {
+            return Err(format!("Unexpected character {} in {}", c, value));
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct CalcParser {
+    tokens: Vec<CalcToken>,
+    position: usize,
+}
+
+impl CalcParser {
+    fn peek(&self) -> Option<&CalcToken> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<CalcToken> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn expect(&mut self, token: CalcToken) -> Result<(), String> {
+        match self.next() {
+            Some(t) if t == token => Ok(()),
+            t => Err(format!("Expected {:?}, found {:?}", token, t)),
+        }
+    }
+
+    fn parse_sum(&mut self) -> Result<CalcNode, String> {
+        let mut node = self.parse_product()?;
+        loop {
+            match self.peek() {
+                Some(CalcToken::Operator('+')) => {
+                    self.next();
+                    node = CalcNode::Sum(Box::new(node), Box::new(self.parse_product()?));
+                }
+                Some(CalcToken::Operator('-')) => {
+                    self.next();
+                    node = CalcNode::Difference(Box::new(node), Box::new(self.parse_product()?));
+                }
+                _ => return Ok(node),
+            }
+        }
+    }
+
+    fn parse_product(&mut self) -> Result<CalcNode, String> {
+        let mut node = self.parse_value()?;
+        loop {
+            match self.peek() {
+                Some(CalcToken::Operator('*')) => {
+                    self.next();
+                    node = CalcNode::Product(Box::new(node), Box::new(self.parse_value()?));
+                }
+                Some(CalcToken::Operator('/')) => {
+                    self.next();
+                    node = CalcNode::Quotient(Box::new(node), Box::new(self.parse_value()?));
+                }
+                _ => return Ok(node),
+            }
+        }
+    }
+
+    fn parse_arguments(&mut self) -> Result<Vec<CalcNode>, String> {
+        let mut args = vec![self.parse_sum()?];
+        while self.peek() == Some(&CalcToken::Comma) {
+            self.next();
+            args.push(self.parse_sum()?);
+        }
+        self.expect(CalcToken::CloseParen)?;
+        Ok(args)
+    }
+
+    fn parse_value(&mut self) -> Result<CalcNode, String> {
+        match self.next() {
+            Some(CalcToken::Dimension(n, unit)) => {
+                if unit == "" {
+                    Ok(CalcNode::Number(n))
+                } else {
+                    match LengthUnit::from_str(&unit) {
+                        Some(unit) => Ok(CalcNode::Length(Length { value: n, unit })),
+                        None => Err(format!("Unknown unit {}", unit)),
+                    }
+                }
+            }
+            Some(CalcToken::Percentage(n)) => Ok(CalcNode::Percentage(n)),
+            Some(CalcToken::OpenParen) => {
+                let node = self.parse_sum()?;
+                self.expect(CalcToken::CloseParen)?;
+                Ok(node)
+            }
+            Some(CalcToken::Function(name)) => {
+                let mut args = self.parse_arguments()?;
+                match name.as_str() {
+                    "calc" if args.len() == 1 => Ok(args.remove(0)),
+                    "min" => Ok(CalcNode::Min(args)),
+                    "max" => Ok(CalcNode::Max(args)),
+                    "clamp" if args.len() == 3 => {
+                        let max = args.pop().unwrap();
+                        let val = args.pop().unwrap();
+                        let min = args.pop().unwrap();
+                        Ok(CalcNode::Clamp(Box::new(min), Box::new(val), Box::new(max)))
+                    }
+                    _ => Err(format!("Invalid math function {}()", name)),
+                }
+            }
+            t => Err(format!("Unexpected token {:?}", t)),
+        }
+    }
+}
+
+pub fn is_math_function(value: &str) -> bool {
+    let value = value.trim().to_lowercase();
+    value.starts_with("calc(")
+        || value.starts_with("min(")
+        || value.starts_with("max(")
+        || value.starts_with("clamp(")
+}
+
+pub fn parse_calc(value: &str) -> Result<CalcNode, String> {
+    let mut parser = CalcParser {
+        tokens: tokenize_calc(value)?,
+        position: 0,
+    };
+    let node = parser.parse_value()?;
+    if parser.position != parser.tokens.len() {
+        return Err(format!("Unexpected trailing input in {}", value));
+    }
+    node.calc_type()?;
+    Ok(node)
+}
+
+pub fn parse_length_percentage(value: &str) -> Result<LengthPercentage, String> {
+    let value = value.replace("!important", "");
+    let value = value.trim();
+
+    if is_math_function(value) {
+        let node = parse_calc(value)?;
+        return match node.calc_type()? {
+            CalcType::Number => Err(format!("{} is a number, not a length", value)),
+            CalcType::Length => match node {
+                CalcNode::Length(l) => Ok(LengthPercentage::Length(l)),
+                node => Ok(LengthPercentage::Calc(Box::new(node))),
+            },
+            CalcType::Percentage => match node {
+                CalcNode::Percentage(p) => Ok(LengthPercentage::Percentage(p)),
+                node => Ok(LengthPercentage::Calc(Box::new(node))),
+            },
+            CalcType::LengthPercentage => Ok(LengthPercentage::Calc(Box::new(node))),
+        };
+    }
+
+    let (number, len) = match split_number(value) {
+        Some(n) => n,
+        None => return Err(format!("Error while parsing css value {}", value)),
+    };
+    let unit = &value[len..];
+
+    if unit == "%" {
+        Ok(LengthPercentage::Percentage(number))
+    } else if unit == "" {
+        // unitless lengths are accepted like in quirks mode
+        Ok(LengthPercentage::Length(Length::px(number)))
+    } else {
+        match LengthUnit::from_str(unit) {
+            Some(unit) => Ok(LengthPercentage::Length(Length {
+                value: number,
+                unit,
+            })),
+            None => Err(format!("Unknown unit {} in {}", unit, value)),
+        }
+    }
+}
+
+pub fn parse_length(value: &str) -> Result<Length, String> {
+    match parse_length_percentage(value)? {
+        LengthPercentage::Length(l) => Ok(l),
+        _ => Err(format!("{} is not a length", value)),
+    }
+}
+
+// Parses a plain number, or a math function that evaluates to one.
+pub fn parse_number(value: &str) -> Result<f64, String> {
+    let value = value.trim();
+    if is_math_function(value) {
+        let node = parse_calc(value)?;
+        return match node.calc_type()? {
+            CalcType::Number => Ok(node.evaluate(&LengthContext::new(0.0, 0.0), 0.0)),
+            t => Err(format!("{} is a {:?}, not a number", value, t)),
+        };
+    }
+    match split_number(value) {
+        Some((n, len)) if len == value.len() => Ok(n),
+        _ => Err(format!("{} is not a number", value)),
+    }
+}
@@ -1,39 +1,753 @@
-use regex::Regex;
+use crate::colors::{linear_to_srgb, srgb_to_linear, Color, ColorScheme};
+use crate::css::{split_css_list, split_css_values};
 use std::collections::HashMap;
 
-#[derive(Debug)]
-pub struct Color([f32; 4]);
-
-impl Color {
-    pub fn new(color: &str) -> Self {
-        let normalized_color = if is_hex_color(color) {
-            hex_to_rgb(color)
-        } else if is_hsl_color(color) {
-            hsl_to_rgba(color)
-        } else if is_hsla_color(color) {
-            hsla_to_rgba(color)
-        } else if is_rgb_color(color) {
-            rgb_to_rgba(color)
-        } else if is_rgba_color(color) {
-            rgba_to_rgba(color)
-        } else if is_color_string(color) {
-            normalize_color(color_string_to_rgba(color))
-        } else if is_hexa_color(color) {
-            hexa_to_argb(color)
+// Parses a CSS color. `currentColor` resolves to `current_color`, an error
+// without it. System colors and `light-dark()` follow `color_scheme`.
+pub fn parse_color(color: &str, current_color: Option<&Color>, color_scheme: ColorScheme) -> Result<Color, String> {
+    let context = ColorContext {
+        current_color: current_color.map(|c| {
+            let (r, g, b, a) = c.to_srgba();
+            [r, g, b, a]
+        }),
+        color_scheme,
+    };
+    parse_rgba(color, &context).map(to_color)
+}
+
+pub fn is_color(color: &str) -> bool {
+    let context = ColorContext {
+        current_color: Some([0.0, 0.0, 0.0, 1.0]),
+        color_scheme: ColorScheme::Light,
+    };
+    parse_rgba(color, &context).is_ok()
+}
+
+pub fn get_system_color(name: &str, color_scheme: ColorScheme) -> Option<Color> {
+    get_system_color_channels(&name.to_lowercase(), color_scheme).map(|c| to_color(normalize_color(c)))
+}
+
+// What colors depending on the element resolve against.
+struct ColorContext {
+    current_color: Option<Rgba>,
+    color_scheme: ColorScheme,
+}
+
+// sRGB channels that may be outside of 0..1, so colors outside of the sRGB
+// gamut survive until they are mixed or painted. The alpha is not premultiplied.
+type Rgba = [f64; 4];
+
+// Out of gamut colors are clipped to sRGB.
+fn to_color(rgba: Rgba) -> Color {
+    let clamp = |c: f64| c.max(0.0).min(1.0);
+    Color::from_srgba(clamp(rgba[0]), clamp(rgba[1]), clamp(rgba[2]), clamp(rgba[3]))
+}
+
+fn make_error(color: &str, reason: &str) -> String {
+    format!("Error while parsing color {}: {}", color, reason)
+}
+
+fn parse_rgba(color: &str, context: &ColorContext) -> Result<Rgba, String> {
+    let color = color.trim();
+    let lower = color.to_lowercase();
+
+    if lower.starts_with('#') {
+        return parse_hex(&lower[1..]).ok_or(make_error(color, "invalid hex color"));
+    }
+
+    match lower.find('(') {
+        Some(open) => {
+            if !lower.ends_with(')') {
+                return Err(make_error(color, "missing )"));
+            }
+            let name = lower[..open].trim();
+            let args = &lower[open + 1..lower.len() - 1];
+            return parse_color_function(name, args, context).map_err(|e| make_error(color, &e));
+        }
+        None => {}
+    }
+
+    if lower == "transparent" {
+        return Ok([0.0, 0.0, 0.0, 0.0]);
+    }
+    if lower == "currentcolor" {
+        return context
+            .current_color
+            .ok_or(make_error(color, "currentColor is not known here"));
+    }
+    match get_named_colors().get(lower.as_str()) {
+        Some(c) => return Ok(normalize_color(*c)),
+        None => {}
+    }
+    match get_system_color_channels(&lower, context.color_scheme) {
+        Some(c) => return Ok(normalize_color(c)),
+        None => {}
+    }
+
+    Err(make_error(color, "unknown color"))
+}
+
+// #rgb, #rgba, #rrggbb and #rrggbbaa
+fn parse_hex(hex: &str) -> Option<Rgba> {
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let digits: Vec<u8> = match hex.len() {
+        3 | 4 => hex
+            .chars()
+            .map(|c| u8::from_str_radix(&c.to_string().repeat(2), 16).unwrap())
+            .collect(),
+        6 | 8 => (0..hex.len() / 2)
+            .map(|i| u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).unwrap())
+            .collect(),
+        _ => return None,
+    };
+    let channel = |i: usize| digits.get(i).map(|d| *d as f64 / 255.0).unwrap_or(1.0);
+    Some([channel(0), channel(1), channel(2), channel(3)])
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Component {
+    Number(f64),
+    Percentage(f64),
+    // in degrees
+    Angle(f64),
+    None,
+}
+
+fn parse_number(value: &str) -> Option<f64> {
+    match value.chars().next() {
+        Some(c) if c.is_ascii_digit() || c == '.' || c == '-' || c == '+' => value.parse::<f64>().ok(),
+        _ => None,
+    }
+}
+
+fn parse_component(value: &str) -> Result<Component, String> {
+    if value == "none" {
+        return Ok(Component::None);
+    }
+    if value.ends_with('%') {
+        return parse_number(&value[..value.len() - 1])
+            .map(Component::Percentage)
+            .ok_or(format!("invalid percentage {}", value));
+    }
+    for (unit, degrees) in &[("deg", 1.0), ("grad", 0.9), ("rad", 180.0 / std::f64::consts::PI), ("turn", 360.0)] {
+        if value.ends_with(unit) {
+            return parse_number(&value[..value.len() - unit.len()])
+                .map(|n| Component::Angle(n * degrees))
+                .ok_or(format!("invalid angle {}", value));
+        }
+    }
+    parse_number(value)
+        .map(Component::Number)
+        .ok_or(format!("invalid component {}", value))
+}
+
+// A number, or a percentage of `percent_reference`. `none` is 0.
+fn to_number(component: Component, percent_reference: f64) -> Result<f64, String> {
+    match component {
+        Component::Number(n) => Ok(n),
+        Component::Percentage(p) => Ok(p / 100.0 * percent_reference),
+        Component::None => Ok(0.0),
+        Component::Angle(_) => Err("unexpected angle".to_string()),
+    }
+}
+
+fn to_hue(component: Component) -> Result<f64, String> {
+    match component {
+        Component::Number(n) | Component::Angle(n) => Ok(n),
+        Component::None => Ok(0.0),
+        Component::Percentage(_) => Err("a hue can't be a percentage".to_string()),
+    }
+}
+
+fn to_alpha(component: Option<Component>) -> Result<f64, String> {
+    match component {
+        Some(c) => to_number(c, 1.0).map(|a| a.max(0.0).min(1.0)),
+        None => Ok(1.0),
+    }
+}
+
+// The three channels and the alpha of a color function, either comma
+// separated, which only `rgb()` and `hsl()` allow, or space separated with
+// the alpha after a slash.
+fn split_arguments(args: &str, allow_commas: bool) -> Result<(Vec<Component>, Option<Component>), String> {
+    let list = split_css_list(args);
+    let (channels, alpha): (Vec<String>, Option<String>) = if list.len() > 1 {
+        if !allow_commas {
+            return Err("unexpected ,".to_string());
+        }
+        if list.len() > 4 {
+            return Err("too many arguments".to_string());
+        }
+        (list[..list.len().min(3)].to_vec(), list.get(3).cloned())
+    } else {
+        let values = split_css_values(&args.replace('/', " / "));
+        match values.iter().position(|v| v == "/") {
+            Some(i) => {
+                if values.len() != i + 2 {
+                    return Err("expected a single alpha after /".to_string());
+                }
+                (values[..i].to_vec(), Some(values[i + 1].clone()))
+            }
+            None => (values, None),
+        }
+    };
+
+    if channels.len() != 3 {
+        return Err("expected 3 channels".to_string());
+    }
+
+    let mut components: Vec<Component> = vec![];
+    for channel in &channels {
+        components.push(parse_component(channel)?);
+    }
+    let alpha = match alpha {
+        Some(a) => Some(parse_component(&a)?),
+        None => None,
+    };
+
+    Ok((components, alpha))
+}
+
+fn parse_color_function(name: &str, args: &str, context: &ColorContext) -> Result<Rgba, String> {
+    if name == "color-mix" {
+        return parse_color_mix(args, context);
+    }
+    if name == "light-dark" {
+        let list = split_css_list(args);
+        if list.len() != 2 {
+            return Err("light-dark() takes two colors".to_string());
+        }
+        return match context.color_scheme {
+            ColorScheme::Light => parse_rgba(&list[0], context),
+            ColorScheme::Dark => parse_rgba(&list[1], context),
+        };
+    }
+    if name == "color" {
+        let values = split_css_values(args);
+        let space = match values.first() {
+            Some(s) => parse_predefined_space(s)?,
+            None => return Err("missing color space".to_string()),
+        };
+        let (c, alpha) = split_arguments(args.trim_start()[values[0].len()..].trim(), false)?;
+        let channels = [to_number(c[0], 1.0)?, to_number(c[1], 1.0)?, to_number(c[2], 1.0)?];
+        let rgb = to_srgb(space, channels);
+        return Ok([rgb[0], rgb[1], rgb[2], to_alpha(alpha)?]);
+    }
+
+    let (space, allow_commas) = match name {
+        "rgb" | "rgba" => (ColorSpace::Srgb, true),
+        "hsl" | "hsla" => (ColorSpace::Hsl, true),
+        "hwb" => (ColorSpace::Hwb, false),
+        "lab" => (ColorSpace::Lab, false),
+        "lch" => (ColorSpace::Lch, false),
+        "oklab" => (ColorSpace::Oklab, false),
+        "oklch" => (ColorSpace::Oklch, false),
+        _ => return Err(format!("unknown function {}()", name)),
+    };
+    let (c, alpha) = split_arguments(args, allow_commas)?;
+
+    let channels = match space {
+        ColorSpace::Srgb => [
+            to_number(c[0], 255.0)? / 255.0,
+            to_number(c[1], 255.0)? / 255.0,
+            to_number(c[2], 255.0)? / 255.0,
+        ],
+        ColorSpace::Hsl | ColorSpace::Hwb => [
+            to_hue(c[0])?,
+            to_number(c[1], 100.0)? / 100.0,
+            to_number(c[2], 100.0)? / 100.0,
+        ],
+        ColorSpace::Lab => [
+            to_number(c[0], 100.0)?.max(0.0),
+            to_number(c[1], 125.0)?,
+            to_number(c[2], 125.0)?,
+        ],
+        ColorSpace::Lch => [
+            to_number(c[0], 100.0)?.max(0.0),
+            to_number(c[1], 150.0)?.max(0.0),
+            to_hue(c[2])?,
+        ],
+        ColorSpace::Oklab => [
+            to_number(c[0], 1.0)?.max(0.0),
+            to_number(c[1], 0.4)?,
+            to_number(c[2], 0.4)?,
+        ],
+        ColorSpace::Oklch => [
+            to_number(c[0], 1.0)?.max(0.0),
+            to_number(c[1], 0.4)?.max(0.0),
+            to_hue(c[2])?,
+        ],
+        _ => unreachable!(),
+    };
+
+    let rgb = to_srgb(space, channels);
+    Ok([rgb[0], rgb[1], rgb[2], to_alpha(alpha)?])
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ColorSpace {
+    Srgb,
+    SrgbLinear,
+    DisplayP3,
+    XyzD50,
+    XyzD65,
+    Lab,
+    Lch,
+    Oklab,
+    Oklch,
+    Hsl,
+    Hwb,
+}
+
+// The spaces `color()` takes.
+fn parse_predefined_space(name: &str) -> Result<ColorSpace, String> {
+    match name {
+        "srgb" => Ok(ColorSpace::Srgb),
+        "srgb-linear" => Ok(ColorSpace::SrgbLinear),
+        "display-p3" => Ok(ColorSpace::DisplayP3),
+        "xyz" | "xyz-d65" => Ok(ColorSpace::XyzD65),
+        "xyz-d50" => Ok(ColorSpace::XyzD50),
+        _ => Err(format!("unsupported color space {}", name)),
+    }
+}
+
+fn parse_mix_space(name: &str) -> Result<ColorSpace, String> {
+    match name {
+        "lab" => Ok(ColorSpace::Lab),
+        "lch" => Ok(ColorSpace::Lch),
+        "oklab" => Ok(ColorSpace::Oklab),
+        "oklch" => Ok(ColorSpace::Oklch),
+        "hsl" => Ok(ColorSpace::Hsl),
+        "hwb" => Ok(ColorSpace::Hwb),
+        _ => parse_predefined_space(name),
+    }
+}
+
+// The index of the hue channel of the cylindrical spaces.
+fn hue_index(space: ColorSpace) -> Option<usize> {
+    match space {
+        ColorSpace::Hsl | ColorSpace::Hwb => Some(0),
+        ColorSpace::Lch | ColorSpace::Oklch => Some(2),
+        _ => None,
+    }
+}
+
+// A gray has no hue, it takes the hue of the other color when mixing.
+fn is_achromatic(space: ColorSpace, c: [f64; 3]) -> bool {
+    match space {
+        ColorSpace::Hsl => c[1].abs() < 1e-6 || c[2] <= 0.0 || c[2] >= 1.0,
+        ColorSpace::Hwb => c[1] + c[2] >= 1.0 - 1e-6,
+        ColorSpace::Lch => c[1] < 0.02,
+        ColorSpace::Oklch => c[1] < 0.0002,
+        _ => false,
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum HueInterpolation {
+    Shorter,
+    Longer,
+    Increasing,
+    Decreasing,
+}
+
+fn fixup_hues(h1: f64, h2: f64, method: HueInterpolation) -> (f64, f64) {
+    let (mut h1, mut h2) = (h1.rem_euclid(360.0), h2.rem_euclid(360.0));
+    let difference = h2 - h1;
+    match method {
+        HueInterpolation::Shorter => {
+            if difference > 180.0 {
+                h1 += 360.0;
+            } else if difference < -180.0 {
+                h2 += 360.0;
+            }
+        }
+        HueInterpolation::Longer => {
+            if difference > 0.0 && difference < 180.0 {
+                h1 += 360.0;
+            } else if difference > -180.0 && difference <= 0.0 {
+                h2 += 360.0;
+            }
+        }
+        HueInterpolation::Increasing => {
+            if h2 < h1 {
+                h2 += 360.0;
+            }
+        }
+        HueInterpolation::Decreasing => {
+            if h1 < h2 {
+                h1 += 360.0;
+            }
+        }
+    }
+    (h1, h2)
+}
+
+// A color with an optional percentage before or after it.
+fn parse_mix_color(value: &str, context: &ColorContext) -> Result<(Rgba, Option<f64>), String> {
+    let mut values = split_css_values(value);
+    let percentage_at = values.iter().position(|v| v.ends_with('%') && !v.contains('('));
+    let percentage = match percentage_at {
+        Some(i) => {
+            let p = values.remove(i);
+            match parse_number(&p[..p.len() - 1]) {
+                Some(p) if p >= 0.0 && p <= 100.0 => Some(p / 100.0),
+                _ => return Err(format!("invalid percentage {}", p)),
+            }
+        }
+        None => None,
+    };
+    if values.len() != 1 {
+        return Err(format!("invalid color-mix() color {}", value));
+    }
+    Ok((parse_rgba(&values[0], context)?, percentage))
+}
+
+// color-mix(in <space> [<hue-interpolation> hue], <color> [<percentage>], <color> [<percentage>])
+fn parse_color_mix(args: &str, context: &ColorContext) -> Result<Rgba, String> {
+    let list = split_css_list(args);
+    if list.len() != 3 {
+        return Err("color-mix() takes a color space and two colors".to_string());
+    }
+
+    let method = split_css_values(&list[0]);
+    if method.len() < 2 || method[0] != "in" {
+        return Err("expected in <color space>".to_string());
+    }
+    let space = parse_mix_space(&method[1])?;
+    let hue_interpolation = match &method[2..] {
+        [] => HueInterpolation::Shorter,
+        [h, hue] if hue == "hue" && hue_index(space).is_some() => match h.as_str() {
+            "shorter" => HueInterpolation::Shorter,
+            "longer" => HueInterpolation::Longer,
+            "increasing" => HueInterpolation::Increasing,
+            "decreasing" => HueInterpolation::Decreasing,
+            _ => return Err(format!("invalid hue interpolation {}", h)),
+        },
+        _ => return Err(format!("invalid interpolation method {}", list[0])),
+    };
+
+    let (color1, p1) = parse_mix_color(&list[1], context)?;
+    let (color2, p2) = parse_mix_color(&list[2], context)?;
+
+    let (p1, p2) = match (p1, p2) {
+        (None, None) => (0.5, 0.5),
+        (Some(p1), None) => (p1, 1.0 - p1),
+        (None, Some(p2)) => (1.0 - p2, p2),
+        (Some(p1), Some(p2)) => (p1, p2),
+    };
+    let sum = p1 + p2;
+    if sum <= 0.0 {
+        return Err("the percentages add up to 0".to_string());
+    }
+    // percentages adding up to less than 100% make the result transparent
+    let alpha_multiplier = sum.min(1.0);
+    let t = p2 / sum;
+
+    let mut c1 = from_srgb(space, [color1[0], color1[1], color1[2]]);
+    let mut c2 = from_srgb(space, [color2[0], color2[1], color2[2]]);
+
+    match hue_index(space) {
+        Some(h) => {
+            if is_achromatic(space, c1) && !is_achromatic(space, c2) {
+                c1[h] = c2[h];
+            } else if is_achromatic(space, c2) && !is_achromatic(space, c1) {
+                c2[h] = c1[h];
+            }
+            let (h1, h2) = fixup_hues(c1[h], c2[h], hue_interpolation);
+            c1[h] = h1;
+            c2[h] = h2;
+        }
+        None => {}
+    }
+
+    // interpolated with premultiplied alpha, except the hue
+    let (a1, a2) = (color1[3], color2[3]);
+    let alpha = a1 * (1.0 - t) + a2 * t;
+    let mut mixed = [0.0; 3];
+    for i in 0..3 {
+        mixed[i] = if Some(i) == hue_index(space) || alpha == 0.0 {
+            c1[i] * (1.0 - t) + c2[i] * t
         } else {
-            [1.0, 1.0, 1.0, 0.0]
+            (c1[i] * a1 * (1.0 - t) + c2[i] * a2 * t) / alpha
         };
-        Color(normalized_color)
     }
 
-    pub fn to_array(&self) -> [f32; 4] {
-        [self.0[0], self.0[1], self.0[2], self.0[3]]
+    let rgb = to_srgb(space, mixed);
+    Ok([rgb[0], rgb[1], rgb[2], alpha * alpha_multiplier])
+}
+
+type Matrix = [[f64; 3]; 3];
+
+fn multiply(m: &Matrix, v: [f64; 3]) -> [f64; 3] {
+    [
+        m[0][0] * v[0] + m[0][1] * v[1] + m[0][2] * v[2],
+        m[1][0] * v[0] + m[1][1] * v[1] + m[1][2] * v[2],
+        m[2][0] * v[0] + m[2][1] * v[1] + m[2][2] * v[2],
+    ]
+}
+
+// The matrices are the ones of the CSS Color 4 sample code.
+const LINEAR_SRGB_TO_XYZ_D65: Matrix = [
+    [0.41239079926595934, 0.357584339383878, 0.1804807884018343],
+    [0.21263900587151027, 0.715168678767756, 0.07219231536073371],
+    [0.01933081871559182, 0.11919477979462598, 0.9505321522496607],
+];
+const XYZ_D65_TO_LINEAR_SRGB: Matrix = [
+    [3.2409699419045226, -1.537383177570094, -0.4986107602930034],
+    [-0.9692436362808796, 1.8759675015077202, 0.04155505740717559],
+    [0.05563007969699366, -0.20397695888897652, 1.0569715142428786],
+];
+const LINEAR_P3_TO_XYZ_D65: Matrix = [
+    [0.4865709486482162, 0.26566769316909306, 0.1982172852343625],
+    [0.2289745640697488, 0.6917385218365064, 0.079286914093745],
+    [0.0, 0.04511338185890264, 1.043944368900976],
+];
+const XYZ_D65_TO_LINEAR_P3: Matrix = [
+    [2.493496911941425, -0.9313836179191239, -0.40271078445071684],
+    [-0.8294889695615747, 1.7626640603183463, 0.023624685841943577],
+    [0.03584583024378447, -0.07617238926804182, 0.9568845240076872],
+];
+const XYZ_D65_TO_D50: Matrix = [
+    [1.0479298208405488, 0.022946793341019088, -0.05019222954313557],
+    [0.029627815688159344, 0.990434484573249, -0.01707382502938514],
+    [-0.009243058152591178, 0.015055144896577895, 0.7518742899580008],
+];
+const XYZ_D50_TO_D65: Matrix = [
+    [0.9554734527042182, -0.023098536874261423, 0.0632593086610217],
+    [-0.028369706963208136, 1.0099954580058226, 0.021041398966943008],
+    [0.012314001688319899, -0.020507696433477912, 1.3303659366080753],
+];
+const XYZ_D65_TO_LMS: Matrix = [
+    [0.8190224379967030, 0.3619062600528904, -0.1288737815209879],
+    [0.0329836539323885, 0.9292868615863434, 0.0361446663506424],
+    [0.0481771893596242, 0.2642395317527308, 0.6335478284694309],
+];
+const LMS_TO_XYZ_D65: Matrix = [
+    [1.2268798758459243, -0.5578149944602171, 0.2813910456659647],
+    [-0.0405757452148008, 1.1122868032803170, -0.0716766654559395],
+    [-0.0763729366746601, -0.4214933324022432, 1.5869240198367816],
+];
+const LMS_TO_OKLAB: Matrix = [
+    [0.2104542683093140, 0.7936177747023054, -0.0040720430116193],
+    [1.9779985324311684, -2.4285922420485799, 0.4505937096174110],
+    [0.0259040424655478, 0.7827717124575296, -0.8086757549230774],
+];
+const OKLAB_TO_LMS: Matrix = [
+    [1.0, 0.3963377773761749, 0.2158037573099136],
+    [1.0, -0.1055613458156586, -0.0638541728258133],
+    [1.0, -0.0894841775298119, -1.2914855480194092],
+];
+
+const D50_WHITE: [f64; 3] = [0.3457 / 0.3585, 1.0, (1.0 - 0.3457 - 0.3585) / 0.3585];
+const LAB_KAPPA: f64 = 24389.0 / 27.0;
+const LAB_EPSILON: f64 = 216.0 / 24389.0;
+
+fn lab_to_xyz_d50(lab: [f64; 3]) -> [f64; 3] {
+    let f1 = (lab[0] + 16.0) / 116.0;
+    let f0 = lab[1] / 500.0 + f1;
+    let f2 = f1 - lab[2] / 200.0;
+    let x = if f0.powi(3) > LAB_EPSILON {
+        f0.powi(3)
+    } else {
+        (116.0 * f0 - 16.0) / LAB_KAPPA
+    };
+    let y = if lab[0] > LAB_KAPPA * LAB_EPSILON {
+        f1.powi(3)
+    } else {
+        lab[0] / LAB_KAPPA
+    };
+    let z = if f2.powi(3) > LAB_EPSILON {
+        f2.powi(3)
+    } else {
+        (116.0 * f2 - 16.0) / LAB_KAPPA
+    };
+    [x * D50_WHITE[0], y * D50_WHITE[1], z * D50_WHITE[2]]
+}
+
+fn xyz_d50_to_lab(xyz: [f64; 3]) -> [f64; 3] {
+    let f = |v: f64| {
+        if v > LAB_EPSILON {
+            v.cbrt()
+        } else {
+            (LAB_KAPPA * v + 16.0) / 116.0
+        }
+    };
+    let f0 = f(xyz[0] / D50_WHITE[0]);
+    let f1 = f(xyz[1] / D50_WHITE[1]);
+    let f2 = f(xyz[2] / D50_WHITE[2]);
+    [116.0 * f1 - 16.0, 500.0 * (f0 - f1), 200.0 * (f1 - f2)]
+}
+
+fn oklab_to_xyz_d65(oklab: [f64; 3]) -> [f64; 3] {
+    let lms = multiply(&OKLAB_TO_LMS, oklab);
+    multiply(&LMS_TO_XYZ_D65, [lms[0].powi(3), lms[1].powi(3), lms[2].powi(3)])
+}
+
+fn xyz_d65_to_oklab(xyz: [f64; 3]) -> [f64; 3] {
+    let lms = multiply(&XYZ_D65_TO_LMS, xyz);
+    multiply(&LMS_TO_OKLAB, [lms[0].cbrt(), lms[1].cbrt(), lms[2].cbrt()])
+}
+
+// lch and oklch are lab and oklab in polar coordinates
+fn polar_to_rectangular(lch: [f64; 3]) -> [f64; 3] {
+    let hue = lch[2].to_radians();
+    [lch[0], lch[1] * hue.cos(), lch[1] * hue.sin()]
+}
+
+fn rectangular_to_polar(lab: [f64; 3]) -> [f64; 3] {
+    let hue = lab[2].atan2(lab[1]).to_degrees().rem_euclid(360.0);
+    [lab[0], (lab[1] * lab[1] + lab[2] * lab[2]).sqrt(), hue]
+}
+
+fn hsl_to_srgb(hsl: [f64; 3]) -> [f64; 3] {
+    let (h, s, l) = (hsl[0].rem_euclid(360.0), hsl[1], hsl[2]);
+    let f = |n: f64| {
+        let k = (n + h / 30.0) % 12.0;
+        let a = s * l.min(1.0 - l);
+        l - a * (k - 3.0).min(9.0 - k).min(1.0).max(-1.0)
+    };
+    [f(0.0), f(8.0), f(4.0)]
+}
+
+fn srgb_to_hsl(rgb: [f64; 3]) -> [f64; 3] {
+    let max = rgb[0].max(rgb[1]).max(rgb[2]);
+    let min = rgb[0].min(rgb[1]).min(rgb[2]);
+    let l = (max + min) / 2.0;
+    let d = max - min;
+    if d == 0.0 {
+        return [0.0, 0.0, l];
     }
+    let s = if l == 0.0 || l == 1.0 {
+        0.0
+    } else {
+        (max - l) / l.min(1.0 - l)
+    };
+    let h = if max == rgb[0] {
+        (rgb[1] - rgb[2]) / d + if rgb[1] < rgb[2] { 6.0 } else { 0.0 }
+    } else if max == rgb[1] {
+        (rgb[2] - rgb[0]) / d + 2.0
+    } else {
+        (rgb[0] - rgb[1]) / d + 4.0
+    };
+    [h * 60.0, s, l]
+}
+
+fn hwb_to_srgb(hwb: [f64; 3]) -> [f64; 3] {
+    let (w, b) = (hwb[1], hwb[2]);
+    if w + b >= 1.0 {
+        let gray = w / (w + b);
+        return [gray, gray, gray];
+    }
+    let rgb = hsl_to_srgb([hwb[0], 1.0, 0.5]);
+    [
+        rgb[0] * (1.0 - w - b) + w,
+        rgb[1] * (1.0 - w - b) + w,
+        rgb[2] * (1.0 - w - b) + w,
+    ]
+}
+
+fn srgb_to_hwb(rgb: [f64; 3]) -> [f64; 3] {
+    let hsl = srgb_to_hsl(rgb);
+    let white = rgb[0].min(rgb[1]).min(rgb[2]);
+    let black = 1.0 - rgb[0].max(rgb[1]).max(rgb[2]);
+    [hsl[0], white, black]
+}
+
+fn linear_srgb_to_xyz_d65(rgb: [f64; 3]) -> [f64; 3] {
+    multiply(&LINEAR_SRGB_TO_XYZ_D65, rgb)
+}
+
+fn xyz_d65_to_linear_srgb(xyz: [f64; 3]) -> [f64; 3] {
+    multiply(&XYZ_D65_TO_LINEAR_SRGB, xyz)
+}
+
+fn lab_to_linear_srgb(lab: [f64; 3]) -> [f64; 3] {
+    xyz_d65_to_linear_srgb(multiply(&XYZ_D50_TO_D65, lab_to_xyz_d50(lab)))
+}
+
+fn to_srgb(space: ColorSpace, c: [f64; 3]) -> [f64; 3] {
+    let linear = match space {
+        ColorSpace::Srgb => return c,
+        ColorSpace::Hsl => return hsl_to_srgb(c),
+        ColorSpace::Hwb => return hwb_to_srgb(c),
+        ColorSpace::SrgbLinear => c,
+        ColorSpace::DisplayP3 => {
+            let p3 = [srgb_to_linear(c[0]), srgb_to_linear(c[1]), srgb_to_linear(c[2])];
+            xyz_d65_to_linear_srgb(multiply(&LINEAR_P3_TO_XYZ_D65, p3))
+        }
+        ColorSpace::XyzD65 => xyz_d65_to_linear_srgb(c),
+        ColorSpace::XyzD50 => xyz_d65_to_linear_srgb(multiply(&XYZ_D50_TO_D65, c)),
+        ColorSpace::Lab => lab_to_linear_srgb(c),
+        ColorSpace::Lch => lab_to_linear_srgb(polar_to_rectangular(c)),
+        ColorSpace::Oklab => xyz_d65_to_linear_srgb(oklab_to_xyz_d65(c)),
+        ColorSpace::Oklch => xyz_d65_to_linear_srgb(oklab_to_xyz_d65(polar_to_rectangular(c))),
+    };
+    [linear_to_srgb(linear[0]), linear_to_srgb(linear[1]), linear_to_srgb(linear[2])]
+}
+
+fn from_srgb(space: ColorSpace, rgb: [f64; 3]) -> [f64; 3] {
+    let linear = [srgb_to_linear(rgb[0]), srgb_to_linear(rgb[1]), srgb_to_linear(rgb[2])];
+    match space {
+        ColorSpace::Srgb => rgb,
+        ColorSpace::Hsl => srgb_to_hsl(rgb),
+        ColorSpace::Hwb => srgb_to_hwb(rgb),
+        ColorSpace::SrgbLinear => linear,
+        ColorSpace::DisplayP3 => {
+            let p3 = multiply(&XYZ_D65_TO_LINEAR_P3, linear_srgb_to_xyz_d65(linear));
+            [linear_to_srgb(p3[0]), linear_to_srgb(p3[1]), linear_to_srgb(p3[2])]
+        }
+        ColorSpace::XyzD65 => linear_srgb_to_xyz_d65(linear),
+        ColorSpace::XyzD50 => multiply(&XYZ_D65_TO_D50, linear_srgb_to_xyz_d65(linear)),
+        ColorSpace::Lab => xyz_d50_to_lab(multiply(&XYZ_D65_TO_D50, linear_srgb_to_xyz_d65(linear))),
+        ColorSpace::Lch => rectangular_to_polar(from_srgb(ColorSpace::Lab, rgb)),
+        ColorSpace::Oklab => xyz_d65_to_oklab(linear_srgb_to_xyz_d65(linear)),
+        ColorSpace::Oklch => rectangular_to_polar(from_srgb(ColorSpace::Oklab, rgb)),
+    }
+}
+
+fn get_system_color_channels(name: &str, color_scheme: ColorScheme) -> Option<[f32; 4]> {
+    let color = match color_scheme {
+        ColorScheme::Light => match name {
+            "canvas" | "field" | "highlighttext" | "selecteditemtext" | "accentcolortext" => [255.0, 255.0, 255.0],
+            "canvastext" | "fieldtext" | "buttontext" | "marktext" => [0.0, 0.0, 0.0],
+            "linktext" => [0.0, 0.0, 238.0],
+            "visitedtext" => [85.0, 26.0, 139.0],
+            "activetext" => [238.0, 0.0, 0.0],
+            "buttonface" => [240.0, 240.0, 240.0],
+            "buttonborder" => [118.0, 118.0, 118.0],
+            "highlight" | "selecteditem" => [51.0, 153.0, 255.0],
+            "mark" => [255.0, 255.0, 0.0],
+            "graytext" => [128.0, 128.0, 128.0],
+            "accentcolor" => [0.0, 117.0, 255.0],
+            _ => return None,
+        },
+        ColorScheme::Dark => match name {
+            "canvas" => [18.0, 18.0, 18.0],
+            "field" => [59.0, 59.0, 59.0],
+            "canvastext" | "fieldtext" | "buttontext" | "highlighttext" | "selecteditemtext" | "accentcolortext" => {
+                [255.0, 255.0, 255.0]
+            }
+            "marktext" => [0.0, 0.0, 0.0],
+            "linktext" => [158.0, 158.0, 255.0],
+            "visitedtext" => [208.0, 173.0, 240.0],
+            "activetext" => [255.0, 158.0, 158.0],
+            "buttonface" | "buttonborder" => [107.0, 107.0, 107.0],
+            "highlight" | "selecteditem" => [38.0, 79.0, 120.0],
+            "mark" => [204.0, 204.0, 0.0],
+            "graytext" => [128.0, 128.0, 128.0],
+            "accentcolor" => [59.0, 142.0, 234.0],
+            _ => return None,
+        },
+    };
+    Some([color[0], color[1], color[2], 255.0])
 }
 
 fn get_named_colors() -> HashMap<&'static str, [f32; 4]> {
     let mut colors = HashMap::new();
-    colors.insert("aliceblue", [250.0, 235.0, 215.0, 255.0]);
+    colors.insert("aliceblue", [240.0, 248.0, 255.0, 255.0]);
     colors.insert("antiquewhite", [250.0, 235.0, 215.0, 255.0]);
     colors.insert("aqua", [0.0, 255.0, 255.0, 255.0]);
     colors.insert("aquamarine", [127.0, 255.0, 212.0, 255.0]);
@@ -58,6 +772,7 @@ fn get_named_colors() -> HashMap<&'static str, [f32; 4]> {
     colors.insert("darkcyan", [0.0, 139.0, 139.0, 255.0]);
     colors.insert("darkgoldenrod", [184.0, 134.0, 11.0, 255.0]);
     colors.insert("darkgray", [169.0, 169.0, 169.0, 255.0]);
+    colors.insert("darkgrey", [169.0, 169.0, 169.0, 255.0]);
     colors.insert("darkgreen", [0.0, 100.0, 0.0, 255.0]);
     colors.insert("darkkhaki", [189.0, 183.0, 107.0, 255.0]);
     colors.insert("darkmagenta", [139.0, 0.0, 139.0, 255.0]);
@@ -69,11 +784,13 @@ fn get_named_colors() -> HashMap<&'static str, [f32; 4]> {
     colors.insert("darkseagreen", [143.0, 188.0, 143.0, 255.0]);
     colors.insert("darkslateblue", [72.0, 61.0, 139.0, 255.0]);
     colors.insert("darkslategray", [47.0, 79.0, 79.0, 255.0]);
+    colors.insert("darkslategrey", [47.0, 79.0, 79.0, 255.0]);
     colors.insert("darkturquoise", [0.0, 206.0, 209.0, 255.0]);
     colors.insert("darkviolet", [148.0, 0.0, 211.0, 255.0]);
     colors.insert("deeppink", [255.0, 20.0, 147.0, 255.0]);
     colors.insert("deepskyblue", [0.0, 191.0, 255.0, 255.0]);
     colors.insert("dimgray", [105.0, 105.0, 105.0, 255.0]);
+    colors.insert("dimgrey", [105.0, 105.0, 105.0, 255.0]);
     colors.insert("dodgerblue", [30.0, 144.0, 255.0, 255.0]);
     colors.insert("firebrick", [178.0, 34.0, 34.0, 255.0]);
     colors.insert("floralwhite", [255.0, 250.0, 240.0, 255.0]);
@@ -84,6 +801,7 @@ fn get_named_colors() -> HashMap<&'static str, [f32; 4]> {
     colors.insert("gold", [255.0, 215.0, 0.0, 255.0]);
     colors.insert("goldenrod", [218.0, 165.0, 32.0, 255.0]);
     colors.insert("gray", [128.0, 128.0, 128.0, 255.0]);
+    colors.insert("grey", [128.0, 128.0, 128.0, 255.0]);
     colors.insert("green", [0.0, 128.0, 0.0, 255.0]);
     colors.insert("greenyellow", [173.0, 255.0, 47.0, 255.0]);
     colors.insert("honeydew", [240.0, 255.0, 240.0, 255.0]);
@@ -101,12 +819,14 @@ fn get_named_colors() -> HashMap<&'static str, [f32; 4]> {
     colors.insert("lightcyan", [224.0, 255.0, 255.0, 255.0]);
     colors.insert("lightgoldenrodyellow", [250.0, 250.0, 210.0, 255.0]);
     colors.insert("lightgray", [211.0, 211.0, 211.0, 255.0]);
+    colors.insert("lightgrey", [211.0, 211.0, 211.0, 255.0]);
     colors.insert("lightgreen", [144.0, 238.0, 144.0, 255.0]);
     colors.insert("lightpink", [255.0, 182.0, 193.0, 255.0]);
     colors.insert("lightsalmon", [255.0, 160.0, 122.0, 255.0]);
     colors.insert("lightseagreen", [32.0, 178.0, 170.0, 255.0]);
     colors.insert("lightskyblue", [135.0, 206.0, 250.0, 255.0]);
     colors.insert("lightslategray", [119.0, 136.0, 153.0, 255.0]);
+    colors.insert("lightslategrey", [119.0, 136.0, 153.0, 255.0]);
     colors.insert("lightsteelblue", [176.0, 196.0, 222.0, 255.0]);
     colors.insert("lightyellow", [255.0, 255.0, 224.0, 255.0]);
     colors.insert("lime", [0.0, 255.0, 0.0, 255.0]);
@@ -160,6 +880,7 @@ fn get_named_colors() -> HashMap<&'static str, [f32; 4]> {
     colors.insert("skyblue", [135.0, 206.0, 235.0, 255.0]);
     colors.insert("slateblue", [106.0, 90.0, 205.0, 255.0]);
     colors.insert("slategray", [112.0, 128.0, 144.0, 255.0]);
+    colors.insert("slategrey", [112.0, 128.0, 144.0, 255.0]);
     colors.insert("snow", [255.0, 250.0, 250.0, 255.0]);
     colors.insert("springgreen", [0.0, 255.0, 127.0, 255.0]);
     colors.insert("steelblue", [70.0, 130.0, 180.0, 255.0]);
@@ -177,163 +898,11 @@ fn get_named_colors() -> HashMap<&'static str, [f32; 4]> {
     colors
 }
 
-fn normalize_color(color: [f32; 4]) -> [f32; 4] {
-    [
-        color[0] / 255.0,
-        color[1] / 255.0,
-        color[2] / 255.0,
-        color[3] / 255.0,
-    ]
-}
-
-fn is_hex_color(color: &str) -> bool {
-    let re = Regex::new(r"^#(?:[0-9a-fA-F]{3}){1,2}$").unwrap();
-    re.is_match(color)
-}
-
-fn hex_to_rgb(color: &str) -> [f32; 4] {
-    let color = color.trim_start_matches('#');
-    let (r, g, b) = if color.len() == 3 {
-        (
-            u8::from_str_radix(&color[0..1].repeat(2), 16).unwrap(),
-            u8::from_str_radix(&color[1..2].repeat(2), 16).unwrap(),
-            u8::from_str_radix(&color[2..3].repeat(2), 16).unwrap(),
-        )
-    } else {
-        (
-            u8::from_str_radix(&color[0..2], 16).unwrap(),
-            u8::from_str_radix(&color[2..4], 16).unwrap(),
-            u8::from_str_radix(&color[4..6], 16).unwrap(),
-        )
-    };
-    [r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0, 1.0]
-}
-
-fn is_hexa_color(color: &str) -> bool {
-    let re = Regex::new(r"^#(?:[0-9a-fA-F]{8})$").unwrap();
-    re.is_match(color)
-}
-
-fn hexa_to_argb(color: &str) -> [f32; 4] {
-    let color = color.trim_start_matches('#');
-    let a = u8::from_str_radix(&color[0..2], 16).unwrap();
-    let r = u8::from_str_radix(&color[2..4], 16).unwrap();
-    let g = u8::from_str_radix(&color[4..6], 16).unwrap();
-    let b = u8::from_str_radix(&color[6..8], 16).unwrap();
+fn normalize_color(color: [f32; 4]) -> Rgba {
     [
-        r as f32 / 255.0,
-        g as f32 / 255.0,
-        b as f32 / 255.0,
-        a as f32 / 255.0,
+        color[0] as f64 / 255.0,
+        color[1] as f64 / 255.0,
+        color[2] as f64 / 255.0,
+        color[3] as f64 / 255.0,
     ]
 }
-
-fn is_hsl_color(color: &str) -> bool {
-    let re = Regex::new(r"hsl\((\d+),\s*(\d+)%,\s*(\d+)%\)").unwrap();
-    re.is_match(color)
-}
-
-fn hsl_to_rgba(color: &str) -> [f32; 4] {
-    let re = Regex::new(r"hsl\((\d+),\s*(\d+)%,\s*(\d+)%\)").unwrap();
-    let caps = re.captures(&color).unwrap();
-    let h = caps.get(1).unwrap().as_str().parse::<f32>().unwrap();
-    let s = caps.get(2).unwrap().as_str().parse::<f32>().unwrap();
-    let l = caps.get(3).unwrap().as_str().parse::<f32>().unwrap();
-    let s = s / 100.0;
-    let l = l / 100.0;
-    let c = (1.0 - (2.0 * l - 1.0).abs()) * s;
-    let x = c * (1.0 - ((h / 60.0) % 2.0 - 1.0).abs());
-    let m = l - c / 2.0;
-    let (r, g, b) = if h < 60.0 {
-        (c, x, 0.0)
-    } else if h < 120.0 {
-        (x, c, 0.0)
-    } else if h < 180.0 {
-        (0.0, c, x)
-    } else if h < 240.0 {
-        (0.0, x, c)
-    } else if h < 300.0 {
-        (x, 0.0, c)
-    } else {
-        (c, 0.0, x)
-    };
-    let r = (r + m) * 255.0;
-    let g = (g + m) * 255.0;
-    let b = (b + m) * 255.0;
-    [r / 255.0, g / 255.0, b / 255.0, 1.0]
-}
-
-fn is_hsla_color(color: &str) -> bool {
-    let re = Regex::new(r"hsla\((\d+),\s*(\d+)%,\s*(\d+)%,\s*(\d+\.\d+)\)").unwrap();
-    re.is_match(color)
-}
-
-fn hsla_to_rgba(color: &str) -> [f32; 4] {
-    let re = Regex::new(r"hsla\((\d+),\s*(\d+)%,\s*(\d+)%,\s*(\d+\.\d+)\)").unwrap();
-    let caps = re.captures(&color).unwrap();
-    let h = caps.get(1).unwrap().as_str().parse::<f32>().unwrap();
-    let s = caps.get(2).unwrap().as_str().parse::<f32>().unwrap();
-    let l = caps.get(3).unwrap().as_str().parse::<f32>().unwrap();
-    let a = caps.get(4).unwrap().as_str().parse::<f32>().unwrap();
-    let s = s / 100.0;
-    let l = l / 100.0;
-    let c = (1.0 - (2.0 * l - 1.0).abs()) * s;
-    let x = c * (1.0 - ((h / 60.0) % 2.0 - 1.0).abs());
-    let m = l - c / 2.0;
-    let (r, g, b) = if h < 60.0 {
-        (c, x, 0.0)
-    } else if h < 120.0 {
-        (x, c, 0.0)
-    } else if h < 180.0 {
-        (0.0, c, x)
-    } else if h < 240.0 {
-        (0.0, x, c)
-    } else if h < 300.0 {
-        (x, 0.0, c)
-    } else {
-        (c, 0.0, x)
-    };
-    let r = (r + m) * 255.0;
-    let g = (g + m) * 255.0;
-    let b = (b + m) * 255.0;
-    [r / 255.0, g / 255.0, b / 255.0, a]
-}
-
-fn is_rgb_color(color: &str) -> bool {
-    let re = Regex::new(r"rgb\((\d+),\s*(\d+),\s*(\d+)\)").unwrap();
-    re.is_match(color)
-}
-
-fn rgb_to_rgba(color: &str) -> [f32; 4] {
-    let re = Regex::new(r"rgb\((\d+),\s*(\d+),\s*(\d+)\)").unwrap();
-    let caps = re.captures(&color).unwrap();
-    let r = caps.get(1).unwrap().as_str().parse::<f32>().unwrap();
-    let g = caps.get(2).unwrap().as_str().parse::<f32>().unwrap();
-    let b = caps.get(3).unwrap().as_str().parse::<f32>().unwrap();
-    [r / 255.0, g / 255.0, b / 255.0, 1.0]
-}
-
-fn is_rgba_color(color: &str) -> bool {
-    let re = Regex::new(r"rgba\((\d+),\s*(\d+),\s*(\d+),\s*(\d+\.\d+)\)").unwrap();
-    re.is_match(color)
-}
-
-fn rgba_to_rgba(color: &str) -> [f32; 4] {
-    let re = Regex::new(r"rgba\((\d+),\s*(\d+),\s*(\d+),\s*(\d+\.\d+)\)").unwrap();
-    let caps = re.captures(&color).unwrap();
-    let r = caps.get(1).unwrap().as_str().parse::<f32>().unwrap();
-    let g = caps.get(2).unwrap().as_str().parse::<f32>().unwrap();
-    let b = caps.get(3).unwrap().as_str().parse::<f32>().unwrap();
-    let a = caps.get(4).unwrap().as_str().parse::<f32>().unwrap();
-    [r / 255.0, g / 255.0, b / 255.0, a]
-}
-
-fn is_color_string(color: &str) -> bool {
-    let colors = get_named_colors();
-    colors.contains_key(&color.to_lowercase().as_str())
-}
-
-fn color_string_to_rgba(color: &str) -> [f32; 4] {
-    let colors = get_named_colors();
-    *colors.get(color.to_lowercase().as_str()).unwrap()
-}
@@ -0,0 +1,104 @@
+use std::fs;
+
+pub trait ResourceLoader {
+    fn load(&self, url: &str) -> Result<String, String>;
+}
+
+// Loads `file://` urls and plain paths from the local filesystem.
+pub struct FileLoader;
+
+impl ResourceLoader for FileLoader {
+    fn load(&self, url: &str) -> Result<String, String> {
+        let path = if url.starts_with("file://") {
+            &url["file://".len()..]
+        } else if has_scheme(url) {
+            return Err(format!("Unsupported url scheme: {}", url));
+        } else {
+            url
+        };
+
+        fs::read_to_string(path).map_err(|e| format!("Error while loading {}: {}", url, e))
+    }
+}
+
+pub fn has_scheme(url: &str) -> bool {
+    match url.find(':') {
+        // a single letter is a windows drive, not a scheme
+        Some(i) if i > 1 => url[..i]
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.'),
+        _ => false,
+    }
+}
+
+fn normalize_path(path: &str) -> String {
+    let absolute = path.starts_with('/');
+    let mut segments: Vec<&str> = vec![];
+
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                if segments.len() > 0 && *segments.last().unwrap() != ".." {
+                    segments.pop();
+                } else if !absolute {
+                    segments.push("..");
+                }
+            }
+            s => segments.push(s),
+        }
+    }
+
+    let joined = segments.join("/");
+    if absolute {
+        "/".to_string() + &joined
+    } else {
+        joined
+    }
+}
+
+// Resolves `href` against the url of the document it appears in.
+pub fn resolve_url(base: &str, href: &str) -> String {
+    let href = href.trim();
+
+    if has_scheme(href) {
+        return href.to_string();
+    }
+
+    // split the base into the part that stays (scheme and host) and its path
+    let (prefix, base_path) = match base.find("://") {
+        Some(i) => {
+            let after_scheme = i + 3;
+            let path_start = base[after_scheme..]
+                .find('/')
+                .map(|p| after_scheme + p)
+                .unwrap_or(base.len());
+            (&base[..path_start], &base[path_start..])
+        }
+        None => ("", base),
+    };
+
+    let base_path = base_path.split(|c| c == '?' || c == '#').next().unwrap_or("");
+
+    if href.starts_with("//") {
+        let scheme = base.find("://").map(|i| &base[..i]).unwrap_or("file");
+        return format!("{}:{}", scheme, href);
+    }
+
+    if href.starts_with('/') {
+        return prefix.to_string() + &normalize_path(href);
+    }
+
+    let directory = match base_path.rfind('/') {
+        Some(i) => &base_path[..i + 1],
+        None => "",
+    };
+
+    let path = normalize_path(&(directory.to_string() + href));
+
+    if prefix != "" && !path.starts_with('/') {
+        return prefix.to_string() + "/" + &path;
+    }
+
+    return prefix.to_string() + &path;
+}
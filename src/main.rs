@@ -1,10 +1,26 @@
+mod borders;
 mod browser_window;
 mod colors;
 mod css;
+mod cssom;
 mod debug;
+mod element_state;
+mod flex_layout;
+mod floats;
+mod generated_content;
+mod grid_layout;
 mod html;
+mod inline_layout;
 mod layout;
+mod length;
+mod loader;
+mod media;
+mod overflow;
+mod properties;
+mod rule_index;
+mod selectors;
 mod styles;
+mod table_layout;
 mod utils;
 mod lisia_colors;
 
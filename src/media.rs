@@ -0,0 +1,315 @@
+use crate::colors::ColorScheme;
+use crate::length::*;
+
+// What the user asked pages to look like.
+#[derive(Clone, Debug)]
+pub struct UserPreferences {
+    pub color_scheme: ColorScheme,
+    pub reduced_motion: bool,
+    // `no-preference`, `more`, `less` or `custom`
+    pub contrast: String,
+}
+
+impl UserPreferences {
+    pub fn new() -> UserPreferences {
+        UserPreferences {
+            color_scheme: ColorScheme::Light,
+            reduced_motion: false,
+            contrast: "no-preference".to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MediaEnvironment {
+    pub media_type: String,
+    pub width: f64,
+    pub height: f64,
+    pub preferences: UserPreferences,
+}
+
+impl MediaEnvironment {
+    pub fn new(width: f64, height: f64) -> MediaEnvironment {
+        MediaEnvironment {
+            media_type: "screen".to_string(),
+            width,
+            height,
+            preferences: UserPreferences::new(),
+        }
+    }
+
+    pub fn with_preferences(width: f64, height: f64, preferences: &UserPreferences) -> MediaEnvironment {
+        let mut environment = MediaEnvironment::new(width, height);
+        environment.preferences = preferences.clone();
+        environment
+    }
+
+    fn length_context(&self) -> LengthContext {
+        LengthContext::new(self.width, self.height)
+    }
+}
+
+fn compare(value: f64, operator: &str, target: f64) -> bool {
+    match operator {
+        "<" => value < target,
+        "<=" => value <= target,
+        ">" => value > target,
+        ">=" => value >= target,
+        "=" => (value - target).abs() < 0.001,
+        _ => false,
+    }
+}
+
+fn flip_operator(operator: &str) -> &str {
+    match operator {
+        "<" => ">",
+        "<=" => ">=",
+        ">" => "<",
+        ">=" => "<=",
+        o => o,
+    }
+}
+
+fn parse_ratio(value: &str) -> Option<f64> {
+    let mut parts = value.split('/');
+    let numerator = parts.next()?.trim().parse::<f64>().ok()?;
+    match parts.next() {
+        Some(d) => Some(numerator / d.trim().parse::<f64>().ok()?),
+        None => Some(numerator),
+    }
+}
+
+fn evaluate_range(name: &str, operator: &str, value: &str, environment: &MediaEnvironment) -> bool {
+    let actual = match name {
+        "width" | "device-width" => environment.width,
+        "height" | "device-height" => environment.height,
+        "aspect-ratio" | "device-aspect-ratio" => {
+            return match parse_ratio(value) {
+                Some(r) => compare(environment.width / environment.height, operator, r),
+                None => false,
+            };
+        }
+        "resolution" => {
+            // a plain window without scaling
+            return match value.trim() {
+                "1dppx" | "1x" | "96dpi" => compare(1.0, operator, 1.0),
+                _ => false,
+            };
+        }
+        _ => return false,
+    };
+
+    match parse_length(value) {
+        Ok(l) => compare(actual, operator, l.to_px(&environment.length_context())),
+        Err(_) => false,
+    }
+}
+
+// Evaluates the contents of one `( ... )` media feature.
+pub fn evaluate_media_feature(feature: &str, environment: &MediaEnvironment) -> bool {
+    let feature = feature.trim();
+
+    for operator in &["<=", ">=", "<", ">", "="] {
+        if let Some(i) = feature.find(operator) {
+            let left = feature[..i].trim();
+            let right = feature[i + operator.len()..].trim();
+
+            // `400px <= width <= 700px`
+            if let Some(j) = right.find(|c| c == '<' || c == '>') {
+                let middle = right[..j].trim();
+                let rest = &right[j..];
+                let second_operator = if rest.starts_with("<=") || rest.starts_with(">=") {
+                    &rest[..2]
+                } else {
+                    &rest[..1]
+                };
+                let last = rest[second_operator.len()..].trim();
+                return evaluate_range(middle, flip_operator(operator), left, environment)
+                    && evaluate_range(middle, second_operator, last, environment);
+            }
+
+            if left.chars().next().map(|c| c.is_alphabetic()).unwrap_or(false) {
+                return evaluate_range(left, operator, right, environment);
+            } else {
+                return evaluate_range(right, flip_operator(operator), left, environment);
+            }
+        }
+    }
+
+    let (name, value) = match feature.find(':') {
+        Some(i) => (feature[..i].trim(), Some(feature[i + 1..].trim())),
+        None => (feature, None),
+    };
+
+    if name.starts_with("min-") {
+        return match value {
+            Some(v) => evaluate_range(&name[4..], ">=", v, environment),
+            None => false,
+        };
+    }
+
+    if name.starts_with("max-") {
+        return match value {
+            Some(v) => evaluate_range(&name[4..], "<=", v, environment),
+            None => false,
+        };
+    }
+
+    match (name, value) {
+        ("width", None) | ("height", None) => true,
+        ("width", Some(v)) | ("height", Some(v)) | ("aspect-ratio", Some(v)) => {
+            evaluate_range(name, "=", v, environment)
+        }
+        ("orientation", Some(v)) => {
+            let orientation = if environment.height >= environment.width {
+                "portrait"
+            } else {
+                "landscape"
+            };
+            v == orientation
+        }
+        ("color", None) => true,
+        ("color", Some(v)) => v == "8",
+        ("monochrome", None) | ("grid", None) => false,
+        ("monochrome", Some(v)) | ("grid", Some(v)) => v == "0",
+        ("hover", None) | ("any-hover", None) => true,
+        ("hover", Some(v)) | ("any-hover", Some(v)) => v == "hover",
+        ("pointer", None) | ("any-pointer", None) => true,
+        ("pointer", Some(v)) | ("any-pointer", Some(v)) => v == "fine",
+        ("scripting", Some(v)) => v == "none",
+        ("prefers-color-scheme", Some(v)) => match environment.preferences.color_scheme {
+            ColorScheme::Light => v == "light",
+            ColorScheme::Dark => v == "dark",
+        },
+        ("prefers-reduced-motion", None) => environment.preferences.reduced_motion,
+        ("prefers-reduced-motion", Some(v)) => {
+            v == if environment.preferences.reduced_motion {
+                "reduce"
+            } else {
+                "no-preference"
+            }
+        }
+        ("prefers-contrast", None) => environment.preferences.contrast != "no-preference",
+        ("prefers-contrast", Some(v)) => v == environment.preferences.contrast,
+        _ => false,
+    }
+}
+
+fn evaluate_condition(condition: &str, environment: &MediaEnvironment) -> bool {
+    let condition = condition.trim();
+
+    // `not (...)`
+    if condition.starts_with("not ") || condition.starts_with("not(") {
+        return !evaluate_condition(&condition[3..], environment);
+    }
+
+    let mut result: Option<bool> = None;
+    let mut combinator = "and";
+    let mut rest = condition;
+
+    while rest.len() > 0 {
+        rest = rest.trim_start();
+        if !rest.starts_with('(') {
+            return false;
+        }
+
+        let mut depth = 0;
+        let mut end = rest.len();
+        for (i, c) in rest.char_indices() {
+            if c == '(' {
+                depth += 1;
+            } else if c == ')' {
+                depth -= 1;
+                if depth == 0 {
+                    end = i;
+                    break;
+                }
+            }
+        }
+        if end == rest.len() {
+            return false;
+        }
+
+        let inner = &rest[1..end];
+        let value = if inner.trim_start().starts_with('(') || inner.trim_start().starts_with("not") {
+            evaluate_condition(inner, environment)
+        } else {
+            evaluate_media_feature(inner, environment)
+        };
+
+        result = Some(match result {
+            None => value,
+            Some(r) => {
+                if combinator == "or" {
+                    r || value
+                } else {
+                    r && value
+                }
+            }
+        });
+
+        rest = rest[end + 1..].trim_start();
+        if rest.starts_with("and") {
+            combinator = "and";
+            rest = &rest[3..];
+        } else if rest.starts_with("or") {
+            combinator = "or";
+            rest = &rest[2..];
+        } else if rest.len() > 0 {
+            return false;
+        }
+    }
+
+    result.unwrap_or(true)
+}
+
+fn evaluate_single_media_query(query: &str, environment: &MediaEnvironment) -> bool {
+    let query = query.trim().to_lowercase();
+
+    if query == "" {
+        return true;
+    }
+
+    if query.starts_with('(') || query.starts_with("not (") || query.starts_with("not(") {
+        return evaluate_condition(&query, environment);
+    }
+
+    let mut words = query.splitn(2, char::is_whitespace);
+    let mut first = words.next().unwrap_or("");
+    let mut rest = words.next().unwrap_or("").trim();
+
+    let negated = first == "not";
+    if first == "not" || first == "only" {
+        let mut words = rest.splitn(2, char::is_whitespace);
+        first = words.next().unwrap_or("");
+        rest = words.next().unwrap_or("").trim();
+    }
+
+    let type_matches = first == "all" || first == environment.media_type;
+
+    let condition_matches = if rest == "" {
+        true
+    } else if rest.starts_with("and") {
+        evaluate_condition(&rest[3..], environment)
+    } else {
+        return false;
+    };
+
+    let matches = type_matches && condition_matches;
+    if negated {
+        !matches
+    } else {
+        matches
+    }
+}
+
+// Evaluates a comma separated media query list like the `media` attribute of
+// `<link>` and `<style>`.
+pub fn evaluate_media_query(query: &str, environment: &MediaEnvironment) -> bool {
+    if query.trim() == "" {
+        return true;
+    }
+    query
+        .split(',')
+        .any(|q| evaluate_single_media_query(q, environment))
+}
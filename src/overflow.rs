@@ -0,0 +1,254 @@
+use crate::colors::*;
+use crate::element_state::*;
+use crate::html::*;
+use crate::layout::*;
+
+// How wide the scrollbars are. They are painted over the edge of the
+// padding box and take no room from the content.
+const SCROLLBAR_WIDTH: f64 = 8.0;
+
+// Where a box clips nothing, on the axis it lets its content overflow.
+const UNCLIPPED: f64 = 1e9;
+
+// A box that clips its content and can be scrolled, if only by a script
+// when it is `hidden`. Either both axes are `visible` or `clip`, or none.
+pub fn is_scroll_container(computed_style: &ComputedStyle) -> bool {
+    computed_style.display != "inline"
+        && computed_style.overflow_x != "visible"
+        && computed_style.overflow_x != "clip"
+}
+
+fn is_user_scrollable(overflow: &str) -> bool {
+    overflow == "scroll" || overflow == "auto"
+}
+
+pub fn intersect_rects(a: &Rect, b: &Rect) -> Rect {
+    let x = a.x.max(b.x);
+    let y = a.y.max(b.y);
+    Rect {
+        x,
+        y,
+        width: ((a.x + a.width).min(b.x + b.width) - x).max(0.0),
+        height: ((a.y + a.height).min(b.y + b.height) - y).max(0.0),
+    }
+}
+
+// Where the content of `element` is painted: its padding box, on the axes
+// it doesn't let its content overflow. `None` when it clips nothing.
+pub fn get_clip_rect(element: &DomElement) -> Option<Rect> {
+    if element.node_type != NodeType::Element || element.computed_flow.is_none() {
+        return None;
+    }
+    let computed_style = element.computed_style.as_ref()?;
+    if computed_style.display == "inline" {
+        return None;
+    }
+    let clips_x = computed_style.overflow_x != "visible";
+    let clips_y = computed_style.overflow_y != "visible";
+    if !clips_x && !clips_y {
+        return None;
+    }
+    let mut rect = get_inner_rect(element, true);
+    if !clips_x {
+        rect.x = -UNCLIPPED;
+        rect.width = 2.0 * UNCLIPPED;
+    }
+    if !clips_y {
+        rect.y = -UNCLIPPED;
+        rect.height = 2.0 * UNCLIPPED;
+    }
+    return Some(rect);
+}
+
+// The right and bottom edges of what the elements in `tree` paint, without
+// what clipping boxes cut off. Absolute boxes only count when `positioned`,
+// when their containing block is inside the scroll container.
+fn get_content_extent(tree: &Vec<DomElement>, positioned: bool, extent: &mut (f64, f64)) {
+    for element in tree {
+        let computed_flow = match element.computed_flow.as_ref() {
+            Some(f) => f,
+            None => continue,
+        };
+        let position = match element.computed_style.as_ref() {
+            Some(c) if element.node_type == NodeType::Element => c.position.as_str(),
+            _ => "static",
+        };
+        if position == "fixed" || (position == "absolute" && !positioned) {
+            continue;
+        }
+        let mut include = |rect: &Rect| {
+            if rect.width > 0.0 || rect.height > 0.0 {
+                extent.0 = extent.0.max(rect.x + rect.width);
+                extent.1 = extent.1.max(rect.y + rect.height);
+            }
+        };
+        include(&Rect {
+            x: computed_flow.x,
+            y: computed_flow.y,
+            width: computed_flow.width,
+            height: computed_flow.height,
+        });
+        for line in &computed_flow.text_lines {
+            include(&Rect {
+                x: line.x,
+                y: line.y,
+                width: line.width,
+                height: line.height,
+            });
+        }
+        if get_clip_rect(element).is_none() && has_laid_out_children(element) {
+            get_content_extent(&element.children, positioned || position != "static", extent);
+        }
+    }
+}
+
+// The size of the scrollable overflow of a scroll container from the top
+// left of its padding box, CSS Overflow 3 §2.2: its padding box and what
+// its descendants paint, with its end padding after them.
+pub fn get_scrollable_size(element: &DomElement) -> (f64, f64) {
+    let padding_box = get_inner_rect(element, true);
+    let computed_style = element.computed_style.as_ref().unwrap();
+    let padding = &computed_style.padding;
+    let mut extent = (f64::NEG_INFINITY, f64::NEG_INFINITY);
+    get_content_extent(&element.children, computed_style.position != "static", &mut extent);
+    let right = (padding_box.x + padding_box.width).max(extent.0 + padding.right);
+    let bottom = (padding_box.y + padding_box.height).max(extent.1 + padding.bottom);
+    return (right - padding_box.x, bottom - padding_box.y);
+}
+
+// How far the content of `element` can be scrolled on each axis.
+fn get_max_scroll(element: &DomElement) -> (f64, f64) {
+    let computed_style = match element.computed_style.as_ref() {
+        Some(c) if element.node_type == NodeType::Element && element.computed_flow.is_some() => c,
+        _ => return (0.0, 0.0),
+    };
+    if !is_scroll_container(computed_style) {
+        return (0.0, 0.0);
+    }
+    let padding_box = get_inner_rect(element, true);
+    let (width, height) = get_scrollable_size(element);
+    return ((width - padding_box.width).max(0.0), (height - padding_box.height).max(0.0));
+}
+
+// The scroll offset of `element`, within what its content allows now, as
+// the content may have shrunk since it was scrolled.
+pub fn get_scroll_offset(element: &DomElement) -> (f64, f64) {
+    if element.scroll_x == 0.0 && element.scroll_y == 0.0 {
+        return (0.0, 0.0);
+    }
+    let (max_x, max_y) = get_max_scroll(element);
+    return (element.scroll_x.min(max_x).max(0.0), element.scroll_y.min(max_y).max(0.0));
+}
+
+// Scrolls the innermost box around the element at `path`, the one under the
+// mouse, that the user can scroll by `dx` and `dy` and still has room to
+// move that way. Returns false when there is none, and the page scrolls
+// instead.
+pub fn scroll_element_at(tree: &mut Vec<DomElement>, path: &[usize], dx: f64, dy: f64) -> bool {
+    for len in (1..path.len() + 1).rev() {
+        let element = match get_element_by_path_mut(tree, &path[..len]) {
+            Some(e) => e,
+            None => continue,
+        };
+        let (overflow_x, overflow_y) = match element.computed_style.as_ref() {
+            Some(c) if element.node_type == NodeType::Element && is_scroll_container(c) => {
+                (c.overflow_x.clone(), c.overflow_y.clone())
+            }
+            _ => continue,
+        };
+        let (max_x, max_y) = get_max_scroll(element);
+        let (scroll_x, scroll_y) = get_scroll_offset(element);
+        let new_x = if is_user_scrollable(&overflow_x) {
+            (scroll_x + dx).min(max_x).max(0.0)
+        } else {
+            scroll_x
+        };
+        let new_y = if is_user_scrollable(&overflow_y) {
+            (scroll_y + dy).min(max_y).max(0.0)
+        } else {
+            scroll_y
+        };
+        if new_x != scroll_x || new_y != scroll_y {
+            element.scroll_x = new_x;
+            element.scroll_y = new_y;
+            return true;
+        }
+    }
+    return false;
+}
+
+// The tracks of `overflow: scroll` and the thumbs of the axes that have
+// something to scroll, over the padding box of `element` moved by `offset`.
+pub fn get_scrollbar_items(element: &DomElement, offset: (f64, f64)) -> Vec<RenderItem> {
+    let computed_style = element.computed_style.as_ref().unwrap();
+    let mut padding_box = get_inner_rect(element, true);
+    padding_box.x += offset.0;
+    padding_box.y += offset.1;
+    let (max_x, max_y) = get_max_scroll(element);
+    let (scroll_x, scroll_y) = get_scroll_offset(element);
+    let track_color = Color::from_srgba(0.5, 0.5, 0.5, 0.15);
+    let thumb_color = Color::from_srgba(0.5, 0.5, 0.5, 0.7);
+
+    let mut items: Vec<RenderItem> = vec![];
+    let mut push = |rect: Rect, color: Color| {
+        let mut item = RenderItem::new();
+        item.x = rect.x;
+        item.y = rect.y;
+        item.width = rect.width;
+        item.height = rect.height;
+        item.baseline = rect.y + rect.height;
+        item.background_color = color;
+        items.push(item);
+    };
+
+    // the vertical one goes along the right edge, the horizontal one along
+    // the bottom
+    let bars = [
+        (&computed_style.overflow_y, max_y, scroll_y, padding_box.height, false),
+        (&computed_style.overflow_x, max_x, scroll_x, padding_box.width, true),
+    ];
+    for (overflow, max, scroll, length, horizontal) in bars.iter() {
+        if *overflow != "scroll" && !(*overflow == "auto" && *max > 0.0) {
+            continue;
+        }
+        let track = if *horizontal {
+            Rect {
+                x: padding_box.x,
+                y: padding_box.y + padding_box.height - SCROLLBAR_WIDTH,
+                width: padding_box.width,
+                height: SCROLLBAR_WIDTH,
+            }
+        } else {
+            Rect {
+                x: padding_box.x + padding_box.width - SCROLLBAR_WIDTH,
+                y: padding_box.y,
+                width: SCROLLBAR_WIDTH,
+                height: padding_box.height,
+            }
+        };
+        if *overflow == "scroll" {
+            push(track.clone(), track_color);
+        }
+        if *max <= 0.0 {
+            continue;
+        }
+        // the thumb is to the track what the scrollport is to the content
+        let thumb_length = (length * length / (length + max)).max(SCROLLBAR_WIDTH.min(*length));
+        let thumb_start = (length - thumb_length) * scroll / max;
+        let thumb = if *horizontal {
+            Rect {
+                x: track.x + thumb_start,
+                width: thumb_length,
+                ..track
+            }
+        } else {
+            Rect {
+                y: track.y + thumb_start,
+                height: thumb_length,
+                ..track
+            }
+        };
+        push(thumb, thumb_color);
+    }
+    return items;
+}
@@ -0,0 +1,116 @@
+pub struct PropertyDefinition {
+    pub name: &'static str,
+    pub initial_value: &'static str,
+    pub inherited: bool,
+}
+
+const fn property(name: &'static str, initial_value: &'static str, inherited: bool) -> PropertyDefinition {
+    PropertyDefinition {
+        name,
+        initial_value,
+        inherited,
+    }
+}
+
+// Every longhand the engine understands, in the order `get_computed_style`
+// lists them.
+pub const PROPERTIES: &[PropertyDefinition] = &[
+    property("align-content", "normal", false),
+    property("align-items", "normal", false),
+    property("align-self", "auto", false),
+    property("background-color", "transparent", false),
+    property("border-bottom-color", "currentcolor", false),
+    property("border-bottom-left-radius", "0", false),
+    property("border-bottom-right-radius", "0", false),
+    property("border-bottom-style", "none", false),
+    property("border-bottom-width", "medium", false),
+    property("border-collapse", "separate", true),
+    property("border-left-color", "currentcolor", false),
+    property("border-left-style", "none", false),
+    property("border-left-width", "medium", false),
+    property("border-right-color", "currentcolor", false),
+    property("border-right-style", "none", false),
+    property("border-right-width", "medium", false),
+    property("border-spacing", "0", true),
+    property("border-top-color", "currentcolor", false),
+    property("border-top-left-radius", "0", false),
+    property("border-top-right-radius", "0", false),
+    property("border-top-style", "none", false),
+    property("border-top-width", "medium", false),
+    property("bottom", "auto", false),
+    property("box-sizing", "content-box", false),
+    property("caption-side", "top", true),
+    property("clear", "none", false),
+    property("color", "canvastext", true),
+    property("color-scheme", "normal", true),
+    property("column-gap", "normal", false),
+    property("content", "normal", false),
+    property("counter-increment", "none", false),
+    property("counter-reset", "none", false),
+    property("counter-set", "none", false),
+    property("display", "inline", false),
+    property("flex-basis", "auto", false),
+    property("flex-direction", "row", false),
+    property("flex-grow", "0", false),
+    property("flex-shrink", "1", false),
+    property("flex-wrap", "nowrap", false),
+    property("float", "none", false),
+    property("font-family", "Times New Roman", true),
+    property("font-size", "medium", true),
+    property("font-style", "normal", true),
+    property("font-weight", "normal", true),
+    property("grid-auto-columns", "auto", false),
+    property("grid-auto-flow", "row", false),
+    property("grid-auto-rows", "auto", false),
+    property("grid-column-end", "auto", false),
+    property("grid-column-start", "auto", false),
+    property("grid-row-end", "auto", false),
+    property("grid-row-start", "auto", false),
+    property("grid-template-areas", "none", false),
+    property("grid-template-columns", "none", false),
+    property("grid-template-rows", "none", false),
+    property("height", "auto", false),
+    property("justify-content", "normal", false),
+    property("justify-items", "legacy", false),
+    property("justify-self", "auto", false),
+    property("left", "auto", false),
+    property("line-height", "normal", true),
+    property("list-style-position", "outside", true),
+    property("list-style-type", "disc", true),
+    property("margin-bottom", "0", false),
+    property("margin-left", "0", false),
+    property("margin-right", "0", false),
+    property("margin-top", "0", false),
+    property("max-height", "none", false),
+    property("max-width", "none", false),
+    property("min-height", "auto", false),
+    property("min-width", "auto", false),
+    property("opacity", "1", false),
+    property("order", "0", false),
+    property("overflow-x", "visible", false),
+    property("overflow-y", "visible", false),
+    property("padding-bottom", "0", false),
+    property("padding-left", "0", false),
+    property("padding-right", "0", false),
+    property("padding-top", "0", false),
+    property("position", "static", false),
+    property("quotes", "auto", true),
+    property("right", "auto", false),
+    property("row-gap", "normal", false),
+    property("table-layout", "auto", false),
+    property("text-align", "start", true),
+    property("text-decoration-line", "none", false),
+    property("top", "auto", false),
+    property("vertical-align", "baseline", false),
+    property("white-space", "normal", true),
+    property("width", "auto", false),
+    property("z-index", "auto", false),
+];
+
+pub fn get_property_definition(name: &str) -> Option<&'static PropertyDefinition> {
+    PROPERTIES.iter().find(|p| p.name == name)
+}
+
+pub fn is_supported_property(name: &str) -> bool {
+    get_property_definition(name).is_some()
+}
@@ -0,0 +1,282 @@
+use crate::element_state::*;
+use crate::html::*;
+use crate::selectors::*;
+use crate::styles::*;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+const FILTER_SIZE: usize = 4096;
+
+fn hash_key(key: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn tag_key(tag: &str) -> String {
+    format!("<{}", tag)
+}
+
+fn id_key(id: &str) -> String {
+    format!("#{}", id)
+}
+
+fn class_key(class: &str) -> String {
+    format!(".{}", class)
+}
+
+// A counting Bloom filter of the tag names, ids and classes of the ancestors
+// of the element being styled. A descendant selector needing something that
+// is not in the filter can't match.
+pub struct AncestorFilter {
+    counts: Vec<u8>,
+}
+
+impl AncestorFilter {
+    pub fn new() -> AncestorFilter {
+        AncestorFilter {
+            counts: vec![0; FILTER_SIZE],
+        }
+    }
+
+    fn positions(hash: u64) -> (usize, usize) {
+        (
+            (hash % FILTER_SIZE as u64) as usize,
+            ((hash >> 32) % FILTER_SIZE as u64) as usize,
+        )
+    }
+
+    fn keys(element: &AncestorElement) -> Vec<u64> {
+        let mut keys = vec![hash_key(&tag_key(&element.tag_name))];
+        match element.attributes.get("id") {
+            Some(id) => keys.push(hash_key(&id_key(id))),
+            None => {}
+        }
+        match element.attributes.get("class") {
+            Some(classes) => {
+                for class in classes.split_whitespace() {
+                    keys.push(hash_key(&class_key(class)));
+                }
+            }
+            None => {}
+        }
+        keys
+    }
+
+    pub fn push(&mut self, element: &AncestorElement) {
+        for key in AncestorFilter::keys(element) {
+            let (a, b) = AncestorFilter::positions(key);
+            self.counts[a] = self.counts[a].saturating_add(1);
+            self.counts[b] = self.counts[b].saturating_add(1);
+        }
+    }
+
+    pub fn pop(&mut self, element: &AncestorElement) {
+        for key in AncestorFilter::keys(element) {
+            let (a, b) = AncestorFilter::positions(key);
+            // a saturated count stays, the filter only gets less precise
+            if self.counts[a] != u8::MAX {
+                self.counts[a] -= 1;
+            }
+            if self.counts[b] != u8::MAX {
+                self.counts[b] -= 1;
+            }
+        }
+    }
+
+    pub fn might_contain(&self, hash: u64) -> bool {
+        let (a, b) = AncestorFilter::positions(hash);
+        self.counts[a] > 0 && self.counts[b] > 0
+    }
+}
+
+pub struct IndexedSelector {
+    // index of the rule in the style sheet order, which is the cascade order
+    pub rule: usize,
+    pub selector: ComplexSelector,
+    // what the ancestor compounds need to find in the ancestor filter
+    pub ancestor_hashes: Vec<u64>,
+}
+
+// The rules of a style sheet bucketed by the rightmost id, class or tag of
+// their selectors, so an element is only tested against the selectors that
+// can match it.
+pub struct RuleIndex {
+    by_id: HashMap<String, Vec<IndexedSelector>>,
+    by_class: HashMap<String, Vec<IndexedSelector>>,
+    by_tag: HashMap<String, Vec<IndexedSelector>>,
+    universal: Vec<IndexedSelector>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MatchedRule {
+    pub rule: usize,
+    pub pseudo_element: Option<String>,
+}
+
+pub struct MatchResult {
+    // in cascade order
+    pub matched_rules: Vec<MatchedRule>,
+    pub state_dependencies: ElementStates,
+    // states of ancestors, by depth, that selectors matching this element
+    // depend on
+    pub ancestor_dependencies: Vec<(usize, ElementStates)>,
+}
+
+impl RuleIndex {
+    pub fn new(rules: &Vec<StyleRule>) -> RuleIndex {
+        let mut index = RuleIndex {
+            by_id: HashMap::new(),
+            by_class: HashMap::new(),
+            by_tag: HashMap::new(),
+            universal: vec![],
+        };
+
+        for (i, rule) in rules.iter().enumerate() {
+            for selector in parse_selector_list(&rule.selector) {
+                let selector = match selector {
+                    Some(s) => s,
+                    None => continue,
+                };
+
+                let mut ancestor_hashes: Vec<u64> = vec![];
+                for compound in &selector.compounds[..selector.compounds.len() - 1] {
+                    match &compound.tag {
+                        Some(tag) => ancestor_hashes.push(hash_key(&tag_key(tag))),
+                        None => {}
+                    }
+                    match &compound.id {
+                        Some(id) => ancestor_hashes.push(hash_key(&id_key(id))),
+                        None => {}
+                    }
+                    for class in &compound.classes {
+                        ancestor_hashes.push(hash_key(&class_key(class)));
+                    }
+                }
+
+                let subject = selector.subject().clone();
+                let indexed = IndexedSelector {
+                    rule: i,
+                    selector,
+                    ancestor_hashes,
+                };
+
+                if let Some(id) = &subject.id {
+                    index.by_id.entry(id.clone()).or_insert(vec![]).push(indexed);
+                } else if let Some(class) = subject.classes.first() {
+                    index.by_class.entry(class.clone()).or_insert(vec![]).push(indexed);
+                } else if let Some(tag) = &subject.tag {
+                    index.by_tag.entry(tag.clone()).or_insert(vec![]).push(indexed);
+                } else {
+                    index.universal.push(indexed);
+                }
+            }
+        }
+
+        return index;
+    }
+
+    fn candidates<'a>(&'a self, element: &DomElement) -> Vec<&'a IndexedSelector> {
+        let mut candidates: Vec<&IndexedSelector> = vec![];
+
+        match element.attributes.get("id").and_then(|id| self.by_id.get(id)) {
+            Some(selectors) => candidates.extend(selectors),
+            None => {}
+        }
+        match element.attributes.get("class") {
+            Some(classes) => {
+                for class in classes.split_whitespace() {
+                    match self.by_class.get(class) {
+                        Some(selectors) => candidates.extend(selectors),
+                        None => {}
+                    }
+                }
+            }
+            None => {}
+        }
+        match self.by_tag.get(&element.tag_name) {
+            Some(selectors) => candidates.extend(selectors),
+            None => {}
+        }
+        candidates.extend(&self.universal);
+
+        return candidates;
+    }
+
+    // `filter` holds the ancestors when given.
+    pub fn match_element(
+        &self,
+        element: &DomElement,
+        ancestors: &[AncestorElement],
+        filter: Option<&AncestorFilter>,
+    ) -> MatchResult {
+        let mut result = MatchResult {
+            matched_rules: vec![],
+            state_dependencies: ElementStates::new(),
+            ancestor_dependencies: vec![],
+        };
+
+        if !element.is_element() {
+            return result;
+        }
+
+        for candidate in self.candidates(element) {
+            let rejected = match filter {
+                Some(filter) => candidate.ancestor_hashes.iter().any(|h| !filter.might_contain(*h)),
+                None => false,
+            };
+            if rejected {
+                continue;
+            }
+
+            let selector = &candidate.selector;
+            if complex_selector_matches(element, ancestors, selector, false) {
+                result.matched_rules.push(MatchedRule {
+                    rule: candidate.rule,
+                    pseudo_element: selector.subject().pseudo_element.clone(),
+                });
+            }
+
+            if selector.has_state_pseudo_classes() && complex_selector_matches(element, ancestors, selector, true) {
+                add_state_dependencies(&mut result, ancestors, selector);
+            }
+        }
+
+        result.matched_rules.sort_by_key(|m| m.rule);
+        result.matched_rules.dedup();
+
+        return result;
+    }
+}
+
+fn get_state_flags(compound: &CompoundSelector) -> ElementStates {
+    let mut states = ElementStates::new();
+    for pseudo_class in &compound.pseudo_classes {
+        match state_flag_of(pseudo_class) {
+            Some(flag) => states.set(flag, true),
+            None => {}
+        }
+    }
+    states
+}
+
+// The subject's states are the element's own, the states in the other
+// compounds belong to every ancestor that could match that compound.
+fn add_state_dependencies(result: &mut MatchResult, ancestors: &[AncestorElement], selector: &ComplexSelector) {
+    result.state_dependencies = result
+        .state_dependencies
+        .union(&get_state_flags(selector.subject()));
+
+    for compound in &selector.compounds[..selector.compounds.len() - 1] {
+        let states = get_state_flags(compound);
+        if states == ElementStates::new() {
+            continue;
+        }
+        for (depth, ancestor) in ancestors.iter().enumerate() {
+            if compound_selector_matches(ancestor, compound, true) {
+                result.ancestor_dependencies.push((depth, states));
+            }
+        }
+    }
+}
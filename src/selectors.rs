@@ -0,0 +1,463 @@
+use crate::css::*;
+use crate::element_state::*;
+use crate::generated_content::*;
+use crate::html::*;
+use std::collections::HashMap;
+
+// What selector matching reads from an element. Styling borrows the ancestors
+// of the element being styled mutably, so they are matched as snapshots.
+pub trait SelectorElement {
+    fn tag_name(&self) -> &str;
+    fn get_attribute(&self, name: &str) -> Option<&String>;
+    fn state(&self) -> &ElementStates;
+    // false for text, comments and pseudo-elements
+    fn is_element(&self) -> bool;
+}
+
+impl SelectorElement for DomElement {
+    fn tag_name(&self) -> &str {
+        &self.tag_name
+    }
+
+    fn get_attribute(&self, name: &str) -> Option<&String> {
+        self.attributes.get(name)
+    }
+
+    fn state(&self) -> &ElementStates {
+        &self.state
+    }
+
+    fn is_element(&self) -> bool {
+        self.node_type == NodeType::Element && self.pseudo_element.is_none()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AncestorElement {
+    pub tag_name: String,
+    pub attributes: HashMap<String, String>,
+    pub state: ElementStates,
+}
+
+impl AncestorElement {
+    pub fn new(element: &DomElement) -> AncestorElement {
+        AncestorElement {
+            tag_name: element.tag_name.clone(),
+            attributes: element.attributes.clone(),
+            state: element.state,
+        }
+    }
+}
+
+impl SelectorElement for AncestorElement {
+    fn tag_name(&self) -> &str {
+        &self.tag_name
+    }
+
+    fn get_attribute(&self, name: &str) -> Option<&String> {
+        self.attributes.get(name)
+    }
+
+    fn state(&self) -> &ElementStates {
+        &self.state
+    }
+
+    fn is_element(&self) -> bool {
+        true
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct AttributeSelector {
+    pub name: String,
+    // `=`, `~=`, `|=`, `^=`, `$=` or `*=`, empty for `[name]`
+    pub operator: String,
+    pub value: String,
+}
+
+// A compound selector like `a.external[href]:hover::before`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CompoundSelector {
+    // uppercased, None for `*`
+    pub tag: Option<String>,
+    pub id: Option<String>,
+    pub classes: Vec<String>,
+    pub attributes: Vec<AttributeSelector>,
+    pub pseudo_classes: Vec<String>,
+    pub pseudo_element: Option<String>,
+}
+
+const PSEUDO_CLASSES: &[&str] = &[
+    "hover",
+    "active",
+    "focus",
+    "focus-visible",
+    "focus-within",
+    "visited",
+    "link",
+    "any-link",
+    "checked",
+    "disabled",
+    "enabled",
+    "root",
+];
+
+pub fn state_flag_of(pseudo_class: &str) -> Option<ElementStateFlag> {
+    match pseudo_class {
+        "hover" => Some(ElementStateFlag::Hover),
+        "active" => Some(ElementStateFlag::Active),
+        "focus" => Some(ElementStateFlag::Focus),
+        "focus-visible" => Some(ElementStateFlag::FocusVisible),
+        "focus-within" => Some(ElementStateFlag::FocusWithin),
+        "visited" | "link" => Some(ElementStateFlag::Visited),
+        _ => None,
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '-' || c == '_' || c == '\\' || !c.is_ascii()
+}
+
+fn read_name(chars: &[char], i: &mut usize) -> String {
+    let start = *i;
+    while *i < chars.len() && is_name_char(chars[*i]) {
+        *i += 1;
+    }
+    chars[start..*i].iter().collect()
+}
+
+fn parse_attribute_selector(inner: &str) -> Option<AttributeSelector> {
+    for operator in &["~=", "|=", "^=", "$=", "*=", "="] {
+        if let Some(i) = inner.find(operator) {
+            let name = inner[..i].trim().to_lowercase();
+            let value = inner[i + operator.len()..].trim();
+            // `[type="a" i]` flags are ignored
+            let value = match value.rfind(|c| c == '"' || c == '\'') {
+                Some(end) if value.len() > 1 && is_css_string(&value[..end + 1]) => {
+                    parse_css_string(&value[..end + 1])
+                }
+                _ => value.split_whitespace().next().unwrap_or("").to_string(),
+            };
+            if name == "" {
+                return None;
+            }
+            return Some(AttributeSelector {
+                name,
+                operator: operator.to_string(),
+                value,
+            });
+        }
+    }
+
+    let name = inner.trim().to_lowercase();
+    if name == "" || !name.chars().all(is_name_char) {
+        return None;
+    }
+    Some(AttributeSelector {
+        name,
+        operator: "".to_string(),
+        value: "".to_string(),
+    })
+}
+
+// Returns None for syntax this engine doesn't understand, those selectors
+// never match.
+pub fn parse_compound_selector(selector: &str) -> Option<CompoundSelector> {
+    let selector = selector.trim();
+    if selector == "" {
+        return None;
+    }
+
+    let chars: Vec<char> = selector.chars().collect();
+    let mut compound = CompoundSelector {
+        tag: None,
+        id: None,
+        classes: vec![],
+        attributes: vec![],
+        pseudo_classes: vec![],
+        pseudo_element: None,
+    };
+
+    let mut i = 0;
+    if chars[0] == '*' {
+        i = 1;
+    } else if is_name_char(chars[0]) {
+        compound.tag = Some(read_name(&chars, &mut i).to_uppercase());
+    }
+
+    while i < chars.len() {
+        // nothing may follow a pseudo-element but user action pseudo-classes
+        if compound.pseudo_element.is_some() && chars[i] != ':' {
+            return None;
+        }
+
+        match chars[i] {
+            '#' => {
+                i += 1;
+                let id = read_name(&chars, &mut i);
+                if id == "" {
+                    return None;
+                }
+                compound.id = Some(id);
+            }
+            '.' => {
+                i += 1;
+                let class = read_name(&chars, &mut i);
+                if class == "" {
+                    return None;
+                }
+                compound.classes.push(class);
+            }
+            '[' => {
+                let end = chars[i..].iter().position(|c| *c == ']')? + i;
+                let inner: String = chars[i + 1..end].iter().collect();
+                compound.attributes.push(parse_attribute_selector(&inner)?);
+                i = end + 1;
+            }
+            ':' => {
+                let is_pseudo_element = i + 1 < chars.len() && chars[i + 1] == ':';
+                i += if is_pseudo_element { 2 } else { 1 };
+                let name = read_name(&chars, &mut i).to_lowercase();
+
+                let is_legacy_pseudo_element = split_pseudo_element(&format!(":{}", name)).1.is_some();
+                if is_pseudo_element || is_legacy_pseudo_element {
+                    if !PSEUDO_ELEMENTS.contains(&name.as_str()) || compound.pseudo_element.is_some() {
+                        return None;
+                    }
+                    compound.pseudo_element = Some(name);
+                } else if PSEUDO_CLASSES.contains(&name.as_str()) {
+                    compound.pseudo_classes.push(name);
+                } else {
+                    return None;
+                }
+            }
+            _ => return None,
+        }
+    }
+
+    Some(compound)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Combinator {
+    Descendant,
+    Child,
+}
+
+// A selector like `nav > ul a:hover`. The sibling combinators are not
+// supported.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ComplexSelector {
+    // left to right, the last one is the subject of the selector
+    pub compounds: Vec<CompoundSelector>,
+    // `combinators[i]` is between `compounds[i]` and `compounds[i + 1]`
+    pub combinators: Vec<Combinator>,
+}
+
+impl ComplexSelector {
+    pub fn subject(&self) -> &CompoundSelector {
+        self.compounds.last().unwrap()
+    }
+
+    pub fn has_state_pseudo_classes(&self) -> bool {
+        self.compounds
+            .iter()
+            .any(|c| c.pseudo_classes.iter().any(|p| state_flag_of(p).is_some()))
+    }
+}
+
+pub fn parse_complex_selector(selector: &str) -> Option<ComplexSelector> {
+    let mut compounds: Vec<CompoundSelector> = vec![];
+    let mut combinators: Vec<Combinator> = vec![];
+
+    let mut current = "".to_string();
+    let mut combinator: Option<Combinator> = None;
+    let mut depth = 0;
+    let mut quote: Option<char> = None;
+
+    for c in selector.trim().chars().chain(std::iter::once(' ')) {
+        if let Some(q) = quote {
+            if c == q {
+                quote = None;
+            }
+            current.push(c);
+            continue;
+        }
+
+        let is_boundary = depth == 0 && (c.is_whitespace() || c == '>' || c == '+' || c == '~');
+        if !is_boundary {
+            if c == '"' || c == '\'' {
+                quote = Some(c);
+            } else if c == '[' || c == '(' {
+                depth += 1;
+            } else if c == ']' || c == ')' {
+                depth -= 1;
+            }
+            current.push(c);
+            continue;
+        }
+
+        if current != "" {
+            if compounds.len() > 0 {
+                combinators.push(combinator.unwrap_or(Combinator::Descendant));
+            }
+            let compound = parse_compound_selector(&current)?;
+            // only the subject may have a pseudo-element
+            if compounds.last().map(|c: &CompoundSelector| c.pseudo_element.is_some()).unwrap_or(false) {
+                return None;
+            }
+            compounds.push(compound);
+            current = "".to_string();
+            combinator = None;
+        }
+
+        match c {
+            '>' if compounds.len() > 0 && combinator.is_none() => combinator = Some(Combinator::Child),
+            '>' | '+' | '~' => return None,
+            _ => {}
+        }
+    }
+
+    if compounds.len() == 0 || combinator.is_some() {
+        return None;
+    }
+
+    Some(ComplexSelector { compounds, combinators })
+}
+
+fn attribute_matches<E: SelectorElement>(element: &E, attribute: &AttributeSelector) -> bool {
+    let value = match element.get_attribute(&attribute.name) {
+        Some(v) => v,
+        None => return false,
+    };
+    let expected = &attribute.value;
+
+    match attribute.operator.as_str() {
+        "" => true,
+        "=" => value == expected,
+        "~=" => value.split_whitespace().any(|v| v == expected),
+        "|=" => value == expected || value.starts_with(&format!("{}-", expected)),
+        "^=" => expected != "" && value.starts_with(expected.as_str()),
+        "$=" => expected != "" && value.ends_with(expected.as_str()),
+        "*=" => expected != "" && value.contains(expected.as_str()),
+        _ => false,
+    }
+}
+
+fn is_link_element<E: SelectorElement>(element: &E) -> bool {
+    (element.tag_name() == "A" || element.tag_name() == "AREA") && element.get_attribute("href").is_some()
+}
+
+fn is_form_control_element<E: SelectorElement>(element: &E) -> bool {
+    match element.tag_name() {
+        "INPUT" | "BUTTON" | "SELECT" | "TEXTAREA" | "OPTION" | "OPTGROUP" | "FIELDSET" => true,
+        _ => false,
+    }
+}
+
+fn pseudo_class_matches<E: SelectorElement>(element: &E, pseudo_class: &str) -> bool {
+    let state = element.state();
+    match pseudo_class {
+        "hover" => state.hover,
+        "active" => state.active,
+        "focus" => state.focus,
+        "focus-visible" => state.focus_visible,
+        "focus-within" => state.focus_within,
+        "visited" => is_link_element(element) && state.visited,
+        "link" => is_link_element(element) && !state.visited,
+        "any-link" => is_link_element(element),
+        "checked" => {
+            (element.tag_name() == "INPUT" && element.get_attribute("checked").is_some())
+                || (element.tag_name() == "OPTION" && element.get_attribute("selected").is_some())
+        }
+        "disabled" => is_form_control_element(element) && element.get_attribute("disabled").is_some(),
+        "enabled" => is_form_control_element(element) && element.get_attribute("disabled").is_none(),
+        "root" => element.tag_name() == "HTML",
+        _ => false,
+    }
+}
+
+// With `ignore_states` the user action pseudo-classes always match, which
+// tells whether the element could match in some state.
+pub fn compound_selector_matches<E: SelectorElement>(
+    element: &E,
+    compound: &CompoundSelector,
+    ignore_states: bool,
+) -> bool {
+    if !element.is_element() {
+        return false;
+    }
+
+    match &compound.tag {
+        Some(tag) if tag != element.tag_name() => return false,
+        _ => {}
+    }
+
+    match &compound.id {
+        Some(id) if element.get_attribute("id") != Some(id) => return false,
+        _ => {}
+    }
+
+    if compound.classes.len() > 0 {
+        let classes = match element.get_attribute("class") {
+            Some(c) => c.split_whitespace().collect::<Vec<&str>>(),
+            None => return false,
+        };
+        if !compound.classes.iter().all(|c| classes.contains(&c.as_str())) {
+            return false;
+        }
+    }
+
+    if !compound.attributes.iter().all(|a| attribute_matches(element, a)) {
+        return false;
+    }
+
+    compound.pseudo_classes.iter().all(|p| {
+        (ignore_states && state_flag_of(p).is_some()) || pseudo_class_matches(element, p)
+    })
+}
+
+// Matches `compounds[..index]` against the ancestors, `ancestors` is ordered
+// from the root to the parent.
+fn ancestors_match(
+    ancestors: &[AncestorElement],
+    selector: &ComplexSelector,
+    index: usize,
+    ignore_states: bool,
+) -> bool {
+    if index == 0 {
+        return true;
+    }
+
+    let compound = &selector.compounds[index - 1];
+    match selector.combinators[index - 1] {
+        Combinator::Child => match ancestors.split_last() {
+            Some((parent, rest)) => {
+                compound_selector_matches(parent, compound, ignore_states)
+                    && ancestors_match(rest, selector, index - 1, ignore_states)
+            }
+            None => false,
+        },
+        Combinator::Descendant => (0..ancestors.len()).rev().any(|i| {
+            compound_selector_matches(&ancestors[i], compound, ignore_states)
+                && ancestors_match(&ancestors[..i], selector, index - 1, ignore_states)
+        }),
+    }
+}
+
+pub fn complex_selector_matches<E: SelectorElement>(
+    element: &E,
+    ancestors: &[AncestorElement],
+    selector: &ComplexSelector,
+    ignore_states: bool,
+) -> bool {
+    compound_selector_matches(element, selector.subject(), ignore_states)
+        && ancestors_match(ancestors, selector, selector.compounds.len() - 1, ignore_states)
+}
+
+pub fn parse_selector_list(selector: &str) -> Vec<Option<ComplexSelector>> {
+    split_css_list(selector)
+        .iter()
+        .map(|s| parse_complex_selector(s))
+        .collect()
+}
@@ -1,13 +1,30 @@
 use crate::css::*;
 use crate::html::*;
+use crate::length::*;
+use crate::loader::*;
+use crate::media::*;
+use crate::lisia_colors::is_color;
 use crate::utils::*;
 use std::collections::HashMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+const USER_AGENT_STYLE_SHEET: &str = include_str!("../default_styles.css");
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CascadeOrigin {
+  UserAgent,
+  User,
+  Author,
+}
 
 #[derive(Clone, Debug)]
 pub struct StyleRule {
   pub css: String,
   pub selector: String,
   pub declarations: Vec<KeyValue>,
+  pub origin: CascadeOrigin,
 }
 
 impl StyleRule {
@@ -16,31 +33,188 @@ impl StyleRule {
       css: "".to_string(),
       selector: "".to_string(),
       declarations: vec![],
+      origin: CascadeOrigin::Author,
+    }
+  }
+}
+
+pub fn with_origin(rules: Vec<StyleRule>, origin: CascadeOrigin) -> Vec<StyleRule> {
+  rules
+    .into_iter()
+    .map(|mut r| {
+      r.origin = origin;
+      r
+    })
+    .collect()
+}
+
+// The user agent style sheet, parsed the first time it's needed.
+pub fn get_user_agent_rules() -> &'static Vec<StyleRule> {
+  static RULES: OnceLock<Vec<StyleRule>> = OnceLock::new();
+  RULES.get_or_init(|| with_origin(parse_css(USER_AGENT_STYLE_SHEET), CascadeOrigin::UserAgent))
+}
+
+#[derive(Clone, Debug)]
+pub struct StyleSheet {
+  pub href: Option<String>,
+  pub media: String,
+  pub disabled: bool,
+  pub rules: Vec<StyleRule>,
+}
+
+impl StyleSheet {
+  pub fn new(rules: Vec<StyleRule>) -> StyleSheet {
+    StyleSheet {
+      href: None,
+      media: "".to_string(),
+      disabled: false,
+      rules,
+    }
+  }
+}
+
+fn get_text_content(element: &DomElement) -> String {
+  let mut text = "".to_string();
+  for child in &element.children {
+    match child.node_type {
+      NodeType::Text => text += &child.node_value,
+      _ => {}
     }
   }
+  return text;
 }
 
-pub fn get_styles(tree: Vec<DomElement>, parent: Option<DomElement>) -> String {
-  let mut style: String = "".to_string();
+fn is_stylesheet_link(element: &DomElement) -> bool {
+  element.tag_name == "LINK"
+    && match element.attributes.get("rel") {
+      Some(rel) => rel
+        .split_whitespace()
+        .any(|r| r.eq_ignore_ascii_case("stylesheet")),
+      None => false,
+    }
+}
+
+// Collects the style sheets of `<style>` and `<link rel="stylesheet">`
+// elements in document order. Linked style sheets are resolved against
+// `base_url` and loaded through `loader`.
+pub fn get_style_sheets(
+  tree: &Vec<DomElement>,
+  base_url: &str,
+  loader: &dyn ResourceLoader,
+) -> Vec<StyleSheet> {
+  let mut sheets: Vec<StyleSheet> = vec![];
 
   for element in tree {
-    if element.children.len() > 0 && element.tag_name != "SCRIPT" {
-      style += &get_styles(element.children.clone(), Some(element.clone()));
+    if element.tag_name == "STYLE" {
+      let mut sheet = StyleSheet::new(parse_css(&get_text_content(element)));
+      sheet.media = get_declaration_value(&element.attributes, "media", "");
+      sheets.push(sheet);
+      continue;
     }
-    match element.node_type {
-      NodeType::Text => match &parent {
-        Some(p) => {
-          if p.tag_name == "STYLE" {
-            style += &element.node_value;
-          }
+
+    if is_stylesheet_link(element) {
+      let href = match element.attributes.get("href") {
+        Some(h) => h,
+        None => continue,
+      };
+      let url = resolve_url(base_url, href);
+
+      let rules = match loader.load(&url) {
+        Ok(css) => parse_css(&css),
+        Err(e) => {
+          println!("{}", e);
+          continue;
         }
-        None => {}
-      },
-      _ => {}
+      };
+
+      let rel = get_declaration_value(&element.attributes, "rel", "");
+      let is_alternate = rel.split_whitespace().any(|r| r.eq_ignore_ascii_case("alternate"));
+
+      sheets.push(StyleSheet {
+        href: Some(url),
+        media: get_declaration_value(&element.attributes, "media", ""),
+        disabled: element.attributes.contains_key("disabled") || is_alternate,
+        rules,
+      });
+      continue;
+    }
+
+    if element.children.len() > 0 && element.tag_name != "SCRIPT" {
+      sheets.extend(get_style_sheets(&element.children, base_url, loader));
+    }
+  }
+
+  return sheets;
+}
+
+// `$GRAVITON_CONFIG_DIR`, or `graviton` in the user's config directory.
+pub fn get_config_dir() -> Option<PathBuf> {
+  match env::var_os("GRAVITON_CONFIG_DIR") {
+    Some(dir) => return Some(PathBuf::from(dir)),
+    None => {}
+  }
+  let base = env::var_os("XDG_CONFIG_HOME")
+    .map(PathBuf::from)
+    .or_else(|| env::var_os("APPDATA").map(PathBuf::from))
+    .or_else(|| env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")))?;
+  Some(base.join("graviton"))
+}
+
+// The name per-site user styles are looked up by: the host of the url, or
+// `file` for local documents.
+pub fn get_site_name(url: &str) -> String {
+  match url.find("://") {
+    Some(i) if !url.starts_with("file://") => {
+      let authority = url[i + 3..]
+        .split(|c| c == '/' || c == '?' || c == '#')
+        .next()
+        .unwrap_or("");
+      let host = authority.rsplit('@').next().unwrap_or(authority);
+      host.split(':').next().unwrap_or(host).to_lowercase()
+    }
+    _ => "file".to_string(),
+  }
+}
+
+// `user.css` in the config directory applies to every page and
+// `sites/<site name>.css` to the pages of one site. Both are user origin.
+pub fn get_user_style_sheets(config_dir: &Path, url: &str, loader: &dyn ResourceLoader) -> Vec<StyleSheet> {
+  let paths = [
+    config_dir.join("user.css"),
+    config_dir.join("sites").join(get_site_name(url) + ".css"),
+  ];
+  let mut sheets: Vec<StyleSheet> = vec![];
+
+  for path in &paths {
+    if !path.is_file() {
+      continue;
+    }
+    let path = path.to_string_lossy().to_string();
+    match loader.load(&path) {
+      Ok(css) => {
+        let mut sheet = StyleSheet::new(with_origin(parse_css(&css), CascadeOrigin::User));
+        sheet.href = Some(path);
+        sheets.push(sheet);
+      }
+      Err(e) => println!("{}", e),
     }
   }
 
-  return style;
+  return sheets;
+}
+
+// Flattens the enabled style sheets whose media matches into one cascade.
+pub fn get_active_rules(sheets: &Vec<StyleSheet>, environment: &MediaEnvironment) -> Vec<StyleRule> {
+  let mut rules: Vec<StyleRule> = vec![];
+
+  for sheet in sheets {
+    if sheet.disabled || !evaluate_media_query(&sheet.media, environment) {
+      continue;
+    }
+    rules.extend(sheet.rules.iter().cloned());
+  }
+
+  return rules;
 }
 
 pub fn get_declaration_value(
@@ -78,3 +252,349 @@ pub fn get_inheritable_declaration_value(
     None => default,
   }
 }
+
+#[derive(Clone, Debug)]
+pub struct PropertyRegistration {
+  pub name: String,
+  pub syntax: String,
+  pub inherits: bool,
+  pub initial_value: Option<String>,
+}
+
+pub fn is_custom_property(key: &str) -> bool {
+  key.starts_with("--")
+}
+
+// Collects `@property --name { syntax; inherits; initial-value }` rules.
+pub fn get_property_registrations(style: &Vec<StyleRule>) -> HashMap<String, PropertyRegistration> {
+  let mut registrations = HashMap::new();
+
+  for rule in style {
+    if !rule.selector.starts_with("@property") {
+      continue;
+    }
+
+    let name = rule.selector["@property".len()..].trim().to_string();
+    if !is_custom_property(&name) {
+      continue;
+    }
+
+    let mut registration = PropertyRegistration {
+      name: name.clone(),
+      syntax: S("*"),
+      inherits: true,
+      initial_value: None,
+    };
+
+    for declaration in &rule.declarations {
+      match declaration.0.as_str() {
+        "syntax" => {
+          registration.syntax = declaration.1.trim_matches(|c| c == '"' || c == '\'').to_string()
+        }
+        "inherits" => registration.inherits = declaration.1 == "true",
+        "initial-value" => registration.initial_value = Some(declaration.1.clone()),
+        _ => {}
+      }
+    }
+
+    // a registration is only valid with an initial value, unless the syntax is universal
+    if registration.syntax != "*" && registration.initial_value.is_none() {
+      println!("@property {} is missing an initial-value", name);
+      continue;
+    }
+
+    registrations.insert(name, registration);
+  }
+
+  return registrations;
+}
+
+fn value_matches_syntax_component(value: &str, component: &str) -> bool {
+  let component = component.trim();
+  let (component, multiplier) = if component.ends_with('+') || component.ends_with('#') {
+    (&component[..component.len() - 1], component.chars().last())
+  } else {
+    (component, None)
+  };
+
+  let values = match multiplier {
+    Some('+') => split_css_values(value),
+    Some('#') => split_css_list(value),
+    _ => vec![value.trim().to_string()],
+  };
+
+  values.iter().all(|v| {
+    let v = v.as_str();
+    match component {
+      "*" => true,
+      "<length>" => parse_length(v).is_ok(),
+      "<percentage>" => match parse_length_percentage(v) {
+        Ok(LengthPercentage::Percentage(_)) => true,
+        _ => false,
+      },
+      "<length-percentage>" => parse_length_percentage(v).is_ok(),
+      "<number>" => parse_number(v).is_ok(),
+      "<integer>" => v.parse::<i64>().is_ok(),
+      "<color>" => is_color(v),
+      "<custom-ident>" => v.len() > 0 && !v.contains(char::is_whitespace),
+      "<url>" => v.starts_with("url("),
+      "<image>" => v.starts_with("url(") || v.contains("gradient("),
+      ident => ident == v,
+    }
+  })
+}
+
+pub fn value_matches_syntax(value: &str, syntax: &str) -> bool {
+  syntax
+    .split('|')
+    .any(|component| value_matches_syntax_component(value, component))
+}
+
+// Returns the position of the `)` closing the parenthesis opened right before `start`.
+fn find_closing_paren(value: &str, start: usize) -> Option<usize> {
+  let mut depth = 1;
+  for (i, c) in value[start..].char_indices() {
+    if c == '(' {
+      depth += 1;
+    } else if c == ')' {
+      depth -= 1;
+      if depth == 0 {
+        return Some(start + i);
+      }
+    }
+  }
+  return None;
+}
+
+// Replaces every `var(--name, fallback)` in `value`. Returns None when a
+// reference can't be resolved and has no fallback, which makes the whole
+// declaration invalid at computed-value time.
+pub fn substitute_var(value: &str, lookup: &mut dyn FnMut(&str) -> Option<String>) -> Option<String> {
+  let mut result = "".to_string();
+  let mut rest = value;
+
+  loop {
+    let start = match rest.find("var(") {
+      Some(s) => s,
+      None => break,
+    };
+    let end = find_closing_paren(rest, start + 4)?;
+
+    result += &rest[..start];
+
+    let arguments = &rest[start + 4..end];
+    let (name, fallback) = match arguments.find(',') {
+      Some(comma) => (arguments[..comma].trim(), Some(&arguments[comma + 1..])),
+      None => (arguments.trim(), None),
+    };
+
+    let substituted = match lookup(name) {
+      Some(v) => v,
+      None => match fallback {
+        Some(f) => substitute_var(f.trim(), lookup)?,
+        None => return None,
+      },
+    };
+    result += &substituted;
+
+    rest = &rest[end + 1..];
+  }
+
+  result += rest;
+  return Some(result.trim().to_string());
+}
+
+fn resolve_custom_property(
+  name: &str,
+  specified: &HashMap<String, String>,
+  computed: &HashMap<String, String>,
+  resolved: &mut HashMap<String, Option<String>>,
+  stack: &mut Vec<String>,
+  cyclic: &mut Vec<String>,
+) -> Option<String> {
+  if let Some(value) = resolved.get(name) {
+    return value.clone();
+  }
+
+  if let Some(position) = stack.iter().position(|n| n == name) {
+    // every property taking part in the cycle becomes guaranteed-invalid
+    for n in &stack[position..] {
+      if !cyclic.contains(n) {
+        cyclic.push(n.clone());
+      }
+    }
+    return None;
+  }
+
+  // not declared on the element, the inherited or initial value, which
+  // has no `var()` left
+  let value = match specified.get(name) {
+    Some(v) => v.clone(),
+    None => return computed.get(name).cloned(),
+  };
+
+  stack.push(name.to_string());
+  let substituted = substitute_var(&value, &mut |n: &str| {
+    resolve_custom_property(n, specified, computed, resolved, stack, cyclic)
+  });
+  stack.pop();
+
+  let value = if cyclic.iter().any(|n| n == name) {
+    None
+  } else {
+    substituted
+  };
+
+  resolved.insert(name.to_string(), value.clone());
+  return value;
+}
+
+// Computes the custom properties of an element from its own declarations and
+// the inherited ones. `var()` references between custom properties are
+// substituted here, so inherited values never contain `var()`.
+pub fn compute_custom_properties(
+  declarations: &HashMap<String, String>,
+  inherit_declarations: &HashMap<String, CssValue>,
+  registrations: &HashMap<String, PropertyRegistration>,
+) -> HashMap<String, String> {
+  let mut inherited: HashMap<String, String> = HashMap::new();
+
+  for (key, value) in inherit_declarations {
+    if !is_custom_property(key) {
+      continue;
+    }
+    match registrations.get(key) {
+      Some(r) if !r.inherits => {}
+      _ => {
+        inherited.insert(key.clone(), value.to_string().clone());
+      }
+    }
+  }
+
+  let initial_value = |key: &str| -> Option<String> {
+    registrations.get(key).and_then(|r| r.initial_value.clone())
+  };
+
+  let mut specified: HashMap<String, String> = HashMap::new();
+  for (key, value) in declarations {
+    if !is_custom_property(key) {
+      continue;
+    }
+    let value = value.replace("!important", "").trim().to_string();
+    let inherits = registrations.get(key).map(|r| r.inherits).unwrap_or(true);
+
+    let value = match value.as_str() {
+      "inherit" => inherited.get(key).cloned().or(initial_value(key)),
+      "initial" => initial_value(key),
+      "unset" => {
+        if inherits {
+          inherited.get(key).cloned()
+        } else {
+          initial_value(key)
+        }
+      }
+      _ => Some(value),
+    };
+
+    match value {
+      Some(v) => {
+        specified.insert(key.clone(), v);
+      }
+      None => {}
+    }
+  }
+
+  let mut custom_properties = inherited.clone();
+
+  for (key, registration) in registrations {
+    if !custom_properties.contains_key(key) {
+      match &registration.initial_value {
+        Some(v) => {
+          custom_properties.insert(key.clone(), v.clone());
+        }
+        None => {}
+      }
+    }
+  }
+
+  let computed = custom_properties.clone();
+  let mut resolved: HashMap<String, Option<String>> = HashMap::new();
+  let mut cyclic: Vec<String> = vec![];
+
+  for key in specified.keys() {
+    let mut stack: Vec<String> = vec![];
+    let value = resolve_custom_property(key, &specified, &computed, &mut resolved, &mut stack, &mut cyclic);
+
+    let value = match (value, registrations.get(key)) {
+      (Some(v), Some(r)) if !value_matches_syntax(&v, &r.syntax) => None,
+      (v, _) => v,
+    };
+
+    match value {
+      Some(v) => {
+        custom_properties.insert(key.clone(), v);
+      }
+      None => {
+        // invalid at computed-value time, the property behaves as `unset`
+        let inherits = registrations.get(key).map(|r| r.inherits).unwrap_or(true);
+        let fallback = if inherits {
+          inherited.get(key).cloned()
+        } else {
+          None
+        };
+        match fallback.or(initial_value(key)) {
+          Some(v) => {
+            custom_properties.insert(key.clone(), v);
+          }
+          None => {
+            custom_properties.remove(key);
+          }
+        }
+      }
+    }
+  }
+
+  return custom_properties;
+}
+
+// Substitutes `var()` in every regular declaration. Declarations that end up
+// invalid are removed so they behave as `unset`.
+pub fn substitute_var_references(
+  declarations: &mut HashMap<String, String>,
+  custom_properties: &HashMap<String, String>,
+) {
+  let keys = declarations
+    .iter()
+    .filter(|(k, v)| !is_custom_property(k) && (v.contains("var(") || is_pending_substitution(v)))
+    .map(|(k, _)| k.clone())
+    .collect::<Vec<String>>();
+
+  let mut lookup = |name: &str| custom_properties.get(name).cloned();
+
+  for key in keys {
+    let value = declarations.get(&key).unwrap().clone();
+
+    let substituted = match parse_pending_substitution(&value) {
+      // the longhand of a shorthand written with `var()`, expand the
+      // substituted shorthand and pick this longhand out of it
+      Some((shorthand, shorthand_value)) => {
+        substitute_var(&shorthand_value, &mut lookup).and_then(|v| {
+          let mut expanded: Vec<KeyValue> = vec![];
+          expand_shorthand(&mut expanded, &shorthand, &v);
+          expanded.into_iter().find(|d| d.0 == key).map(|d| d.1)
+        })
+      }
+      None => substitute_var(&value, &mut lookup),
+    };
+
+    match substituted {
+      Some(v) => {
+        declarations.insert(key, v);
+      }
+      None => {
+        println!("Invalid at computed-value time: {}: {}", key, value);
+        declarations.remove(&key);
+      }
+    }
+  }
+}
@@ -0,0 +1,1545 @@
+use crate::css::*;
+use crate::element_state::*;
+use crate::floats::*;
+use crate::html::*;
+use crate::inline_layout::*;
+use crate::layout::*;
+use crate::length::*;
+use crate::utils::*;
+use std::ops::Range;
+
+pub fn is_table(computed_style: &ComputedStyle) -> bool {
+    computed_style.display == "table" || computed_style.display == "inline-table"
+}
+
+pub fn is_collapsing_table(computed_style: &ComputedStyle) -> bool {
+    is_table(computed_style) && computed_style.values.get("border-collapse").map(|b| b.as_str()) == Some("collapse")
+}
+
+fn get_display(element: &DomElement) -> &str {
+    match element.computed_style.as_ref() {
+        Some(c) if element.node_type == NodeType::Element => c.display.as_str(),
+        _ => "",
+    }
+}
+
+// The boxes that only have a meaning in a table. Out of one they are wrapped
+// in an anonymous table, CSS 2.1 §17.2.1.
+pub fn is_internal_table_box(element: &DomElement) -> bool {
+    is_block_level(element)
+        && match get_display(element) {
+            "table-row-group" | "table-header-group" | "table-footer-group" | "table-row" | "table-cell"
+            | "table-column-group" | "table-column" | "table-caption" => true,
+            _ => false,
+        }
+}
+
+fn is_row_group(element: &DomElement) -> bool {
+    match get_display(element) {
+        "table-row-group" | "table-header-group" | "table-footer-group" => true,
+        _ => false,
+    }
+}
+
+// White space and comments between the parts of a table, that make no box.
+fn is_ignored(element: &DomElement) -> bool {
+    match element.node_type {
+        NodeType::Text => element.node_value.trim() == "",
+        NodeType::Element => !is_displayed(element),
+        _ => true,
+    }
+}
+
+// The end of the run of table parts from `start`, and the white space
+// between them, that goes in one anonymous table.
+pub fn get_table_run_end(tree: &[DomElement], start: usize, end: usize) -> usize {
+    let mut run_end = start;
+    let mut i = start;
+    while i < end && (is_internal_table_box(&tree[i]) || is_ignored(&tree[i])) {
+        i += 1;
+        if is_internal_table_box(&tree[i - 1]) {
+            run_end = i;
+        }
+    }
+    return run_end;
+}
+
+fn get_children<'a>(tree: &'a mut Vec<DomElement>, path: &[usize]) -> &'a mut Vec<DomElement> {
+    let mut children = tree;
+    for i in path {
+        children = &mut children[*i].children;
+    }
+    return children;
+}
+
+fn with_index(path: &Vec<usize>, i: usize) -> Vec<usize> {
+    let mut path = path.clone();
+    path.push(i);
+    return path;
+}
+
+pub struct TableLayout {
+    // the content width, wider than it was given when the columns need it
+    pub width: f64,
+    // the content height
+    pub height: f64,
+    pub baseline: Option<f64>,
+    // the margin boxes of the captions above and below the table box
+    pub captions_above: f64,
+    pub captions_below: f64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ColumnWidth {
+    Auto,
+    // a border box width
+    Fixed(f64),
+    // of the width the columns share
+    Percentage(f64),
+}
+
+// A cell in the grid: an element with `display: table-cell`, or the run of
+// other content an anonymous cell wraps. `parent` is the path from the
+// table to the element whose children `range` is in, empty for the table's
+// own.
+#[derive(Clone, Debug)]
+struct TableCell {
+    parent: Vec<usize>,
+    range: Range<usize>,
+    anonymous: bool,
+    row: usize,
+    column: usize,
+    row_span: usize,
+    column_span: usize,
+    width: ColumnWidth,
+    // the border box widths it can take
+    min_width: f64,
+    max_width: f64,
+    // where it was laid out, its border box height and the distance from
+    // its top to its baseline
+    y: f64,
+    height: f64,
+    baseline: f64,
+    align: String,
+}
+
+#[derive(Clone, Debug)]
+struct TableRow {
+    // the path of the row element, `None` for an anonymous row
+    path: Option<Vec<usize>>,
+    group: usize,
+    y: f64,
+    height: f64,
+    // the distance from the top to the baseline of its baseline aligned cells
+    baseline: Option<f64>,
+}
+
+#[derive(Clone, Debug)]
+struct TableRowGroup {
+    path: Option<Vec<usize>>,
+    rows: Range<usize>,
+}
+
+#[derive(Clone, Debug)]
+struct TableColumn {
+    // the column element and the column group it is in
+    element: Option<Vec<usize>>,
+    group: Option<Vec<usize>>,
+    width: ColumnWidth,
+    min_width: f64,
+    max_width: f64,
+    x: f64,
+    used_width: f64,
+}
+
+impl TableColumn {
+    fn new(element: Option<Vec<usize>>, group: Option<Vec<usize>>, width: ColumnWidth) -> TableColumn {
+        TableColumn {
+            element,
+            group,
+            width,
+            min_width: 0.0,
+            max_width: 0.0,
+            x: 0.0,
+            used_width: 0.0,
+        }
+    }
+}
+
+struct TableGrid {
+    // the indices of the captions
+    captions: Vec<usize>,
+    groups: Vec<TableRowGroup>,
+    rows: Vec<TableRow>,
+    columns: Vec<TableColumn>,
+    cells: Vec<TableCell>,
+}
+
+// The rows of a row group, or of the anonymous one around misplaced rows,
+// before the cells are put in the grid.
+struct RowSource {
+    path: Option<Vec<usize>>,
+    parent: Vec<usize>,
+    range: Range<usize>,
+}
+
+struct TableSettings {
+    collapse: bool,
+    // the horizontal and vertical space between the cells, and around them
+    spacing: (f64, f64),
+    fixed: bool,
+    // the borders and padding of the table box
+    edges: Margin,
+}
+
+impl TableSettings {
+    // An anonymous table has no box of its own, and takes the border model
+    // and spacing its parts inherit, from `computed_style` of the first.
+    fn new(computed_style: &ComputedStyle, anonymous: bool) -> TableSettings {
+        let values = &computed_style.values;
+        let collapse = values.get("border-collapse").map(|b| b.as_str()) == Some("collapse");
+        let spacing = if collapse {
+            (0.0, 0.0)
+        } else {
+            get_border_spacing(computed_style)
+        };
+        let fixed = !anonymous
+            && values.get("table-layout").map(|t| t.as_str()) == Some("fixed")
+            && computed_style.width.is_some();
+        let edges = if anonymous {
+            Margin::zero()
+        } else {
+            let border = computed_style.border.widths();
+            let padding = &computed_style.padding;
+            Margin {
+                top: border.top + padding.top,
+                right: border.right + padding.right,
+                bottom: border.bottom + padding.bottom,
+                left: border.left + padding.left,
+            }
+        };
+        TableSettings {
+            collapse,
+            spacing,
+            fixed,
+            edges,
+        }
+    }
+}
+
+fn get_border_spacing(computed_style: &ComputedStyle) -> (f64, f64) {
+    let value = computed_style.values.get("border-spacing").cloned().unwrap_or(S("0"));
+    let lengths: Vec<f64> = split_css_values(&value)
+        .iter()
+        .map(|v| match parse_length(v) {
+            Ok(l) => l.to_px(&computed_style.length_context).max(0.0),
+            Err(e) => {
+                println!("{}", e);
+                0.0
+            }
+        })
+        .collect();
+    match lengths.len() {
+        0 => (0.0, 0.0),
+        1 => (lengths[0], lengths[0]),
+        _ => (lengths[0], lengths[1]),
+    }
+}
+
+fn get_span(element: &DomElement, name: &str) -> Option<usize> {
+    element.attributes.get(name).and_then(|s| s.trim().parse::<usize>().ok())
+}
+
+// The width a cell or column asks for, of its border box.
+fn get_column_width(computed_style: &ComputedStyle, edges: f64) -> ColumnWidth {
+    match &computed_style.width {
+        Some(LengthPercentage::Percentage(p)) => ColumnWidth::Percentage(*p),
+        Some(w) if !w.has_percentage() => match resolve_size(computed_style, &computed_style.width, None, edges) {
+            Some(width) => ColumnWidth::Fixed(width + edges),
+            None => ColumnWidth::Auto,
+        },
+        _ => ColumnWidth::Auto,
+    }
+}
+
+fn get_cell_edges(computed_style: &ComputedStyle) -> f64 {
+    // percentages depend on the width being found, they count as zero
+    let padding = computed_style.padding_length.resolve(&computed_style.length_context, 0.0);
+    let border = computed_style.border.widths();
+    return padding.left + padding.right + border.left + border.right;
+}
+
+// Splits the children in `range` of `parent` into rows: row elements, and
+// runs of anything else that make anonymous rows.
+fn collect_rows(tree: &Vec<DomElement>, parent: &Vec<usize>, range: Range<usize>) -> Vec<RowSource> {
+    let mut rows: Vec<RowSource> = vec![];
+    let mut i = range.start;
+    while i < range.end {
+        if is_ignored(&tree[i]) {
+            i += 1;
+        } else if get_display(&tree[i]) == "table-row" {
+            let path = with_index(parent, i);
+            rows.push(RowSource {
+                path: Some(path.clone()),
+                parent: path,
+                range: 0..tree[i].children.len(),
+            });
+            i += 1;
+        } else {
+            let start = i;
+            while i < range.end && get_display(&tree[i]) != "table-row" {
+                i += 1;
+            }
+            rows.push(RowSource {
+                path: None,
+                parent: parent.clone(),
+                range: start..i,
+            });
+        }
+    }
+    return rows;
+}
+
+// Splits the children in `range` of a row into cells: cell elements, and
+// runs of anything else that make anonymous cells.
+fn collect_cells(tree: &Vec<DomElement>, range: Range<usize>) -> Vec<(Range<usize>, bool)> {
+    let mut cells: Vec<(Range<usize>, bool)> = vec![];
+    let mut i = range.start;
+    while i < range.end {
+        if is_ignored(&tree[i]) {
+            i += 1;
+        } else if get_display(&tree[i]) == "table-cell" {
+            cells.push((i..i + 1, false));
+            i += 1;
+        } else {
+            let start = i;
+            while i < range.end && get_display(&tree[i]) != "table-cell" {
+                i += 1;
+            }
+            let mut end = i;
+            while end > start && is_ignored(&tree[end - 1]) {
+                end -= 1;
+            }
+            cells.push((start..end, true));
+        }
+    }
+    return cells;
+}
+
+fn collect_columns(tree: &Vec<DomElement>, i: usize, columns: &mut Vec<TableColumn>) {
+    let element = &tree[i];
+    let get_width = |e: &DomElement| match e.computed_style.as_ref() {
+        Some(c) => get_column_width(c, 0.0),
+        None => ColumnWidth::Auto,
+    };
+    if get_display(element) == "table-column" {
+        let span = get_span(element, "span").unwrap_or(1).max(1).min(1000);
+        for _ in 0..span {
+            columns.push(TableColumn::new(Some(vec![i]), None, get_width(element)));
+        }
+        return;
+    }
+    let group_width = get_width(element);
+    let mut has_columns = false;
+    for (j, child) in element.children.iter().enumerate() {
+        if get_display(child) != "table-column" || !is_displayed(child) {
+            continue;
+        }
+        has_columns = true;
+        let span = get_span(child, "span").unwrap_or(1).max(1).min(1000);
+        let width = match get_width(child) {
+            ColumnWidth::Auto => group_width,
+            width => width,
+        };
+        for _ in 0..span {
+            columns.push(TableColumn::new(Some(vec![i, j]), Some(vec![i]), width));
+        }
+    }
+    if !has_columns {
+        let span = get_span(element, "span").unwrap_or(1).max(1).min(1000);
+        for _ in 0..span {
+            columns.push(TableColumn::new(None, Some(vec![i]), group_width));
+        }
+    }
+}
+
+// Sorts the children in `range` of a table into captions, columns, row
+// groups, rows and cells, with anonymous boxes around what is out of place,
+// and puts the cells in the grid, CSS 2.1 §17.2.1 and §17.5.
+fn build_table(tree: &Vec<DomElement>, range: Range<usize>) -> TableGrid {
+    let mut captions: Vec<usize> = vec![];
+    let mut columns: Vec<TableColumn> = vec![];
+    let mut sources: Vec<(Option<Vec<usize>>, String, Vec<RowSource>)> = vec![];
+
+    let mut i = range.start;
+    while i < range.end {
+        let element = &tree[i];
+        let display = get_display(element);
+        if is_ignored(element) {
+            i += 1;
+        } else if display == "table-caption" {
+            captions.push(i);
+            i += 1;
+        } else if display == "table-column" || display == "table-column-group" {
+            collect_columns(tree, i, &mut columns);
+            i += 1;
+        } else if is_row_group(element) {
+            let len = element.children.len();
+            sources.push((Some(vec![i]), display.to_string(), collect_rows(&element.children, &vec![i], 0..len)));
+            i += 1;
+        } else {
+            // rows and cells right in the table go in an anonymous row group
+            let start = i;
+            while i < range.end {
+                let display = get_display(&tree[i]);
+                if display == "table-caption"
+                    || display == "table-column"
+                    || display == "table-column-group"
+                    || is_row_group(&tree[i])
+                {
+                    break;
+                }
+                i += 1;
+            }
+            sources.push((None, S("table-row-group"), collect_rows(tree, &vec![], start..i)));
+        }
+    }
+
+    // the first header group goes first and the first footer group last
+    match sources.iter().position(|s| s.1 == "table-header-group") {
+        Some(p) => {
+            let header = sources.remove(p);
+            sources.insert(0, header);
+        }
+        None => {}
+    }
+    match sources.iter().position(|s| s.1 == "table-footer-group") {
+        Some(p) => {
+            let footer = sources.remove(p);
+            sources.push(footer);
+        }
+        None => {}
+    }
+
+    let mut groups: Vec<TableRowGroup> = vec![];
+    let mut rows: Vec<TableRow> = vec![];
+    let mut row_cells: Vec<(Vec<usize>, Vec<(Range<usize>, bool)>)> = vec![];
+    for (path, _, group_rows) in sources {
+        let start = rows.len();
+        for row in group_rows {
+            let mut parent: &Vec<DomElement> = tree;
+            for (k, j) in row.parent.iter().enumerate() {
+                parent = if k == 0 { &tree[*j].children } else { &parent[*j].children };
+            }
+            let parent = if row.parent.len() == 0 { tree } else { parent };
+            row_cells.push((row.parent.clone(), collect_cells(parent, row.range.clone())));
+            rows.push(TableRow {
+                path: row.path,
+                group: groups.len(),
+                y: 0.0,
+                height: 0.0,
+                baseline: None,
+            });
+        }
+        groups.push(TableRowGroup {
+            path,
+            rows: start..rows.len(),
+        });
+    }
+
+    // each cell takes the first free slot of its row, rows spanned by the
+    // cells above included
+    let mut cells: Vec<TableCell> = vec![];
+    let mut occupied: Vec<Vec<bool>> = vec![vec![]; rows.len()];
+    for (r, (parent, row)) in row_cells.into_iter().enumerate() {
+        let group_end = groups[rows[r].group].rows.end;
+        let mut column = 0;
+        for (range, anonymous) in row {
+            while occupied[r].get(column) == Some(&true) {
+                column += 1;
+            }
+            let mut cell = TableCell {
+                parent: parent.clone(),
+                range: range.clone(),
+                anonymous,
+                row: r,
+                column,
+                row_span: 1,
+                column_span: 1,
+                width: ColumnWidth::Auto,
+                min_width: 0.0,
+                max_width: 0.0,
+                y: 0.0,
+                height: 0.0,
+                baseline: 0.0,
+                align: S("baseline"),
+            };
+            if !anonymous {
+                let mut element: &DomElement = &tree[parent.first().copied().unwrap_or(range.start)];
+                for j in parent.iter().skip(1) {
+                    element = &element.children[*j];
+                }
+                if parent.len() > 0 {
+                    element = &element.children[range.start];
+                }
+                cell.column_span = get_span(element, "colspan").unwrap_or(1).max(1).min(1000);
+                // a row span of zero goes to the end of the row group
+                cell.row_span = match get_span(element, "rowspan") {
+                    Some(0) => group_end - r,
+                    Some(n) => n.min(group_end - r).min(65534),
+                    None => 1,
+                };
+                let computed_style = element.computed_style.as_ref().unwrap();
+                cell.width = get_column_width(computed_style, get_cell_edges(computed_style));
+                cell.align = computed_style.values.get("vertical-align").cloned().unwrap_or(S("baseline"));
+            }
+            for row in &mut occupied[r..r + cell.row_span] {
+                if row.len() < column + cell.column_span {
+                    row.resize(column + cell.column_span, false);
+                }
+                for slot in &mut row[column..column + cell.column_span] {
+                    *slot = true;
+                }
+            }
+            column += cell.column_span;
+            cells.push(cell);
+        }
+    }
+
+    let column_count = occupied.iter().map(|r| r.len()).max().unwrap_or(0);
+    while columns.len() < column_count {
+        columns.push(TableColumn::new(None, None, ColumnWidth::Auto));
+    }
+
+    TableGrid {
+        captions,
+        groups,
+        rows,
+        columns,
+        cells,
+    }
+}
+
+// The narrowest and widest each column gets from the cells in it, CSS 2.1
+// §17.5.2.2. Cells that span several columns widen them after the others,
+// in proportion to how wide they already are.
+fn measure_columns(
+    tree: &mut Vec<DomElement>,
+    grid: &mut TableGrid,
+    measure_text: &dyn Fn(String, f64, String) -> (f64, f64),
+    spacing: f64,
+) {
+    for cell in &mut grid.cells {
+        let children = get_children(tree, &cell.parent);
+        let (min_width, max_width) = if cell.anonymous {
+            get_block_content_widths(children, cell.range.clone(), measure_text)
+        } else {
+            let element = &mut children[cell.range.start];
+            let (min_width, max_width) = get_content_widths(element, measure_text);
+            let edges = get_cell_edges(element.computed_style.as_ref().unwrap());
+            (min_width + edges, max_width + edges)
+        };
+        // a fixed width is a minimum, and the widest the cell gets
+        match cell.width {
+            ColumnWidth::Fixed(width) => {
+                cell.min_width = min_width.max(width);
+                cell.max_width = cell.min_width;
+            }
+            _ => {
+                cell.min_width = min_width;
+                cell.max_width = max_width.max(min_width);
+            }
+        }
+    }
+
+    for column in &mut grid.columns {
+        match column.width {
+            ColumnWidth::Fixed(width) => {
+                column.min_width = width;
+                column.max_width = width;
+            }
+            _ => {}
+        }
+    }
+
+    let mut cells: Vec<&TableCell> = grid.cells.iter().collect();
+    cells.sort_by_key(|c| c.column_span);
+    for cell in cells {
+        let columns = &mut grid.columns[cell.column..cell.column + cell.column_span];
+        if cell.column_span == 1 {
+            let column = &mut columns[0];
+            column.width = match (column.width, cell.width) {
+                (ColumnWidth::Percentage(a), ColumnWidth::Percentage(b)) => ColumnWidth::Percentage(a.max(b)),
+                (ColumnWidth::Percentage(p), _) | (_, ColumnWidth::Percentage(p)) => ColumnWidth::Percentage(p),
+                (ColumnWidth::Fixed(a), ColumnWidth::Fixed(b)) => ColumnWidth::Fixed(a.max(b)),
+                (ColumnWidth::Auto, ColumnWidth::Fixed(b)) => ColumnWidth::Fixed(b),
+                (width, _) => width,
+            };
+            column.min_width = column.min_width.max(cell.min_width);
+            column.max_width = column.max_width.max(cell.max_width);
+            continue;
+        }
+        let inner_spacing = (cell.column_span - 1) as f64 * spacing;
+        let min_sum: f64 = columns.iter().map(|c| c.min_width).sum::<f64>() + inner_spacing;
+        let max_sum: f64 = columns.iter().map(|c| c.max_width).sum::<f64>() + inner_spacing;
+        let weights: Vec<f64> = columns.iter().map(|c| c.max_width).collect();
+        let total: f64 = weights.iter().sum();
+        let count = columns.len() as f64;
+        let share = |excess: f64, k: usize| {
+            if total > 0.0 {
+                excess * weights[k] / total
+            } else {
+                excess / count
+            }
+        };
+        if cell.min_width > min_sum {
+            for (k, column) in columns.iter_mut().enumerate() {
+                column.min_width += share(cell.min_width - min_sum, k);
+            }
+        }
+        if cell.max_width > max_sum {
+            for (k, column) in columns.iter_mut().enumerate() {
+                column.max_width += share(cell.max_width - max_sum, k);
+            }
+        }
+    }
+
+    for column in &mut grid.columns {
+        column.max_width = column.max_width.max(column.min_width);
+    }
+}
+
+fn get_total_spacing(grid: &TableGrid, spacing: f64) -> f64 {
+    match grid.columns.len() {
+        0 => 0.0,
+        n => (n + 1) as f64 * spacing,
+    }
+}
+
+// How narrow the captions let the table box get.
+fn get_captions_min_width(
+    tree: &mut Vec<DomElement>,
+    grid: &TableGrid,
+    settings: &TableSettings,
+    measure_text: &dyn Fn(String, f64, String) -> (f64, f64),
+) -> f64 {
+    let mut min_width: f64 = 0.0;
+    for i in &grid.captions {
+        let (caption_min, _) = get_intrinsic_widths(&mut tree[*i], measure_text);
+        min_width = min_width.max(caption_min - settings.edges.left - settings.edges.right);
+    }
+    return min_width;
+}
+
+fn get_table_widths(
+    tree: &mut Vec<DomElement>,
+    range: Range<usize>,
+    settings: &TableSettings,
+    measure_text: &dyn Fn(String, f64, String) -> (f64, f64),
+) -> (f64, f64) {
+    let mut grid = build_table(tree, range);
+    measure_columns(tree, &mut grid, measure_text, settings.spacing.0);
+    let spacing = get_total_spacing(&grid, settings.spacing.0);
+    let captions_min = get_captions_min_width(tree, &grid, settings, measure_text);
+    let min_width = grid.columns.iter().map(|c| c.min_width).sum::<f64>() + spacing;
+    let max_width = grid.columns.iter().map(|c| c.max_width).sum::<f64>() + spacing;
+    let min_width = min_width.max(captions_min);
+    return (min_width, max_width.max(min_width));
+}
+
+pub fn get_table_content_widths(
+    element: &mut DomElement,
+    measure_text: &dyn Fn(String, f64, String) -> (f64, f64),
+) -> (f64, f64) {
+    let settings = TableSettings::new(element.computed_style.as_ref().unwrap(), false);
+    let len = element.children.len();
+    return get_table_widths(&mut element.children, 0..len, &settings, measure_text);
+}
+
+fn get_anonymous_settings(tree: &Vec<DomElement>, range: &Range<usize>) -> TableSettings {
+    let first = tree[range.clone()].iter().find(|e| is_internal_table_box(e)).unwrap();
+    return TableSettings::new(first.computed_style.as_ref().unwrap(), true);
+}
+
+pub fn get_anonymous_table_widths(
+    tree: &mut Vec<DomElement>,
+    range: Range<usize>,
+    measure_text: &dyn Fn(String, f64, String) -> (f64, f64),
+) -> (f64, f64) {
+    let settings = get_anonymous_settings(tree, &range);
+    return get_table_widths(tree, range, &settings, measure_text);
+}
+
+// Gives the columns their widths out of `available`. How is up to the user
+// agent in CSS 2.1 §17.5.2.2: percentages first, then every column grows
+// from its narrowest towards its widest in proportion, and what is still
+// left goes to the `auto` columns.
+fn distribute_widths(columns: &mut Vec<TableColumn>, available: f64) {
+    for column in columns.iter_mut() {
+        column.used_width = match column.width {
+            ColumnWidth::Percentage(p) => column.min_width.max(p / 100.0 * available),
+            _ => column.min_width,
+        };
+    }
+    let is_percentage = |c: &TableColumn| match c.width {
+        ColumnWidth::Percentage(_) => true,
+        _ => false,
+    };
+    let mut remaining = available - columns.iter().map(|c| c.used_width).sum::<f64>();
+    if remaining < 0.0 {
+        // percentages give way, down to the narrowest their columns get
+        let excess: f64 = columns
+            .iter()
+            .filter(|c| is_percentage(c))
+            .map(|c| c.used_width - c.min_width)
+            .sum();
+        if excess > 0.0 {
+            let f = (-remaining).min(excess) / excess;
+            for column in columns.iter_mut().filter(|c| is_percentage(c)) {
+                column.used_width -= (column.used_width - column.min_width) * f;
+            }
+        }
+        return;
+    }
+
+    let growth: f64 = columns
+        .iter()
+        .filter(|c| !is_percentage(c))
+        .map(|c| c.max_width - c.min_width)
+        .sum();
+    if growth > 0.0 {
+        let f = (remaining / growth).min(1.0);
+        for column in columns.iter_mut().filter(|c| !is_percentage(c)) {
+            column.used_width += (column.max_width - column.min_width) * f;
+        }
+        remaining -= growth * f;
+    }
+    if remaining <= 0.0 {
+        return;
+    }
+
+    let kinds: [fn(&ColumnWidth) -> bool; 3] = [
+        |w| *w == ColumnWidth::Auto,
+        |w| match w {
+            ColumnWidth::Fixed(_) => true,
+            _ => false,
+        },
+        |w| match w {
+            ColumnWidth::Percentage(_) => true,
+            _ => false,
+        },
+    ];
+    for kind in kinds.iter() {
+        let targets: Vec<usize> = (0..columns.len()).filter(|k| kind(&columns[*k].width)).collect();
+        if targets.len() == 0 {
+            continue;
+        }
+        let total: f64 = targets.iter().map(|k| columns[*k].used_width).sum();
+        for k in &targets {
+            let column = &mut columns[*k];
+            column.used_width += if total > 0.0 {
+                remaining * column.used_width / total
+            } else {
+                remaining / targets.len() as f64
+            };
+        }
+        return;
+    }
+}
+
+// The column widths of the fixed table layout, CSS 2.1 §17.5.2.1: from the
+// columns, then from the cells of the first row, and the columns that have
+// neither share what is left.
+fn get_fixed_column_widths(grid: &mut TableGrid, available: f64, spacing: f64) {
+    let resolve = |w: ColumnWidth| match w {
+        ColumnWidth::Fixed(w) => Some(w),
+        ColumnWidth::Percentage(p) => Some(p / 100.0 * available),
+        ColumnWidth::Auto => None,
+    };
+    let mut widths: Vec<Option<f64>> = grid.columns.iter().map(|c| resolve(c.width)).collect();
+    for cell in grid.cells.iter().filter(|c| c.row == 0) {
+        let columns = cell.column..cell.column + cell.column_span;
+        if columns.clone().any(|c| widths[c].is_some()) {
+            continue;
+        }
+        match resolve(cell.width) {
+            Some(width) => {
+                let inner_spacing = (cell.column_span - 1) as f64 * spacing;
+                let share = (width - inner_spacing).max(0.0) / cell.column_span as f64;
+                for c in columns {
+                    widths[c] = Some(share);
+                }
+            }
+            None => {}
+        }
+    }
+
+    let set: f64 = widths.iter().map(|w| w.unwrap_or(0.0)).sum();
+    let auto_count = widths.iter().filter(|w| w.is_none()).count();
+    let scale = if auto_count == 0 && set > 0.0 && set < available {
+        available / set
+    } else {
+        1.0
+    };
+    for (column, width) in grid.columns.iter_mut().zip(widths) {
+        column.used_width = match width {
+            Some(w) => w * scale,
+            None => (available - set).max(0.0) / auto_count as f64,
+        };
+    }
+}
+
+// The baseline of the first line box in `range`, CSS 2.1 §17.5.3.
+fn get_first_baseline(tree: &[DomElement]) -> Option<f64> {
+    for element in tree {
+        match element.node_type {
+            NodeType::Text => {
+                let computed_flow = element.computed_flow.as_ref();
+                match computed_flow.and_then(|f| f.text_lines.iter().find(|l| l.text.trim() != "")) {
+                    Some(line) => return Some(line.baseline),
+                    None => {}
+                }
+            }
+            NodeType::Element if is_displayed(element) && !is_out_of_flow(element) => {
+                let computed_style = element.computed_style.as_ref().unwrap();
+                if computed_style.float != "none" || !has_laid_out_children(element) {
+                    continue;
+                }
+                match get_first_baseline(&element.children) {
+                    Some(baseline) => return Some(baseline),
+                    None => {}
+                }
+            }
+            _ => {}
+        }
+    }
+    return None;
+}
+
+// Lays out a cell with its border box at `x` and `y`, `width` wide. Returns
+// its height and the distance from its top to its baseline.
+fn layout_cell(
+    tree: &mut Vec<DomElement>,
+    cell: &TableCell,
+    measure_text: &dyn Fn(String, f64, String) -> (f64, f64),
+    container: &InlineContainer,
+    (x, y): (f64, f64),
+    width: f64,
+) -> (f64, f64) {
+    let children = get_children(tree, &cell.parent);
+    if cell.anonymous {
+        let mut cell_container = container.clone();
+        cell_container.x = x;
+        cell_container.y = y;
+        cell_container.width = width;
+        cell_container.height = None;
+        let block = layout_block_children(
+            children,
+            cell.range.clone(),
+            measure_text,
+            &cell_container,
+            CollapsedMargin::empty(),
+            &mut FloatContext::new(),
+        );
+        let height = (block.end_y + block.end_margin.resolve() - y).max(0.0);
+        let baseline = get_first_baseline(&children[cell.range.clone()]).map(|b| b - y);
+        return (height, baseline.unwrap_or(height));
+    }
+
+    let element = &mut children[cell.range.start];
+    let computed_style = element.computed_style.as_mut().unwrap();
+    resolve_box_edges(computed_style, container.width);
+    let padding = computed_style.padding.clone();
+    let border = computed_style.border.widths();
+    let content_width = (width - padding.left - padding.right - border.left - border.right).max(0.0);
+    let block = layout_block_box(
+        element,
+        measure_text,
+        x,
+        y,
+        content_width,
+        None,
+        CollapsedMargin::empty(),
+        &mut FloatContext::new(),
+        None,
+    );
+    // without a line box, the bottom of the content box
+    let baseline = match get_first_baseline(&element.children) {
+        Some(b) => b - y,
+        None => block.height - padding.bottom - border.bottom,
+    };
+    return (block.height, baseline);
+}
+
+// Lays out a caption with its margin box at `x` and `y` in a table box
+// `width` wide, and returns the height of its margin box.
+fn layout_caption(
+    element: &mut DomElement,
+    measure_text: &dyn Fn(String, f64, String) -> (f64, f64),
+    (x, y): (f64, f64),
+    width: f64,
+) -> f64 {
+    let computed_style = element.computed_style.as_mut().unwrap();
+    resolve_box_edges(computed_style, width);
+    let computed_style = element.computed_style.as_ref().unwrap();
+    let (content_width, margin_left, _) = get_block_width(computed_style, width, None);
+    let margin = computed_style.margin.clone();
+    let block = layout_block_box(
+        element,
+        measure_text,
+        x + margin_left,
+        y,
+        content_width,
+        None,
+        CollapsedMargin::new(margin.top),
+        &mut FloatContext::new(),
+        None,
+    );
+    return block.top_margin.resolve() + block.height + margin.bottom;
+}
+
+fn get_row_height(tree: &Vec<DomElement>, row: &TableRow) -> f64 {
+    let element = match &row.path {
+        Some(path) => get_element_by_path(tree, path),
+        None => None,
+    };
+    match element.and_then(|e| e.computed_style.as_ref()) {
+        Some(c) => resolve_size(c, &c.height, None, 0.0).unwrap_or(0.0),
+        None => 0.0,
+    }
+}
+
+fn set_box_flow(tree: &mut Vec<DomElement>, path: &Vec<usize>, rect: Rect) {
+    match get_element_by_path_mut(tree, path) {
+        Some(element) => {
+            let mut flow = ComputedFlow::empty();
+            flow.x = rect.x;
+            flow.y = rect.y;
+            flow.width = rect.width;
+            flow.height = rect.height;
+            set_computed_flow(element, flow);
+        }
+        None => {}
+    }
+}
+
+// Lays out the table whose parts are the children in `range`, with its
+// grid in `container`, CSS 2.1 §17.5. The content height is at least
+// `min_height` and the container's height, the rows share what the cells
+// leave. Captions go above and below the table box.
+fn layout_table(
+    tree: &mut Vec<DomElement>,
+    range: Range<usize>,
+    settings: &TableSettings,
+    measure_text: &dyn Fn(String, f64, String) -> (f64, f64),
+    container: &InlineContainer,
+    min_height: Option<f64>,
+) -> TableLayout {
+    let mut grid = build_table(tree, range);
+    let (h_spacing, v_spacing) = settings.spacing;
+    let total_spacing = get_total_spacing(&grid, h_spacing);
+
+    let width = if settings.fixed {
+        get_fixed_column_widths(&mut grid, (container.width - total_spacing).max(0.0), h_spacing);
+        let columns_width: f64 = grid.columns.iter().map(|c| c.used_width).sum();
+        container.width.max(columns_width + total_spacing)
+    } else {
+        measure_columns(tree, &mut grid, measure_text, h_spacing);
+        let min_width = grid.columns.iter().map(|c| c.min_width).sum::<f64>() + total_spacing;
+        let captions_min = get_captions_min_width(tree, &grid, settings, measure_text);
+        let width = container.width.max(min_width).max(captions_min);
+        distribute_widths(&mut grid.columns, width - total_spacing);
+        width
+    };
+    let mut x = container.x + h_spacing;
+    for column in &mut grid.columns {
+        column.x = x;
+        x += column.used_width + h_spacing;
+    }
+    let span_width = |grid: &TableGrid, cell: &TableCell| {
+        let last = &grid.columns[cell.column + cell.column_span - 1];
+        last.x + last.used_width - grid.columns[cell.column].x
+    };
+
+    // the rows are laid out from the top, each as tall as the cells that
+    // end in it need
+    let mut y = container.y;
+    for r in 0..grid.rows.len() {
+        y += v_spacing;
+        let mut height = get_row_height(tree, &grid.rows[r]);
+        let mut baseline: Option<f64> = None;
+        for k in 0..grid.cells.len() {
+            if grid.cells[k].row != r {
+                continue;
+            }
+            let cell = &grid.cells[k];
+            let position = (grid.columns[cell.column].x, y);
+            let (cell_height, cell_baseline) =
+                layout_cell(tree, cell, measure_text, container, position, span_width(&grid, cell));
+            let cell = &mut grid.cells[k];
+            cell.y = y;
+            cell.height = cell_height;
+            cell.baseline = cell_baseline;
+            if cell.align == "baseline" {
+                baseline = Some(baseline.unwrap_or(0.0).max(cell_baseline));
+            }
+        }
+        for cell in &grid.cells {
+            if cell.row + cell.row_span - 1 != r {
+                continue;
+            }
+            let needed = match baseline {
+                Some(b) if cell.row_span == 1 && cell.align == "baseline" => b - cell.baseline + cell.height,
+                _ => cell.height - (y - cell.y),
+            };
+            height = height.max(needed);
+        }
+        let row = &mut grid.rows[r];
+        row.y = y;
+        row.height = height;
+        row.baseline = baseline;
+        y += height;
+    }
+    if grid.rows.len() > 0 {
+        y += v_spacing;
+    }
+
+    // a taller table makes its rows taller
+    let target = container.height.unwrap_or(0.0).max(min_height.unwrap_or(0.0));
+    let content_height = y - container.y;
+    if target > content_height && grid.rows.len() > 0 {
+        let extra = target - content_height;
+        let total: f64 = grid.rows.iter().map(|r| r.height).sum();
+        let count = grid.rows.len() as f64;
+        let mut shift = 0.0;
+        for row in &mut grid.rows {
+            row.y += shift;
+            let grow = if total > 0.0 { extra * row.height / total } else { extra / count };
+            row.height += grow;
+            shift += grow;
+        }
+        y += extra;
+    }
+
+    // cells stretch over their rows, with their content aligned in them
+    let mut first_row_baseline: Option<f64> = None;
+    for cell in &grid.cells {
+        let top = grid.rows[cell.row].y;
+        let last = &grid.rows[cell.row + cell.row_span - 1];
+        let box_height = last.y + last.height - top;
+        let free = box_height - cell.height;
+        let offset = match cell.align.as_str() {
+            "top" => 0.0,
+            "middle" => free / 2.0,
+            "bottom" => free,
+            _ => match grid.rows[cell.row].baseline {
+                Some(b) => b - cell.baseline,
+                None => 0.0,
+            },
+        };
+        if cell.row == 0 {
+            first_row_baseline = Some(first_row_baseline.unwrap_or(0.0).max(top + offset + cell.baseline));
+        }
+        let dy = top - cell.y;
+        let children = get_children(tree, &cell.parent);
+        if cell.anonymous {
+            for element in &mut children[cell.range.clone()] {
+                translate_element(element, 0.0, dy + offset);
+            }
+            continue;
+        }
+        let element = &mut children[cell.range.start];
+        translate_element(element, 0.0, dy);
+        for child in &mut element.children {
+            translate_element(child, 0.0, offset);
+        }
+        let computed_flow = element.computed_flow.as_mut().unwrap();
+        computed_flow.height = box_height;
+        computed_flow.hover_rect.height = box_height;
+        computed_flow.baseline = computed_flow.baseline.map(|b| b + offset);
+    }
+
+    // rows and row groups span the columns, columns have no box
+    let grid_x = grid.columns.first().map(|c| c.x).unwrap_or(container.x);
+    let grid_width = grid.columns.last().map(|c| c.x + c.used_width - grid_x).unwrap_or(0.0);
+    for row in &grid.rows {
+        match &row.path {
+            Some(path) => set_box_flow(
+                tree,
+                path,
+                Rect {
+                    x: grid_x,
+                    y: row.y,
+                    width: grid_width,
+                    height: row.height,
+                },
+            ),
+            None => {}
+        }
+    }
+    for group in &grid.groups {
+        let path = match &group.path {
+            Some(p) => p,
+            None => continue,
+        };
+        let (top, bottom) = if group.rows.len() > 0 {
+            let last = &grid.rows[group.rows.end - 1];
+            (grid.rows[group.rows.start].y, last.y + last.height)
+        } else {
+            (container.y, container.y)
+        };
+        let rect = Rect {
+            x: grid_x,
+            y: top,
+            width: grid_width,
+            height: bottom - top,
+        };
+        set_box_flow(tree, path, rect);
+    }
+    for column in &grid.columns {
+        for path in column.element.iter().chain(column.group.iter()) {
+            match get_element_by_path_mut(tree, path) {
+                Some(element) => clear_computed_flow(element),
+                None => {}
+            }
+        }
+    }
+
+    let height = y - container.y;
+    // a first row without baseline aligned cells has its baseline at the
+    // lowest of theirs
+    let baseline = match grid.rows.first() {
+        Some(row) => row.baseline.map(|b| row.y + b).or(first_row_baseline),
+        None => None,
+    };
+
+    let edges = &settings.edges;
+    let table_width = edges.left + width + edges.right;
+    let table_x = container.x - edges.left;
+    let mut above: Vec<usize> = vec![];
+    let mut below: Vec<usize> = vec![];
+    for i in &grid.captions {
+        let computed_style = tree[*i].computed_style.as_ref().unwrap();
+        match computed_style.values.get("caption-side").map(|s| s.as_str()) {
+            Some("bottom") | Some("block-end") => below.push(*i),
+            _ => above.push(*i),
+        }
+    }
+    let table_top = container.y - edges.top;
+    let mut caption_y = table_top;
+    for i in &above {
+        caption_y += layout_caption(&mut tree[*i], measure_text, (table_x, caption_y), table_width);
+    }
+    let captions_above = caption_y - table_top;
+    for i in &above {
+        translate_element(&mut tree[*i], 0.0, -captions_above);
+    }
+    let table_bottom = container.y + height + edges.bottom;
+    let mut caption_y = table_bottom;
+    for i in &below {
+        caption_y += layout_caption(&mut tree[*i], measure_text, (table_x, caption_y), table_width);
+    }
+
+    TableLayout {
+        width,
+        height,
+        baseline,
+        captions_above,
+        captions_below: caption_y - table_bottom,
+    }
+}
+
+// Lays out the grid of a table element in `container`, its content box.
+pub fn layout_table_box(
+    element: &mut DomElement,
+    measure_text: &dyn Fn(String, f64, String) -> (f64, f64),
+    container: &InlineContainer,
+    min_height: Option<f64>,
+) -> TableLayout {
+    let settings = TableSettings::new(element.computed_style.as_ref().unwrap(), false);
+    let len = element.children.len();
+    return layout_table(&mut element.children, 0..len, &settings, measure_text, container, min_height);
+}
+
+// Lays out the table parts in `range` as an anonymous table, with its
+// captions and grid from the top of `container`.
+pub fn layout_anonymous_table(
+    tree: &mut Vec<DomElement>,
+    range: Range<usize>,
+    measure_text: &dyn Fn(String, f64, String) -> (f64, f64),
+    container: &InlineContainer,
+) -> TableLayout {
+    let settings = get_anonymous_settings(tree, &range);
+    let mut table = layout_table(tree, range.clone(), &settings, measure_text, container, None);
+    if table.captions_above != 0.0 {
+        for element in &mut tree[range] {
+            translate_element(element, 0.0, table.captions_above);
+        }
+        table.baseline = table.baseline.map(|b| b + table.captions_above);
+    }
+    return table;
+}
+
+// Which of two conflicting borders wins, CSS 2.1 §17.6.2.1: `hidden`, then
+// the wider one, then the style, then the box it comes from. `origin` goes
+// from the table to the cell.
+fn wins(a: &(BorderSide, u8), b: &(BorderSide, u8)) -> bool {
+    let rank = |style: &str| match style {
+        "double" => 8,
+        "solid" => 7,
+        "dashed" => 6,
+        "dotted" => 5,
+        "ridge" => 4,
+        "outset" => 3,
+        "groove" => 2,
+        "inset" => 1,
+        _ => 0,
+    };
+    if b.0.style == "hidden" {
+        return false;
+    }
+    if a.0.style == "hidden" {
+        return true;
+    }
+    if a.0.width != b.0.width {
+        return a.0.width > b.0.width;
+    }
+    if rank(&a.0.style) != rank(&b.0.style) {
+        return rank(&a.0.style) > rank(&b.0.style);
+    }
+    return a.1 > b.1;
+}
+
+// The border that wins among `candidates`, the ones above and to the left
+// first as they win ties.
+fn resolve_border(candidates: Vec<(BorderSide, u8)>) -> BorderSide {
+    let mut winner = candidates[0].clone();
+    for candidate in candidates.into_iter().skip(1) {
+        if wins(&candidate, &winner) {
+            winner = candidate;
+        }
+    }
+    let mut border = winner.0;
+    if border.style == "hidden" {
+        border.width = 0.0;
+    }
+    return border;
+}
+
+fn half(side: &BorderSide) -> BorderSide {
+    BorderSide {
+        width: side.width / 2.0,
+        style: side.style.clone(),
+        color: side.color,
+    }
+}
+
+fn get_specified_border(element: &DomElement) -> Border {
+    let computed_style = element.computed_style.as_ref().unwrap();
+    return get_computed_border(
+        &computed_style.values,
+        &computed_style.length_context,
+        &computed_style.color,
+        computed_style.color_scheme,
+    );
+}
+
+fn no_border(element: &DomElement) -> Border {
+    let mut border = get_specified_border(element);
+    for side in [&mut border.top, &mut border.right, &mut border.bottom, &mut border.left] {
+        side.width = 0.0;
+        side.style = S("none");
+    }
+    return border;
+}
+
+fn get_cell_element<'a>(tree: &'a Vec<DomElement>, cell: &TableCell) -> Option<&'a DomElement> {
+    if cell.anonymous {
+        return None;
+    }
+    let path = with_index(&cell.parent, cell.range.start);
+    return get_element_by_path(tree, &path);
+}
+
+// Sets the `border` of the parts of a table before it is laid out. Rows,
+// row groups and columns have none of their own. In the collapsing border
+// model, CSS 2.1 §17.6.2, the borders of all of them meet in one border on
+// each grid line, that the cells on either side and the table each paint
+// half of.
+fn resolve_grid_borders(tree: &mut Vec<DomElement>, range: Range<usize>, table: Option<&mut ComputedStyle>) {
+    let grid = build_table(tree, range.clone());
+    let collapse = match &table {
+        Some(t) => is_collapsing_table(t),
+        None => get_anonymous_settings(tree, &range).collapse,
+    };
+
+    let border_of = |path: &Option<Vec<usize>>, origin: u8| -> Option<(Border, u8)> {
+        let element = get_element_by_path(tree, path.as_ref()?)?;
+        Some((get_specified_border(element), origin))
+    };
+    let table_border = table.as_ref().map(|t| {
+        (
+            get_computed_border(&t.values, &t.length_context, &t.color, t.color_scheme),
+            0,
+        )
+    });
+    let row_borders: Vec<Option<(Border, u8)>> = grid.rows.iter().map(|r| border_of(&r.path, 4)).collect();
+    let group_borders: Vec<Option<(Border, u8)>> = grid.groups.iter().map(|g| border_of(&g.path, 3)).collect();
+    let column_borders: Vec<Option<(Border, u8)>> = grid.columns.iter().map(|c| border_of(&c.element, 2)).collect();
+    let column_group_borders: Vec<Option<(Border, u8)>> = grid.columns.iter().map(|c| border_of(&c.group, 1)).collect();
+    let cell_borders: Vec<Option<(Border, u8)>> = grid
+        .cells
+        .iter()
+        .map(|c| get_cell_element(tree, c).map(|e| (get_specified_border(e), 5)))
+        .collect();
+
+    let rows = grid.rows.len();
+    let columns = grid.columns.len();
+    let mut slots: Vec<Vec<Option<usize>>> = vec![vec![None; columns]; rows];
+    for (k, cell) in grid.cells.iter().enumerate() {
+        for r in cell.row..cell.row + cell.row_span {
+            for c in cell.column..cell.column + cell.column_span {
+                slots[r][c] = Some(k);
+            }
+        }
+    }
+    let group_of = |r: usize| grid.rows[r].group;
+    let is_group_start = |r: usize| r == 0 || group_of(r - 1) != group_of(r);
+    let is_group_end = |r: usize| r + 1 == rows || group_of(r + 1) != group_of(r);
+    let is_column_group_start = |c: usize| c == 0 || grid.columns[c - 1].group != grid.columns[c].group;
+    let is_column_group_end = |c: usize| c + 1 == columns || grid.columns[c + 1].group != grid.columns[c].group;
+
+    let mut used: Vec<Option<Border>> = vec![None; grid.cells.len()];
+    let mut outer: [Vec<BorderSide>; 4] = [vec![], vec![], vec![], vec![]];
+    if collapse {
+        for (k, cell) in grid.cells.iter().enumerate() {
+            let own = cell_borders[k].clone();
+            if own.is_none() {
+                continue;
+            }
+            let (r0, r1) = (cell.row, cell.row + cell.row_span);
+            let (c0, c1) = (cell.column, cell.column + cell.column_span);
+            let mut sides: Vec<Vec<(BorderSide, u8)>> = vec![vec![], vec![], vec![], vec![]];
+            let push = |list: &mut Vec<(BorderSide, u8)>, border: &Option<(Border, u8)>, side: usize| match border {
+                Some((b, origin)) => list.push((
+                    [&b.top, &b.right, &b.bottom, &b.left][side].clone(),
+                    *origin,
+                )),
+                None => {}
+            };
+            let neighbours = |list: &mut Vec<(BorderSide, u8)>, slots: Vec<Option<usize>>, side: usize| {
+                let mut seen: Vec<usize> = vec![];
+                for slot in slots {
+                    match slot {
+                        Some(n) if !seen.contains(&n) => {
+                            seen.push(n);
+                            push(list, &cell_borders[n], side);
+                        }
+                        _ => {}
+                    }
+                }
+            };
+
+            // top
+            if r0 == 0 {
+                push(&mut sides[0], &table_border, 0);
+                for c in c0..c1 {
+                    push(&mut sides[0], &column_group_borders[c], 0);
+                    push(&mut sides[0], &column_borders[c], 0);
+                }
+            } else {
+                neighbours(&mut sides[0], (c0..c1).map(|c| slots[r0 - 1][c]).collect(), 2);
+                push(&mut sides[0], &row_borders[r0 - 1], 2);
+                if is_group_start(r0) {
+                    push(&mut sides[0], &group_borders[group_of(r0 - 1)], 2);
+                }
+            }
+            if is_group_start(r0) {
+                push(&mut sides[0], &group_borders[group_of(r0)], 0);
+            }
+            push(&mut sides[0], &row_borders[r0], 0);
+            push(&mut sides[0], &own, 0);
+
+            // bottom
+            push(&mut sides[2], &own, 2);
+            push(&mut sides[2], &row_borders[r1 - 1], 2);
+            if is_group_end(r1 - 1) {
+                push(&mut sides[2], &group_borders[group_of(r1 - 1)], 2);
+            }
+            if r1 == rows {
+                for c in c0..c1 {
+                    push(&mut sides[2], &column_borders[c], 2);
+                    push(&mut sides[2], &column_group_borders[c], 2);
+                }
+                push(&mut sides[2], &table_border, 2);
+            } else {
+                if is_group_end(r1 - 1) {
+                    push(&mut sides[2], &group_borders[group_of(r1)], 0);
+                }
+                push(&mut sides[2], &row_borders[r1], 0);
+                neighbours(&mut sides[2], (c0..c1).map(|c| slots[r1][c]).collect(), 0);
+            }
+
+            // left
+            if c0 == 0 {
+                push(&mut sides[3], &table_border, 3);
+                for r in r0..r1 {
+                    if is_group_start(r) || r == r0 {
+                        push(&mut sides[3], &group_borders[group_of(r)], 3);
+                    }
+                    push(&mut sides[3], &row_borders[r], 3);
+                }
+            } else {
+                neighbours(&mut sides[3], (r0..r1).map(|r| slots[r][c0 - 1]).collect(), 1);
+                push(&mut sides[3], &column_borders[c0 - 1], 1);
+                if is_column_group_start(c0) {
+                    push(&mut sides[3], &column_group_borders[c0 - 1], 1);
+                }
+            }
+            if is_column_group_start(c0) {
+                push(&mut sides[3], &column_group_borders[c0], 3);
+            }
+            push(&mut sides[3], &column_borders[c0], 3);
+            push(&mut sides[3], &own, 3);
+
+            // right
+            push(&mut sides[1], &own, 1);
+            push(&mut sides[1], &column_borders[c1 - 1], 1);
+            if is_column_group_end(c1 - 1) {
+                push(&mut sides[1], &column_group_borders[c1 - 1], 1);
+            }
+            if c1 == columns {
+                for r in r0..r1 {
+                    push(&mut sides[1], &row_borders[r], 1);
+                    if is_group_end(r) || r + 1 == r1 {
+                        push(&mut sides[1], &group_borders[group_of(r)], 1);
+                    }
+                }
+                push(&mut sides[1], &table_border, 1);
+            } else {
+                if is_column_group_end(c1 - 1) {
+                    push(&mut sides[1], &column_group_borders[c1], 3);
+                }
+                push(&mut sides[1], &column_borders[c1], 3);
+                neighbours(&mut sides[1], (r0..r1).map(|r| slots[r][c1]).collect(), 3);
+            }
+
+            let resolved: Vec<BorderSide> = sides.into_iter().map(resolve_border).collect();
+            if r0 == 0 {
+                outer[0].push(resolved[0].clone());
+            }
+            if c1 == columns {
+                outer[1].push(resolved[1].clone());
+            }
+            if r1 == rows {
+                outer[2].push(resolved[2].clone());
+            }
+            if c0 == 0 {
+                outer[3].push(resolved[3].clone());
+            }
+            used[k] = Some(Border {
+                top: half(&resolved[0]),
+                right: half(&resolved[1]),
+                bottom: half(&resolved[2]),
+                left: half(&resolved[3]),
+            });
+        }
+    }
+
+    match table {
+        Some(table) => {
+            table.border = get_computed_border(&table.values, &table.length_context, &table.color, table.color_scheme);
+            if collapse {
+                // the widest border on each edge, half of it
+                let border = &table.border;
+                let sides: Vec<BorderSide> = [&border.top, &border.right, &border.bottom, &border.left]
+                    .iter()
+                    .zip(outer.iter())
+                    .map(|(side, borders)| {
+                        let widest = borders.iter().fold(None, |w: Option<&BorderSide>, b| match w {
+                            Some(w) if w.width >= b.width => Some(w),
+                            _ => Some(b),
+                        });
+                        match widest {
+                            Some(b) => half(b),
+                            None => half(&resolve_border(vec![((*side).clone(), 0)])),
+                        }
+                    })
+                    .collect();
+                table.border = Border {
+                    top: sides[0].clone(),
+                    right: sides[1].clone(),
+                    bottom: sides[2].clone(),
+                    left: sides[3].clone(),
+                };
+                table.padding = Margin::zero();
+            }
+        }
+        None => {}
+    }
+
+    let mut boxes: Vec<&Vec<usize>> = vec![];
+    boxes.extend(grid.rows.iter().filter_map(|r| r.path.as_ref()));
+    boxes.extend(grid.groups.iter().filter_map(|g| g.path.as_ref()));
+    for column in &grid.columns {
+        boxes.extend(column.element.iter().chain(column.group.iter()));
+    }
+    for path in boxes {
+        match get_element_by_path_mut(tree, path) {
+            Some(element) if element.computed_style.is_some() => {
+                let border = no_border(element);
+                element.computed_style.as_mut().unwrap().border = border;
+            }
+            _ => {}
+        }
+    }
+
+    for (k, cell) in grid.cells.iter().enumerate() {
+        let children = get_children(tree, &cell.parent);
+        if cell.anonymous {
+            resolve_borders_in(children, cell.range.clone());
+            continue;
+        }
+        let element = &mut children[cell.range.start];
+        let border = match used[k].take() {
+            Some(border) => border,
+            None => get_specified_border(element),
+        };
+        element.computed_style.as_mut().unwrap().border = border;
+        let len = element.children.len();
+        resolve_borders_in(&mut element.children, 0..len);
+    }
+    for i in &grid.captions {
+        let len = tree[*i].children.len();
+        resolve_borders_in(&mut tree[*i].children, 0..len);
+    }
+}
+
+fn resolve_borders_in(tree: &mut Vec<DomElement>, range: Range<usize>) {
+    let mut i = range.start;
+    while i < range.end {
+        if is_internal_table_box(&tree[i]) {
+            let start = i;
+            i = get_table_run_end(tree, start, range.end);
+            resolve_grid_borders(tree, start..i, None);
+            continue;
+        }
+        let element = &mut tree[i];
+        i += 1;
+        if element.node_type != NodeType::Element || !has_laid_out_children(element) {
+            continue;
+        }
+        let len = element.children.len();
+        match element.computed_style.as_mut() {
+            Some(c) if is_table(c) => resolve_grid_borders(&mut element.children, 0..len, Some(c)),
+            _ => resolve_borders_in(&mut element.children, 0..len),
+        }
+    }
+}
+
+// Gives the parts of the tables in `tree` the borders they are laid out and
+// painted with, see `resolve_grid_borders`.
+pub fn resolve_table_borders(tree: &mut Vec<DomElement>) {
+    let len = tree.len();
+    resolve_borders_in(tree, 0..len);
+}